@@ -5,3 +5,7 @@ pub const BIN_ACTION_SYNC_PARA_HEADER: u8 = BIN_ACTION_START;
 pub const BIN_ACTION_DISPATCH_BLOCK: u8 = BIN_ACTION_START + 1;
 pub const BIN_ACTION_SYNC_HEADER: u8 = BIN_ACTION_START + 2;
 pub const BIN_ACTION_SYNC_COMBINED_HEADERS: u8 = BIN_ACTION_START + 3;
+pub const BIN_ACTION_INIT_BEEFY: u8 = BIN_ACTION_START + 4;
+pub const BIN_ACTION_SYNC_PARA_HEADER_WITH_BEEFY: u8 = BIN_ACTION_START + 5;
+pub const BIN_ACTION_FAST_SYNC: u8 = BIN_ACTION_START + 6;
+pub const BIN_ACTION_GET_BEEFY_STATE: u8 = BIN_ACTION_START + 7;
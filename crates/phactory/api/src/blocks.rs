@@ -5,7 +5,7 @@ use scale_info::TypeInfo;
 pub use sp_consensus_grandpa::{AuthorityList, SetId};
 
 pub use phala_trie_storage::ser::StorageChanges;
-use sp_core::U256;
+use sp_core::{H256, U256};
 use sp_runtime::{generic::Header, traits::Hash as HashT};
 
 pub type StorageProof = Vec<Vec<u8>>;
@@ -97,6 +97,91 @@ pub struct SyncCombinedHeadersReq {
     pub proof: StorageProof,
 }
 
+/// A compressed ECDSA public key of a BEEFY authority.
+pub type BeefyAuthorityId = [u8; 33];
+
+/// The BEEFY authority set of the relay chain, to start BEEFY validation with.
+///
+/// `proof` proves `Beefy::Authorities` and `Beefy::ValidatorSetId` against the state root of the
+/// last GRANDPA finalized relay chain block of the worker.
+#[derive(TypeInfo, Encode, Decode, Clone, Debug)]
+pub struct InitBeefyReq {
+    pub authorities: Vec<BeefyAuthorityId>,
+    pub validator_set_id: u64,
+    pub proof: StorageProof,
+}
+
+/// The BEEFY validation state of a worker.
+#[derive(TypeInfo, Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct BeefyState {
+    /// The id of the BEEFY authority set the worker accepts commitments of.
+    pub validator_set_id: u64,
+    /// The last BEEFY finalized relay chain block imported by the worker.
+    pub latest_block: u32,
+}
+
+/// The MMR proof of a single leaf, in the layout of `sp_mmr_primitives::Proof`.
+#[derive(TypeInfo, Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct MmrLeafProof {
+    pub leaf_indices: Vec<u64>,
+    pub leaf_count: u64,
+    pub items: Vec<H256>,
+}
+
+/// The merkle proof of a parachain head in the para heads root of an MMR leaf.
+///
+/// The leaves of the tree are `(para_id, head_data).encode()` sorted by the para id.
+#[derive(TypeInfo, Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct ParaHeadProof {
+    pub proof: Vec<H256>,
+    pub number_of_leaves: u32,
+    pub leaf_index: u32,
+}
+
+/// Proves a parachain head with a BEEFY finalized MMR root of the relay chain.
+#[derive(TypeInfo, Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct BeefyParaHeadProof {
+    /// The encoded `VersionedFinalityProof` of a relay chain block. Omitted to prove against the
+    /// last MMR root the worker has verified.
+    pub finality_proof: Option<Vec<u8>>,
+    /// The authorities of the next BEEFY set, required if `finality_proof` is the first one
+    /// signed by that set.
+    pub next_authorities: Option<Vec<BeefyAuthorityId>>,
+    /// The encoded `MmrLeaf` of the relay chain block the parachain head is read from.
+    pub mmr_leaf: Vec<u8>,
+    pub mmr_proof: MmrLeafProof,
+    pub para_head_proof: ParaHeadProof,
+}
+
+#[derive(TypeInfo, Encode, Decode, Clone, Debug)]
+pub struct SyncParachainHeaderWithBeefyReq {
+    pub headers: Headers,
+    pub proof: BeefyParaHeadProof,
+}
+
+/// The outbound messages of a parachain block, proved against the state root of the block.
+///
+/// `proof` proves `PhalaMq::OutboundMessagesV2` (or `PhalaMq::OutboundMessages` on older chains)
+/// and `Timestamp::Now` of the block.
+#[derive(TypeInfo, Encode, Decode, Clone, Debug)]
+pub struct ReplayBlock {
+    pub block_number: chain::BlockNumber,
+    pub now_ms: u64,
+    pub messages: Vec<phala_mq::Message>,
+    pub proof: StorageProof,
+}
+
+/// Start from the chain state at `block_number` instead of the genesis.
+///
+/// The parachain headers must have been synced beyond `block_number`. `replay` carries the blocks
+/// with the messages needed to reconstruct the computing state of the worker, in ascending order.
+#[derive(TypeInfo, Encode, Decode, Clone, Debug)]
+pub struct FastSyncReq {
+    pub block_number: chain::BlockNumber,
+    pub storage: StorageState,
+    pub replay: Vec<ReplayBlock>,
+}
+
 #[derive(TypeInfo, Encode, Decode, Clone, Debug)]
 pub struct DispatchBlockReq {
     pub blocks: Vec<BlockHeaderWithChanges>,
@@ -0,0 +1,231 @@
+//! Types to inspect, verify and compare cluster state dumps.
+//!
+//! A cluster state dump is created by `SaveClusterState` and can only be decrypted by the pRuntime
+//! of the worker it is addressed to. That pRuntime summarizes the dump into a [`ClusterStateSummary`]
+//! without any secrets, so the summaries of different dumps can be compared anywhere.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+/// The contents of a cluster state dump. Ids and hashes are 0x-prefixed hex strings.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClusterStateSummary {
+    /// The file name of the dump.
+    pub filename: String,
+    /// The checkpoint version the dump was created with.
+    pub version: u32,
+    pub cluster_id: String,
+    /// The ecdh public key of the worker that created the dump.
+    pub sender: String,
+    pub block_number: u32,
+    /// blake2_256 of the decrypted dump.
+    pub state_hash: String,
+    pub system_contract: Option<String>,
+    /// Root of the cluster storage trie.
+    pub storage_root: Option<String>,
+    /// Number of nodes in the cluster storage trie.
+    pub storage_nodes: u64,
+    /// Total size of the nodes in the cluster storage trie.
+    pub storage_bytes: u64,
+    /// Number of pending outgoing messages of each sender.
+    pub pending_messages: BTreeMap<String, u64>,
+    pub contracts: Vec<ContractSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ContractSummary {
+    pub id: String,
+    pub code_hash: Option<String>,
+    pub weight: u32,
+    pub sidevm_code_hash: Option<String>,
+    /// Root of the contract's child storage trie, `None` if the contract has no storage.
+    pub storage_root: Option<String>,
+}
+
+impl ClusterStateSummary {
+    pub fn contract(&self, id: &str) -> Option<&ContractSummary> {
+        self.contracts.iter().find(|c| c.id == id)
+    }
+}
+
+/// The result of verifying a dump against the chain state of the receiving worker.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClusterStateVerification {
+    pub summary: ClusterStateSummary,
+    /// The block number of the chain state the dump was verified against.
+    pub verified_at: u32,
+    /// Problems found, empty if the dump is valid.
+    pub issues: Vec<String>,
+}
+
+impl ClusterStateVerification {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Request to load a cluster state dump, optionally with only some of its contracts.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LoadClusterStateRequest {
+    pub filename: String,
+    /// Ids of the contracts to restore, all contracts are restored if `None`.
+    #[serde(default)]
+    pub contracts: Option<Vec<String>>,
+}
+
+/// The differences between two dumps of the same cluster.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClusterStateDiff {
+    /// Issues that make the dumps incomparable, e.g. they are of different clusters.
+    pub incompatible: Vec<String>,
+    pub block_number: Option<(u32, u32)>,
+    pub system_contract: Option<(Option<String>, Option<String>)>,
+    pub storage_root: Option<(Option<String>, Option<String>)>,
+    /// Contracts only in the second dump.
+    pub added: Vec<String>,
+    /// Contracts only in the first dump.
+    pub removed: Vec<String>,
+    pub changed: Vec<ContractDiff>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ContractDiff {
+    pub id: String,
+    /// Names of the fields of [`ContractSummary`] that differ.
+    pub fields: Vec<String>,
+}
+
+impl ClusterStateDiff {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Compare the dump `a` with the dump `b`.
+pub fn diff(a: &ClusterStateSummary, b: &ClusterStateSummary) -> ClusterStateDiff {
+    fn changed<T: PartialEq + Clone>(a: &T, b: &T) -> Option<(T, T)> {
+        (a != b).then(|| (a.clone(), b.clone()))
+    }
+
+    let mut diff = ClusterStateDiff::default();
+    if a.cluster_id != b.cluster_id {
+        diff.incompatible.push(alloc::format!(
+            "Different clusters: {} and {}",
+            a.cluster_id,
+            b.cluster_id
+        ));
+    }
+    if a.version != b.version {
+        diff.incompatible.push(alloc::format!(
+            "Different versions: {} and {}",
+            a.version,
+            b.version
+        ));
+    }
+    diff.block_number = changed(&a.block_number, &b.block_number);
+    diff.system_contract = changed(&a.system_contract, &b.system_contract);
+    diff.storage_root = changed(&a.storage_root, &b.storage_root);
+
+    let contracts_a: BTreeMap<_, _> = a.contracts.iter().map(|c| (&c.id, c)).collect();
+    let contracts_b: BTreeMap<_, _> = b.contracts.iter().map(|c| (&c.id, c)).collect();
+    for (id, ca) in &contracts_a {
+        let Some(cb) = contracts_b.get(id) else {
+            diff.removed.push((*id).clone());
+            continue;
+        };
+        let mut fields = Vec::new();
+        if ca.code_hash != cb.code_hash {
+            fields.push("code_hash".into());
+        }
+        if ca.weight != cb.weight {
+            fields.push("weight".into());
+        }
+        if ca.sidevm_code_hash != cb.sidevm_code_hash {
+            fields.push("sidevm_code_hash".into());
+        }
+        if ca.storage_root != cb.storage_root {
+            fields.push("storage_root".into());
+        }
+        if !fields.is_empty() {
+            diff.changed.push(ContractDiff {
+                id: (*id).clone(),
+                fields,
+            });
+        }
+    }
+    diff.added = contracts_b
+        .keys()
+        .filter(|id| !contracts_a.contains_key(*id))
+        .map(|id| (*id).clone())
+        .collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contract(id: &str, weight: u32, storage_root: &str) -> ContractSummary {
+        ContractSummary {
+            id: id.into(),
+            code_hash: Some("0xc0de".into()),
+            weight,
+            sidevm_code_hash: None,
+            storage_root: Some(storage_root.into()),
+        }
+    }
+
+    fn summary(block_number: u32, contracts: Vec<ContractSummary>) -> ClusterStateSummary {
+        ClusterStateSummary {
+            filename: "cluster.bin".into(),
+            version: 1,
+            cluster_id: "0x01".into(),
+            sender: "0x02".into(),
+            block_number,
+            state_hash: "0x03".into(),
+            system_contract: Some("0xaa".into()),
+            storage_root: Some(alloc::format!("0x{block_number}")),
+            storage_nodes: 1,
+            storage_bytes: 1,
+            pending_messages: Default::default(),
+            contracts,
+        }
+    }
+
+    #[test]
+    fn diff_works() {
+        let a = summary(
+            10,
+            vec![contract("0xaa", 0, "0x01"), contract("0xbb", 0, "0x01")],
+        );
+        assert!(diff(&a, &a).is_empty());
+
+        let b = summary(
+            12,
+            vec![contract("0xaa", 1, "0x02"), contract("0xcc", 0, "0x01")],
+        );
+        let d = diff(&a, &b);
+        assert!(d.incompatible.is_empty());
+        assert_eq!(d.block_number, Some((10, 12)));
+        assert_eq!(
+            d.storage_root,
+            Some((Some("0x10".into()), Some("0x12".into())))
+        );
+        assert_eq!(d.system_contract, None);
+        assert_eq!(d.added, vec!["0xcc".to_string()]);
+        assert_eq!(d.removed, vec!["0xbb".to_string()]);
+        assert_eq!(
+            d.changed,
+            vec![ContractDiff {
+                id: "0xaa".into(),
+                fields: vec!["weight".into(), "storage_root".into()],
+            }]
+        );
+
+        let mut c = a.clone();
+        c.cluster_id = "0x09".into();
+        assert_eq!(diff(&a, &c).incompatible.len(), 1);
+    }
+}
@@ -31,6 +31,12 @@ pub struct InitArgs {
     /// Max number of checkpoint files kept
     pub max_checkpoint_files: u32,
 
+    /// Write checkpoints as a base plus deltas in a background thread.
+    pub incremental_checkpoint: bool,
+
+    /// Max number of deltas on top of a base checkpoint before a new base is written.
+    pub max_checkpoint_deltas: u32,
+
     /// Number of cores used to run phat contracts
     pub cores: u32,
 
@@ -48,6 +54,9 @@ pub struct InitArgs {
 
     /// The max retry times of getting the attestation report.
     pub ra_max_retries: u32,
+
+    /// The region hint advertised along with the worker endpoints.
+    pub region: Option<String>,
 }
 
 pub use phala_git_revision::git_revision;
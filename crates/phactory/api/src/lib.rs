@@ -2,6 +2,7 @@ extern crate alloc;
 
 pub mod actions;
 pub mod blocks;
+pub mod cluster_state;
 pub mod crypto;
 pub mod ecall_args;
 pub mod endpoints;
@@ -21,6 +21,34 @@ pub fn new_pruntime_client_no_log(base_url: String) -> PhactoryApiClient<RpcRequ
     PhactoryApiClient::new(RpcRequest::new(base_url).disable_log())
 }
 
+/// Call a `/bin_api` method of pRuntime with a SCALE encoded input.
+///
+/// Returns the payload of the response, or an error if pRuntime failed to handle the request.
+pub async fn bin_api_request(
+    base_url: &str,
+    method: &str,
+    input: &impl parity_scale_codec::Encode,
+) -> Result<serde_json::Value> {
+    let url = alloc::format!("{base_url}/bin_api/{method}");
+    let body = reqwest::Client::new()
+        .post(url)
+        .header("Connection", "close")
+        .body(input.encode())
+        .send()
+        .await?
+        .bytes()
+        .await?;
+    let response: serde_json::Value = serde_json::from_slice(&body)?;
+    let payload = match &response["payload"] {
+        serde_json::Value::String(payload) => serde_json::from_str(payload)?,
+        payload => payload.clone(),
+    };
+    if response["status"] != "ok" {
+        anyhow::bail!("{method} failed: {payload}");
+    }
+    Ok(payload)
+}
+
 pub struct RpcRequest {
     base_url: String,
     disable_log: bool,
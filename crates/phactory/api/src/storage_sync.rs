@@ -1,5 +1,6 @@
 use super::blocks::{
-    AuthoritySetChange, BlockHeaderWithChanges, HeaderToSync, RuntimeHasher, StorageProof,
+    AuthoritySetChange, BeefyParaHeadProof, BeefyState, BlockHeaderWithChanges, HeaderToSync,
+    InitBeefyReq, RuntimeHasher, StorageProof,
 };
 
 use alloc::string::String;
@@ -26,6 +27,9 @@ pub enum Error {
     /// Storage proof failed
     #[display(fmt = "StorageProofFailed({_0})")]
     StorageProofFailed(String),
+    /// BEEFY finality proof or MMR proof failed
+    #[display(fmt = "BeefyValidateFailed({_0})")]
+    BeefyValidateFailed(String),
     /// Relay chain header not synced before syncing parachain header
     RelaychainHeaderNotSynced,
     /// Some header parent hash mismatches it's parent header
@@ -64,6 +68,24 @@ pub trait BlockValidator {
         proof: StorageProof,
         items: &[(&[u8], &[u8])],
     ) -> Result<()>;
+
+    /// Start BEEFY validation of the bridge.
+    fn initialize_beefy(&mut self, bridge_id: u64, request: InitBeefyReq) -> Result<()>;
+
+    /// Validate a parachain head with a BEEFY finalized MMR root of the relay chain.
+    ///
+    /// Without `para_head` only the finality proof and the MMR leaf are imported, which is enough
+    /// to follow the BEEFY authority set changes.
+    fn validate_beefy_para_head(
+        &mut self,
+        bridge_id: u64,
+        para_id: u32,
+        para_head: Option<&[u8]>,
+        proof: BeefyParaHeadProof,
+    ) -> Result<()>;
+
+    /// The BEEFY validation state, `None` if BEEFY is not initialized.
+    fn beefy_state(&self) -> Option<BeefyState>;
 }
 
 pub trait StorageSynchronizer {
@@ -85,6 +107,21 @@ pub trait StorageSynchronizer {
         storage_key: &[u8],
     ) -> Result<chain::BlockNumber>;
 
+    /// Start validating the parachain headers with BEEFY instead of the relaychain headers
+    fn initialize_beefy(&mut self, request: InitBeefyReq) -> Result<()>;
+
+    /// Given the parachain headers in sequence, validate it with a BEEFY proof of the last header and
+    /// cached the state_roots for block validation
+    fn sync_parachain_header_with_beefy(
+        &mut self,
+        headers: Vec<chain::Header>,
+        proof: BeefyParaHeadProof,
+        para_id: u32,
+    ) -> Result<chain::BlockNumber>;
+
+    /// The BEEFY validation state, `None` if BEEFY is not initialized.
+    fn beefy_state(&self) -> Option<BeefyState>;
+
     /// Feed in a block of storage changes
     fn feed_block(
         &mut self,
@@ -96,6 +133,18 @@ pub trait StorageSynchronizer {
     /// Assume synced to given block.
     fn assume_at_block(&mut self, block_number: chain::BlockNumber) -> Result<()>;
     fn state_validated(&self) -> bool;
+
+    /// Validate storage items against the state root of a synced header whose block is not fed yet
+    fn validate_block_storage(
+        &self,
+        block_number: chain::BlockNumber,
+        proof: StorageProof,
+        items: &[(&[u8], &[u8])],
+    ) -> Result<()>;
+
+    /// Skip to a synced header with the state of the block loaded from elsewhere instead of
+    /// feeding all the blocks before it
+    fn fast_sync_to(&mut self, block_number: chain::BlockNumber, state_root: &Hash) -> Result<()>;
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -262,6 +311,64 @@ where
         state_roots.pop_front();
         Ok(())
     }
+
+    /// The state root of a synced header whose block is not fed yet.
+    fn state_root_at(
+        &self,
+        state_roots: &VecDeque<Hash>,
+        block_number: chain::BlockNumber,
+    ) -> Option<Hash> {
+        // Before the first block is fed, the queue starts with the root of the block before it.
+        let first = if self.genesis_state_validated {
+            self.block_number_next
+        } else {
+            self.block_number_next - 1
+        };
+        let index = block_number.checked_sub(first)?;
+        state_roots.get(index as usize).cloned()
+    }
+
+    pub fn validate_block_storage(
+        &self,
+        state_roots: &VecDeque<Hash>,
+        block_number: chain::BlockNumber,
+        proof: StorageProof,
+        items: &[(&[u8], &[u8])],
+    ) -> Result<()> {
+        let state_root = self
+            .state_root_at(state_roots, block_number)
+            .ok_or(Error::NoStateRoot)?;
+        self.validator
+            .validate_storage_proof(state_root, proof, items)
+    }
+
+    /// Skip to the given block, with a state already validated against its header.
+    pub fn fast_sync_to(
+        &mut self,
+        state_roots: &mut VecDeque<Hash>,
+        block_number: chain::BlockNumber,
+        state_root: &Hash,
+    ) -> Result<()> {
+        if self.block_number_next > 1 || self.genesis_state_validated {
+            return Err(Error::CannotLoadStateAfterSyncing);
+        }
+        let expected = self
+            .state_root_at(state_roots, block_number)
+            .ok_or(Error::NoStateRoot)?;
+        if &expected != state_root {
+            return Err(Error::StateRootMismatch {
+                block: block_number,
+                expected,
+                actual: *state_root,
+            });
+        }
+        // Drop the roots up to the given block, the queue starts from the next block then.
+        let n_dropped = block_number - (self.block_number_next - 1) + 1;
+        *state_roots = state_roots.skip(n_dropped as usize);
+        self.block_number_next = block_number + 1;
+        self.genesis_state_validated = true;
+        Ok(())
+    }
 }
 
 #[derive(Default, Debug)]
@@ -330,6 +437,23 @@ impl<Validator: BlockValidator> StorageSynchronizer for SolochainSynchronizer<Va
         Err(Error::ChainModeMismatch)
     }
 
+    fn initialize_beefy(&mut self, _request: InitBeefyReq) -> Result<()> {
+        Err(Error::ChainModeMismatch)
+    }
+
+    fn sync_parachain_header_with_beefy(
+        &mut self,
+        _headers: Vec<chain::Header>,
+        _proof: BeefyParaHeadProof,
+        _para_id: u32,
+    ) -> Result<chain::BlockNumber> {
+        Err(Error::ChainModeMismatch)
+    }
+
+    fn beefy_state(&self) -> Option<BeefyState> {
+        None
+    }
+
     fn assume_at_block(&mut self, block_number: chain::BlockNumber) -> Result<()> {
         if self.sync_state.block_number_next > 1 || self.sync_state.header_number_next > 0 {
             return Err(Error::CannotLoadStateAfterSyncing);
@@ -342,6 +466,21 @@ impl<Validator: BlockValidator> StorageSynchronizer for SolochainSynchronizer<Va
     fn state_validated(&self) -> bool {
         self.sync_state.genesis_state_validated
     }
+
+    fn validate_block_storage(
+        &self,
+        block_number: chain::BlockNumber,
+        proof: StorageProof,
+        items: &[(&[u8], &[u8])],
+    ) -> Result<()> {
+        self.sync_state
+            .validate_block_storage(&self.state_roots, block_number, proof, items)
+    }
+
+    fn fast_sync_to(&mut self, block_number: chain::BlockNumber, state_root: &Hash) -> Result<()> {
+        self.sync_state
+            .fast_sync_to(&mut self.state_roots, block_number, state_root)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -363,6 +502,36 @@ impl<Validator: BlockValidator> ParachainSynchronizer<Validator> {
     }
 }
 
+impl<Validator> ParachainSynchronizer<Validator> {
+    /// Enqueue the state roots of the validated headers for storage validation.
+    fn enqueue_para_headers(&mut self, headers: &[chain::Header]) -> chain::BlockNumber {
+        for hdr in headers.iter() {
+            self.para_state_roots.push_back(hdr.state_root);
+        }
+        let last_number = headers
+            .last()
+            .map(|hdr| hdr.number)
+            .unwrap_or(self.para_header_number_next - 1);
+        self.para_header_number_next = last_number + 1;
+        last_number
+    }
+}
+
+fn check_header_sequence(headers: &[chain::Header]) -> Result<()> {
+    for (i, header) in headers.iter().enumerate() {
+        if i > 0 && headers[i - 1].hash() != header.parent_hash {
+            log::error!(
+                "Parent hash of {} mismatch: actual={:?} expected={:?}",
+                header.number,
+                headers[i - 1].hash(),
+                header.parent_hash
+            );
+            return Err(Error::HeaderHashMismatch);
+        }
+    }
+    Ok(())
+}
+
 impl<Validator: BlockValidator> StorageSynchronizer for ParachainSynchronizer<Validator> {
     fn counters(&self) -> Counters {
         Counters {
@@ -423,27 +592,56 @@ impl<Validator: BlockValidator> StorageSynchronizer for ParachainSynchronizer<Va
         )?;
 
         // 2. check header sequence
-        for (i, header) in headers.iter().enumerate() {
-            if i > 0 && headers[i - 1].hash() != header.parent_hash {
-                log::error!(
-                    "Parent hash of {} mismatch: actual={:?} expected={:?}",
-                    header.number,
-                    headers[i - 1].hash(),
-                    header.parent_hash
-                );
-                return Err(Error::HeaderHashMismatch);
+        check_header_sequence(&headers)?;
+
+        self.last_relaychain_state_root = None;
+        Ok(self.enqueue_para_headers(&headers))
+    }
+
+    fn initialize_beefy(&mut self, request: InitBeefyReq) -> Result<()> {
+        self.sync_state
+            .validator
+            .initialize_beefy(self.sync_state.main_bridge, request)
+    }
+
+    fn sync_parachain_header_with_beefy(
+        &mut self,
+        mut headers: Vec<chain::Header>,
+        proof: BeefyParaHeadProof,
+        para_id: u32,
+    ) -> Result<chain::BlockNumber> {
+        headers.retain(|header| header.number >= self.para_header_number_next);
+
+        let last_hdr = match headers.first() {
+            Some(first_hdr) => {
+                if self.para_header_number_next != first_hdr.number {
+                    return Err(Error::BlockNumberMismatch);
+                }
+                headers.last()
             }
-        }
+            None => None,
+        };
 
-        // All checks passed, enqueue the state roots for storage validation.
-        for hdr in headers.iter() {
-            self.para_state_roots.push_back(hdr.state_root);
+        // 1. validate the BEEFY proof. The proof is imported even if there is no new header in order
+        // to follow the authority set changes of BEEFY.
+        self.sync_state.validator.validate_beefy_para_head(
+            self.sync_state.main_bridge,
+            para_id,
+            last_hdr.map(|hdr| hdr.encode()).as_deref(),
+            proof,
+        )?;
+        if last_hdr.is_none() {
+            return Ok(self.para_header_number_next - 1);
         }
 
-        self.last_relaychain_state_root = None;
-        self.para_header_number_next = last_hdr.number + 1;
+        // 2. check header sequence
+        check_header_sequence(&headers)?;
+
+        Ok(self.enqueue_para_headers(&headers))
+    }
 
-        Ok(last_hdr.number)
+    fn beefy_state(&self) -> Option<BeefyState> {
+        self.sync_state.validator.beefy_state()
     }
 
     /// Feed in a block of storage changes
@@ -469,6 +667,21 @@ impl<Validator: BlockValidator> StorageSynchronizer for ParachainSynchronizer<Va
     fn state_validated(&self) -> bool {
         self.sync_state.genesis_state_validated
     }
+
+    fn validate_block_storage(
+        &self,
+        block_number: chain::BlockNumber,
+        proof: StorageProof,
+        items: &[(&[u8], &[u8])],
+    ) -> Result<()> {
+        self.sync_state
+            .validate_block_storage(&self.para_state_roots, block_number, proof, items)
+    }
+
+    fn fast_sync_to(&mut self, block_number: chain::BlockNumber, state_root: &Hash) -> Result<()> {
+        self.sync_state
+            .fast_sync_to(&mut self.para_state_roots, block_number, state_root)
+    }
 }
 
 // We create this new type to help serialize the original dyn StorageSynchronizer.
@@ -534,6 +747,24 @@ impl<Validator: BlockValidator> StorageSynchronizer for Synchronizer<Validator>
             .sync_parachain_header(headers, proof, storage_key)
     }
 
+    fn initialize_beefy(&mut self, request: InitBeefyReq) -> Result<()> {
+        self.as_dyn_mut().initialize_beefy(request)
+    }
+
+    fn sync_parachain_header_with_beefy(
+        &mut self,
+        headers: Vec<chain::Header>,
+        proof: BeefyParaHeadProof,
+        para_id: u32,
+    ) -> Result<chain::BlockNumber> {
+        self.as_dyn_mut()
+            .sync_parachain_header_with_beefy(headers, proof, para_id)
+    }
+
+    fn beefy_state(&self) -> Option<BeefyState> {
+        self.as_dyn().beefy_state()
+    }
+
     fn feed_block(
         &mut self,
         block: &BlockHeaderWithChanges,
@@ -550,4 +781,206 @@ impl<Validator: BlockValidator> StorageSynchronizer for Synchronizer<Validator>
     fn state_validated(&self) -> bool {
         self.as_dyn().state_validated()
     }
+
+    fn validate_block_storage(
+        &self,
+        block_number: chain::BlockNumber,
+        proof: StorageProof,
+        items: &[(&[u8], &[u8])],
+    ) -> Result<()> {
+        self.as_dyn()
+            .validate_block_storage(block_number, proof, items)
+    }
+
+    fn fast_sync_to(&mut self, block_number: chain::BlockNumber, state_root: &Hash) -> Result<()> {
+        self.as_dyn_mut().fast_sync_to(block_number, state_root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::StorageChanges;
+
+    /// Accepts any header, and a storage proof if it is the state root itself.
+    struct MockValidator;
+
+    impl BlockValidator for MockValidator {
+        fn submit_finalized_headers(
+            &mut self,
+            _bridge_id: u64,
+            _header: chain::Header,
+            _ancestry_proof: Vec<chain::Header>,
+            _grandpa_proof: Vec<u8>,
+            _authority_set_change: Option<AuthoritySetChange>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn validate_storage_proof(
+            &self,
+            state_root: Hash,
+            proof: StorageProof,
+            _items: &[(&[u8], &[u8])],
+        ) -> Result<()> {
+            if proof != [state_root.as_bytes()] {
+                return Err(Error::StorageProofFailed("bad proof".into()));
+            }
+            Ok(())
+        }
+
+        fn initialize_beefy(&mut self, _bridge_id: u64, _request: InitBeefyReq) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn validate_beefy_para_head(
+            &mut self,
+            _bridge_id: u64,
+            _para_id: u32,
+            _para_head: Option<&[u8]>,
+            _proof: BeefyParaHeadProof,
+        ) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn beefy_state(&self) -> Option<BeefyState> {
+            None
+        }
+    }
+
+    fn header(number: chain::BlockNumber, parent: Option<&chain::Header>) -> chain::Header {
+        chain::Header {
+            parent_hash: parent.map(|p| p.hash()).unwrap_or_default(),
+            number,
+            state_root: Hash::repeat_byte(number as u8),
+            extrinsics_root: Default::default(),
+            digest: Default::default(),
+        }
+    }
+
+    /// A parachain synchronizer with the headers of blocks 0..=last synced.
+    fn synced_parachain(
+        state_roots: impl Fn(chain::BlockNumber) -> Hash,
+        last: chain::BlockNumber,
+    ) -> Synchronizer<MockValidator> {
+        let mut sync = Synchronizer::new_parachain(MockValidator, 0, 1);
+        let relay_header = header(1, None);
+        let relay_root = relay_header.state_root;
+        sync.sync_header(
+            vec![HeaderToSync {
+                header: relay_header,
+                justification: Some(vec![]),
+            }],
+            None,
+        )
+        .unwrap();
+        let mut headers: Vec<chain::Header> = vec![];
+        for number in 0..=last {
+            let mut hdr = header(number, headers.last());
+            hdr.state_root = state_roots(number);
+            headers.push(hdr);
+        }
+        sync.sync_parachain_header(headers, vec![relay_root.as_bytes().to_vec()], b"")
+            .unwrap();
+        sync
+    }
+
+    fn root_of(number: chain::BlockNumber) -> Hash {
+        Hash::repeat_byte(number as u8 + 1)
+    }
+
+    fn proof_of(number: chain::BlockNumber) -> StorageProof {
+        vec![root_of(number).as_bytes().to_vec()]
+    }
+
+    #[test]
+    fn state_root_at_counts_from_genesis_before_the_first_block() {
+        let state = BlockSyncState::new(MockValidator, 0, 1, 1);
+        // The queue starts with the root of the genesis block
+        let roots: VecDeque<Hash> = (0..4).map(root_of).collect();
+        assert_eq!(state.state_root_at(&roots, 0), Some(root_of(0)));
+        assert_eq!(state.state_root_at(&roots, 3), Some(root_of(3)));
+        assert_eq!(state.state_root_at(&roots, 4), None);
+    }
+
+    #[test]
+    fn state_root_at_counts_from_the_next_block_after_validation() {
+        let mut state = BlockSyncState::new(MockValidator, 0, 1, 5);
+        state.genesis_state_validated = true;
+        let roots: VecDeque<Hash> = (5..9).map(root_of).collect();
+        assert_eq!(state.state_root_at(&roots, 4), None);
+        assert_eq!(state.state_root_at(&roots, 5), Some(root_of(5)));
+        assert_eq!(state.state_root_at(&roots, 8), Some(root_of(8)));
+        assert_eq!(state.state_root_at(&roots, 9), None);
+    }
+
+    #[test]
+    fn validate_block_storage_uses_the_root_of_the_block() {
+        let sync = synced_parachain(root_of, 5);
+        for number in 0..=5 {
+            sync.validate_block_storage(number, proof_of(number), &[])
+                .unwrap();
+        }
+        assert!(matches!(
+            sync.validate_block_storage(3, proof_of(4), &[]),
+            Err(Error::StorageProofFailed(_))
+        ));
+        assert!(matches!(
+            sync.validate_block_storage(6, proof_of(6), &[]),
+            Err(Error::NoStateRoot)
+        ));
+    }
+
+    #[test]
+    fn fast_sync_to_continues_from_the_next_block() {
+        let pairs = [(b"key".to_vec(), b"value".to_vec())];
+        let mut storage = Storage::default();
+        storage.load(pairs.iter().cloned());
+        let snapshot_root = *storage.root();
+        let changes = StorageChanges {
+            main_storage_changes: vec![(b"key".to_vec(), Some(b"changed".to_vec()))],
+            child_storage_changes: vec![],
+        };
+        let (next_root, _) = storage.calc_root_if_changes(
+            &changes.main_storage_changes,
+            &changes.child_storage_changes,
+        );
+        let state_roots = |number| match number {
+            3 => snapshot_root,
+            4 => next_root,
+            _ => root_of(number),
+        };
+        let mut sync = synced_parachain(state_roots, 5);
+
+        assert!(matches!(
+            sync.fast_sync_to(3, &root_of(3)),
+            Err(Error::StateRootMismatch { block: 3, .. })
+        ));
+        assert!(matches!(
+            sync.fast_sync_to(6, &root_of(6)),
+            Err(Error::NoStateRoot)
+        ));
+        sync.fast_sync_to(3, &snapshot_root).unwrap();
+        assert_eq!(sync.counters().next_block_number, 4);
+        assert!(sync.state_validated());
+        assert!(matches!(
+            sync.fast_sync_to(4, &next_root),
+            Err(Error::CannotLoadStateAfterSyncing)
+        ));
+
+        // The roots before the snapshot are dropped
+        assert!(matches!(
+            sync.validate_block_storage(3, proof_of(3), &[]),
+            Err(Error::NoStateRoot)
+        ));
+        sync.validate_block_storage(5, proof_of(5), &[]).unwrap();
+
+        let block = BlockHeaderWithChanges {
+            block_header: header(4, None),
+            storage_changes: changes,
+        };
+        sync.feed_block(&block, &mut storage, false).unwrap();
+        assert_eq!(storage.root(), &next_root);
+        assert_eq!(sync.counters().next_block_number, 5);
+    }
 }
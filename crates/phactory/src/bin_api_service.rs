@@ -12,6 +12,15 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         })
     }
 
+    /// Sign a chunk message of a chunk-framed response body.
+    pub fn sign_http_response_chunk(&self, message: &[u8]) -> Option<String> {
+        self.system.as_ref().map(|state| {
+            let bytes = wrap_content_to_sign(message, SignedContentType::RpcResponseChunk);
+            let sig = state.identity_key.sign(&bytes).0;
+            hex::encode(sig)
+        })
+    }
+
     fn get_info_json(&self) -> Result<Value, Value> {
         Ok(json!(self.get_info()))
     }
@@ -30,6 +39,35 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         Ok(json!({ "synced_to": resp.synced_to }))
     }
 
+    fn bin_init_beefy(&mut self, input: blocks::InitBeefyReq) -> Result<Value, Value> {
+        self.init_beefy(input).map_err(display)?;
+        Ok(json!({}))
+    }
+
+    fn bin_sync_para_header_with_beefy(
+        &mut self,
+        input: SyncParachainHeaderWithBeefyReq,
+    ) -> Result<Value, Value> {
+        let resp = self
+            .sync_para_header_with_beefy(input.headers, input.proof)
+            .map_err(display)?;
+        Ok(json!({ "synced_to": resp.synced_to }))
+    }
+
+    fn bin_get_beefy_state(&mut self) -> Result<Value, Value> {
+        let state = self.beefy_state().map_err(display)?;
+        Ok(json!({
+            "validator_set_id": state.as_ref().map(|state| state.validator_set_id),
+            "latest_block": state.as_ref().map(|state| state.latest_block),
+        }))
+    }
+
+    fn bin_fast_sync(&mut self, input: blocks::FastSyncReq) -> Result<Value, Value> {
+        let block_number = input.block_number;
+        self.fast_sync(input).map_err(display)?;
+        Ok(json!({ "synced_to": block_number }))
+    }
+
     fn bin_sync_combined_headers(&mut self, input: SyncCombinedHeadersReq) -> Result<Value, Value> {
         let resp = self
             .sync_combined_headers(
@@ -74,6 +112,12 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             BIN_ACTION_SYNC_PARA_HEADER => self.bin_sync_para_header(load_scale(input)?),
             BIN_ACTION_SYNC_COMBINED_HEADERS => self.bin_sync_combined_headers(load_scale(input)?),
             BIN_ACTION_DISPATCH_BLOCK => self.bin_dispatch_block(req_id, load_scale(input)?),
+            BIN_ACTION_INIT_BEEFY => self.bin_init_beefy(load_scale(input)?),
+            BIN_ACTION_SYNC_PARA_HEADER_WITH_BEEFY => {
+                self.bin_sync_para_header_with_beefy(load_scale(input)?)
+            }
+            BIN_ACTION_FAST_SYNC => self.bin_fast_sync(load_scale(input)?),
+            BIN_ACTION_GET_BEEFY_STATE => self.bin_get_beefy_state(),
             _ => Err(error_msg("Action not found")),
         }
     }
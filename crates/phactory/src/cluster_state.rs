@@ -0,0 +1,695 @@
+//! Inspection, verification and selective loading of cluster state dumps.
+//!
+//! A dump is created by `save_cluster_state` for a single receiving worker, so only the pRuntime of
+//! that worker can read it. The summaries it returns contain no secrets and can be compared
+//! offline with [`phactory_api::cluster_state::diff`].
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use hash_db::{HashDBRef, Prefix};
+use parity_scale_codec::Decode;
+use phactory_api::cluster_state::{ClusterStateSummary, ClusterStateVerification, ContractSummary};
+use phala_crypto::{
+    aead,
+    ecdh::{self, EcdhKey, EcdhPublicKey},
+};
+use phala_mq::{checkpoint_helper, MessageDispatcher, MessageSendQueue};
+use phala_types::WorkerPublicKey;
+use pink::types::AccountId;
+use sp_core::{
+    crypto::Pair as _,
+    hashing::{blake2_256, twox_128, twox_64},
+    storage::ChildInfo,
+    H256,
+};
+use sp_runtime::traits::BlakeTwo256;
+use sp_trie::{
+    trie_types::{TrieDB, TrieDBBuilder},
+    Trie,
+};
+
+use crate::{
+    contracts::pink::Cluster, derive_key_for_cluster_state, hex, ClusterState, Phactory,
+    CHECKPOINT_VERSION,
+};
+
+/// A decrypted cluster state dump.
+pub(crate) struct ClusterStateDump {
+    /// The ecdh public key of the worker that created the dump.
+    pub sender: EcdhPublicKey,
+    /// blake2_256 of the decrypted dump.
+    pub state_hash: [u8; 32],
+    pub state: ClusterState<'static>,
+}
+
+/// Decrypt the dump `filename` in the storage path.
+///
+/// The message channels of the contracts in the dump are attached to `recv_mq` and `send_mq`.
+pub(crate) fn read_dump(
+    ecdh_key: &EcdhKey,
+    storage_path: &str,
+    filename: &str,
+    recv_mq: &mut MessageDispatcher,
+    send_mq: &mut MessageSendQueue,
+) -> Result<ClusterStateDump> {
+    if Path::new(filename)
+        .file_name()
+        .and_then(|name| name.to_str())
+        != Some(filename)
+    {
+        bail!("Invalid cluster state filename: {filename}");
+    }
+    let fullname = PathBuf::from(storage_path).join(filename);
+    let mut reader =
+        File::open(&fullname).with_context(|| format!("Failed to open {}", fullname.display()))?;
+    let mut sender: EcdhPublicKey = Default::default();
+    reader
+        .read_exact(&mut sender)
+        .context("Failed to read the sender of the cluster state")?;
+    let key = ecdh::agree(ecdh_key, &sender)
+        .map_err(|_| anyhow!("Failed to derive ECDH key for cluster state"))?;
+    let key128 = derive_key_for_cluster_state(&key);
+    let mut data = vec![];
+    aead::stream::new_aes128gcm_reader(key128, &mut reader)
+        .read_to_end(&mut data)
+        .context("Failed to decrypt the cluster state, is it sent to this worker?")?;
+    if data.len() < 4 {
+        bail!("Truncated cluster state");
+    }
+    let (version, payload) = data.split_at(4);
+    let version = u32::from_be_bytes(version.try_into().expect("Should be 4 bytes"));
+    if version != CHECKPOINT_VERSION {
+        bail!("Incompatible cluster state version {version}, expected {CHECKPOINT_VERSION}");
+    }
+    // The recv_mq and send_mq are used to restore the rx/tx handles during the deserialization.
+    let state: ClusterState = checkpoint_helper::using_dispatcher(recv_mq, move || {
+        checkpoint_helper::using_send_mq(send_mq, move || serde_cbor::from_slice(payload))
+    })
+    .context("Failed to decode the cluster state")?;
+    Ok(ClusterStateDump {
+        sender,
+        state_hash: blake2_256(&data),
+        state,
+    })
+}
+
+/// Read-only view of the cluster storage trie nodes, recording the nodes that are read.
+struct NodeDb<'a> {
+    cluster: &'a Cluster,
+    visited: RefCell<BTreeSet<H256>>,
+}
+
+impl<'a> NodeDb<'a> {
+    fn new(cluster: &'a Cluster) -> Self {
+        Self {
+            cluster,
+            visited: Default::default(),
+        }
+    }
+}
+
+impl HashDBRef<BlakeTwo256, Vec<u8>> for NodeDb<'_> {
+    fn get(&self, key: &H256, _prefix: Prefix) -> Option<Vec<u8>> {
+        let (_, node) = self.cluster.storage.get(key.as_bytes())?;
+        self.visited.borrow_mut().insert(*key);
+        Some(node.clone())
+    }
+
+    fn contains(&self, key: &H256, _prefix: Prefix) -> bool {
+        self.cluster.storage.get(key.as_bytes()).is_some()
+    }
+}
+
+fn read_storage(cluster: &Cluster, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    let Some(root) = cluster.storage.root() else {
+        return Ok(None);
+    };
+    sp_trie::read_trie_value::<sp_trie::LayoutV0<BlakeTwo256>, _>(
+        &NodeDb::new(cluster),
+        &root,
+        key,
+        None,
+        None,
+    )
+    .map_err(|err| anyhow!("Failed to read the cluster storage: {err:?}"))
+}
+
+fn storage_value_key(pallet: &str, item: &str) -> Vec<u8> {
+    [twox_128(pallet.as_bytes()), twox_128(item.as_bytes())].concat()
+}
+
+fn system_contract(cluster: &Cluster) -> Result<Option<AccountId>> {
+    let Some(value) = read_storage(cluster, &storage_value_key("Pink", "SystemContract"))? else {
+        return Ok(None);
+    };
+    Ok(Some(
+        AccountId::decode(&mut &value[..]).context("Invalid system contract")?,
+    ))
+}
+
+/// The root of the child trie holding the storage of the contract.
+fn contract_storage_root(cluster: &Cluster, contract: &AccountId) -> Result<Option<Vec<u8>>> {
+    let key = [
+        &storage_value_key("Contracts", "ContractInfoOf")[..],
+        &twox_64(contract.as_ref()),
+        contract.as_ref(),
+    ]
+    .concat();
+    let Some(info) = read_storage(cluster, &key)? else {
+        return Ok(None);
+    };
+    // The trie id is the first field of the `ContractInfo`.
+    let trie_id = Vec::<u8>::decode(&mut &info[..]).context("Invalid contract info")?;
+    let child_root_key = ChildInfo::new_default(&trie_id)
+        .prefixed_storage_key()
+        .into_inner();
+    let Some(root) = read_storage(cluster, &child_root_key)? else {
+        return Ok(None);
+    };
+    if root.len() != 32 {
+        bail!("Invalid storage root of contract {}", hex(contract));
+    }
+    Ok(Some(root))
+}
+
+/// The hashes of all the nodes of the trie with the given root, failing if any of them is missing.
+fn trie_nodes(cluster: &Cluster, root: &H256) -> Result<BTreeSet<H256>> {
+    let db = NodeDb::new(cluster);
+    {
+        let trie: TrieDB<BlakeTwo256> = TrieDBBuilder::new(&db, root).build();
+        let iter = trie
+            .iter()
+            .map_err(|err| anyhow!("Failed to read the cluster storage: {err:?}"))?;
+        for item in iter {
+            item.map_err(|err| anyhow!("Failed to read the cluster storage: {err:?}"))?;
+        }
+    }
+    Ok(db.visited.into_inner())
+}
+
+/// Drop the trie nodes of the cluster storage that only belong to the storage of contracts not in
+/// `keep`.
+///
+/// The main trie is kept as a whole, it holds the code, the accounts and the contract infos of the
+/// cluster.
+fn prune_storage(cluster: &mut Cluster, keep: &BTreeSet<AccountId>) -> Result<()> {
+    let Some(root) = cluster.storage.root() else {
+        return Ok(());
+    };
+    let mut nodes = trie_nodes(cluster, &root)?;
+    for contract in keep {
+        if let Some(child_root) = contract_storage_root(cluster, contract)? {
+            nodes.extend(trie_nodes(cluster, &H256::from_slice(&child_root))?);
+        }
+    }
+    cluster
+        .storage
+        .retain(|hash| hash.len() == 32 && nodes.contains(&H256::from_slice(hash)));
+    Ok(())
+}
+
+/// Check that every node of the cluster storage matches its hash, and that the root is present.
+fn check_storage_nodes(cluster: &Cluster, issues: &mut Vec<String>) {
+    let mut bad_nodes = 0;
+    for (hash, (rc, node)) in cluster.storage.iter() {
+        if *rc <= 0 || hash[..] != blake2_256(node)[..] {
+            bad_nodes += 1;
+        }
+    }
+    if bad_nodes > 0 {
+        issues.push(format!(
+            "{bad_nodes} corrupted nodes in the cluster storage"
+        ));
+    }
+    match cluster.storage.root() {
+        Some(root) if cluster.storage.get(root.as_bytes()).is_none() => {
+            issues.push(format!("The cluster storage root {} is missing", hex(root)));
+        }
+        Some(_) => {}
+        None => issues.push("The cluster storage is empty".into()),
+    }
+}
+
+fn summarize(filename: &str, dump: &ClusterStateDump) -> Result<ClusterStateSummary> {
+    let ClusterState {
+        block_number,
+        pending_messages,
+        contracts,
+        cluster,
+        ..
+    } = &dump.state;
+    let mut contract_summaries = vec![];
+    for (id, contract) in contracts.iter() {
+        let info = contract.info();
+        contract_summaries.push(ContractSummary {
+            id: hex(id),
+            code_hash: Some(info.code_hash).filter(|hash| !hash.is_empty()),
+            weight: info.weight,
+            sidevm_code_hash: info.sidevm.map(|sidevm| sidevm.code_hash),
+            storage_root: contract_storage_root(cluster, id)?.map(hex),
+        });
+    }
+    Ok(ClusterStateSummary {
+        filename: filename.into(),
+        version: CHECKPOINT_VERSION,
+        cluster_id: hex(cluster.id),
+        sender: hex(dump.sender),
+        block_number: *block_number,
+        state_hash: hex(dump.state_hash),
+        system_contract: system_contract(cluster)?.map(hex),
+        storage_root: cluster.storage.root().map(hex),
+        storage_nodes: cluster.storage.iter().count() as u64,
+        storage_bytes: cluster
+            .storage
+            .iter()
+            .map(|(_, (_, node))| node.len() as u64)
+            .sum(),
+        pending_messages: pending_messages
+            .iter()
+            .map(|(sender, channel)| (sender.to_string(), channel.messages().len() as u64))
+            .collect(),
+        contracts: contract_summaries,
+    })
+}
+
+/// Reads the cluster state dumps sent to a worker, without access to the state of the worker.
+///
+/// Decrypting a dump takes a while, so it is done without holding the [`Phactory`].
+pub struct ClusterStateReader {
+    ecdh_key: EcdhKey,
+    storage_path: String,
+}
+
+impl ClusterStateReader {
+    /// Decrypt a dump with the message channels of its contracts detached.
+    fn read(&self, filename: &str) -> Result<ClusterStateDump> {
+        read_dump(
+            &self.ecdh_key,
+            &self.storage_path,
+            filename,
+            &mut MessageDispatcher::new(),
+            &mut MessageSendQueue::new(),
+        )
+    }
+
+    /// Summarize a dump.
+    pub fn inspect(&self, filename: &str) -> Result<ClusterStateSummary> {
+        summarize(filename, &self.read(filename)?)
+    }
+
+    /// Decrypt a dump and check its integrity, to be verified against the chain state with
+    /// [`Phactory::verify_cluster_state`].
+    pub fn check(&self, filename: &str) -> Result<CheckedClusterState> {
+        let dump = self.read(filename)?;
+        let summary = summarize(filename, &dump)?;
+        let cluster = &dump.state.cluster;
+        let mut issues = vec![];
+        for (id, contract) in dump.state.contracts.iter() {
+            if contract.cluster_id() != &cluster.id {
+                issues.push(format!(
+                    "Contract {} belongs to another cluster {}",
+                    hex(id),
+                    hex(contract.cluster_id())
+                ));
+            }
+        }
+        check_storage_nodes(cluster, &mut issues);
+        Ok(CheckedClusterState {
+            dump,
+            summary,
+            issues,
+        })
+    }
+}
+
+/// A dump checked by [`ClusterStateReader::check`].
+pub struct CheckedClusterState {
+    dump: ClusterStateDump,
+    summary: ClusterStateSummary,
+    issues: Vec<String>,
+}
+
+impl<Platform: pal::Platform> Phactory<Platform> {
+    pub fn cluster_state_reader(&self) -> Result<ClusterStateReader> {
+        let system = self.system.as_ref().context("System is uninitialized")?;
+        Ok(ClusterStateReader {
+            ecdh_key: system.ecdh_key.clone(),
+            storage_path: self.args.storage_path.clone(),
+        })
+    }
+
+    /// Verify a checked dump sent to this worker against the chain state.
+    pub fn verify_cluster_state(
+        &self,
+        checked: CheckedClusterState,
+    ) -> Result<ClusterStateVerification> {
+        let system = self.system.as_ref().context("System is uninitialized")?;
+        let chain = &self
+            .runtime_state
+            .as_ref()
+            .context("Runtime is uninitialized")?
+            .chain_storage;
+        let CheckedClusterState {
+            dump,
+            summary,
+            mut issues,
+        } = checked;
+        let cluster = &dump.state.cluster;
+
+        if dump.state.block_number <= system.block_number {
+            issues.push(format!(
+                "Staled cluster state, current_block_number={}, state_block_number={}",
+                system.block_number, dump.state.block_number,
+            ));
+        }
+        if let Some(deployed) = &system.contract_cluster {
+            issues.push(format!(
+                "Cluster {} has already been deployed on this worker",
+                hex(deployed.id)
+            ));
+        }
+        let sender = WorkerPublicKey(dump.sender);
+        if !chain.is_worker_registered(&sender) {
+            issues.push(format!("The sender {} is not registered", summary.sender));
+        }
+        if chain.get_worker_cluster(&sender) != Some(cluster.id) {
+            issues.push(format!(
+                "The sender {} is not a worker of the cluster",
+                summary.sender
+            ));
+        }
+        let receiver = system.identity_key.public();
+        if chain.get_worker_cluster(&receiver) != Some(cluster.id) {
+            issues.push("This worker is not attached to the cluster".into());
+        }
+
+        match chain.get_cluster_info(&cluster.id) {
+            None => issues.push(format!(
+                "Cluster {} is not found on-chain",
+                summary.cluster_id
+            )),
+            Some(info) => {
+                let on_chain = Some(hex(info.system_contract));
+                if summary.system_contract != on_chain {
+                    issues.push(format!(
+                        "System contract mismatch, dump={:?}, on-chain={:?}",
+                        summary.system_contract, on_chain
+                    ));
+                }
+            }
+        }
+
+        let on_chain: BTreeSet<String> = chain
+            .get_cluster_contracts(&cluster.id)
+            .iter()
+            .map(hex)
+            .collect();
+        let in_dump: BTreeSet<String> = summary.contracts.iter().map(|c| c.id.clone()).collect();
+        for id in in_dump.difference(&on_chain) {
+            issues.push(format!(
+                "Contract {id} is not deployed to the cluster on-chain"
+            ));
+        }
+        for id in on_chain.difference(&in_dump) {
+            issues.push(format!(
+                "Contract {id} is deployed on-chain but missing in the dump"
+            ));
+        }
+
+        Ok(ClusterStateVerification {
+            summary,
+            verified_at: system.block_number,
+            issues,
+        })
+    }
+}
+
+/// Keep only the given contracts and their storage in the cluster state, failing if any of them
+/// is not in it.
+///
+/// The storage of the other contracts is dropped, except for the system contract. The cluster no
+/// longer matches the other workers of the cluster, so it is marked as partial: it executes no
+/// transactions and can not be dumped again. The pending outbound messages are dropped as well.
+pub(crate) fn select_contracts(state: &mut ClusterState, contracts: &[String]) -> Result<()> {
+    let mut selected = BTreeSet::new();
+    for id in contracts {
+        let id = crate::try_decode_hex(id)
+            .ok()
+            .and_then(|id| <[u8; 32]>::try_from(id).ok())
+            .map(AccountId::new)
+            .ok_or_else(|| anyhow!("Invalid contract id: {id}"))?;
+        if state.contracts.get(&id).is_none() {
+            bail!("Contract {} is not in the cluster state", hex(&id));
+        }
+        selected.insert(id);
+    }
+    let cluster = state.cluster.to_mut();
+    let mut keep = selected.clone();
+    keep.extend(system_contract(cluster)?);
+    prune_storage(cluster, &keep)?;
+    cluster.partial = true;
+    state.contracts.to_mut().retain(|id| selected.contains(id));
+    state.pending_messages.clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contracts::{Contract, ContractsKeeper},
+        derive_key_for_cluster_state,
+        secret_channel::SecretReceiver,
+    };
+    use parity_scale_codec::Encode;
+    use phala_crypto::sr25519::KDF;
+    use phala_mq::MessageOrigin;
+    use phala_types::contract::command_topic;
+    use pink::storage::ClusterStorage;
+    use sp_core::{sr25519, Pair as _};
+    use sp_trie::{trie_types::TrieDBMutBuilderV1, TrieMut};
+    use std::borrow::Cow;
+    use std::io::Write;
+
+    const CLUSTER_ID: [u8; 32] = [0xc1; 32];
+
+    fn account(n: u8) -> AccountId {
+        AccountId::new([n; 32])
+    }
+
+    fn ecdh_key(n: u8) -> EcdhKey {
+        sr25519::Pair::from_seed(&[n; 32])
+            .derive_ecdh_key()
+            .unwrap()
+    }
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("phactory-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.display().to_string()
+    }
+
+    fn build_trie(
+        db: &mut phala_trie_storage::MemoryDB<BlakeTwo256>,
+        pairs: &[(Vec<u8>, Vec<u8>)],
+    ) -> H256 {
+        let mut root = Default::default();
+        {
+            let mut trie = TrieDBMutBuilderV1::new(db, &mut root).build();
+            for (key, value) in pairs {
+                trie.insert(key, value).unwrap();
+            }
+        }
+        root
+    }
+
+    /// A cluster with the system contract `account(1)` and the given contracts, each of them with
+    /// some storage.
+    fn test_cluster(contracts: &[AccountId]) -> Cluster {
+        let mut db = Default::default();
+        let system = account(1);
+        let mut pairs = vec![(storage_value_key("Pink", "SystemContract"), system.encode())];
+        for contract in std::iter::once(&system).chain(contracts) {
+            let raw: &[u8] = contract.as_ref();
+            let trie_id = [&b"trie-"[..], raw].concat();
+            // Long enough to be stored as a separate value node
+            let child_root = build_trie(&mut db, &[(b"value".to_vec(), raw.repeat(3))]);
+            let info_key = [
+                &storage_value_key("Contracts", "ContractInfoOf")[..],
+                &twox_64(raw),
+                raw,
+            ]
+            .concat();
+            pairs.push((info_key, trie_id.encode()));
+            pairs.push((
+                ChildInfo::new_default(&trie_id)
+                    .prefixed_storage_key()
+                    .into_inner(),
+                child_root.as_bytes().to_vec(),
+            ));
+        }
+        let root = build_trie(&mut db, &pairs);
+        let mut storage = ClusterStorage::default();
+        storage.commit(
+            root,
+            db.drain()
+                .into_iter()
+                .map(|(hash, node)| (hash.as_bytes().to_vec(), node))
+                .collect(),
+        );
+        Cluster {
+            id: CLUSTER_ID.into(),
+            config: Default::default(),
+            storage,
+            partial: false,
+        }
+    }
+
+    fn test_state(contracts: &[AccountId]) -> ClusterState<'static> {
+        let send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = ContractsKeeper::default();
+        for contract in contracts {
+            let address = H256::from(<[u8; 32]>::from(contract.clone()));
+            let key = sr25519::Pair::from_seed(&address.0);
+            let ecdh_key = key.derive_ecdh_key().unwrap();
+            keeper.insert(Contract::new(
+                send_mq.channel(MessageOrigin::Contract(address), key.into()),
+                SecretReceiver::new_secret(
+                    recv_mq.subscribe(command_topic(address)).into(),
+                    ecdh_key.clone(),
+                ),
+                ecdh_key,
+                CLUSTER_ID.into(),
+                contract.clone(),
+                None,
+            ));
+        }
+        ClusterState {
+            block_number: 10,
+            pending_messages: vec![],
+            contracts: Cow::Owned(keeper),
+            cluster: Cow::Owned(test_cluster(contracts)),
+        }
+    }
+
+    /// Write a dump like `save_cluster_state` does.
+    fn write_dump(
+        dir: &str,
+        filename: &str,
+        sender: &EcdhKey,
+        receiver: &EcdhPublicKey,
+        state: &ClusterState,
+    ) {
+        let key = ecdh::agree(sender, receiver).unwrap();
+        let mut file = File::create(Path::new(dir).join(filename)).unwrap();
+        file.write_all(&sender.public()).unwrap();
+        let mut writer =
+            aead::stream::new_aes128gcm_writer(derive_key_for_cluster_state(&key), [7; 7], file);
+        writer.write_all(&CHECKPOINT_VERSION.to_be_bytes()).unwrap();
+        serde_cbor::to_writer(writer, state).unwrap();
+    }
+
+    #[test]
+    fn read_dump_sent_to_this_worker() {
+        let dir = temp_dir("cluster-state");
+        let (sender, receiver) = (ecdh_key(1), ecdh_key(2));
+        let contracts = [account(2), account(3)];
+        let state = test_state(&contracts);
+        write_dump(&dir, "cluster.bin", &sender, &receiver.public(), &state);
+
+        let reader = ClusterStateReader {
+            ecdh_key: receiver,
+            storage_path: dir.clone(),
+        };
+        let summary = reader.inspect("cluster.bin").unwrap();
+        assert_eq!(summary.cluster_id, hex(CLUSTER_ID));
+        assert_eq!(summary.sender, hex(sender.public()));
+        assert_eq!(summary.block_number, 10);
+        assert_eq!(summary.system_contract, Some(hex(account(1))));
+        assert_eq!(summary.storage_root, state.cluster.storage.root().map(hex));
+        let ids: Vec<_> = summary.contracts.iter().map(|c| c.id.clone()).collect();
+        assert_eq!(ids, contracts.iter().map(hex).collect::<Vec<_>>());
+        assert!(summary.contracts.iter().all(|c| c.storage_root.is_some()));
+
+        let checked = reader.check("cluster.bin").unwrap();
+        assert!(checked.issues.is_empty(), "{:?}", checked.issues);
+        assert_eq!(checked.summary.state_hash, summary.state_hash);
+
+        // Only the receiver can read the dump
+        let other = ClusterStateReader {
+            ecdh_key: ecdh_key(3),
+            storage_path: dir.clone(),
+        };
+        assert!(other.inspect("cluster.bin").is_err());
+        // The dump must be in the storage path
+        assert!(reader.inspect("../cluster.bin").is_err());
+        assert!(reader.inspect("missing.bin").is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn select_contracts_prunes_the_storage_of_the_others() {
+        let (selected, dropped) = (account(2), account(3));
+        let mut state = test_state(&[selected.clone(), dropped.clone()]);
+        let storage_root = |contract: &AccountId, state: &ClusterState| {
+            H256::from_slice(
+                &contract_storage_root(&state.cluster, contract)
+                    .unwrap()
+                    .unwrap(),
+            )
+        };
+        let system_root = storage_root(&account(1), &state);
+        let selected_root = storage_root(&selected, &state);
+        let dropped_root = storage_root(&dropped, &state);
+        let nodes = state.cluster.storage.iter().count();
+
+        assert!(select_contracts(&mut state, &[hex(account(4))]).is_err());
+        assert!(select_contracts(&mut state, &["0x1234".into()]).is_err());
+        assert!(!state.cluster.partial);
+        assert_eq!(state.cluster.storage.iter().count(), nodes);
+
+        select_contracts(&mut state, &[hex(&selected)]).unwrap();
+        let cluster = &state.cluster;
+        assert!(cluster.partial);
+        assert_eq!(state.contracts.keys().collect::<Vec<_>>(), vec![&selected]);
+        assert!(cluster.storage.iter().count() < nodes);
+        // The main trie, the storage of the selected contract and of the system contract are kept
+        trie_nodes(cluster, &cluster.storage.root().unwrap()).unwrap();
+        trie_nodes(cluster, &selected_root).unwrap();
+        trie_nodes(cluster, &system_root).unwrap();
+        assert!(trie_nodes(cluster, &dropped_root).is_err());
+        let mut issues = vec![];
+        check_storage_nodes(cluster, &mut issues);
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+
+    #[test]
+    fn check_storage_nodes_reports_corruption() {
+        let mut cluster = test_cluster(&[account(2)]);
+        let mut issues = vec![];
+        check_storage_nodes(&cluster, &mut issues);
+        assert!(issues.is_empty(), "{issues:?}");
+
+        let root = cluster.storage.root().unwrap();
+        let (hash, _) = cluster.storage.iter().next().unwrap();
+        let hash = hash.clone();
+        cluster
+            .storage
+            .commit(root, vec![(hash, (b"corrupted".to_vec(), 1))]);
+        check_storage_nodes(&cluster, &mut issues);
+        assert_eq!(issues, vec!["1 corrupted nodes in the cluster storage"]);
+
+        issues.clear();
+        cluster.storage.set_root(H256::repeat_byte(7));
+        check_storage_nodes(&cluster, &mut issues);
+        assert!(issues[1].contains("is missing"), "{issues:?}");
+    }
+}
@@ -8,13 +8,14 @@ use anyhow::Result;
 use parity_scale_codec::{Decode, Encode};
 use phala_crypto::sr25519::Persistence;
 use phala_mq::{ContractClusterId, MessageOrigin};
+use phala_serde_more as more;
 use phala_types::contract::messaging::ResourceType;
 use pink::{
     capi::v1::{
         ecall::{ECalls, ECallsRo},
         ocall::{
-            BatchHttpResult, ExecContext, HttpRequest, HttpRequestError, HttpResponse, OCalls,
-            StorageChanges,
+            BatchHttpResult, BatchHttpResultV2, ExecContext, HttpRequest, HttpRequestError,
+            HttpRequestV2, HttpResponse, HttpResponseV2, OCalls, StorageChanges,
         },
     },
     local_cache::{self, StorageQuotaExceeded},
@@ -28,13 +29,16 @@ use sp_core::{blake2_256, sr25519, twox_64};
 use ::pink::{
     capi::v1,
     constants::WEIGHT_REF_TIME_PER_SECOND,
-    types::{AccountId, Balance, ExecSideEffects, Hash, TransactionArguments},
+    types::{AccountId, Balance, DryRunResult, ExecSideEffects, Hash, TransactionArguments},
 };
 use tracing::info;
 
 pub use phala_types::contract::InkCommand;
 
+pub(crate) mod deploy_permission;
 pub(crate) mod http_counters;
+pub(crate) mod http_egress;
+pub(crate) mod query_policy;
 
 #[derive(Debug, Encode, Decode)]
 pub enum Query {
@@ -59,6 +63,19 @@ pub enum Query {
     },
 }
 
+/// An ink message to execute as a transaction would, without committing anything.
+///
+/// Sent to the `contract/dry_run` API in place of a `Query`, and replied with the
+/// `DryRunResult` of the call.
+#[derive(Debug, Encode, Decode)]
+pub struct DryRunRequest {
+    pub payload: Vec<u8>,
+    /// Amount of tokens deposit to the caller.
+    pub deposit: u128,
+    /// Amount of tokens transfer from the caller to the target contract.
+    pub transfer: u128,
+}
+
 #[derive(Debug, Encode, Decode)]
 pub enum Response {
     Payload(Vec<u8>),
@@ -72,6 +89,12 @@ pub enum QueryError {
     NoResponse,
     ServiceUnavailable,
     Timeout,
+    /// The origin is not allowed by the query policy of the contract.
+    Forbidden,
+    /// The origin exceeded its quota in the query policy of the contract.
+    QuotaExceeded,
+    /// The origin holds less balance than required by the query policy of the contract.
+    PaymentRequired,
 }
 
 #[derive(Serialize, Deserialize, Default, Clone)]
@@ -79,6 +102,12 @@ pub struct ClusterConfig {
     pub log_handler: Option<AccountId>,
     pub runtime_version: (u32, u32),
     pub secret_salt: [u8; 32],
+    #[serde(default, with = "more::scale_bytes")]
+    pub http_egress_policies: http_egress::HttpEgressPolicies,
+    #[serde(default, with = "more::scale_bytes")]
+    pub query_policies: query_policy::QueryPolicies,
+    #[serde(default, with = "more::scale_bytes")]
+    pub deploy_permission: deploy_permission::DeployPermission,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -86,6 +115,12 @@ pub struct Cluster {
     pub id: ContractClusterId,
     pub config: ClusterConfig,
     pub storage: pink::storage::ClusterStorage,
+    /// Only the storage of some contracts was restored from a cluster state dump.
+    ///
+    /// Such a cluster serves the queries of these contracts but executes no transactions, since
+    /// the storage of the other contracts is missing.
+    #[serde(default)]
+    pub partial: bool,
 }
 
 pub struct RuntimeHandleMut<'a> {
@@ -158,6 +193,17 @@ impl RuntimeHandle<'_> {
     fn runtime_version(&self) -> (u32, u32) {
         self.cluster.config.runtime_version
     }
+
+    pub fn dry_run(
+        &self,
+        contract: AccountId,
+        input_data: Vec<u8>,
+        tx_args: TransactionArguments,
+    ) -> DryRunResult {
+        context::using_entry_contract(contract.clone(), move || {
+            self.contract_dry_run(contract, input_data, tx_args)
+        })
+    }
 }
 
 pub(crate) mod context {
@@ -373,12 +419,56 @@ impl OCalls for RuntimeHandle<'_> {
         context::with(|ctx| ctx.chain_storage().pink_system_code().1)
     }
 
+    fn randomness_beacon(&self, block_number: u32) -> Option<[u8; 32]> {
+        context::with(|ctx| ctx.chain_storage().randomness_beacon(block_number))
+    }
+
     fn http_request(
         &self,
         contract: AccountId,
         request: HttpRequest,
     ) -> Result<HttpResponse, HttpRequestError> {
-        let result = pink_extension_runtime::http_request(request, context::time_remaining());
+        self.http_request_v2(contract, request.into())
+            .map(Into::into)
+            .or_else(pink_extension_runtime::v1_0_compatible_error)
+    }
+
+    fn batch_http_request(
+        &self,
+        contract: AccountId,
+        requests: Vec<HttpRequest>,
+        timeout_ms: u64,
+    ) -> BatchHttpResult {
+        let requests = requests.into_iter().map(Into::into).collect();
+        let results = self.batch_http_request_v2(contract, requests, timeout_ms)?;
+        Ok(results
+            .into_iter()
+            .map(|result| {
+                result
+                    .map(Into::into)
+                    .or_else(pink_extension_runtime::v1_1_compatible_error)
+            })
+            .collect())
+    }
+
+    fn http_request_v2(
+        &self,
+        contract: AccountId,
+        request: HttpRequestV2,
+    ) -> Result<HttpResponseV2, HttpRequestError> {
+        let policy = self.cluster.config.http_egress_policies.get(&contract);
+        let result = http_egress::acquire(&contract, policy, &request.url, request.body.len())
+            .and_then(|_| {
+                let allowance = http_egress::byte_allowance(&contract, policy);
+                let result = pink_extension_runtime::http_request_v2_filtered(
+                    request,
+                    context::time_remaining(),
+                    http_egress::host_filter(policy),
+                    allowance.as_ref().map(|a| a.budget()),
+                );
+                http_egress::add_received(&contract, allowance.as_ref());
+                result
+            });
         match &result {
             Ok(response) => {
                 http_counters::add(contract, response.status_code);
@@ -390,16 +480,44 @@ impl OCalls for RuntimeHandle<'_> {
         result
     }
 
-    fn batch_http_request(
+    fn batch_http_request_v2(
         &self,
         contract: AccountId,
-        requests: Vec<HttpRequest>,
+        requests: Vec<HttpRequestV2>,
         timeout_ms: u64,
-    ) -> BatchHttpResult {
-        let results = pink_extension_runtime::batch_http_request(
-            requests,
-            context::time_remaining().min(timeout_ms),
-        )?;
+    ) -> BatchHttpResultV2 {
+        // Check it before taking the quota, otherwise the whole batch is rejected after the quota
+        // is taken.
+        if requests.len() > pink_extension_runtime::MAX_CONCURRENT_REQUESTS {
+            return Err(HttpRequestError::TooManyRequests);
+        }
+        let policy = self.cluster.config.http_egress_policies.get(&contract);
+        // Requests rejected by the policy are not sent, but still take their place in the results.
+        let mut rejected = Vec::new();
+        let mut accepted = Vec::new();
+        for (i, request) in requests.into_iter().enumerate() {
+            match http_egress::acquire(&contract, policy, &request.url, request.body.len()) {
+                Ok(()) => accepted.push(request),
+                Err(err) => rejected.push((i, err)),
+            }
+        }
+        let allowance = http_egress::byte_allowance(&contract, policy);
+        let batch_result = if accepted.is_empty() {
+            Ok(vec![])
+        } else {
+            pink_extension_runtime::batch_http_request_v2_filtered(
+                accepted,
+                context::time_remaining().min(timeout_ms),
+                http_egress::host_filter(policy),
+                allowance.as_ref().map(|a| a.budget()),
+            )
+        };
+        // Count the bytes downloaded even if the batch timed out.
+        http_egress::add_received(&contract, allowance.as_ref());
+        let mut results = batch_result?;
+        for (i, err) in rejected {
+            results.insert(i, Err(err));
+        }
         for result in &results {
             match result {
                 Ok(r) => {
@@ -481,6 +599,10 @@ impl OCalls for RuntimeHandleMut<'_> {
         self.readonly().latest_system_code()
     }
 
+    fn randomness_beacon(&self, block_number: u32) -> Option<[u8; 32]> {
+        self.readonly().randomness_beacon(block_number)
+    }
+
     fn http_request(
         &self,
         contract: AccountId,
@@ -499,6 +621,24 @@ impl OCalls for RuntimeHandleMut<'_> {
             .batch_http_request(contract, requests, timeout_ms)
     }
 
+    fn http_request_v2(
+        &self,
+        contract: AccountId,
+        request: HttpRequestV2,
+    ) -> Result<HttpResponseV2, HttpRequestError> {
+        self.readonly().http_request_v2(contract, request)
+    }
+
+    fn batch_http_request_v2(
+        &self,
+        contract: AccountId,
+        requests: Vec<HttpRequestV2>,
+        timeout_ms: u64,
+    ) -> BatchHttpResultV2 {
+        self.readonly()
+            .batch_http_request_v2(contract, requests, timeout_ms)
+    }
+
     fn emit_system_event_block(&self, number: u64, encoded_block: Vec<u8>) {
         info!(target: "phactory::event_chain", number, payload=%hex_fmt::HexFmt(encoded_block));
     }
@@ -545,6 +685,7 @@ impl Cluster {
         let mut cluster = Cluster {
             id: *id,
             storage: Default::default(),
+            partial: false,
             config: ClusterConfig {
                 runtime_version,
                 secret_salt,
@@ -628,6 +769,10 @@ impl Cluster {
         self.default_runtime_mut().deposit(who.clone(), amount)
     }
 
+    pub fn free_balance(&self, who: &::pink::types::AccountId) -> Balance {
+        self.default_runtime().free_balance(who.clone())
+    }
+
     pub(crate) async fn handle_query(
         mut self,
         contract_id: &AccountId,
@@ -643,6 +788,16 @@ impl Cluster {
                 estimating,
             } => {
                 let origin = origin.cloned().ok_or(QueryError::BadOrigin)?;
+                // Rejected queries must not take the slots of the scheduler.
+                let policy = self.config.query_policies.get(contract_id).cloned();
+                let (gas_limit, gas_free) = query_policy::acquire_ink(
+                    contract_id,
+                    policy.as_ref(),
+                    &origin,
+                    deposit,
+                    transfer,
+                    || self.free_balance(&origin),
+                )?;
                 let _guard = context
                     .query_scheduler
                     .acquire(contract_id.clone(), context.weight)
@@ -680,14 +835,21 @@ impl Cluster {
                         context::using_entry_contract(contract_id.clone(), || {
                             let mut runtime = self.runtime_mut(log_handler);
                             let args = TransactionArguments {
-                                origin,
+                                origin: origin.clone(),
                                 transfer,
-                                gas_limit: WEIGHT_REF_TIME_PER_SECOND * 10,
-                                gas_free: true,
+                                gas_limit,
+                                gas_free,
                                 storage_deposit_limit: None,
                                 deposit,
                             };
-                            let ink_result = runtime.call(contract_id, input_data, mode, args);
+                            let ink_result =
+                                runtime.call(contract_id.clone(), input_data, mode, args);
+                            query_policy::add_gas_consumed(
+                                &contract_id,
+                                policy.as_ref(),
+                                Some(&origin),
+                                &ink_result,
+                            );
                             let effects = if mode.is_estimating() {
                                 None
                             } else {
@@ -709,6 +871,8 @@ impl Cluster {
                     contracts::SidevmHandle::Stopped(_) => return Err(QueryError::SidevmNotFound),
                     contracts::SidevmHandle::Running(sender) => sender,
                 };
+                let policy = self.config.query_policies.get(contract_id);
+                query_policy::acquire(contract_id, policy, origin)?;
                 let origin = origin.cloned().map(Into::into);
 
                 let (reply_tx, rx) = tokio::sync::oneshot::channel();
@@ -739,13 +903,22 @@ impl Cluster {
                 deposit,
                 transfer,
             } => {
+                let origin = origin.cloned().ok_or(QueryError::BadOrigin)?;
+                let policy = self.config.query_policies.get(contract_id).cloned();
+                let (gas_limit, gas_free) = query_policy::acquire_ink(
+                    contract_id,
+                    policy.as_ref(),
+                    &origin,
+                    deposit,
+                    transfer,
+                    || self.free_balance(&origin),
+                )?;
                 let _guard = context
                     .query_scheduler
                     .acquire(contract_id.clone(), context.weight)
                     .await
                     .or(Err(QueryError::ServiceUnavailable))?;
 
-                let origin = origin.cloned().ok_or(QueryError::BadOrigin)?;
                 let mut ctx = context::ContractExecContext::new(
                     ExecutionMode::Estimating,
                     context.now_ms,
@@ -758,10 +931,10 @@ impl Cluster {
                 context::using(&mut ctx, move || {
                     let mut runtime = self.runtime_mut(log_handler);
                     let args = TransactionArguments {
-                        origin,
+                        origin: origin.clone(),
                         transfer,
-                        gas_limit: WEIGHT_REF_TIME_PER_SECOND * 10,
-                        gas_free: true,
+                        gas_limit,
+                        gas_free,
                         storage_deposit_limit: None,
                         deposit,
                     };
@@ -772,12 +945,90 @@ impl Cluster {
                         ExecutionMode::Estimating,
                         args,
                     );
+                    query_policy::add_gas_consumed(
+                        contract_id,
+                        policy.as_ref(),
+                        Some(&origin),
+                        &ink_result,
+                    );
                     Ok((Response::Payload(ink_result), None))
                 })
             }
         }
     }
 
+    /// Execute an ink message in estimating mode and trace it, committing nothing.
+    pub(crate) async fn handle_dry_run(
+        self,
+        contract_id: &AccountId,
+        origin: Option<&AccountId>,
+        req: DryRunRequest,
+        context: QueryContext,
+    ) -> Result<DryRunResult, QueryError> {
+        let DryRunRequest {
+            payload: input_data,
+            deposit,
+            transfer,
+        } = req;
+        if self.config.runtime_version < (1, 2) {
+            return Err(QueryError::RuntimeError(
+                "Dry run requires runtime 1.2 or later".into(),
+            ));
+        }
+        let origin = origin.cloned().ok_or(QueryError::BadOrigin)?;
+        // Rejected queries must not take the slots of the scheduler.
+        let policy = self.config.query_policies.get(contract_id).cloned();
+        let (gas_limit, gas_free) = query_policy::acquire_ink(
+            contract_id,
+            policy.as_ref(),
+            &origin,
+            deposit,
+            transfer,
+            || self.free_balance(&origin),
+        )?;
+        let _guard = context
+            .query_scheduler
+            .acquire(contract_id.clone(), context.weight)
+            .await
+            .or(Err(QueryError::ServiceUnavailable))?;
+
+        let mut ctx = context::ContractExecContext::new(
+            ExecutionMode::Estimating,
+            context.now_ms,
+            context.block_number,
+            context.worker_pubkey,
+            context.chain_storage,
+            context.req_id,
+        );
+        let log_handler = context.log_handler.clone();
+        let span = tracing::Span::current();
+        let contract_id = contract_id.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = span.enter();
+            context::using(&mut ctx, move || {
+                let runtime = self.runtime(log_handler);
+                let args = TransactionArguments {
+                    origin: origin.clone(),
+                    transfer,
+                    gas_limit,
+                    gas_free,
+                    storage_deposit_limit: None,
+                    deposit,
+                };
+                let output = runtime.dry_run(contract_id.clone(), input_data, args);
+                query_policy::add_gas_consumed(
+                    &contract_id,
+                    policy.as_ref(),
+                    Some(&origin),
+                    &output.result,
+                );
+                Ok(output)
+            })
+        })
+        .await
+        .map_err(|_| QueryError::RuntimeError("Failed to spawn blocking task".into()))?
+    }
+
     pub(crate) fn handle_command(
         &mut self,
         contract_id: &AccountId,
@@ -785,6 +1036,11 @@ impl Cluster {
         cmd: InkCommand,
         context: &mut TransactionContext,
     ) -> TransactionResult {
+        if self.partial {
+            return Err(TransactionError::Other(
+                "The cluster is partially restored".into(),
+            ));
+        }
         match cmd {
             InkCommand::InkMessage {
                 nonce,
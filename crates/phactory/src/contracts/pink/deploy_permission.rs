@@ -0,0 +1,56 @@
+use parity_scale_codec::{Decode, Encode};
+use phala_types::contract::{ClusterPermission, CodeIndex};
+use pink::types::AccountId;
+use sp_core::H256;
+use std::collections::BTreeSet;
+
+/// Who can upload code to or instantiate contracts in a cluster, mirrored from the phat pallet.
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct DeployPermission {
+    /// `None` for clusters created before the permission was tracked by the workers, which
+    /// accept whatever the pallet has let through.
+    pub permission: Option<ClusterPermission<AccountId>>,
+    pub owner: Option<AccountId>,
+    /// Accounts granted the deployer role of the cluster.
+    pub deployers: BTreeSet<AccountId>,
+    /// The code hashes allowed to be instantiated. `None` means no restriction.
+    pub code_allowlist: Option<Vec<H256>>,
+}
+
+impl DeployPermission {
+    /// Same rules as `check_cluster_permission` in the phat pallet.
+    pub fn can_deploy(&self, deployer: &AccountId) -> bool {
+        let Some(permission) = &self.permission else {
+            return true;
+        };
+        let is_deployer = || self.deployers.contains(deployer);
+        match permission {
+            ClusterPermission::Public => true,
+            ClusterPermission::OnlyOwner(owner) => deployer == owner,
+            ClusterPermission::AllowList(accounts) => {
+                self.owner.as_ref() == Some(deployer)
+                    || accounts.contains(deployer)
+                    || is_deployer()
+            }
+            ClusterPermission::DenyList(accounts) => !accounts.contains(deployer),
+            ClusterPermission::Governance => is_deployer(),
+        }
+    }
+
+    pub fn can_instantiate(&self, code_index: &CodeIndex<H256>) -> bool {
+        let Some(allowed) = &self.code_allowlist else {
+            return true;
+        };
+        match code_index {
+            CodeIndex::WasmCode(code_hash) => allowed.contains(code_hash),
+        }
+    }
+
+    pub fn set_deployer(&mut self, account: AccountId, enabled: bool) {
+        if enabled {
+            self.deployers.insert(account);
+        } else {
+            self.deployers.remove(&account);
+        }
+    }
+}
@@ -32,6 +32,7 @@ pub(super) fn counters() -> &'static Mutex<HttpGlobolCounters> {
 }
 
 pub(super) fn add(contract: AccountId, status_code: u16) {
+    crate::metrics::record_http(&contract, status_code);
     let success = status_code / 100 == 2;
     let mut counters = counters().lock().unwrap();
     let now = std::time::SystemTime::now()
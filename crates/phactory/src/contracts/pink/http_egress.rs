@@ -0,0 +1,290 @@
+use parity_scale_codec::{Decode, Encode};
+use pink::{
+    capi::v1::ocall::HttpRequestError,
+    types::{AccountId, HttpEgressPolicy},
+};
+use pink_extension_runtime::{ByteBudget, HostFilter};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+const WINDOW_SECS: u64 = 60;
+
+/// HTTP egress policies of a cluster, set by the system contract.
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct HttpEgressPolicies {
+    /// Policy applied to contracts without their own policy.
+    pub default: Option<HttpEgressPolicy>,
+    /// Policies grouped by contract account ID.
+    pub by_contract: BTreeMap<AccountId, HttpEgressPolicy>,
+}
+
+impl HttpEgressPolicies {
+    pub fn get(&self, contract: &AccountId) -> Option<&HttpEgressPolicy> {
+        self.by_contract.get(contract).or(self.default.as_ref())
+    }
+
+    pub fn set(&mut self, contract: Option<AccountId>, policy: Option<HttpEgressPolicy>) {
+        match (contract, policy) {
+            (None, policy) => self.default = policy,
+            (Some(contract), Some(policy)) => {
+                self.by_contract.insert(contract, policy);
+            }
+            (Some(contract), None) => {
+                self.by_contract.remove(&contract);
+            }
+        }
+    }
+}
+
+/// HTTP egress usage of a contract in the current window.
+#[derive(Debug, Default)]
+struct Usage {
+    window: u64,
+    requests: u32,
+    bytes: u64,
+}
+
+static USAGES: once_cell::sync::OnceCell<Mutex<BTreeMap<AccountId, Usage>>> =
+    once_cell::sync::OnceCell::new();
+
+fn usages() -> &'static Mutex<BTreeMap<AccountId, Usage>> {
+    USAGES.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+pub(super) fn current_window() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / WINDOW_SECS
+}
+
+/// Check an outgoing request against the policy and count it into the quota of the contract.
+pub(super) fn acquire(
+    contract: &AccountId,
+    policy: Option<&HttpEgressPolicy>,
+    url: &str,
+    body_size: usize,
+) -> Result<(), HttpRequestError> {
+    let Some(policy) = policy else {
+        return Ok(());
+    };
+    let url: reqwest::Url = url.parse().or(Err(HttpRequestError::InvalidUrl))?;
+    if !policy.is_host_allowed(url.host_str().unwrap_or_default()) {
+        return Err(HttpRequestError::DestinationNotAllowed);
+    }
+    let window = current_window();
+    let mut usages = usages().lock().unwrap();
+    // The usages of the past windows are no longer needed.
+    usages.retain(|_, usage| usage.window == window);
+    let usage = usages.entry(contract.clone()).or_insert_with(|| Usage {
+        window,
+        ..Default::default()
+    });
+    if let Some(max) = policy.max_requests_per_minute {
+        if usage.requests >= max {
+            return Err(HttpRequestError::QuotaExceeded);
+        }
+    }
+    if let Some(max) = policy.max_bytes_per_minute {
+        if usage.bytes.saturating_add(body_size as u64) > max {
+            return Err(HttpRequestError::QuotaExceeded);
+        }
+    }
+    usage.requests += 1;
+    usage.bytes += body_size as u64;
+    Ok(())
+}
+
+/// Build the filter checking every redirect hop of a request against the domain lists of the
+/// policy, so that an allowed host can not redirect the request to a blocked one.
+pub(super) fn host_filter(policy: Option<&HttpEgressPolicy>) -> Option<HostFilter> {
+    let policy = policy?.clone();
+    Some(Arc::new(move |host: &str| policy.is_host_allowed(host)))
+}
+
+/// The response bytes a contract may still download in the current window.
+pub(super) struct ByteAllowance {
+    start: u64,
+    budget: ByteBudget,
+}
+
+impl ByteAllowance {
+    /// The budget checked by the runtime while downloading the responses.
+    pub fn budget(&self) -> ByteBudget {
+        self.budget.clone()
+    }
+}
+
+/// Take the bytes left in the quota of the contract for the responses of the requests to send.
+///
+/// `None` if the policy doesn't limit the bytes.
+pub(super) fn byte_allowance(
+    contract: &AccountId,
+    policy: Option<&HttpEgressPolicy>,
+) -> Option<ByteAllowance> {
+    let max = policy?.max_bytes_per_minute?;
+    let window = current_window();
+    let used = usages()
+        .lock()
+        .unwrap()
+        .get(contract)
+        .filter(|usage| usage.window == window)
+        .map_or(0, |usage| usage.bytes);
+    let start = max.saturating_sub(used);
+    Some(ByteAllowance {
+        start,
+        budget: Arc::new(AtomicU64::new(start)),
+    })
+}
+
+/// Count the bytes downloaded under the allowance into the quota of the contract.
+///
+/// This includes the bytes of the responses truncated or aborted while downloading.
+pub(super) fn add_received(contract: &AccountId, allowance: Option<&ByteAllowance>) {
+    let Some(allowance) = allowance else {
+        return;
+    };
+    let received = allowance.start - allowance.budget.load(Ordering::Relaxed);
+    let window = current_window();
+    let mut usages = usages().lock().unwrap();
+    if let Some(usage) = usages.get_mut(contract) {
+        if usage.window == window {
+            usage.bytes = usage.bytes.saturating_add(received);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_quota_is_enforced() {
+        let contract = AccountId::new([1; 32]);
+        let policy = HttpEgressPolicy {
+            max_requests_per_minute: Some(2),
+            ..Default::default()
+        };
+        let url = "https://example.com/";
+        assert!(acquire(&contract, Some(&policy), url, 0).is_ok());
+        assert!(acquire(&contract, Some(&policy), url, 0).is_ok());
+        assert!(matches!(
+            acquire(&contract, Some(&policy), url, 0),
+            Err(HttpRequestError::QuotaExceeded)
+        ));
+        assert!(acquire(&contract, None, url, 0).is_ok());
+    }
+
+    #[test]
+    fn bandwidth_quota_is_enforced() {
+        let contract = AccountId::new([2; 32]);
+        let policy = HttpEgressPolicy {
+            max_bytes_per_minute: Some(100),
+            ..Default::default()
+        };
+        let url = "https://example.com/";
+        assert!(acquire(&contract, Some(&policy), url, 10).is_ok());
+        let allowance = byte_allowance(&contract, Some(&policy)).unwrap();
+        assert_eq!(allowance.budget().load(Ordering::Relaxed), 90);
+        allowance.budget().fetch_sub(90, Ordering::Relaxed);
+        add_received(&contract, Some(&allowance));
+        assert!(byte_allowance(&contract, None).is_none());
+        assert!(matches!(
+            acquire(&contract, Some(&policy), url, 1),
+            Err(HttpRequestError::QuotaExceeded)
+        ));
+    }
+
+    #[test]
+    fn past_windows_are_evicted() {
+        let stale = AccountId::new([5; 32]);
+        let contract = AccountId::new([6; 32]);
+        usages().lock().unwrap().insert(
+            stale.clone(),
+            Usage {
+                window: current_window() - 1,
+                requests: 1,
+                bytes: 0,
+            },
+        );
+        let policy = HttpEgressPolicy::default();
+        assert!(acquire(&contract, Some(&policy), "https://example.com/", 0).is_ok());
+        let usages = usages().lock().unwrap();
+        assert!(!usages.contains_key(&stale));
+        assert!(usages.contains_key(&contract));
+    }
+
+    #[test]
+    fn destination_is_checked() {
+        let contract = AccountId::new([3; 32]);
+        let policy = HttpEgressPolicy {
+            allowed_domains: vec!["example.com".into()],
+            ..Default::default()
+        };
+        assert!(acquire(&contract, Some(&policy), "https://api.example.com/", 0).is_ok());
+        assert!(matches!(
+            acquire(&contract, Some(&policy), "https://example.org/", 0),
+            Err(HttpRequestError::DestinationNotAllowed)
+        ));
+    }
+
+    #[test]
+    fn redirect_to_blocked_host_is_rejected() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 302 Found\r\nLocation: http://evil.example.org/\r\nContent-Length: 0\r\n\r\n",
+                )
+                .unwrap();
+        });
+        let contract = AccountId::new([7; 32]);
+        let policy = HttpEgressPolicy {
+            allowed_domains: vec!["127.0.0.1".into()],
+            ..Default::default()
+        };
+        let url = format!("http://127.0.0.1:{port}/");
+        assert!(acquire(&contract, Some(&policy), &url, 0).is_ok());
+        let request = pink::capi::v1::ocall::HttpRequestV2::new(url, "GET", vec![], vec![]);
+        let result = pink_extension_runtime::http_request_v2_filtered(
+            request,
+            5000,
+            host_filter(Some(&policy)),
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(HttpRequestError::DestinationNotAllowed)
+        ));
+        server.join().unwrap();
+        assert!(host_filter(None).is_none());
+    }
+
+    #[test]
+    fn contract_policy_overrides_default() {
+        let contract = AccountId::new([4; 32]);
+        let mut policies = HttpEgressPolicies::default();
+        let default = HttpEgressPolicy {
+            max_requests_per_minute: Some(1),
+            ..Default::default()
+        };
+        policies.set(None, Some(default.clone()));
+        assert_eq!(policies.get(&contract), Some(&default));
+        policies.set(Some(contract.clone()), Some(Default::default()));
+        assert_eq!(policies.get(&contract), Some(&Default::default()));
+        policies.set(Some(contract.clone()), None);
+        assert_eq!(policies.get(&contract), Some(&default));
+    }
+}
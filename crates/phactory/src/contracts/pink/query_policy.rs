@@ -0,0 +1,292 @@
+use parity_scale_codec::{Compact, Decode, Encode};
+use pink::{
+    constants::WEIGHT_REF_TIME_PER_SECOND,
+    types::{AccountId, Balance, QueryPolicy},
+};
+use std::{collections::BTreeMap, sync::Mutex};
+
+use super::{http_egress::current_window, QueryError};
+
+/// Query policies of a cluster, set by the system contract.
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct QueryPolicies {
+    /// Policy applied to contracts without their own policy.
+    pub default: Option<QueryPolicy>,
+    /// Policies grouped by contract account ID.
+    pub by_contract: BTreeMap<AccountId, QueryPolicy>,
+}
+
+impl QueryPolicies {
+    pub fn get(&self, contract: &AccountId) -> Option<&QueryPolicy> {
+        self.by_contract.get(contract).or(self.default.as_ref())
+    }
+
+    pub fn set(&mut self, contract: Option<AccountId>, policy: Option<QueryPolicy>) {
+        match (contract, policy) {
+            (None, policy) => self.default = policy,
+            (Some(contract), Some(policy)) => {
+                self.by_contract.insert(contract, policy);
+            }
+            (Some(contract), None) => {
+                self.by_contract.remove(&contract);
+            }
+        }
+    }
+}
+
+/// Query usage of an origin to a contract in the current window.
+#[derive(Debug, Default)]
+struct Usage {
+    window: u64,
+    queries: u32,
+    gas: u64,
+}
+
+type UsageKey = (AccountId, Option<AccountId>);
+
+static USAGES: once_cell::sync::OnceCell<Mutex<BTreeMap<UsageKey, Usage>>> =
+    once_cell::sync::OnceCell::new();
+
+fn usages() -> &'static Mutex<BTreeMap<UsageKey, Usage>> {
+    USAGES.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Check a query against the policy and count it into the quota of the origin.
+///
+/// Returns the gas left to the origin in the current window if the gas is limited.
+pub(super) fn acquire(
+    contract: &AccountId,
+    policy: Option<&QueryPolicy>,
+    origin: Option<&AccountId>,
+) -> Result<Option<u64>, QueryError> {
+    let Some(policy) = policy else {
+        return Ok(None);
+    };
+    if !policy.is_origin_allowed(origin.map(AsRef::<[u8; 32]>::as_ref)) {
+        return Err(QueryError::Forbidden);
+    }
+    let window = current_window();
+    let mut usages = usages().lock().unwrap();
+    // The usages of the past windows are no longer needed.
+    usages.retain(|_, usage| usage.window == window);
+    let usage = usages
+        .entry((contract.clone(), origin.cloned()))
+        .or_insert_with(|| Usage {
+            window,
+            ..Default::default()
+        });
+    if let Some(max) = policy.max_queries_per_minute {
+        if usage.queries >= max {
+            return Err(QueryError::QuotaExceeded);
+        }
+    }
+    let gas_left = match policy.max_gas_per_minute {
+        Some(max) if usage.gas >= max => return Err(QueryError::QuotaExceeded),
+        Some(max) => Some(max - usage.gas),
+        None => None,
+    };
+    usage.queries += 1;
+    Ok(gas_left)
+}
+
+/// Check an ink query against the policy, which requires an origin.
+///
+/// `balance` gives the free balance of the origin in the cluster, only read if the policy
+/// requires a minimum balance. Returns the gas limit of the query and whether it is free of gas.
+pub(super) fn acquire_ink(
+    contract: &AccountId,
+    policy: Option<&QueryPolicy>,
+    origin: &AccountId,
+    deposit: Balance,
+    transfer: Balance,
+    balance: impl FnOnce() -> Balance,
+) -> Result<(u64, bool), QueryError> {
+    const MAX_QUERY_GAS: u64 = WEIGHT_REF_TIME_PER_SECOND * 10;
+    if let Some(policy) = policy {
+        // Don't tell a forbidden origin about the balance required.
+        if !policy.is_origin_allowed(Some(AsRef::<[u8; 32]>::as_ref(origin))) {
+            return Err(QueryError::Forbidden);
+        }
+        if let Some(min_balance) = policy.min_balance_required {
+            if deposit > 0 || balance() < min_balance.max(transfer) {
+                return Err(QueryError::PaymentRequired);
+            }
+        }
+    }
+    let gas_left = acquire(contract, policy, Some(origin))?;
+    let gas_limit = gas_left.map_or(MAX_QUERY_GAS, |gas| gas.min(MAX_QUERY_GAS));
+    let gas_free = policy.map_or(true, |p| p.min_balance_required.is_none());
+    Ok((gas_limit, gas_free))
+}
+
+/// Count the gas consumed by an ink query into the quota of the origin.
+///
+/// The `output` is the encoded `ContractResult` returned by the runtime.
+pub(super) fn add_gas_consumed(
+    contract: &AccountId,
+    policy: Option<&QueryPolicy>,
+    origin: Option<&AccountId>,
+    output: &[u8],
+) {
+    if policy.map_or(true, |p| p.max_gas_per_minute.is_none()) {
+        return;
+    }
+    // The result starts with the consumed weight, of which the ref time is the first field.
+    let Ok(Compact(gas)) = Compact::<u64>::decode(&mut &output[..]) else {
+        error!("Failed to decode the gas consumed by the query");
+        return;
+    };
+    let window = current_window();
+    let mut usages = usages().lock().unwrap();
+    if let Some(usage) = usages.get_mut(&(contract.clone(), origin.cloned())) {
+        if usage.window == window {
+            usage.gas = usage.gas.saturating_add(gas);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins_are_checked() {
+        let contract = AccountId::new([1; 32]);
+        let alice = AccountId::new([2; 32]);
+        let policy = QueryPolicy {
+            allowed_origins: vec![[2; 32].into()],
+            ..Default::default()
+        };
+        assert!(acquire(&contract, Some(&policy), Some(&alice)).is_ok());
+        assert!(matches!(
+            acquire(&contract, Some(&policy), Some(&contract)),
+            Err(QueryError::Forbidden)
+        ));
+        assert!(matches!(
+            acquire(&contract, Some(&policy), None),
+            Err(QueryError::Forbidden)
+        ));
+        assert!(acquire(&contract, None, None).is_ok());
+    }
+
+    #[test]
+    fn query_quota_is_counted_per_origin() {
+        let contract = AccountId::new([3; 32]);
+        let alice = AccountId::new([4; 32]);
+        let bob = AccountId::new([5; 32]);
+        let policy = QueryPolicy {
+            max_queries_per_minute: Some(1),
+            ..Default::default()
+        };
+        assert!(acquire(&contract, Some(&policy), Some(&alice)).is_ok());
+        assert!(matches!(
+            acquire(&contract, Some(&policy), Some(&alice)),
+            Err(QueryError::QuotaExceeded)
+        ));
+        assert!(acquire(&contract, Some(&policy), Some(&bob)).is_ok());
+    }
+
+    #[test]
+    fn gas_quota_is_enforced() {
+        let contract = AccountId::new([6; 32]);
+        let alice = AccountId::new([7; 32]);
+        let policy = QueryPolicy {
+            max_gas_per_minute: Some(100),
+            ..Default::default()
+        };
+        let origin = Some(&alice);
+        assert_eq!(
+            acquire(&contract, Some(&policy), origin).unwrap(),
+            Some(100)
+        );
+        add_gas_consumed(&contract, Some(&policy), origin, &Compact(60u64).encode());
+        assert_eq!(acquire(&contract, Some(&policy), origin).unwrap(), Some(40));
+        add_gas_consumed(&contract, Some(&policy), origin, &Compact(40u64).encode());
+        assert!(matches!(
+            acquire(&contract, Some(&policy), origin),
+            Err(QueryError::QuotaExceeded)
+        ));
+    }
+
+    #[test]
+    fn ink_gas_limit_follows_the_gas_left() {
+        let contract = AccountId::new([8; 32]);
+        let alice = AccountId::new([9; 32]);
+        let policy = QueryPolicy {
+            max_gas_per_minute: Some(100),
+            ..Default::default()
+        };
+        let (gas_limit, gas_free) =
+            acquire_ink(&contract, Some(&policy), &alice, 0, 0, || 0).unwrap();
+        assert_eq!(gas_limit, 100);
+        assert!(gas_free);
+        let (unlimited, _) = acquire_ink(&contract, None, &alice, 0, 0, || 0).unwrap();
+        assert_eq!(unlimited, WEIGHT_REF_TIME_PER_SECOND * 10);
+    }
+
+    #[test]
+    fn min_balance_is_checked_against_the_balance() {
+        let contract = AccountId::new([13; 32]);
+        let alice = AccountId::new([14; 32]);
+        let policy = QueryPolicy {
+            min_balance_required: Some(10),
+            ..Default::default()
+        };
+        let try_query = |deposit, transfer, balance| {
+            acquire_ink(&contract, Some(&policy), &alice, deposit, transfer, || {
+                balance
+            })
+        };
+        // Funded by a deposit instead of the balance
+        assert!(matches!(
+            try_query(100, 0, 100),
+            Err(QueryError::PaymentRequired)
+        ));
+        // Balance too low
+        assert!(matches!(
+            try_query(0, 0, 9),
+            Err(QueryError::PaymentRequired)
+        ));
+        // Balance too low for the transfer
+        assert!(matches!(
+            try_query(0, 11, 10),
+            Err(QueryError::PaymentRequired)
+        ));
+        let (_, gas_free) = try_query(0, 0, 10).unwrap();
+        assert!(!gas_free);
+        assert!(try_query(0, 10, 10).is_ok());
+    }
+
+    #[test]
+    fn forbidden_origin_is_rejected_before_the_balance_check() {
+        let contract = AccountId::new([15; 32]);
+        let alice = AccountId::new([16; 32]);
+        let policy = QueryPolicy {
+            allowed_origins: vec![[17; 32].into()],
+            min_balance_required: Some(10),
+            ..Default::default()
+        };
+        let result = acquire_ink(&contract, Some(&policy), &alice, 0, 0, || {
+            panic!("The balance of a forbidden origin should not be read")
+        });
+        assert!(matches!(result, Err(QueryError::Forbidden)));
+    }
+
+    #[test]
+    fn past_windows_are_evicted() {
+        let contract = AccountId::new([10; 32]);
+        let alice = AccountId::new([11; 32]);
+        let stale = (contract.clone(), Some(AccountId::new([12; 32])));
+        usages().lock().unwrap().insert(
+            stale.clone(),
+            Usage {
+                window: current_window() - 1,
+                queries: 1,
+                gas: 0,
+            },
+        );
+        let policy = QueryPolicy::default();
+        assert!(acquire(&contract, Some(&policy), Some(&alice)).is_ok());
+        assert!(usages().lock().unwrap().get(&stale).is_none());
+    }
+}
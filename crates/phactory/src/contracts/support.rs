@@ -162,6 +162,10 @@ impl Contract {
         &self.address
     }
 
+    pub(crate) fn cluster_id(&self) -> &phala_mq::ContractClusterId {
+        &self.cluster_id
+    }
+
     pub(crate) fn sidevm_handle(&self) -> Option<SidevmHandle> {
         self.sidevm_info
             .as_ref()
@@ -198,6 +202,9 @@ impl Contract {
     }
 
     pub(crate) fn on_block_end(&mut self, env: &mut ExecuteEnv) -> TransactionResult {
+        if env.contract_cluster.partial {
+            return Ok(None);
+        }
         let Some(OnBlockEnd { selector, gas_limit }) = self.on_block_end else {
             return Ok(None);
         };
@@ -274,6 +281,7 @@ impl Contract {
             do_start_sidevm(spawner, &code, *self.address.as_ref(), self.weight)?
         };
 
+        crate::metrics::record_sidevm_start(&self.address);
         let start_time = chrono::Utc::now().to_rfc3339();
         self.sidevm_info = Some(SidevmInfo {
             code,
@@ -308,6 +316,7 @@ impl Contract {
                 if !need_restart {
                     return Ok(());
                 }
+                crate::metrics::record_sidevm_start(&self.address);
                 sidevm_info.start_time = chrono::Utc::now().to_rfc3339();
                 do_start_sidevm(
                     spawner,
@@ -346,6 +355,7 @@ impl Contract {
             SidevmHandle::Running(tx) => tx.clone(),
         };
         let result = tx.try_send(message);
+        crate::metrics::record_sidevm_message(&self.address, result.is_err());
         if let Err(err) = result {
             use tokio::sync::mpsc::error::TrySendError;
             match err {
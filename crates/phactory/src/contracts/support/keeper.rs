@@ -49,6 +49,14 @@ impl ContractsKeeper {
             .map(|(_, v)| v)
     }
 
+    /// Keep only the contracts whose ids satisfy the predicate.
+    pub fn retain(&mut self, mut f: impl FnMut(&AccountId) -> bool) {
+        self.contracts = std::mem::take(&mut self.contracts)
+            .into_iter()
+            .filter(|(id, _)| f(id))
+            .collect();
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&AccountId, &Contract)> {
         self.contracts.iter()
     }
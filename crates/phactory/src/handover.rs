@@ -0,0 +1,574 @@
+//! Resumable worker handover.
+//!
+//! The worker key is handed over with the handover pRPCs. After that, the new pRuntime pulls a
+//! snapshot of the old pRuntime's state in chunks. The snapshot is a checkpoint encrypted with
+//! the worker key, so it covers the chain state, the contracts and the clusters. Each received
+//! chunk is recorded in the [`HandoverProgress`] file, so an interrupted transfer can resume
+//! without downloading everything again.
+//!
+//! The old pRuntime keeps running until the new one catches up with the chain and sends
+//! [`HandoverCommand::Complete`]; then the old pRuntime retires. If the new pRuntime fails to sync,
+//! it sends [`HandoverCommand::Abort`] and discards the received key and state. The old pRuntime
+//! stays in charge.
+
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use parity_scale_codec::Encode;
+use phactory_api::ecall_args::InitArgs;
+use phala_crypto::sr25519::Persistence;
+use phala_serde_more as more;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sp_core::{hashing::blake2_256, sr25519, Pair};
+use thiserror::Error;
+
+use crate::{BlockNumber, Phactory};
+
+/// Size of the state chunks served to the new pRuntime.
+pub const DEFAULT_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+const PROGRESS_FILE: &str = "handover.progress.json";
+const SNAPSHOT_FILE: &str = "handover.snapshot.seal";
+const STAGING_FILE: &str = "handover.staging.seal";
+const RETIRED_FILE: &str = "handover.retired";
+const COMMAND_SIGNING_CONTEXT: &[u8] = b"phala/handover/command";
+
+#[derive(Debug, Error)]
+pub enum HandoverError {
+    #[error("State transfer is not available: {0}")]
+    Unavailable(&'static str),
+    #[error("No active handover session")]
+    NoSession,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Describes the state snapshot taken by the old pRuntime for a handover session.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StateManifest {
+    pub session_id: [u8; 32],
+    /// The block the snapshot was taken at.
+    pub block_number: BlockNumber,
+    pub total_size: u64,
+    pub chunk_size: u64,
+    /// The blake2_256 hash of each chunk.
+    pub chunk_hashes: Vec<[u8; 32]>,
+}
+
+impl StateManifest {
+    fn from_file(
+        session_id: [u8; 32],
+        block_number: BlockNumber,
+        path: &Path,
+        chunk_size: u64,
+    ) -> Result<Self> {
+        let mut file = File::open(path).context("Failed to open the state snapshot")?;
+        let mut chunk_hashes = vec![];
+        let mut total_size = 0;
+        let mut buffer = vec![0u8; chunk_size as usize];
+        loop {
+            let len = read_full(&mut file, &mut buffer)?;
+            if len == 0 {
+                break;
+            }
+            chunk_hashes.push(blake2_256(&buffer[..len]));
+            total_size += len as u64;
+        }
+        Ok(Self {
+            session_id,
+            block_number,
+            total_size,
+            chunk_size,
+            chunk_hashes,
+        })
+    }
+
+    pub fn num_chunks(&self) -> u32 {
+        self.chunk_hashes.len() as u32
+    }
+
+    /// Returns the offset and the length of the chunk in the snapshot.
+    pub fn chunk_range(&self, index: u32) -> Result<(u64, u64)> {
+        if index >= self.num_chunks() {
+            bail!("Chunk index {index} out of range");
+        }
+        let offset = index as u64 * self.chunk_size;
+        let len = self.chunk_size.min(self.total_size - offset);
+        Ok((offset, len))
+    }
+
+    pub fn verify_chunk(&self, index: u32, data: &[u8]) -> Result<()> {
+        let (_, len) = self.chunk_range(index)?;
+        if data.len() as u64 != len || blake2_256(data) != self.chunk_hashes[index as usize] {
+            bail!("Chunk {index} mismatches the manifest");
+        }
+        Ok(())
+    }
+}
+
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match reader.read(&mut buffer[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+/// The state transfer served by the old pRuntime.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct HandoverSession {
+    /// Taken lazily on the first manifest request.
+    snapshot: Option<(StateManifest, PathBuf)>,
+    served: BTreeSet<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HandoverServingStatus {
+    pub block_number: Option<BlockNumber>,
+    pub chunks_served: u32,
+    pub total_chunks: u32,
+}
+
+#[derive(Encode, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HandoverCommand {
+    /// The new pRuntime has caught up, the old one should retire.
+    Complete,
+    /// The new pRuntime gave up, the old one should keep running.
+    Abort,
+}
+
+/// A command signed with the worker key, which is held by both pRuntimes after the key handover.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedHandoverCommand {
+    pub command: HandoverCommand,
+    pub session_id: [u8; 32],
+    #[serde(with = "more::scale_bytes")]
+    pub signature: sr25519::Signature,
+}
+
+impl SignedHandoverCommand {
+    fn payload(command: HandoverCommand, session_id: &[u8; 32]) -> Vec<u8> {
+        (COMMAND_SIGNING_CONTEXT, command, session_id).encode()
+    }
+
+    pub fn sign(command: HandoverCommand, session_id: [u8; 32], key: &sr25519::Pair) -> Self {
+        let signature = key.sign(&Self::payload(command, &session_id));
+        Self {
+            command,
+            session_id,
+            signature,
+        }
+    }
+
+    pub fn verify(&self, session_id: &[u8; 32], pubkey: &sr25519::Public) -> Result<()> {
+        if &self.session_id != session_id {
+            bail!("Handover session mismatch");
+        }
+        let payload = Self::payload(self.command, session_id);
+        if !sr25519::Pair::verify(&self.signature, payload, pubkey) {
+            bail!("Invalid handover command signature");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum HandoverStage {
+    /// Waiting for the worker key.
+    KeyExchange,
+    /// The worker key is received and the state is being transferred.
+    TransferringState {
+        manifest: StateManifest,
+        received: BTreeSet<u32>,
+    },
+    /// The state is restored and the new pRuntime is catching up with the chain.
+    Syncing {
+        manifest: StateManifest,
+    },
+    Completed,
+    RolledBack {
+        reason: String,
+    },
+}
+
+/// The progress of a handover on the new pRuntime, persisted in the storage path.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HandoverProgress {
+    /// The URL of the old pRuntime.
+    pub from: String,
+    pub stage: HandoverStage,
+    pub retries: u32,
+    pub last_error: Option<String>,
+}
+
+impl HandoverProgress {
+    pub fn new(from: &str) -> Self {
+        Self {
+            from: from.into(),
+            stage: HandoverStage::KeyExchange,
+            retries: 0,
+            last_error: None,
+        }
+    }
+
+    pub fn load(storage_path: &str) -> Result<Option<Self>> {
+        let content = match fs::read(Path::new(storage_path).join(PROGRESS_FILE)) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            other => other.context("Failed to read handover progress")?,
+        };
+        serde_json::from_slice(&content)
+            .context("Failed to decode handover progress")
+            .map(Some)
+    }
+
+    pub fn save(&self, storage_path: &str) -> Result<()> {
+        let path = Path::new(storage_path).join(PROGRESS_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+        let content = serde_json::to_vec_pretty(self)?;
+        fs::write(&tmp_path, content).context("Failed to write handover progress")?;
+        fs::rename(tmp_path, path).context("Failed to write handover progress")?;
+        Ok(())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.stage,
+            HandoverStage::Completed | HandoverStage::RolledBack { .. }
+        )
+    }
+
+    /// Moves to the state transfer stage. Chunks already received for the same snapshot are kept.
+    pub fn start_transfer(&mut self, storage_path: &str, manifest: StateManifest) -> Result<()> {
+        if let HandoverStage::TransferringState {
+            manifest: current, ..
+        } = &self.stage
+        {
+            if current == &manifest {
+                return Ok(());
+            }
+        }
+        let file = File::create(staging_file(storage_path))
+            .context("Failed to create the handover staging file")?;
+        file.set_len(manifest.total_size)?;
+        self.stage = HandoverStage::TransferringState {
+            manifest,
+            received: Default::default(),
+        };
+        Ok(())
+    }
+
+    pub fn missing_chunks(&self) -> Vec<u32> {
+        match &self.stage {
+            HandoverStage::TransferringState { manifest, received } => (0..manifest.num_chunks())
+                .filter(|index| !received.contains(index))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the received and total number of chunks.
+    pub fn transfer_progress(&self) -> Option<(u32, u32)> {
+        match &self.stage {
+            HandoverStage::TransferringState { manifest, received } => {
+                Some((received.len() as u32, manifest.num_chunks()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn receive_chunk(&mut self, storage_path: &str, index: u32, data: &[u8]) -> Result<()> {
+        let HandoverStage::TransferringState { manifest, received } = &mut self.stage else {
+            bail!("Not transferring state");
+        };
+        manifest.verify_chunk(index, data)?;
+        let (offset, _) = manifest.chunk_range(index)?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(staging_file(storage_path))
+            .context("Failed to open the handover staging file")?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        file.sync_data()?;
+        received.insert(index);
+        Ok(())
+    }
+
+    /// Installs the received state as the latest checkpoint, to be loaded when the pRuntime
+    /// initializes.
+    pub fn finish_transfer(&mut self, storage_path: &str) -> Result<()> {
+        if !self.missing_chunks().is_empty() {
+            bail!("State transfer not finished");
+        }
+        let HandoverStage::TransferringState { manifest, .. } = &self.stage else {
+            bail!("Not transferring state");
+        };
+        let checkpoint_file = crate::checkpoint_filename_for(manifest.block_number, storage_path);
+        let info = serde_json::json!({
+            "handover_from": self.from,
+            "block_number": manifest.block_number,
+        });
+        fs::write(
+            crate::checkpoint_info_filename_for(&checkpoint_file),
+            info.to_string(),
+        )
+        .context("Failed to write checkpoint info file")?;
+        fs::rename(staging_file(storage_path), &checkpoint_file)
+            .context("Failed to install the received checkpoint")?;
+        info!("Received state installed to {checkpoint_file}");
+        self.stage = HandoverStage::Syncing {
+            manifest: manifest.clone(),
+        };
+        Ok(())
+    }
+}
+
+fn staging_file(storage_path: &str) -> PathBuf {
+    Path::new(storage_path).join(STAGING_FILE)
+}
+
+pub fn is_retired(storage_path: &str) -> bool {
+    Path::new(storage_path).join(RETIRED_FILE).exists()
+}
+
+/// Removes the worker key and the state received from the old pRuntime.
+///
+/// The checkpoints in the storage path are removed as well, since they were taken with the key.
+pub fn discard_received_state(args: &InitArgs) -> Result<()> {
+    let runtime_data = Path::new(&args.sealing_path).join(crate::RUNTIME_SEALED_DATA_FILE);
+    if runtime_data.exists() {
+        fs::remove_file(runtime_data).context("Failed to remove the received worker key")?;
+    }
+    let staging = staging_file(&args.storage_path);
+    if staging.exists() {
+        fs::remove_file(staging).context("Failed to remove the staging file")?;
+    }
+    crate::incremental_checkpoint::remove_all(&args.storage_path)?;
+    for filename in crate::glob_checkpoint_files(&args.storage_path)? {
+        if let Err(err) = crate::remove_checkpoint(&filename) {
+            warn!(
+                "Failed to remove checkpoint {}: {err:?}",
+                filename.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform> {
+    /// Called after the worker key is sent to a new pRuntime.
+    pub(crate) fn start_handover_session(&mut self) {
+        if let Some(session) = self.handover_session.take() {
+            session.remove_snapshot();
+        }
+        self.handover_session = Some(Default::default());
+    }
+
+    pub fn handover_manifest(&mut self) -> Result<StateManifest, HandoverError> {
+        if self.args.safe_mode_level > 0 {
+            return Err(HandoverError::Unavailable("pRuntime is in safe mode"));
+        }
+        let session = self
+            .handover_session
+            .as_ref()
+            .ok_or(HandoverError::NoSession)?;
+        if let Some((manifest, _)) = &session.snapshot {
+            return Ok(manifest.clone());
+        }
+        let (block_number, _) = self
+            .current_block()
+            .map_err(|err| anyhow!("Failed to get current block: {err:?}"))?;
+        let key = self
+            .system
+            .as_ref()
+            .ok_or(HandoverError::Unavailable("runtime is not ready"))?
+            .identity_key
+            .dump_secret_key();
+        let path = Path::new(&self.args.storage_path).join(SNAPSHOT_FILE);
+        info!("Taking state snapshot at block {block_number} for handover");
+        let file = File::create(&path).context("Failed to create the state snapshot")?;
+        self.take_checkpoint_to_writer(&key, file)?;
+        let session_id = rand::random();
+        let manifest =
+            StateManifest::from_file(session_id, block_number, &path, DEFAULT_CHUNK_SIZE)?;
+        info!(
+            "State snapshot taken, {} bytes in {} chunks",
+            manifest.total_size,
+            manifest.num_chunks()
+        );
+        if let Some(session) = self.handover_session.as_mut() {
+            session.snapshot = Some((manifest.clone(), path));
+        }
+        Ok(manifest)
+    }
+
+    pub fn handover_chunk(
+        &mut self,
+        session_id: &[u8; 32],
+        index: u32,
+    ) -> Result<Vec<u8>, HandoverError> {
+        let session = self
+            .handover_session
+            .as_mut()
+            .ok_or(HandoverError::NoSession)?;
+        let Some((manifest, path)) = &session.snapshot else {
+            return Err(HandoverError::NoSession);
+        };
+        if &manifest.session_id != session_id {
+            return Err(HandoverError::NoSession);
+        }
+        let (offset, len) = manifest.chunk_range(index)?;
+        let mut file = File::open(path).context("Failed to open the state snapshot")?;
+        file.seek(SeekFrom::Start(offset))
+            .context("Failed to seek the state snapshot")?;
+        let mut data = vec![0u8; len as usize];
+        file.read_exact(&mut data)
+            .context("Failed to read the state snapshot")?;
+        session.served.insert(index);
+        Ok(data)
+    }
+
+    pub fn handover_command(
+        &mut self,
+        command: SignedHandoverCommand,
+    ) -> Result<(), HandoverError> {
+        let session = self
+            .handover_session
+            .as_ref()
+            .ok_or(HandoverError::NoSession)?;
+        let Some((manifest, _)) = &session.snapshot else {
+            return Err(HandoverError::NoSession);
+        };
+        let pubkey = self
+            .system
+            .as_ref()
+            .ok_or(HandoverError::Unavailable("runtime is not ready"))?
+            .identity_key
+            .public();
+        command.verify(&manifest.session_id, &pubkey)?;
+        match command.command {
+            HandoverCommand::Complete => {
+                fs::write(Path::new(&self.args.storage_path).join(RETIRED_FILE), [])
+                    .context("Failed to mark the pRuntime as retired")?;
+                self.retired = true;
+                info!("Handover completed, this pRuntime is retired");
+            }
+            HandoverCommand::Abort => {
+                info!("Handover aborted by the new pRuntime, keep running");
+            }
+        }
+        if let Some(session) = self.handover_session.take() {
+            session.remove_snapshot();
+        }
+        Ok(())
+    }
+
+    pub fn handover_serving_status(&self) -> Option<HandoverServingStatus> {
+        let session = self.handover_session.as_ref()?;
+        let manifest = session.snapshot.as_ref().map(|(manifest, _)| manifest);
+        Some(HandoverServingStatus {
+            block_number: manifest.map(|m| m.block_number),
+            chunks_served: session.served.len() as u32,
+            total_chunks: manifest.map(|m| m.num_chunks()).unwrap_or_default(),
+        })
+    }
+
+    /// Signs a handover command with the worker key received from the old pRuntime.
+    pub fn sign_handover_command(
+        platform: &Platform,
+        sealing_path: &str,
+        command: HandoverCommand,
+        session_id: [u8; 32],
+    ) -> Result<SignedHandoverCommand> {
+        let runtime_data =
+            Self::load_runtime_data(platform, sealing_path).context("Worker key not received")?;
+        let key = sr25519::Pair::restore_from_secret_key(&runtime_data.sk);
+        Ok(SignedHandoverCommand::sign(command, session_id, &key))
+    }
+
+    pub fn is_retired(&self) -> bool {
+        self.retired
+    }
+}
+
+impl HandoverSession {
+    fn remove_snapshot(self) {
+        if let Some((_, path)) = self.snapshot {
+            if let Err(err) = fs::remove_file(&path) {
+                warn!("Failed to remove {}: {err}", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("phactory-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.display().to_string()
+    }
+
+    #[test]
+    fn chunked_transfer_can_resume() {
+        let from_dir = temp_dir("handover-from");
+        let to_dir = temp_dir("handover-to");
+        let snapshot = Path::new(&from_dir).join(SNAPSHOT_FILE);
+        let state: Vec<u8> = (0..2500u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&snapshot, &state).unwrap();
+
+        let manifest = StateManifest::from_file([1; 32], 42, &snapshot, 1024).unwrap();
+        assert_eq!(manifest.num_chunks(), 3);
+        assert_eq!(manifest.chunk_range(2).unwrap(), (2048, 452));
+        let chunk = |index: u32| {
+            let (offset, len) = manifest.chunk_range(index).unwrap();
+            state[offset as usize..(offset + len) as usize].to_vec()
+        };
+
+        let mut progress = HandoverProgress::new("http://localhost:8000");
+        progress.start_transfer(&to_dir, manifest.clone()).unwrap();
+        assert!(progress.receive_chunk(&to_dir, 0, &chunk(1)).is_err());
+        progress.receive_chunk(&to_dir, 1, &chunk(1)).unwrap();
+        progress.save(&to_dir).unwrap();
+
+        // Interrupted here, the transfer resumes from the saved progress.
+        let mut progress = HandoverProgress::load(&to_dir).unwrap().unwrap();
+        progress.start_transfer(&to_dir, manifest.clone()).unwrap();
+        assert_eq!(progress.missing_chunks(), vec![0, 2]);
+        assert!(progress.finish_transfer(&to_dir).is_err());
+        for index in progress.missing_chunks() {
+            progress
+                .receive_chunk(&to_dir, index, &chunk(index))
+                .unwrap();
+        }
+        progress.finish_transfer(&to_dir).unwrap();
+        assert_eq!(progress.stage, HandoverStage::Syncing { manifest });
+        let checkpoint = crate::checkpoint_filename_for(42, &to_dir);
+        assert_eq!(fs::read(checkpoint).unwrap(), state);
+
+        fs::remove_dir_all(from_dir).unwrap();
+        fs::remove_dir_all(to_dir).unwrap();
+    }
+
+    #[test]
+    fn commands_are_bound_to_session() {
+        let key = sr25519::Pair::from_seed(&[1; 32]);
+        let command = SignedHandoverCommand::sign(HandoverCommand::Complete, [1; 32], &key);
+        assert!(command.verify(&[1; 32], &key.public()).is_ok());
+        assert!(command.verify(&[2; 32], &key.public()).is_err());
+        let other = sr25519::Pair::from_seed(&[2; 32]);
+        assert!(command.verify(&[1; 32], &other.public()).is_err());
+        let mut tampered = command;
+        tampered.command = HandoverCommand::Abort;
+        assert!(tampered.verify(&[1; 32], &key.public()).is_err());
+    }
+}
@@ -0,0 +1,884 @@
+//! Incremental checkpoints.
+//!
+//! The serialized Phactory state is split into content-defined chunks. A checkpoint file contains
+//! the list of chunk hashes making up the state at its block, plus the chunks not found in the
+//! files before it. A chain is a base file, which contains all of its chunks, followed by deltas
+//! which only contain the chunks changed since the base or earlier deltas.
+//!
+//! The dispatching thread only takes a copy of the state, the same way as the RCU dispatch does.
+//! The copy is serialized and split into chunks in a background thread, while the chunks are
+//! encrypted and written to disk in another one. When the deltas grow too many or too large, the
+//! next checkpoint starts a new chain with a fresh base.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use parity_scale_codec::{Decode, Encode, IoReader};
+use phactory_api::ecall_args::InitArgs;
+use phala_crypto::{aead, sr25519::Persistence};
+use serde::{de::DeserializeOwned, Serialize};
+use sp_core::hashing::blake2_256;
+
+use crate::{
+    derive_key_for_checkpoint, serialize_phactory_to_writer, write_checkpoint_info, BlockNumber,
+    Phactory,
+};
+
+const BASE_FILE: &str = "checkpoint.base";
+const DELTA_FILE: &str = "checkpoint.delta";
+const INCREMENTAL_CHECKPOINT_VERSION: u32 = 1;
+
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+/// Gives chunks of 64KB on average.
+const CHUNK_BOUNDARY_MASK: u64 = 0xffff << 48;
+/// Max number of chunks queued to the writer thread before the serialization waits for it.
+const WRITE_QUEUE_LEN: usize = 1024;
+
+type ChunkHash = [u8; 32];
+
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64
+    let mut table = [0u64; 256];
+    let mut state = 0_u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Splits a byte stream at content-defined boundaries, so that a local change in the state only
+/// changes the chunks around it.
+#[derive(Default)]
+struct Chunker {
+    buffer: Vec<u8>,
+    hash: u64,
+}
+
+impl Chunker {
+    fn feed(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunks = vec![];
+        for &byte in data {
+            self.buffer.push(byte);
+            self.hash = (self.hash << 1).wrapping_add(GEAR[byte as usize]);
+            let len = self.buffer.len();
+            if (len >= MIN_CHUNK_SIZE && self.hash & CHUNK_BOUNDARY_MASK == 0)
+                || len >= MAX_CHUNK_SIZE
+            {
+                chunks.push(std::mem::take(&mut self.buffer));
+                self.hash = 0;
+            }
+        }
+        chunks
+    }
+
+    fn finish(self) -> Option<Vec<u8>> {
+        Some(self.buffer).filter(|buffer| !buffer.is_empty())
+    }
+}
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+struct CheckpointHeader {
+    version: u32,
+    /// The block of the base checkpoint, equal to `block_number` in a base file.
+    base: BlockNumber,
+    block_number: BlockNumber,
+}
+
+enum WriterMessage {
+    Chunk(Vec<u8>),
+    Finish(Vec<ChunkHash>),
+}
+
+/// Collects the chunks of the serialized state and sends the new ones to the writer thread.
+struct DeltaWriter<'a> {
+    chunker: Chunker,
+    known: &'a HashSet<ChunkHash>,
+    added: HashSet<ChunkHash>,
+    manifest: Vec<ChunkHash>,
+    sender: SyncSender<WriterMessage>,
+}
+
+impl<'a> DeltaWriter<'a> {
+    fn new(known: &'a HashSet<ChunkHash>, sender: SyncSender<WriterMessage>) -> Self {
+        Self {
+            chunker: Default::default(),
+            known,
+            added: Default::default(),
+            manifest: vec![],
+            sender,
+        }
+    }
+
+    fn emit(&mut self, chunk: Vec<u8>) -> io::Result<()> {
+        let hash = blake2_256(&chunk);
+        self.manifest.push(hash);
+        if !self.known.contains(&hash) && self.added.insert(hash) {
+            self.send(WriterMessage::Chunk(chunk))?;
+        }
+        Ok(())
+    }
+
+    fn send(&self, message: WriterMessage) -> io::Result<()> {
+        self.sender
+            .send(message)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Checkpoint writer exited"))
+    }
+
+    /// Returns the hashes of the chunks sent to the writer.
+    fn finish(mut self) -> io::Result<HashSet<ChunkHash>> {
+        if let Some(chunk) = std::mem::take(&mut self.chunker).finish() {
+            self.emit(chunk)?;
+        }
+        let manifest = std::mem::take(&mut self.manifest);
+        self.send(WriterMessage::Finish(manifest))?;
+        Ok(self.added)
+    }
+}
+
+impl Write for DeltaWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in self.chunker.feed(buf) {
+            self.emit(chunk)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes a checkpoint file in a background thread. Returns the size of the chunks written.
+fn spawn_writer(
+    path: PathBuf,
+    key: [u8; 16],
+    header: CheckpointHeader,
+    receiver: Receiver<WriterMessage>,
+) -> Result<JoinHandle<Result<u64>>> {
+    let handle = std::thread::Builder::new()
+        .name("checkpoint-writer".into())
+        .spawn(move || {
+            let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+            let result = write_checkpoint_file(&tmp_path, key, header, receiver).and_then(|size| {
+                std::fs::rename(&tmp_path, &path).context("Failed to rename checkpoint file")?;
+                Ok(size)
+            });
+            if result.is_err() {
+                let _ = std::fs::remove_file(&tmp_path);
+            }
+            result
+        })
+        .context("Failed to spawn checkpoint writer")?;
+    Ok(handle)
+}
+
+fn write_checkpoint_file(
+    path: &Path,
+    key: [u8; 16],
+    header: CheckpointHeader,
+    receiver: Receiver<WriterMessage>,
+) -> Result<u64> {
+    let file = File::create(path).context("Failed to create checkpoint file")?;
+    let nonce = rand::random();
+    let mut writer = aead::stream::new_aes128gcm_writer(key, nonce, io::BufWriter::new(file));
+    writer.write_all(&header.encode())?;
+    let mut size = 0;
+    loop {
+        match receiver.recv() {
+            Ok(WriterMessage::Chunk(chunk)) => {
+                size += chunk.len() as u64;
+                writer.write_all(&Some(chunk).encode())?;
+            }
+            Ok(WriterMessage::Finish(manifest)) => {
+                writer.write_all(&None::<Vec<u8>>.encode())?;
+                writer.write_all(&manifest.encode())?;
+                break;
+            }
+            Err(_) => bail!("Checkpoint serialization aborted"),
+        }
+    }
+    writer.flush().context("Failed to flush encrypted writer")?;
+    Ok(size)
+}
+
+/// Reads a checkpoint file, adding its chunks to `chunks`. Returns the header and the manifest.
+fn read_checkpoint_file(
+    path: &Path,
+    key: [u8; 16],
+    chunks: &mut HashMap<ChunkHash, Vec<u8>>,
+) -> Result<(CheckpointHeader, Vec<ChunkHash>)> {
+    let file = File::open(path).context("Failed to open checkpoint file")?;
+    let reader = aead::stream::new_aes128gcm_reader(key, io::BufReader::new(file));
+    let mut input = IoReader(reader);
+    let header = CheckpointHeader::decode(&mut input).context("Failed to decode header")?;
+    if header.version > INCREMENTAL_CHECKPOINT_VERSION {
+        bail!("Checkpoint version {} is not supported", header.version);
+    }
+    while let Some(chunk) =
+        Option::<Vec<u8>>::decode(&mut input).context("Failed to decode chunk")?
+    {
+        chunks.insert(blake2_256(&chunk), chunk);
+    }
+    let manifest = Vec::decode(&mut input).context("Failed to decode manifest")?;
+    Ok((header, manifest))
+}
+
+fn base_filename_for(basedir: &str, block_number: BlockNumber) -> PathBuf {
+    Path::new(basedir).join(format!("{BASE_FILE}-{block_number:0>9}"))
+}
+
+fn delta_filename_for(basedir: &str, base: BlockNumber, block_number: BlockNumber) -> PathBuf {
+    Path::new(basedir).join(format!("{DELTA_FILE}-{base:0>9}-{block_number:0>9}"))
+}
+
+fn glob_sorted(pattern: &str) -> Result<Vec<(BlockNumber, PathBuf)>> {
+    let mut files: Vec<_> = glob::glob(pattern)?
+        .filter_map(|path| path.ok())
+        .filter_map(|path| {
+            let block = path.to_str()?.rsplit('-').next()?.parse().ok()?;
+            Some((block, path))
+        })
+        .collect();
+    files.sort_by_key(|(block, _)| *block);
+    Ok(files)
+}
+
+/// The files of a checkpoint chain, deltas sorted by block number.
+pub(crate) struct CheckpointChain {
+    base: (BlockNumber, PathBuf),
+    deltas: Vec<(BlockNumber, PathBuf)>,
+}
+
+impl CheckpointChain {
+    /// The block of the latest checkpoint in the chain.
+    pub(crate) fn block_number(&self) -> BlockNumber {
+        self.deltas.last().unwrap_or(&self.base).0
+    }
+
+    fn files(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(&self.base)
+            .chain(self.deltas.iter())
+            .map(|(_, path)| path.as_path())
+    }
+
+    fn remove(&self) {
+        for path in self.files() {
+            remove_file_with_info(path);
+        }
+    }
+}
+
+fn glob_chains(basedir: &str) -> Result<Vec<CheckpointChain>> {
+    let bases = glob_sorted(&format!("{basedir}/{BASE_FILE}-*"))?;
+    let mut chains = vec![];
+    for base in bases.into_iter().rev() {
+        let deltas = glob_sorted(&format!("{basedir}/{DELTA_FILE}-{:0>9}-*", base.0))?;
+        chains.push(CheckpointChain { base, deltas });
+    }
+    Ok(chains)
+}
+
+/// The latest incremental checkpoint in the storage path.
+pub(crate) fn latest_chain(basedir: &str) -> Result<Option<CheckpointChain>> {
+    Ok(glob_chains(basedir)?.into_iter().next())
+}
+
+fn remove_file_with_info(path: &Path) {
+    let info = crate::checkpoint_info_filename_for(&path.display().to_string());
+    for path in [path, Path::new(&info)] {
+        if let Err(err) = std::fs::remove_file(path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                error!("Failed to remove {}: {err}", path.display());
+            }
+        }
+    }
+}
+
+/// Keeps the latest `max_kept` chains and removes older ones.
+fn remove_outdated_chains(basedir: &str, max_kept: u32) -> Result<()> {
+    for chain in glob_chains(basedir)?
+        .into_iter()
+        .skip(max_kept.max(1) as usize)
+    {
+        for path in chain.files() {
+            remove_file_with_info(path);
+        }
+        info!("Removed checkpoint chain based on block {}", chain.base.0);
+    }
+    Ok(())
+}
+
+/// Removes all incremental checkpoints in the storage path.
+pub(crate) fn remove_all(basedir: &str) -> Result<()> {
+    for chain in glob_chains(basedir)? {
+        chain.remove();
+    }
+    Ok(())
+}
+
+/// The chunks already on disk in the current chain.
+#[derive(Default, Clone)]
+struct ChainState {
+    base: BlockNumber,
+    known: HashSet<ChunkHash>,
+    base_size: u64,
+    delta_size: u64,
+    deltas: u32,
+}
+
+impl ChainState {
+    fn needs_compaction(&self, max_deltas: u32) -> bool {
+        self.deltas >= max_deltas || self.delta_size > self.base_size / 2
+    }
+}
+
+struct PendingWrite {
+    /// Returns the size of the chunks written and the chain state including them.
+    handle: JoinHandle<Result<(u64, ChainState)>>,
+    block_number: BlockNumber,
+    is_base: bool,
+}
+
+/// The incremental checkpoint writer, shared by the RCU copies of Phactory.
+#[derive(Default)]
+pub(crate) struct IncrementalCheckpoints {
+    chain: Option<ChainState>,
+    pending: Option<PendingWrite>,
+}
+
+pub(crate) type SharedIncrementalCheckpoints = Arc<Mutex<IncrementalCheckpoints>>;
+
+impl IncrementalCheckpoints {
+    /// Collects the result of the previous write. Returns false if it is still in progress.
+    fn collect_finished(&mut self, basedir: &str, max_kept: u32) -> bool {
+        let Some(pending) = self.pending.take() else {
+            return true;
+        };
+        if !pending.handle.is_finished() {
+            self.pending = Some(pending);
+            return false;
+        }
+        let kind = if pending.is_base { "base" } else { "delta" };
+        match pending.handle.join() {
+            Ok(Ok((size, mut chain))) => {
+                info!(
+                    "Checkpoint {kind} at block {} written, {size} bytes",
+                    pending.block_number
+                );
+                if pending.is_base {
+                    chain.base_size = size;
+                    if let Err(err) = remove_outdated_chains(basedir, max_kept) {
+                        error!("Failed to remove outdated checkpoints: {err:?}");
+                    }
+                } else {
+                    chain.delta_size += size;
+                    chain.deltas += 1;
+                }
+                self.chain = Some(chain);
+            }
+            Ok(Err(err)) => {
+                error!("Failed to write checkpoint {kind}: {err:?}");
+                self.chain = None;
+            }
+            Err(_) => {
+                error!("Checkpoint writer panicked");
+                self.chain = None;
+            }
+        }
+        true
+    }
+}
+
+impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform> {
+    /// Starts writing an incremental checkpoint in background.
+    ///
+    /// Returns false if the previous checkpoint is still being written.
+    pub(crate) fn take_incremental_checkpoint(&mut self) -> Result<bool> {
+        if self.args.safe_mode_level > 0 {
+            bail!("Checkpoint is disabled in safe mode");
+        }
+        let basedir = self.args.storage_path.clone();
+        let shared = self.incremental_checkpoints.clone();
+        let mut writer = shared
+            .lock()
+            .map_err(|_| anyhow!("Checkpoint writer poisoned"))?;
+        if !writer.collect_finished(&basedir, self.args.max_checkpoint_files) {
+            info!("Previous checkpoint is still being written, skipped");
+            return Ok(false);
+        }
+        let (current_block, _) = self
+            .current_block()
+            .map_err(|err| anyhow!("Failed to get current block: {err:?}"))?;
+        let key = self
+            .system
+            .as_ref()
+            .context("Take checkpoint failed, runtime is not ready")?
+            .identity_key
+            .dump_secret_key();
+
+        let (chain, is_base) = match writer.chain.take() {
+            Some(chain) if !chain.needs_compaction(self.args.max_checkpoint_deltas) => {
+                (chain, false)
+            }
+            _ => (
+                ChainState {
+                    base: current_block,
+                    ..Default::default()
+                },
+                true,
+            ),
+        };
+        let path = if is_base {
+            base_filename_for(&basedir, current_block)
+        } else {
+            delta_filename_for(&basedir, chain.base, current_block)
+        };
+        let header = CheckpointHeader {
+            version: INCREMENTAL_CHECKPOINT_VERSION,
+            base: chain.base,
+            block_number: current_block,
+        };
+        let info = self.checkpoint_info()?;
+        let snapshot = self.clone();
+        let handle = std::thread::Builder::new()
+            .name("checkpoint-serializer".into())
+            .spawn(move || {
+                let (sender, receiver) = sync_channel(WRITE_QUEUE_LEN);
+                let handle = spawn_writer(
+                    path.clone(),
+                    derive_key_for_checkpoint(&key),
+                    header,
+                    receiver,
+                )?;
+                let mut chain = chain;
+                let mut delta_writer = DeltaWriter::new(&chain.known, sender);
+                let added = serialize_phactory_to_writer(&snapshot, &mut delta_writer)
+                    .and_then(|_| delta_writer.finish().map_err(Into::into));
+                // The writer exits once the sender is dropped.
+                let size = handle
+                    .join()
+                    .map_err(|_| anyhow!("Checkpoint writer panicked"))?;
+                let added = added.context("Failed to serialize checkpoint")?;
+                let size = size?;
+                info!(
+                    "Checkpoint at block {} serialized, {} new chunks written",
+                    header.block_number,
+                    added.len()
+                );
+                chain.known.extend(added);
+                // Written after the checkpoint file is in place, so an info file always describes
+                // a complete checkpoint.
+                write_checkpoint_info(&path.display().to_string(), &info)?;
+                Ok((size, chain))
+            })
+            .context("Failed to spawn checkpoint serializer")?;
+        writer.pending = Some(PendingWrite {
+            handle,
+            block_number: current_block,
+            is_base,
+        });
+        Ok(true)
+    }
+
+    /// Loads the latest checkpoint of the chain by replaying its deltas on top of the base.
+    ///
+    /// Replay stops at the first corrupted delta, and falls back to the earlier checkpoints if the
+    /// state fails to load. If `remove_corrupted_checkpoint` is set, the deltas not loaded are
+    /// removed, keeping the base and the deltas before them. The whole chain is removed only if
+    /// the base is corrupted.
+    pub(crate) fn restore_from_chain(
+        key: &[u8],
+        chain: CheckpointChain,
+        args: &InitArgs,
+    ) -> Result<Self> {
+        let base = chain.base.0;
+        match Self::replay_chain(key, &chain, args) {
+            Ok(factory) => Ok(factory),
+            Err(_err /*Don't leak it into the log*/) => {
+                error!("Failed to load checkpoint chain based on block {base}");
+                if args.remove_corrupted_checkpoint {
+                    error!("Removing checkpoint chain based on block {base}");
+                    chain.remove();
+                }
+                bail!("Failed to load checkpoint chain based on block {base}");
+            }
+        }
+    }
+
+    fn replay_chain(key: &[u8], chain: &CheckpointChain, args: &InitArgs) -> Result<Self> {
+        let key128 = derive_key_for_checkpoint(key);
+        let loaded = load_chain(key128, chain, args.remove_corrupted_checkpoint)?;
+        let (factory, index) =
+            restore_latest(&loaded, chain, args.remove_corrupted_checkpoint, |reader| {
+                Self::restore_from_state_reader(reader, args)
+            })?;
+        // Continue the chain unless a delta is skipped, in which case later deltas would be lost
+        // on the next load.
+        if loaded.complete && index + 1 == loaded.checkpoints.len() {
+            let mut state = loaded.state;
+            state.known = loaded.chunks.into_keys().collect();
+            factory
+                .incremental_checkpoints
+                .lock()
+                .map_err(|_| anyhow!("Checkpoint writer poisoned"))?
+                .chain = Some(state);
+        }
+        Ok(factory)
+    }
+}
+
+/// The checkpoints of a chain which are readable, from the base up to the first corrupted delta.
+struct LoadedChain {
+    chunks: HashMap<ChunkHash, Vec<u8>>,
+    /// The block number and the manifest of the base, followed by those of the deltas.
+    checkpoints: Vec<(BlockNumber, Vec<ChunkHash>)>,
+    state: ChainState,
+    /// Whether all the deltas in the chain are readable.
+    complete: bool,
+}
+
+/// Reads the base and the deltas of the chain. Deltas from the first corrupted one on are
+/// removed if `remove_corrupted` is set.
+fn load_chain(
+    key128: [u8; 16],
+    chain: &CheckpointChain,
+    remove_corrupted: bool,
+) -> Result<LoadedChain> {
+    let mut chunks = HashMap::new();
+    let (base_path, deltas) = (chain.base.1.as_path(), &chain.deltas);
+    let (header, manifest) = read_checkpoint_file(base_path, key128, &mut chunks)
+        .with_context(|| format!("Failed to load checkpoint {}", base_path.display()))?;
+    let mut state = ChainState {
+        base: header.block_number,
+        base_size: file_size(base_path),
+        ..Default::default()
+    };
+    let mut checkpoints = vec![(header.block_number, manifest)];
+    let mut complete = true;
+    for (index, (_, path)) in deltas.iter().enumerate() {
+        match read_checkpoint_file(path, key128, &mut chunks) {
+            Ok((header, manifest)) if header.base == state.base => {
+                checkpoints.push((header.block_number, manifest));
+                state.delta_size += file_size(path);
+                state.deltas += 1;
+            }
+            _ => {
+                error!(
+                    "Failed to load checkpoint delta {}, replay stopped at block {}",
+                    path.display(),
+                    checkpoints.last().expect("The base is always loaded").0
+                );
+                complete = false;
+                if remove_corrupted {
+                    for (_, path) in &deltas[index..] {
+                        error!("Removing {}", path.display());
+                        remove_file_with_info(path);
+                    }
+                }
+                break;
+            }
+        }
+    }
+    Ok(LoadedChain {
+        chunks,
+        checkpoints,
+        state,
+        complete,
+    })
+}
+
+/// Restores the latest checkpoint in the loaded chain, falling back to the earlier ones if it
+/// fails. Returns the restored value and the index of the checkpoint, 0 being the base.
+///
+/// The deltas failing to restore are removed if `remove_corrupted` is set.
+fn restore_latest<T>(
+    loaded: &LoadedChain,
+    chain: &CheckpointChain,
+    remove_corrupted: bool,
+    mut restore: impl FnMut(ManifestReader<std::slice::Iter<ChunkHash>>) -> Result<T>,
+) -> Result<(T, usize)> {
+    for (index, (block_number, manifest)) in loaded.checkpoints.iter().enumerate().rev() {
+        let result = match manifest
+            .iter()
+            .find(|hash| !loaded.chunks.contains_key(*hash))
+        {
+            Some(missing) => Err(anyhow!(
+                "Chunk {} missing in the checkpoint chain",
+                crate::hex(missing)
+            )),
+            None => {
+                info!(
+                    "Loading checkpoint at block {block_number} from {} chunks",
+                    manifest.len()
+                );
+                restore(ManifestReader {
+                    chunks: &loaded.chunks,
+                    manifest: manifest.iter(),
+                    current: &[],
+                })
+            }
+        };
+        match result {
+            Ok(value) => return Ok((value, index)),
+            Err(_err /*Don't leak it into the log*/) => {
+                error!("Failed to load checkpoint at block {block_number}");
+                if index > 0 && remove_corrupted {
+                    let path = &chain.deltas[index - 1].1;
+                    error!("Removing {}", path.display());
+                    remove_file_with_info(path);
+                }
+            }
+        }
+    }
+    bail!("No checkpoint in the chain can be loaded")
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or_default()
+}
+
+/// Reads the serialized state by concatenating the chunks in the manifest.
+struct ManifestReader<'a, I> {
+    chunks: &'a HashMap<ChunkHash, Vec<u8>>,
+    manifest: I,
+    current: &'a [u8],
+}
+
+impl<'a, I: Iterator<Item = &'a ChunkHash>> Read for ManifestReader<'a, I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            let Some(hash) = self.manifest.next() else {
+                return Ok(0);
+            };
+            self.current = self
+                .chunks
+                .get(hash)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Chunk missing"))?;
+        }
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current[..len]);
+        self.current = &self.current[len..];
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    fn write(
+        path: PathBuf,
+        header: CheckpointHeader,
+        known: &HashSet<ChunkHash>,
+        data: &[u8],
+    ) -> HashSet<ChunkHash> {
+        let (sender, receiver) = sync_channel(4);
+        let handle = spawn_writer(path, [1; 16], header, receiver).unwrap();
+        let mut writer = DeltaWriter::new(known, sender);
+        writer.write_all(data).unwrap();
+        let added = writer.finish().unwrap();
+        handle.join().unwrap().unwrap();
+        added
+    }
+
+    #[test]
+    fn local_changes_only_touch_nearby_chunks() {
+        let data = state(2 * 1024 * 1024, 1);
+        let chunks = |data: &[u8]| {
+            let mut chunker = Chunker::default();
+            let mut chunks = chunker.feed(data);
+            chunks.extend(chunker.finish());
+            chunks
+        };
+        let original = chunks(&data);
+        assert!(original.iter().all(|c| c.len() <= MAX_CHUNK_SIZE));
+        assert_eq!(original.concat(), data);
+
+        let mut changed = data.clone();
+        changed.splice(1000..1000, [0u8; 100]);
+        let changed = chunks(&changed);
+        let original: HashSet<_> = original.iter().map(|c| blake2_256(c)).collect();
+        let new = changed
+            .iter()
+            .filter(|c| !original.contains(&blake2_256(c)))
+            .count();
+        assert!(new <= 2, "{} chunks changed", new);
+    }
+
+    #[test]
+    fn deltas_replay_on_top_of_base() {
+        let dir = std::env::temp_dir().join(format!("phactory-ckpt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let basedir = dir.display().to_string();
+
+        let v1 = state(1024 * 1024, 1);
+        let mut v2 = v1.clone();
+        v2[500_000..500_100].copy_from_slice(&[0; 100]);
+
+        let header = |base, block_number| CheckpointHeader {
+            version: INCREMENTAL_CHECKPOINT_VERSION,
+            base,
+            block_number,
+        };
+        let mut known = write(
+            base_filename_for(&basedir, 10),
+            header(10, 10),
+            &Default::default(),
+            &v1,
+        );
+        let added = write(
+            delta_filename_for(&basedir, 10, 20),
+            header(10, 20),
+            &known,
+            &v2,
+        );
+        assert!(!added.is_empty() && added.len() <= 2);
+        known.extend(added);
+
+        let chain = latest_chain(&basedir).unwrap().unwrap();
+        assert_eq!(chain.block_number(), 20);
+        let mut chunks = HashMap::new();
+        let mut manifest = vec![];
+        for path in chain.files() {
+            manifest = read_checkpoint_file(path, [1; 16], &mut chunks).unwrap().1;
+        }
+        assert_eq!(chunks.keys().copied().collect::<HashSet<_>>(), known);
+        let mut replayed = vec![];
+        ManifestReader {
+            chunks: &chunks,
+            manifest: manifest.iter(),
+            current: &[],
+        }
+        .read_to_end(&mut replayed)
+        .unwrap();
+        assert_eq!(replayed, v2);
+
+        assert!(read_checkpoint_file(&chain.base.1, [2; 16], &mut chunks).is_err());
+        remove_all(&basedir).unwrap();
+        assert!(latest_chain(&basedir).unwrap().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn read_state(mut reader: ManifestReader<std::slice::Iter<ChunkHash>>) -> Result<Vec<u8>> {
+        let mut state = vec![];
+        reader.read_to_end(&mut state)?;
+        Ok(state)
+    }
+
+    #[test]
+    fn corrupted_delta_keeps_base_and_earlier_deltas() {
+        let dir =
+            std::env::temp_dir().join(format!("phactory-ckpt-corrupted-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let basedir = dir.display().to_string();
+
+        let v1 = state(1024 * 1024, 1);
+        let mut v2 = v1.clone();
+        v2[200_000..200_100].copy_from_slice(&[0; 100]);
+        let mut v3 = v2.clone();
+        v3[800_000..800_100].copy_from_slice(&[0; 100]);
+
+        let header = |base, block_number| CheckpointHeader {
+            version: INCREMENTAL_CHECKPOINT_VERSION,
+            base,
+            block_number,
+        };
+        let known = write(
+            base_filename_for(&basedir, 10),
+            header(10, 10),
+            &Default::default(),
+            &v1,
+        );
+        let write_deltas = || {
+            let mut known = known.clone();
+            known.extend(write(
+                delta_filename_for(&basedir, 10, 20),
+                header(10, 20),
+                &known,
+                &v2,
+            ));
+            write(
+                delta_filename_for(&basedir, 10, 30),
+                header(10, 30),
+                &known,
+                &v3,
+            );
+        };
+
+        // A corrupted middle delta
+        write_deltas();
+        let middle = delta_filename_for(&basedir, 10, 20);
+        let mut data = std::fs::read(&middle).unwrap();
+        let mid = data.len() / 2;
+        data[mid] ^= 1;
+        std::fs::write(&middle, data).unwrap();
+
+        let chain = latest_chain(&basedir).unwrap().unwrap();
+        assert_eq!(chain.block_number(), 30);
+        let loaded = load_chain([1; 16], &chain, true).unwrap();
+        assert!(!loaded.complete);
+        assert_eq!(loaded.checkpoints.len(), 1);
+        let (restored, index) = restore_latest(&loaded, &chain, true, read_state).unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(restored, v1);
+        // The base is kept, while the corrupted delta and the ones after it are removed
+        let chain = latest_chain(&basedir).unwrap().unwrap();
+        assert_eq!(chain.block_number(), 10);
+        assert!(chain.deltas.is_empty());
+
+        // The latest state fails to load
+        write_deltas();
+        let chain = latest_chain(&basedir).unwrap().unwrap();
+        let loaded = load_chain([1; 16], &chain, true).unwrap();
+        assert!(loaded.complete);
+        assert_eq!(loaded.checkpoints.len(), 3);
+        let (restored, index) = restore_latest(&loaded, &chain, true, |reader| {
+            let state = read_state(reader)?;
+            anyhow::ensure!(state != v3, "Bad state");
+            Ok(state)
+        })
+        .unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(restored, v2);
+        let chain = latest_chain(&basedir).unwrap().unwrap();
+        assert_eq!(chain.block_number(), 20);
+
+        // Nothing can be loaded
+        let loaded = load_chain([1; 16], &chain, true).unwrap();
+        assert!(restore_latest(&loaded, &chain, true, |_| -> Result<()> {
+            bail!("Bad state")
+        })
+        .is_err());
+        assert_eq!(latest_chain(&basedir).unwrap().unwrap().block_number(), 10);
+
+        remove_all(&basedir).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -36,10 +36,12 @@ use sp_core::{crypto::Pair, sr25519, H256};
 // use pink::InkModule;
 
 use phactory_api::{
-    blocks::{self, SyncCombinedHeadersReq, SyncParachainHeaderReq},
+    blocks::{
+        self, SyncCombinedHeadersReq, SyncParachainHeaderReq, SyncParachainHeaderWithBeefyReq,
+    },
     ecall_args::InitArgs,
     endpoints::EndpointType,
-    prpc::{self as pb, GetEndpointResponse, InitRuntimeResponse, NetworkConfig},
+    prpc::{self as pb, InitRuntimeResponse, NetworkConfig},
     storage_sync::{StorageSynchronizer, Synchronizer},
 };
 
@@ -55,9 +57,10 @@ use std::time::Instant;
 use types::Error;
 
 pub use chain::BlockNumber;
+pub use cluster_state::{CheckedClusterState, ClusterStateReader};
 pub use contracts::pink;
 pub use prpc_service::RpcService;
-pub use storage::ChainStorage;
+pub use storage::{ChainStorage, ChainValidator};
 pub use system::gk;
 pub use types::BlockInfo;
 pub type PRuntimeLightValidation = LightValidation<chain::Runtime>;
@@ -65,10 +68,16 @@ pub type PRuntimeLightValidation = LightValidation<chain::Runtime>;
 pub mod benchmark;
 
 mod bin_api_service;
+mod cluster_state;
 mod contracts;
 mod cryptography;
+pub mod handover;
 mod im_helpers;
+mod incremental_checkpoint;
 mod light_validation;
+pub mod metrics;
+#[cfg(any(test, feature = "mock-platform"))]
+pub mod mock_platform;
 mod prpc_service;
 mod secret_channel;
 mod storage;
@@ -87,7 +96,7 @@ struct RuntimeState {
     recv_mq: MessageDispatcher,
 
     // chain storage synchonizing
-    storage_synchronizer: Synchronizer<LightValidation<chain::Runtime>>,
+    storage_synchronizer: Synchronizer<ChainValidator>,
 
     // TODO.kevin: use a better serialization approach
     chain_storage: ChainStorage,
@@ -230,8 +239,6 @@ pub struct Phactory<Platform> {
     runtime_info: Option<InitRuntimeResponse>,
     runtime_state: Option<RuntimeState>,
     endpoints: BTreeMap<EndpointType, String>,
-    #[serde(skip)]
-    signed_endpoints: Option<GetEndpointResponse>,
     // The deserialzation of system requires the mq, which inside the runtime_state, to be ready.
     #[serde(skip)]
     system: Option<system::System<Platform>>,
@@ -243,10 +250,20 @@ pub struct Phactory<Platform> {
     #[serde(skip)]
     handover_last_challenge: Option<HandoverChallenge<chain::BlockNumber>>,
 
+    // state transfer to the pRuntime which the worker key is handed over to
+    #[serde(skip)]
+    handover_session: Option<handover::HandoverSession>,
+
+    // the worker has been handed over to another pRuntime
+    #[serde(skip)]
+    retired: bool,
+
     #[serde(skip)]
     #[serde(default = "Instant::now")]
     last_checkpoint: Instant,
     #[serde(skip)]
+    incremental_checkpoints: incremental_checkpoint::SharedIncrementalCheckpoints,
+    #[serde(skip)]
     query_scheduler: RequestScheduler<AccountId>,
 
     #[serde(default)]
@@ -298,10 +315,12 @@ impl<Platform: pal::Platform> Phactory<Platform> {
             runtime_state: None,
             system: None,
             endpoints: Default::default(),
-            signed_endpoints: None,
             handover_ecdh_key: None,
             handover_last_challenge: None,
+            handover_session: None,
+            retired: false,
             last_checkpoint: Instant::now(),
+            incremental_checkpoints: Default::default(),
             query_scheduler: Default::default(),
             netconfig: Default::default(),
             can_load_chain_state: false,
@@ -319,11 +338,13 @@ impl<Platform: pal::Platform> Phactory<Platform> {
         }
 
         self.can_load_chain_state = !system::gk_master_key_exists(&args.sealing_path);
+        self.retired = handover::is_retired(&args.storage_path);
         self.args = Arc::new(args);
         self.query_scheduler = create_query_scheduler(self.args.cores);
     }
 
     pub fn set_args(&mut self, args: InitArgs) {
+        self.retired = handover::is_retired(&args.storage_path);
         self.args = Arc::new(args);
         if let Some(system) = &mut self.system {
             system.sealing_path = self.args.sealing_path.clone();
@@ -543,15 +564,12 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
     }
 
     pub fn save_checkpoint_info(&self, filename: &str) -> anyhow::Result<()> {
-        let info = self.get_info();
-        let content =
-            serde_json::to_string_pretty(&info).context("Failed to serialize checkpoint info")?;
-        let info_filename = checkpoint_info_filename_for(filename);
-        let mut file =
-            File::create(info_filename).context("Failed to create checkpoint info file")?;
-        file.write_all(content.as_bytes())
-            .context("Failed to write checkpoint info file")?;
-        Ok(())
+        write_checkpoint_info(filename, &self.checkpoint_info()?)
+    }
+
+    fn checkpoint_info(&self) -> anyhow::Result<String> {
+        serde_json::to_string_pretty(&self.get_info())
+            .context("Failed to serialize checkpoint info")
     }
 
     pub fn take_checkpoint_to_writer<W: std::io::Write>(
@@ -580,6 +598,14 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         };
         let files = glob_checkpoint_files_sorted(&args.storage_path)
             .context("Glob checkpoint files failed")?;
+        let chain = incremental_checkpoint::latest_chain(&args.storage_path)
+            .context("Glob incremental checkpoint files failed")?;
+        if let Some(chain) = chain {
+            let latest_full = files.first().map(|(block, _)| *block);
+            if Some(chain.block_number()) >= latest_full {
+                return Self::restore_from_chain(&runtime_data.sk, chain, args).map(Some);
+            }
+        }
         if files.is_empty() {
             return Ok(None);
         }
@@ -634,9 +660,16 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
     ) -> anyhow::Result<Self> {
         let key128 = derive_key_for_checkpoint(key);
         let dec_reader = aead::stream::new_aes128gcm_reader(key128, reader);
+        Self::restore_from_state_reader(dec_reader, args)
+    }
+
+    fn restore_from_state_reader<R: std::io::Read>(
+        reader: R,
+        args: &InitArgs,
+    ) -> anyhow::Result<Self> {
         system::sidevm_config(args.cores as _);
 
-        let mut factory = deserialize_phactory_from_reader(dec_reader, args.safe_mode_level)
+        let mut factory = deserialize_phactory_from_reader(reader, args.safe_mode_level)
             .context("Failed to deserialize Phactory")?;
         factory.set_args(args.clone());
         factory
@@ -821,6 +854,14 @@ where
     Phactory::load_state(&mut deserializer, safe_mode_level).context("Failed to load factory")
 }
 
+fn write_checkpoint_info(filename: &str, content: &str) -> anyhow::Result<()> {
+    let info_filename = checkpoint_info_filename_for(filename);
+    let mut file = File::create(info_filename).context("Failed to create checkpoint info file")?;
+    file.write_all(content.as_bytes())
+        .context("Failed to write checkpoint info file")?;
+    Ok(())
+}
+
 fn serialize_phactory_to_writer<Platform, R>(phatory: &Phactory<Platform>, writer: R) -> Result<()>
 where
     Platform: Serialize + DeserializeOwned,
@@ -0,0 +1,785 @@
+//! BEEFY light client of the relay chain.
+//!
+//! Instead of following every relay chain header with GRANDPA justifications, the client only
+//! imports BEEFY signed commitments. A commitment carries the root of the relay chain MMR, whose
+//! leaves in turn commit the heads of all the parachains and the next BEEFY authority set. So a
+//! parachain head can be proved with a commitment, an MMR proof of one leaf and a merkle proof of
+//! the head in that leaf.
+//!
+//! The types below mirror the SCALE encoding of `sp_consensus_beefy` and `sp_mmr_primitives`.
+
+use ckb_merkle_mountain_range::{leaf_index_to_pos, Merge, MerkleProof};
+use derive_more::Display;
+use parity_scale_codec::{Decode, DecodeAll, Encode};
+use phactory_api::blocks::{
+    BeefyAuthorityId, BeefyParaHeadProof, BeefyState, MmrLeafProof, ParaHeadProof,
+};
+use phala_serde_more as more;
+use serde::{Deserialize, Serialize};
+use sp_core::{keccak_256, H256};
+
+pub type ValidatorSetId = u64;
+pub type Signature = [u8; 65];
+
+/// The id of the MMR root in the payload of a commitment.
+pub const MMR_ROOT_ID: [u8; 2] = *b"mh";
+
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum BeefyError {
+    #[display(fmt = "no BEEFY authority")]
+    EmptyAuthoritySet,
+    #[display(fmt = "invalid BEEFY authority key")]
+    InvalidAuthorityKey,
+    #[display(fmt = "failed to decode the finality proof")]
+    InvalidFinalityProof,
+    #[display(fmt = "commitment of block {_0} is not newer than the last one")]
+    OutdatedCommitment(u32),
+    #[display(fmt = "unexpected validator set id {_0}")]
+    UnexpectedValidatorSetId(ValidatorSetId),
+    #[display(fmt = "the next authority set is unknown")]
+    UnknownNextAuthoritySet,
+    #[display(fmt = "the next authorities mismatch the committed authority set")]
+    NextAuthoritiesMismatch,
+    #[display(fmt = "the number of signatures mismatches the validator set")]
+    ValidatorSetLenMismatch,
+    #[display(fmt = "invalid signature of authority {_0}")]
+    InvalidSignature(usize),
+    #[display(fmt = "not enough signatures, got {_0}, required {_1}")]
+    NotEnoughSignatures(usize, usize),
+    #[display(fmt = "no MMR root in the commitment")]
+    MissingMmrRoot,
+    #[display(fmt = "no BEEFY finalized MMR root yet")]
+    NoMmrRoot,
+    #[display(fmt = "invalid MMR leaf proof")]
+    InvalidMmrProof,
+    #[display(fmt = "invalid para head proof")]
+    InvalidParaHeadProof,
+}
+
+pub type Result<T, E = BeefyError> = core::result::Result<T, E>;
+
+/// The message signed by the BEEFY authorities.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct Commitment {
+    pub payload: Vec<([u8; 2], Vec<u8>)>,
+    pub block_number: u32,
+    pub validator_set_id: ValidatorSetId,
+}
+
+impl Commitment {
+    pub fn mmr_root(&self) -> Option<H256> {
+        let (_, value) = self.payload.iter().find(|(id, _)| id == &MMR_ROOT_ID)?;
+        H256::decode_all(&mut &value[..]).ok()
+    }
+}
+
+/// A commitment with the signatures of the authorities, in the order of the authority set.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SignedCommitment {
+    pub commitment: Commitment,
+    pub signatures: Vec<Option<Signature>>,
+}
+
+/// The compact encoding of `SignedCommitment`, where the present signatures are marked in a bit
+/// field from the most significant bit.
+#[derive(Encode, Decode)]
+struct CompactSignedCommitment {
+    commitment: Commitment,
+    signatures_from: Vec<u8>,
+    validator_set_len: u32,
+    signatures_compact: Vec<Signature>,
+}
+
+#[derive(Encode, Decode)]
+enum VersionedFinalityProof {
+    #[codec(index = 1)]
+    V1(CompactSignedCommitment),
+}
+
+impl SignedCommitment {
+    /// Decode from an encoded `VersionedFinalityProof`, as found in the BEEFY justifications.
+    pub fn decode_versioned(mut encoded: &[u8]) -> Result<Self> {
+        let VersionedFinalityProof::V1(compact) = VersionedFinalityProof::decode_all(&mut encoded)
+            .map_err(|_| BeefyError::InvalidFinalityProof)?;
+        let mut compact_signatures = compact.signatures_compact.into_iter();
+        let mut signatures = Vec::with_capacity(compact.validator_set_len as usize);
+        for i in 0..compact.validator_set_len as usize {
+            let byte = compact
+                .signatures_from
+                .get(i / 8)
+                .ok_or(BeefyError::InvalidFinalityProof)?;
+            if byte & (1 << (7 - i % 8)) == 0 {
+                signatures.push(None);
+            } else {
+                let signature = compact_signatures
+                    .next()
+                    .ok_or(BeefyError::InvalidFinalityProof)?;
+                signatures.push(Some(signature));
+            }
+        }
+        if compact_signatures.next().is_some() {
+            return Err(BeefyError::InvalidFinalityProof);
+        }
+        Ok(Self {
+            commitment: compact.commitment,
+            signatures,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn encode_versioned(&self) -> Vec<u8> {
+        let mut signatures_from = vec![0u8; (self.signatures.len() + 7) / 8];
+        for (i, signature) in self.signatures.iter().enumerate() {
+            if signature.is_some() {
+                signatures_from[i / 8] |= 1 << (7 - i % 8);
+            }
+        }
+        VersionedFinalityProof::V1(CompactSignedCommitment {
+            commitment: self.commitment.clone(),
+            signatures_from,
+            validator_set_len: self.signatures.len() as u32,
+            signatures_compact: self.signatures.iter().flatten().cloned().collect(),
+        })
+        .encode()
+    }
+}
+
+/// The BEEFY authority set committed in the MMR leaves.
+#[derive(Encode, Decode, Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct BeefyAuthoritySet {
+    pub id: ValidatorSetId,
+    pub len: u32,
+    /// Merkle root of the ethereum addresses of the authorities.
+    pub keyset_commitment: H256,
+}
+
+/// The MMR leaf of a relay chain block.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct MmrLeaf {
+    pub version: u8,
+    pub parent_number_and_hash: (u32, H256),
+    pub beefy_next_authority_set: BeefyAuthoritySet,
+    /// Merkle root of the heads of the parachains.
+    pub leaf_extra: H256,
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct BeefyClient {
+    #[serde(with = "more::scale_bytes")]
+    authorities: Vec<BeefyAuthorityId>,
+    validator_set_id: ValidatorSetId,
+    /// The next authority set, learned from a verified MMR leaf.
+    next_set: Option<BeefyAuthoritySet>,
+    /// The last BEEFY finalized relay chain block.
+    latest_block: u32,
+    /// The MMR root of `latest_block`.
+    mmr_root: Option<H256>,
+}
+
+impl BeefyClient {
+    pub fn new(
+        authorities: Vec<BeefyAuthorityId>,
+        validator_set_id: ValidatorSetId,
+    ) -> Result<Self> {
+        if authorities.is_empty() {
+            return Err(BeefyError::EmptyAuthoritySet);
+        }
+        Ok(Self {
+            authorities,
+            validator_set_id,
+            next_set: None,
+            latest_block: 0,
+            mmr_root: None,
+        })
+    }
+
+    pub fn state(&self) -> BeefyState {
+        BeefyState {
+            validator_set_id: self.validator_set_id,
+            latest_block: self.latest_block,
+        }
+    }
+
+    /// Verify the head of a parachain, or only import the finality proof and the MMR leaf if
+    /// `para_head` is `None`.
+    ///
+    /// Nothing is imported if any part of the proof is invalid.
+    pub fn verify_para_head(
+        &mut self,
+        para_id: u32,
+        para_head: Option<&[u8]>,
+        proof: BeefyParaHeadProof,
+    ) -> Result<()> {
+        let mut client = self.clone();
+        if let Some(finality_proof) = &proof.finality_proof {
+            client.import_finality_proof(finality_proof, proof.next_authorities)?;
+        }
+        let leaf = client.import_mmr_leaf(&proof.mmr_leaf, &proof.mmr_proof)?;
+        if let Some(para_head) = para_head {
+            let para_leaf = (para_id, para_head).encode();
+            if !verify_merkle_proof(leaf.leaf_extra, &para_leaf, &proof.para_head_proof) {
+                return Err(BeefyError::InvalidParaHeadProof);
+            }
+        }
+        *self = client;
+        Ok(())
+    }
+
+    fn import_finality_proof(
+        &mut self,
+        finality_proof: &[u8],
+        next_authorities: Option<Vec<BeefyAuthorityId>>,
+    ) -> Result<()> {
+        let signed = SignedCommitment::decode_versioned(finality_proof)?;
+        let commitment = &signed.commitment;
+        if self.mmr_root.is_some() && commitment.block_number <= self.latest_block {
+            return Err(BeefyError::OutdatedCommitment(commitment.block_number));
+        }
+        let next_authorities = if commitment.validator_set_id == self.validator_set_id {
+            None
+        } else if commitment.validator_set_id == self.validator_set_id + 1 {
+            let next_set = self
+                .next_set
+                .as_ref()
+                .ok_or(BeefyError::UnknownNextAuthoritySet)?;
+            let keys = next_authorities.ok_or(BeefyError::UnknownNextAuthoritySet)?;
+            if keys.len() != next_set.len as usize
+                || authority_set_root(&keys)? != next_set.keyset_commitment
+            {
+                return Err(BeefyError::NextAuthoritiesMismatch);
+            }
+            Some(keys)
+        } else {
+            return Err(BeefyError::UnexpectedValidatorSetId(
+                commitment.validator_set_id,
+            ));
+        };
+        let authorities = next_authorities.as_ref().unwrap_or(&self.authorities);
+        verify_signatures(&commitment.encode(), authorities, &signed.signatures)?;
+        let mmr_root = commitment.mmr_root().ok_or(BeefyError::MissingMmrRoot)?;
+
+        if let Some(keys) = next_authorities {
+            info!(
+                "BEEFY authority set changed to {} at block {}",
+                commitment.validator_set_id, commitment.block_number
+            );
+            self.authorities = keys;
+            self.validator_set_id = commitment.validator_set_id;
+            self.next_set = None;
+        }
+        self.latest_block = commitment.block_number;
+        self.mmr_root = Some(mmr_root);
+        Ok(())
+    }
+
+    fn import_mmr_leaf(&mut self, leaf: &[u8], proof: &MmrLeafProof) -> Result<MmrLeaf> {
+        let mmr_root = self.mmr_root.ok_or(BeefyError::NoMmrRoot)?;
+        if !verify_mmr_proof(mmr_root, leaf, proof) {
+            return Err(BeefyError::InvalidMmrProof);
+        }
+        let leaf = MmrLeaf::decode_all(&mut &leaf[..]).map_err(|_| BeefyError::InvalidMmrProof)?;
+        let next_set = &leaf.beefy_next_authority_set;
+        if next_set.id == self.validator_set_id + 1 {
+            self.next_set = Some(next_set.clone());
+        }
+        Ok(leaf)
+    }
+}
+
+/// The number of signatures required to finalize a block, the same as `sp_consensus_beefy`.
+fn threshold(authorities: usize) -> usize {
+    let faulty = authorities.saturating_sub(1) / 3;
+    authorities - faulty
+}
+
+fn verify_signatures(
+    message: &[u8],
+    authorities: &[BeefyAuthorityId],
+    signatures: &[Option<Signature>],
+) -> Result<()> {
+    if signatures.len() != authorities.len() {
+        return Err(BeefyError::ValidatorSetLenMismatch);
+    }
+    let hash = keccak_256(message);
+    let mut signed = 0;
+    for (i, (authority, signature)) in authorities.iter().zip(signatures).enumerate() {
+        let Some(signature) = signature else {
+            continue;
+        };
+        if recover(&hash, signature).as_ref() != Some(authority) {
+            return Err(BeefyError::InvalidSignature(i));
+        }
+        signed += 1;
+    }
+    let required = threshold(authorities.len());
+    if signed < required {
+        return Err(BeefyError::NotEnoughSignatures(signed, required));
+    }
+    Ok(())
+}
+
+fn recover(hash: &[u8; 32], signature: &Signature) -> Option<BeefyAuthorityId> {
+    let v = signature[64];
+    let recovery_id = libsecp256k1::RecoveryId::parse(if v >= 27 { v - 27 } else { v }).ok()?;
+    let signature = libsecp256k1::Signature::parse_overflowing_slice(&signature[..64]).ok()?;
+    let message = libsecp256k1::Message::parse(hash);
+    let public = libsecp256k1::recover(&message, &signature, &recovery_id).ok()?;
+    Some(public.serialize_compressed())
+}
+
+/// The ethereum address of an authority, which is what the MMR leaves commit.
+fn eth_address(authority: &BeefyAuthorityId) -> Result<[u8; 20]> {
+    let public = libsecp256k1::PublicKey::parse_compressed(authority)
+        .map_err(|_| BeefyError::InvalidAuthorityKey)?;
+    let hash = keccak_256(&public.serialize()[1..]);
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    Ok(address)
+}
+
+fn authority_set_root(authorities: &[BeefyAuthorityId]) -> Result<H256> {
+    let addresses = authorities
+        .iter()
+        .map(eth_address)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(merkle_root(&addresses))
+}
+
+fn keccak_merge(left: &H256, right: &H256) -> H256 {
+    let mut combined = [0u8; 64];
+    combined[..32].copy_from_slice(left.as_bytes());
+    combined[32..].copy_from_slice(right.as_bytes());
+    keccak_256(&combined).into()
+}
+
+/// The root of the keccak binary merkle tree built by `binary_merkle_tree::merkle_root`, where the
+/// last node of a level with odd number of nodes is promoted to the upper level.
+pub fn merkle_root<T: AsRef<[u8]>>(leaves: &[T]) -> H256 {
+    let mut level: Vec<H256> = leaves
+        .iter()
+        .map(|leaf| keccak_256(leaf.as_ref()).into())
+        .collect();
+    if level.is_empty() {
+        return H256::zero();
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => keccak_merge(left, right),
+                [single] => *single,
+                _ => unreachable!("chunks of 2"),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// Verify a proof generated by `binary_merkle_tree::merkle_proof`.
+pub fn verify_merkle_proof(root: H256, leaf: &[u8], proof: &ParaHeadProof) -> bool {
+    if proof.leaf_index >= proof.number_of_leaves {
+        return false;
+    }
+    let mut hash: H256 = keccak_256(leaf).into();
+    let mut position = proof.leaf_index;
+    let mut width = proof.number_of_leaves;
+    let mut items = proof.proof.iter();
+    while width > 1 {
+        if position % 2 == 1 {
+            let Some(left) = items.next() else {
+                return false;
+            };
+            hash = keccak_merge(left, &hash);
+        } else if position + 1 < width {
+            let Some(right) = items.next() else {
+                return false;
+            };
+            hash = keccak_merge(&hash, right);
+        }
+        // Otherwise the node is promoted to the upper level as is.
+        position /= 2;
+        width = (width + 1) / 2;
+    }
+    items.next().is_none() && hash == root
+}
+
+struct Keccak256Merge;
+
+impl Merge for Keccak256Merge {
+    type Item = H256;
+
+    fn merge(left: &H256, right: &H256) -> ckb_merkle_mountain_range::Result<H256> {
+        Ok(keccak_merge(left, right))
+    }
+}
+
+/// Verify the MMR proof of a single leaf, the same way as `pallet_mmr::verify_leaves_proof`.
+pub fn verify_mmr_proof(root: H256, leaf: &[u8], proof: &MmrLeafProof) -> bool {
+    let [leaf_index] = proof.leaf_indices[..] else {
+        return false;
+    };
+    if leaf_index >= proof.leaf_count {
+        return false;
+    }
+    let mmr_size = 2 * proof.leaf_count - proof.leaf_count.count_ones() as u64;
+    let leaf_hash: H256 = keccak_256(leaf).into();
+    MerkleProof::<H256, Keccak256Merge>::new(mmr_size, proof.items.clone())
+        .verify(root, vec![(leaf_index_to_pos(leaf_index), leaf_hash)])
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_merkle_mountain_range::util::{MemMMR, MemStore};
+    use hex_literal::hex;
+
+    /// BEEFY proofs of a relay chain, replayed from the initial authority set.
+    ///
+    /// `fixtures/beefy-recorded.scale` is recorded from a real relay chain with
+    /// `pherry --record-beefy-fixture`, see `recorded_fixture_replays`.
+    ///
+    /// `fixtures/beefy-synthetic.scale` is signed by `generate_fixture` with deterministic test
+    /// keys over an in-memory MMR. It is only used to forge the invalid proofs of the negative
+    /// cases.
+    #[derive(Encode, Decode)]
+    struct Fixture {
+        authorities: Vec<BeefyAuthorityId>,
+        validator_set_id: ValidatorSetId,
+        para_id: u32,
+        steps: Vec<Step>,
+    }
+
+    #[derive(Encode, Decode, Clone)]
+    struct Step {
+        para_head: Option<Vec<u8>>,
+        proof: BeefyParaHeadProof,
+    }
+
+    const PARA_ID: u32 = 2000;
+
+    fn fixture() -> Fixture {
+        Fixture::decode_all(&mut &include_bytes!("fixtures/beefy-synthetic.scale")[..])
+            .expect("Invalid fixture")
+    }
+
+    fn client_of(fixture: &Fixture) -> BeefyClient {
+        BeefyClient::new(fixture.authorities.clone(), fixture.validator_set_id).unwrap()
+    }
+
+    fn import(client: &mut BeefyClient, step: Step) -> Result<()> {
+        client.verify_para_head(PARA_ID, step.para_head.as_deref(), step.proof)
+    }
+
+    fn resign(step: &Step, f: impl FnOnce(&mut SignedCommitment)) -> Step {
+        let mut step = step.clone();
+        let finality_proof = step.proof.finality_proof.as_ref().unwrap();
+        let mut signed = SignedCommitment::decode_versioned(finality_proof).unwrap();
+        f(&mut signed);
+        step.proof.finality_proof = Some(signed.encode_versioned());
+        step
+    }
+
+    const RECORDED_FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/light_validation/fixtures/beefy-recorded.scale"
+    );
+
+    /// Follows the last authority set change of the relay chain and proves a parachain head with
+    /// the commitments, the validator sets, the MMR leaves and the para heads of the recording.
+    #[test]
+    #[ignore = "needs fixtures/beefy-recorded.scale, recorded with `pherry --record-beefy-fixture`"]
+    fn recorded_fixture_replays() {
+        let encoded = std::fs::read(RECORDED_FIXTURE).expect("The fixture is not recorded");
+        let fixture = Fixture::decode_all(&mut &encoded[..]).expect("Invalid fixture");
+        let mut client = client_of(&fixture);
+        let mut finalized = 0;
+        for step in fixture.steps {
+            let finality_proof = step.proof.finality_proof.as_ref().unwrap();
+            finalized = SignedCommitment::decode_versioned(finality_proof)
+                .unwrap()
+                .commitment
+                .block_number;
+            client
+                .verify_para_head(fixture.para_id, step.para_head.as_deref(), step.proof)
+                .unwrap();
+        }
+        assert_eq!(client.validator_set_id, fixture.validator_set_id + 1);
+        assert_eq!(client.latest_block, finalized);
+    }
+
+    #[test]
+    fn rejects_outdated_commitment() {
+        let fixture = fixture();
+        let mut client = client_of(&fixture);
+        import(&mut client, fixture.steps[0].clone()).unwrap();
+        assert_eq!(
+            import(&mut client, fixture.steps[0].clone()),
+            Err(BeefyError::OutdatedCommitment(4))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_para_head() {
+        let fixture = fixture();
+        let mut client = client_of(&fixture);
+        let mut step = fixture.steps[0].clone();
+        step.para_head = Some(b"forged head".to_vec());
+        assert_eq!(
+            import(&mut client, step),
+            Err(BeefyError::InvalidParaHeadProof)
+        );
+        // Nothing imported
+        assert_eq!(client, client_of(&fixture));
+    }
+
+    #[test]
+    fn rejects_invalid_signatures() {
+        let fixture = fixture();
+        let mut client = client_of(&fixture);
+        let step = resign(&fixture.steps[0], |signed| signed.signatures[1] = None);
+        assert_eq!(
+            import(&mut client, step),
+            Err(BeefyError::NotEnoughSignatures(2, 3))
+        );
+        let step = resign(&fixture.steps[0], |signed| {
+            signed.signatures[3] = signed.signatures[0];
+        });
+        assert_eq!(
+            import(&mut client, step),
+            Err(BeefyError::InvalidSignature(3))
+        );
+        let step = resign(&fixture.steps[0], |signed| {
+            signed.commitment.block_number += 1;
+        });
+        assert!(matches!(
+            import(&mut client, step),
+            Err(BeefyError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_unproved_authority_set_change() {
+        let fixture = fixture();
+        let mut client = client_of(&fixture);
+        for step in fixture.steps[..2].iter().cloned() {
+            import(&mut client, step).unwrap();
+        }
+        let mut step = fixture.steps[2].clone();
+        step.proof.next_authorities = None;
+        assert_eq!(
+            import(&mut client, step.clone()),
+            Err(BeefyError::UnknownNextAuthoritySet)
+        );
+        step.proof.next_authorities = Some(fixture.authorities.clone());
+        assert_eq!(
+            import(&mut client, step),
+            Err(BeefyError::NextAuthoritiesMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_leaf_without_finality_proof() {
+        let fixture = fixture();
+        let mut client = client_of(&fixture);
+        assert_eq!(
+            import(&mut client, fixture.steps[3].clone()),
+            Err(BeefyError::NoMmrRoot)
+        );
+    }
+
+    #[test]
+    fn merkle_root_matches_binary_merkle_tree() {
+        // Computed with `binary_merkle_tree::merkle_root::<Keccak256, _>`.
+        assert_eq!(merkle_root::<&[u8]>(&[]), H256::zero());
+        assert_eq!(
+            merkle_root(&[b"a"]),
+            H256(hex!(
+                "3ac225168df54212a25c1c01fd35bebfea408fdac2e31ddd6f80a4bbf9a5f1cb"
+            ))
+        );
+        assert_eq!(
+            merkle_root(&[b"a", b"b", b"c", b"d", b"e"]),
+            H256(hex!(
+                "1dd0d2a6ae466d665cb26e1a31f07c57ae5df7d2bc559cd5826d417be9141a5d"
+            ))
+        );
+    }
+
+    #[test]
+    fn merkle_proof_works() {
+        for n in 1..=9u32 {
+            let leaves: Vec<_> = (0..n).map(|i| i.encode()).collect();
+            let root = merkle_root(&leaves);
+            for i in 0..n {
+                let proof = merkle_proof(&leaves, i);
+                assert!(verify_merkle_proof(root, &leaves[i as usize], &proof));
+                assert!(!verify_merkle_proof(root, &n.encode(), &proof));
+            }
+        }
+    }
+
+    /// Same as `binary_merkle_tree::merkle_proof`.
+    fn merkle_proof<T: AsRef<[u8]>>(leaves: &[T], leaf_index: u32) -> ParaHeadProof {
+        let mut level: Vec<H256> = leaves
+            .iter()
+            .map(|leaf| keccak_256(leaf.as_ref()).into())
+            .collect();
+        let mut position = leaf_index as usize;
+        let mut proof = vec![];
+        while level.len() > 1 {
+            let sibling = position ^ 1;
+            if sibling < level.len() {
+                proof.push(level[sibling]);
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => keccak_merge(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            position /= 2;
+        }
+        ParaHeadProof {
+            proof,
+            number_of_leaves: leaves.len() as u32,
+            leaf_index,
+        }
+    }
+
+    fn secret(seed: u8) -> libsecp256k1::SecretKey {
+        libsecp256k1::SecretKey::parse(&[seed; 32]).unwrap()
+    }
+
+    fn authority_set(seeds: &[u8]) -> Vec<BeefyAuthorityId> {
+        seeds
+            .iter()
+            .map(|seed| {
+                libsecp256k1::PublicKey::from_secret_key(&secret(*seed)).serialize_compressed()
+            })
+            .collect()
+    }
+
+    fn para_head(block: u32) -> Vec<u8> {
+        format!("para head at {block}").into_bytes()
+    }
+
+    fn mmr_leaf(block: u32) -> MmrLeaf {
+        let (id, seeds) = if block <= 6 {
+            (1, &[11, 12, 13, 14][..])
+        } else {
+            (2, &[21, 22, 23, 24, 25][..])
+        };
+        let next_authorities = authority_set(seeds);
+        let para_heads = [
+            (1000u32, b"other para".to_vec()).encode(),
+            (PARA_ID, para_head(block)).encode(),
+        ];
+        MmrLeaf {
+            version: 0,
+            parent_number_and_hash: (block - 1, keccak_256(&(block - 1).encode()).into()),
+            beefy_next_authority_set: BeefyAuthoritySet {
+                id,
+                len: next_authorities.len() as u32,
+                keyset_commitment: authority_set_root(&next_authorities).unwrap(),
+            },
+            leaf_extra: merkle_root(&para_heads),
+        }
+    }
+
+    /// The MMR root at `block` and the proof of the leaf of `leaf_block`.
+    fn mmr_proof(block: u32, leaf_block: u32) -> (H256, MmrLeafProof) {
+        let mut mmr = MemMMR::<H256, Keccak256Merge>::new(0, MemStore::default());
+        let mut positions = vec![];
+        for b in 1..=block {
+            positions.push(mmr.push(keccak_256(&mmr_leaf(b).encode()).into()).unwrap());
+        }
+        let leaf_index = leaf_block - 1;
+        let proof = mmr.gen_proof(vec![positions[leaf_index as usize]]).unwrap();
+        let proof = MmrLeafProof {
+            leaf_indices: vec![leaf_index as u64],
+            leaf_count: block as u64,
+            items: proof.proof_items().to_vec(),
+        };
+        (mmr.get_root().unwrap(), proof)
+    }
+
+    fn finality_proof(block: u32, validator_set_id: u64, seeds: &[Option<u8>]) -> Vec<u8> {
+        let (mmr_root, _) = mmr_proof(block, block);
+        let commitment = Commitment {
+            payload: vec![(MMR_ROOT_ID, mmr_root.encode())],
+            block_number: block,
+            validator_set_id,
+        };
+        let message = libsecp256k1::Message::parse(&keccak_256(&commitment.encode()));
+        let signatures = seeds
+            .iter()
+            .map(|seed| {
+                let (signature, recovery_id) = libsecp256k1::sign(&message, &secret((*seed)?));
+                let mut bytes = [0u8; 65];
+                bytes[..64].copy_from_slice(&signature.serialize());
+                bytes[64] = recovery_id.serialize();
+                Some(bytes)
+            })
+            .collect();
+        SignedCommitment {
+            commitment,
+            signatures,
+        }
+        .encode_versioned()
+    }
+
+    fn step(
+        finalized: Option<(u32, u64, &[Option<u8>])>,
+        next_authorities: Option<&[u8]>,
+        mmr_block: u32,
+        leaf_block: u32,
+        with_para_head: bool,
+    ) -> Step {
+        let (_, mmr_proof) = mmr_proof(mmr_block, leaf_block);
+        let para_heads = [
+            (1000u32, b"other para".to_vec()).encode(),
+            (PARA_ID, para_head(leaf_block)).encode(),
+        ];
+        Step {
+            para_head: with_para_head.then(|| para_head(leaf_block)),
+            proof: BeefyParaHeadProof {
+                finality_proof: finalized
+                    .map(|(block, set_id, seeds)| finality_proof(block, set_id, seeds)),
+                next_authorities: next_authorities.map(authority_set),
+                mmr_leaf: mmr_leaf(leaf_block).encode(),
+                mmr_proof,
+                para_head_proof: merkle_proof(&para_heads, 1),
+            },
+        }
+    }
+
+    #[test]
+    #[ignore = "for generating the fixture"]
+    fn generate_fixture() {
+        // The relay chain has BEEFY set 0 of 4 authorities in blocks 1..=6 and set 1 of 4
+        // authorities in blocks 7..=12.
+        let set0 = [Some(1), Some(2), Some(3), None];
+        let set1 = [Some(11), None, Some(13), Some(14)];
+        let fixture = Fixture {
+            authorities: authority_set(&[1, 2, 3, 4]),
+            validator_set_id: 0,
+            para_id: PARA_ID,
+            steps: vec![
+                step(Some((4, 0, &set0)), None, 4, 4, true),
+                // Only follow the authority set.
+                step(Some((6, 0, &set0)), None, 6, 6, false),
+                step(Some((9, 1, &set1)), Some(&[11, 12, 13, 14]), 9, 9, true),
+                // Prove an earlier block with the last MMR root.
+                step(None, None, 9, 7, true),
+            ],
+        };
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/light_validation/fixtures/beefy-synthetic.scale"
+        );
+        std::fs::write(path, fixture.encode()).unwrap();
+    }
+}
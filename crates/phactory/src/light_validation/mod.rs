@@ -34,6 +34,7 @@
 // // Ensure we're `no_std` when compiling for Wasm.
 // #![cfg_attr(not(feature = "std"), no_std)]
 
+pub mod beefy;
 mod error;
 mod justification;
 pub mod storage_proof;
@@ -200,6 +201,13 @@ where
         Ok(())
     }
 
+    /// The last finalized header of the bridge.
+    pub fn last_finalized_header(&self, bridge_id: BridgeId) -> Option<&T::Header> {
+        self.tracked_bridges
+            .get(&bridge_id)
+            .map(|bridge| &bridge.last_finalized_block_header)
+    }
+
     pub fn validate_storage_proof(
         &self,
         state_root: T::Hash,
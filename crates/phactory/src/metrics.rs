@@ -0,0 +1,522 @@
+//! Rolling time series of the pRuntime workload.
+//!
+//! The metrics are kept in memory in one minute buckets and periodically sealed to the sealing
+//! directory, so that they survive pRuntime restarts. They are exported in the Prometheus text
+//! format by [`render_prometheus`].
+
+use crate::pal::Sealing;
+use pink::types::AccountId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info};
+
+/// Length of a bucket in seconds.
+const BUCKET_SECS: u64 = 60;
+/// Number of buckets kept for each series.
+const MAX_BUCKETS: usize = 60;
+/// Minimum interval between two persistences.
+const PERSIST_INTERVAL_SECS: u64 = 60;
+const METRICS_FILE: &str = "metrics.seal";
+
+/// Counters of a single contract.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ContractCounters {
+    pub queries: u64,
+    pub query_failures: u64,
+    pub query_time_ms: u64,
+    pub http_requests: u64,
+    pub http_failures: u64,
+    pub sidevm_messages: u64,
+    pub sidevm_dropped_messages: u64,
+    pub sidevm_starts: u64,
+}
+
+impl ContractCounters {
+    fn add(&mut self, other: &Self) {
+        self.queries += other.queries;
+        self.query_failures += other.query_failures;
+        self.query_time_ms += other.query_time_ms;
+        self.http_requests += other.http_requests;
+        self.http_failures += other.http_failures;
+        self.sidevm_messages += other.sidevm_messages;
+        self.sidevm_dropped_messages += other.sidevm_dropped_messages;
+        self.sidevm_starts += other.sidevm_starts;
+    }
+}
+
+/// Counters of the block synchronization.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BlockSyncCounters {
+    /// Number of blocks dispatched.
+    pub blocks: u64,
+    /// Total time spent on dispatching blocks.
+    pub dispatch_time_ms: u64,
+    /// Total lag between the block timestamp and the time it was dispatched.
+    pub lag_ms: u64,
+}
+
+impl BlockSyncCounters {
+    fn add(&mut self, other: &Self) {
+        self.blocks += other.blocks;
+        self.dispatch_time_ms += other.dispatch_time_ms;
+        self.lag_ms += other.lag_ms;
+    }
+}
+
+/// Counters collected in a single bucket.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Bucket {
+    /// Start time of the bucket in seconds since the UNIX epoch.
+    pub start: u64,
+    pub block_sync: BlockSyncCounters,
+    pub by_contract: BTreeMap<AccountId, ContractCounters>,
+}
+
+/// The metrics of a pRuntime instance.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Counters accumulated since the metrics were created.
+    pub block_sync_total: BlockSyncCounters,
+    /// Counters accumulated since the metrics were created, grouped by contract.
+    pub contracts_total: BTreeMap<AccountId, ContractCounters>,
+    /// The rolling time series, the latest bucket is at the back.
+    pub series: VecDeque<Bucket>,
+}
+
+impl Metrics {
+    fn current_bucket(&mut self, now: u64) -> &mut Bucket {
+        let start = now - now % BUCKET_SECS;
+        if self.series.back().map(|b| b.start) != Some(start) {
+            self.series.push_back(Bucket {
+                start,
+                ..Default::default()
+            });
+            while self.series.len() > MAX_BUCKETS {
+                self.series.pop_front();
+            }
+        }
+        self.series.back_mut().expect("pushed above")
+    }
+
+    fn update_contract(&mut self, contract: &AccountId, f: impl Fn(&mut ContractCounters)) {
+        f(self.contracts_total.entry(contract.clone()).or_default());
+        let bucket = self.current_bucket(now_secs());
+        f(bucket.by_contract.entry(contract.clone()).or_default());
+    }
+
+    fn record_query(&mut self, contract: &AccountId, time_ms: u64, success: bool) {
+        self.update_contract(contract, |c| {
+            c.queries += 1;
+            c.query_time_ms += time_ms;
+            if !success {
+                c.query_failures += 1;
+            }
+        })
+    }
+
+    fn record_http(&mut self, contract: &AccountId, status_code: u16) {
+        let success = status_code / 100 == 2;
+        self.update_contract(contract, |c| {
+            c.http_requests += 1;
+            if !success {
+                c.http_failures += 1;
+            }
+        })
+    }
+
+    fn record_block_dispatched(&mut self, counters: &BlockSyncCounters) {
+        self.block_sync_total.add(counters);
+        self.current_bucket(now_secs()).block_sync.add(counters);
+    }
+
+    /// The sum of the counters in the last `n` complete buckets.
+    fn recent(&self, n: usize) -> Bucket {
+        let now = now_secs();
+        let current = now - now % BUCKET_SECS;
+        let mut sum = Bucket::default();
+        for bucket in self
+            .series
+            .iter()
+            .rev()
+            .filter(|b| b.start < current)
+            .take_while(|b| b.start + (n as u64) * BUCKET_SECS >= current)
+        {
+            sum.block_sync.add(&bucket.block_sync);
+            for (contract, counters) in &bucket.by_contract {
+                sum.by_contract
+                    .entry(contract.clone())
+                    .or_default()
+                    .add(counters);
+            }
+        }
+        sum
+    }
+}
+
+struct State {
+    metrics: Metrics,
+    last_persisted: Option<Instant>,
+}
+
+static STATE: once_cell::sync::OnceCell<Mutex<State>> = once_cell::sync::OnceCell::new();
+
+fn state() -> &'static Mutex<State> {
+    STATE.get_or_init(|| {
+        Mutex::new(State {
+            metrics: Default::default(),
+            last_persisted: None,
+        })
+    })
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn with_metrics<T>(f: impl FnOnce(&mut Metrics) -> T) -> T {
+    f(&mut state().lock().unwrap().metrics)
+}
+
+/// Record a finished contract query.
+pub(crate) fn record_query(contract: &AccountId, time_ms: u64, success: bool) {
+    with_metrics(|m| m.record_query(contract, time_ms, success))
+}
+
+/// Record an outgoing HTTP request. A status code of 0 means the request failed to complete.
+pub(crate) fn record_http(contract: &AccountId, status_code: u16) {
+    with_metrics(|m| m.record_http(contract, status_code))
+}
+
+/// Record a message pushed to a sidevm instance.
+pub(crate) fn record_sidevm_message(contract: &AccountId, dropped: bool) {
+    with_metrics(|m| {
+        m.update_contract(contract, |c| {
+            c.sidevm_messages += 1;
+            if dropped {
+                c.sidevm_dropped_messages += 1;
+            }
+        })
+    })
+}
+
+/// Record a sidevm instance (re)start.
+pub(crate) fn record_sidevm_start(contract: &AccountId) {
+    with_metrics(|m| m.update_contract(contract, |c| c.sidevm_starts += 1))
+}
+
+/// Record a dispatched block.
+pub(crate) fn record_block_dispatched(dispatch_time_ms: u64, block_timestamp_ms: u64) {
+    let lag_ms = now_ms().saturating_sub(block_timestamp_ms);
+    let counters = BlockSyncCounters {
+        blocks: 1,
+        dispatch_time_ms,
+        lag_ms,
+    };
+    with_metrics(|m| m.record_block_dispatched(&counters))
+}
+
+/// The share of the query capacity used in the last 5 minutes, in permill.
+pub(crate) fn load_permill(cores: u32) -> u32 {
+    const WINDOW_MINUTES: u64 = 5;
+    let query_time_ms: u64 = with_metrics(|m| {
+        m.recent(WINDOW_MINUTES as usize)
+            .by_contract
+            .values()
+            .map(|c| c.query_time_ms)
+            .sum()
+    });
+    let capacity_ms = WINDOW_MINUTES * BUCKET_SECS * 1000 * cores.max(1) as u64;
+    (query_time_ms * 1000 / capacity_ms).min(1000) as u32
+}
+
+/// Get a copy of the current metrics.
+pub fn snapshot() -> Metrics {
+    with_metrics(|m| m.clone())
+}
+
+fn metrics_file(sealing_path: &str) -> PathBuf {
+    Path::new(sealing_path).join(METRICS_FILE)
+}
+
+fn unseal(platform: &impl Sealing, path: &Path) -> anyhow::Result<Option<Metrics>> {
+    let Some(content) = platform.unseal_data(path).map_err(Into::into)? else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_slice(&content)?))
+}
+
+fn seal(platform: &impl Sealing, path: &Path, metrics: &Metrics) -> anyhow::Result<()> {
+    let content = serde_json::to_vec(metrics)?;
+    platform.seal_data(path, &content).map_err(Into::into)
+}
+
+/// Load the metrics sealed in the sealing directory, if any.
+pub fn load(platform: &impl Sealing, sealing_path: &str) {
+    let path = metrics_file(sealing_path);
+    match unseal(platform, &path) {
+        Ok(Some(metrics)) => {
+            info!("Loaded metrics from {}", path.display());
+            state().lock().unwrap().metrics = metrics;
+        }
+        Ok(None) => {}
+        Err(err) => {
+            error!("Failed to load metrics from {}: {err:?}", path.display());
+        }
+    }
+}
+
+/// Seal the metrics to the sealing directory if it has not been done recently.
+pub(crate) fn maybe_persist(platform: &impl Sealing, sealing_path: &str) {
+    let metrics = {
+        let mut state = state().lock().unwrap();
+        if let Some(last) = state.last_persisted {
+            if last.elapsed().as_secs() < PERSIST_INTERVAL_SECS {
+                return;
+            }
+        }
+        state.last_persisted = Some(Instant::now());
+        state.metrics.clone()
+    };
+    let path = metrics_file(sealing_path);
+    if let Err(err) = seal(platform, &path, &metrics) {
+        error!("Failed to persist metrics to {}: {err:?}", path.display());
+    }
+}
+
+/// Render the metrics in the Prometheus text exposition format.
+///
+/// Counters are accumulated since the metrics were created, while the `*_1m` and `*_1h` gauges
+/// are computed from the rolling time series.
+pub fn render_prometheus() -> String {
+    with_metrics(|m| m.render_prometheus())
+}
+
+impl Metrics {
+    fn render_prometheus(&self) -> String {
+        let last_1m = self.recent(1);
+        let last_1h = self.recent(60);
+        let mut out = String::new();
+
+        macro_rules! contract_metric {
+            ($name: literal, $type: literal, $help: literal, $src: expr, $field: ident) => {{
+                let _ = writeln!(out, "# HELP {} {}", $name, $help);
+                let _ = writeln!(out, "# TYPE {} {}", $name, $type);
+                for (contract, counters) in $src.iter() {
+                    let _ = writeln!(
+                        out,
+                        "{}{{contract=\"0x{}\"}} {}",
+                        $name,
+                        hex_fmt::HexFmt(contract),
+                        counters.$field
+                    );
+                }
+            }};
+        }
+        macro_rules! sync_metric {
+            ($name: literal, $type: literal, $help: literal, $value: expr) => {{
+                let _ = writeln!(out, "# HELP {} {}", $name, $help);
+                let _ = writeln!(out, "# TYPE {} {}", $name, $type);
+                let _ = writeln!(out, "{} {}", $name, $value);
+            }};
+        }
+
+        let totals = &self.contracts_total;
+        contract_metric!(
+            "pruntime_contract_queries_total",
+            "counter",
+            "Number of contract queries.",
+            totals,
+            queries
+        );
+        contract_metric!(
+            "pruntime_contract_query_failures_total",
+            "counter",
+            "Number of failed contract queries.",
+            totals,
+            query_failures
+        );
+        contract_metric!(
+            "pruntime_contract_query_time_ms_total",
+            "counter",
+            "Time spent on contract queries in milliseconds.",
+            totals,
+            query_time_ms
+        );
+        contract_metric!(
+            "pruntime_contract_http_requests_total",
+            "counter",
+            "Number of outgoing HTTP requests.",
+            totals,
+            http_requests
+        );
+        contract_metric!(
+            "pruntime_contract_http_failures_total",
+            "counter",
+            "Number of failed outgoing HTTP requests.",
+            totals,
+            http_failures
+        );
+        contract_metric!(
+            "pruntime_contract_sidevm_messages_total",
+            "counter",
+            "Number of messages pushed to sidevm.",
+            totals,
+            sidevm_messages
+        );
+        contract_metric!(
+            "pruntime_contract_sidevm_dropped_messages_total",
+            "counter",
+            "Number of messages dropped by sidevm.",
+            totals,
+            sidevm_dropped_messages
+        );
+        contract_metric!(
+            "pruntime_contract_sidevm_starts_total",
+            "counter",
+            "Number of sidevm instance starts.",
+            totals,
+            sidevm_starts
+        );
+
+        let last_1m = &last_1m.by_contract;
+        contract_metric!(
+            "pruntime_contract_queries_1m",
+            "gauge",
+            "Number of contract queries in the last complete minute.",
+            last_1m,
+            queries
+        );
+        contract_metric!(
+            "pruntime_contract_http_requests_1m",
+            "gauge",
+            "Number of outgoing HTTP requests in the last complete minute.",
+            last_1m,
+            http_requests
+        );
+        contract_metric!(
+            "pruntime_contract_http_failures_1m",
+            "gauge",
+            "Number of failed outgoing HTTP requests in the last complete minute.",
+            last_1m,
+            http_failures
+        );
+        contract_metric!(
+            "pruntime_contract_sidevm_messages_1m",
+            "gauge",
+            "Number of messages pushed to sidevm in the last complete minute.",
+            last_1m,
+            sidevm_messages
+        );
+
+        let sync = &self.block_sync_total;
+        sync_metric!(
+            "pruntime_blocks_dispatched_total",
+            "counter",
+            "Number of dispatched blocks.",
+            sync.blocks
+        );
+        sync_metric!(
+            "pruntime_block_dispatch_time_ms_total",
+            "counter",
+            "Time spent on dispatching blocks in milliseconds.",
+            sync.dispatch_time_ms
+        );
+        sync_metric!(
+            "pruntime_block_lag_ms_total",
+            "counter",
+            "Sum of the lags between block timestamps and their dispatch in milliseconds.",
+            sync.lag_ms
+        );
+        let recent = &last_1h.block_sync;
+        let avg = |total: u64| total.checked_div(recent.blocks).unwrap_or_default();
+        sync_metric!(
+            "pruntime_blocks_dispatched_1h",
+            "gauge",
+            "Number of blocks dispatched in the last hour.",
+            recent.blocks
+        );
+        sync_metric!(
+            "pruntime_block_dispatch_time_ms_avg_1h",
+            "gauge",
+            "Average time spent on dispatching a block in the last hour.",
+            avg(recent.dispatch_time_ms)
+        );
+        sync_metric!(
+            "pruntime_block_lag_ms_avg_1h",
+            "gauge",
+            "Average lag between block timestamps and their dispatch in the last hour.",
+            avg(recent.lag_ms)
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_bounded() {
+        let mut metrics = Metrics::default();
+        for i in 0..(MAX_BUCKETS as u64 + 10) {
+            metrics.current_bucket(i * BUCKET_SECS).block_sync.blocks += 1;
+        }
+        assert_eq!(metrics.series.len(), MAX_BUCKETS);
+        assert_eq!(metrics.series.front().unwrap().start, 10 * BUCKET_SECS);
+        metrics.current_bucket((MAX_BUCKETS as u64 + 9) * BUCKET_SECS + 1);
+        assert_eq!(metrics.series.len(), MAX_BUCKETS);
+    }
+
+    #[test]
+    fn prometheus_output_contains_contract_counters() {
+        let mut metrics = Metrics::default();
+        let contract = AccountId::new([0xab; 32]);
+        metrics.record_http(&contract, 200);
+        metrics.record_http(&contract, 500);
+        let output = metrics.render_prometheus();
+        let line = format!(
+            "pruntime_contract_http_failures_total{{contract=\"0x{}\"}} 1",
+            hex_fmt::HexFmt(&contract)
+        );
+        assert!(output.contains(&line));
+        assert!(output.contains("# TYPE pruntime_blocks_dispatched_total counter"));
+    }
+
+    #[test]
+    fn metrics_are_sealed() {
+        use crate::mock_platform::MockPlatform;
+
+        let path =
+            std::env::temp_dir().join(format!("phactory-metrics-sealed-{}", std::process::id()));
+        let mut metrics = Metrics::default();
+        let contract = AccountId::new([0xcd; 32]);
+        metrics.record_query(&contract, 5, false);
+
+        let platform = MockPlatform::new(b"alice");
+        seal(&platform, &path, &metrics).unwrap();
+        let content = std::fs::read(&path).unwrap();
+        assert!(serde_json::from_slice::<Metrics>(&content).is_err());
+
+        let restored = unseal(&platform, &path).unwrap().unwrap();
+        assert_eq!(restored.contracts_total[&contract].query_failures, 1);
+        assert!(unseal(&MockPlatform::new(b"bob"), &path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(unseal(&platform, &path).unwrap().is_none());
+    }
+}
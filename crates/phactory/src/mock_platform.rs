@@ -0,0 +1,323 @@
+//! A software platform to run phactory without SGX, for integration tests.
+//!
+//! Sealing keys and the measurement are derived from a seed, so pRuntimes started with the same
+//! seed can unseal each other's data. The attestation reports it creates are
+//! [`AttestationReport::Mock`], which are only accepted by chains with `MockAttestationEnabled` set
+//! in the registry pallet.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context as _, Result};
+use parity_scale_codec::Encode;
+use phala_crypto::aead;
+use phala_types::{AttestationProvider, AttestationReport};
+use serde::{Deserialize, Serialize};
+use sp_core::hashing::blake2_256;
+
+use pal::{AppInfo, AppVersion, Machine, MemoryStats, MemoryUsage, Sealing, RA};
+
+/// A clock that can be frozen and moved by tests.
+///
+/// It follows the system clock until [`MockClock::set`] is called.
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    /// Unix timestamp in seconds, 0 means following the system clock.
+    frozen_at: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Current unix timestamp in seconds.
+    pub fn now(&self) -> u64 {
+        match self.frozen_at.load(Ordering::Relaxed) {
+            0 => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("System time before unix epoch")
+                .as_secs(),
+            now => now,
+        }
+    }
+
+    /// Freeze the clock at given unix timestamp in seconds.
+    pub fn set(&self, now: u64) {
+        self.frozen_at.store(now, Ordering::Relaxed);
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        self.set(self.now() + duration.as_secs());
+    }
+
+    /// Unfreeze the clock.
+    pub fn follow_system(&self) {
+        self.set(0);
+    }
+}
+
+/// Neither the seed nor the sealing key is serialized. On deserialization, the seed is read from
+/// the environment again, in the same way as the platform pRuntime starts with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "MockPlatformConfig")]
+pub struct MockPlatform {
+    #[serde(skip)]
+    seed: Vec<u8>,
+    #[serde(skip)]
+    sealing_key: [u8; 32],
+    measurement: Vec<u8>,
+    machine_id: Vec<u8>,
+    cpu_core_num: u32,
+    cpu_feature_level: u32,
+    #[serde(skip)]
+    clock: MockClock,
+}
+
+#[derive(Deserialize)]
+struct MockPlatformConfig {
+    measurement: Vec<u8>,
+    machine_id: Vec<u8>,
+    cpu_core_num: u32,
+    cpu_feature_level: u32,
+}
+
+impl From<MockPlatformConfig> for MockPlatform {
+    fn from(config: MockPlatformConfig) -> Self {
+        let seed = seed_from_env();
+        Self {
+            sealing_key: derive_key(b"phactory-mock-sealing-key", &seed),
+            seed,
+            measurement: config.measurement,
+            machine_id: config.machine_id,
+            cpu_core_num: config.cpu_core_num,
+            cpu_feature_level: config.cpu_feature_level,
+            clock: MockClock::default(),
+        }
+    }
+}
+
+fn seed_from_env() -> Vec<u8> {
+    std::env::var("PHACTORY_MOCK_SEED")
+        .unwrap_or_else(|_| "mock".into())
+        .into_bytes()
+}
+
+fn derive_key(domain: &[u8], seed: &[u8]) -> [u8; 32] {
+    blake2_256(&[domain, seed].concat())
+}
+
+impl MockPlatform {
+    pub fn new(seed: &[u8]) -> Self {
+        Self {
+            seed: seed.to_vec(),
+            sealing_key: derive_key(b"phactory-mock-sealing-key", seed),
+            measurement: derive_key(b"phactory-mock-measurement", seed).to_vec(),
+            machine_id: derive_key(b"phactory-mock-machine-id", seed).to_vec(),
+            cpu_core_num: 1,
+            cpu_feature_level: 1,
+            clock: MockClock::default(),
+        }
+    }
+
+    /// Create a platform with the seed from env `PHACTORY_MOCK_SEED`.
+    pub fn from_env() -> Self {
+        Self::new(&seed_from_env())
+    }
+
+    /// Override the measurement, which is used as the runtime hash in the attestation reports.
+    pub fn with_measurement(mut self, measurement: Vec<u8>) -> Self {
+        self.measurement = measurement;
+        self
+    }
+
+    pub fn with_machine_id(mut self, machine_id: Vec<u8>) -> Self {
+        self.machine_id = machine_id;
+        self
+    }
+
+    pub fn with_cpu(mut self, core_num: u32, feature_level: u32) -> Self {
+        self.cpu_core_num = core_num;
+        self.cpu_feature_level = feature_level;
+        self
+    }
+
+    /// Share the clock with the test, so it can control the timestamp of the reports.
+    pub fn with_clock(mut self, clock: MockClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+}
+
+impl Sealing for MockPlatform {
+    type SealError = anyhow::Error;
+    type UnsealError = anyhow::Error;
+
+    fn seal_data(&self, path: impl AsRef<Path>, data: &[u8]) -> Result<()> {
+        let iv = crate::generate_random_iv();
+        let mut sealed = data.to_vec();
+        aead::encrypt(&iv, &self.sealing_key, &mut sealed)
+            .map_err(|err| anyhow!("Failed to seal data: {err:?}"))?;
+        std::fs::write(path, [&iv[..], &sealed].concat())?;
+        Ok(())
+    }
+
+    fn unseal_data(&self, path: impl AsRef<Path>) -> Result<Option<Vec<u8>>> {
+        let mut sealed = match std::fs::read(path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            other => other?,
+        };
+        if sealed.len() < aead::IV_BYTES {
+            return Err(anyhow!("Sealed data too short"));
+        }
+        let (iv, data) = sealed.split_at_mut(aead::IV_BYTES);
+        let data = aead::decrypt(iv, &self.sealing_key, data)
+            .map_err(|err| anyhow!("Failed to unseal data: {err:?}"))
+            .context("Sealed by a platform with another seed?")?;
+        Ok(Some(data.to_vec()))
+    }
+}
+
+impl RA for MockPlatform {
+    type Error = anyhow::Error;
+
+    fn create_attestation_report(
+        &self,
+        provider: Option<AttestationProvider>,
+        data: &[u8],
+        _timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        match provider {
+            Some(AttestationProvider::Mock) => {
+                let report = Some(AttestationReport::Mock {
+                    runtime_hash: self.measurement.clone(),
+                    report_data: data.to_vec(),
+                    timestamp: self.clock.now(),
+                });
+                Ok(report.encode())
+            }
+            None => Ok(None::<AttestationProvider>.encode()),
+            _ => Err(anyhow!(
+                "Attestation provider `{provider:?}` is not supported on the mock platform"
+            )),
+        }
+    }
+
+    fn quote_test(&self, provider: Option<AttestationProvider>) -> Result<()> {
+        match provider {
+            Some(AttestationProvider::Mock) | None => Ok(()),
+            _ => Err(anyhow!(
+                "Attestation provider `{provider:?}` is not supported on the mock platform"
+            )),
+        }
+    }
+
+    fn measurement(&self) -> Option<Vec<u8>> {
+        Some(self.measurement.clone())
+    }
+}
+
+impl Machine for MockPlatform {
+    fn machine_id(&self) -> Vec<u8> {
+        self.machine_id.clone()
+    }
+
+    fn cpu_core_num(&self) -> u32 {
+        self.cpu_core_num
+    }
+
+    fn cpu_feature_level(&self) -> u32 {
+        self.cpu_feature_level
+    }
+}
+
+impl MemoryStats for MockPlatform {
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::default()
+    }
+}
+
+impl AppInfo for MockPlatform {
+    fn app_version() -> AppVersion {
+        AppVersion {
+            major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or_default(),
+            minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or_default(),
+            patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parity_scale_codec::Decode;
+
+    #[test]
+    fn sealing_depends_on_seed() {
+        let path =
+            std::env::temp_dir().join(format!("phactory-mock-sealed-{}", std::process::id()));
+        let platform = MockPlatform::new(b"alice");
+        platform.seal_data(&path, b"secret").unwrap();
+        assert_eq!(
+            MockPlatform::new(b"alice").unseal_data(&path).unwrap(),
+            Some(b"secret".to_vec())
+        );
+        assert!(MockPlatform::new(b"bob").unseal_data(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(platform.unseal_data(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn seed_is_not_serialized() {
+        let platform = MockPlatform::from_env().with_cpu(4, 2);
+        let serialized = serde_json::to_string(&platform).unwrap();
+        assert!(!serialized.contains("seed"));
+        assert!(!serialized.contains("sealing_key"));
+        let restored: MockPlatform = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(restored.sealing_key, platform.sealing_key);
+        assert_eq!(restored.cpu_core_num(), 4);
+
+        // The seed is taken from the environment rather than the serialized platform
+        let serialized = serde_json::to_string(&MockPlatform::new(b"alice")).unwrap();
+        let restored: MockPlatform = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(restored.seed, seed_from_env());
+        assert_eq!(restored.sealing_key, platform.sealing_key);
+
+        let path = std::env::temp_dir().join(format!(
+            "phactory-mock-sealed-restored-{}",
+            std::process::id()
+        ));
+        platform.seal_data(&path, b"secret").unwrap();
+        assert_eq!(
+            restored.unseal_data(&path).unwrap(),
+            Some(b"secret".to_vec())
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn report_follows_the_clock() {
+        let clock = MockClock::default();
+        clock.set(1000);
+        let platform = MockPlatform::new(b"alice").with_clock(clock.clone());
+        clock.advance(Duration::from_secs(10));
+        let report = platform
+            .create_attestation_report(Some(AttestationProvider::Mock), &[1; 32], Duration::ZERO)
+            .unwrap();
+        let report = Option::<AttestationReport>::decode(&mut &report[..]).unwrap();
+        assert_eq!(
+            report,
+            Some(AttestationReport::Mock {
+                runtime_hash: platform.measurement().unwrap(),
+                report_data: vec![1; 32],
+                timestamp: 1010,
+            })
+        );
+        assert!(platform
+            .create_attestation_report(Some(AttestationProvider::Ias), &[], Duration::ZERO)
+            .is_err());
+    }
+}
@@ -1,7 +1,6 @@
 use std::borrow::Cow;
 use std::convert::TryFrom;
 use std::future::Future;
-use std::io::Read;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
@@ -19,18 +18,21 @@ use pb::{
 };
 use phactory_api::blocks::StorageState;
 use phactory_api::{blocks, crypto, endpoints::EndpointType, prpc as pb};
-use phala_crypto::ecdh::{self, EcdhPublicKey};
+use phala_crypto::ecdh;
 use phala_crypto::{
     key_share,
     sr25519::{Persistence, KDF},
 };
+use phala_dcap::Quote as DcapQuote;
 use phala_mq::MessageOrigin;
 use phala_pallets::utils::attestation::{validate as validate_attestation_report, IasFields};
 use phala_types::contract::contract_id_preimage;
 use phala_types::{
-    contract, messaging::EncryptedKey, wrap_content_to_sign, AttestationReport,
-    ChallengeHandlerInfo, EncryptedWorkerKey, HandoverChallenge, SignedContentType,
-    VersionedWorkerEndpoints, WorkerEndpointPayload, WorkerPublicKey, WorkerRegistrationInfoV2,
+    contract,
+    messaging::{EncryptedKey, SystemEvent},
+    wrap_content_to_sign, AttestationReport, ChallengeHandlerInfo, EncryptedWorkerKey,
+    HandoverChallenge, SignedContentType, VersionedWorkerEndpoints, WorkerCapabilities,
+    WorkerEndpointPayload, WorkerEndpointsV2, WorkerPublicKey, WorkerRegistrationInfoV2,
 };
 use sp_application_crypto::UncheckedFrom;
 use tracing::{error, info};
@@ -53,6 +55,34 @@ fn now() -> u64 {
     now.as_secs()
 }
 
+/// A contract query request verified and decrypted.
+struct OpenedQuery {
+    origin: Option<chain::AccountId>,
+    contract_id: AccountId,
+    payload: Vec<u8>,
+    nonce: [u8; 32],
+    ecdh_key: ecdh::EcdhKey,
+    pubkey: ecdh::EcdhPublicKey,
+}
+
+impl OpenedQuery {
+    /// Encrypt the response to the query.
+    fn seal_response(&self, response: Vec<u8>) -> RpcResult<pb::ContractQueryResponse> {
+        let response = contract::ContractQueryResponse {
+            nonce: self.nonce,
+            result: contract::Data(response),
+        };
+        let encrypted_resp = crypto::EncryptedData::encrypt(
+            &self.ecdh_key,
+            &self.pubkey,
+            crate::generate_random_iv(),
+            &response.encode(),
+        )
+        .map_err(from_debug)?;
+        Ok(pb::ContractQueryResponse::new(encrypted_resp))
+    }
+}
+
 impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform> {
     fn runtime_state(&mut self) -> RpcResult<&mut RuntimeState> {
         self.runtime_state
@@ -217,6 +247,46 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         })
     }
 
+    /// Start validating the parachain headers with BEEFY, see `sync_para_header_with_beefy`.
+    pub(crate) fn init_beefy(&mut self, request: blocks::InitBeefyReq) -> RpcResult<()> {
+        info!(set_id = request.validator_set_id, "init_beefy");
+        self.runtime_state()?
+            .storage_synchronizer
+            .initialize_beefy(request)
+            .map_err(from_display)
+    }
+
+    /// The BEEFY validation state, `None` if BEEFY is not initialized.
+    pub(crate) fn beefy_state(&mut self) -> RpcResult<Option<blocks::BeefyState>> {
+        Ok(self.runtime_state()?.storage_synchronizer.beefy_state())
+    }
+
+    /// Sync parachain headers with a BEEFY proof of the last header, which doesn't require the
+    /// relaychain headers to be synced.
+    pub(crate) fn sync_para_header_with_beefy(
+        &mut self,
+        headers: blocks::Headers,
+        proof: blocks::BeefyParaHeadProof,
+    ) -> RpcResult<pb::SyncedTo> {
+        info!(
+            range=?(
+                headers.first().map(|h| h.number),
+                headers.last().map(|h| h.number)
+            ),
+            "sync_para_header_with_beefy",
+        );
+
+        let state = self.runtime_state()?;
+        let last_header = state
+            .storage_synchronizer
+            .sync_parachain_header_with_beefy(headers, proof, state.para_id)
+            .map_err(from_display)?;
+
+        Ok(pb::SyncedTo {
+            synced_to: last_header,
+        })
+    }
+
     /// Sync a combined batch of relaychain & parachain headers
     /// NOTE:
     ///   - The two latest headers MUST be aligned with each other by the `Para.Heads` read from the relaychain storage.
@@ -259,6 +329,11 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             ),
             "dispatch_block",
         );
+        if self.retired {
+            return Err(from_display(
+                "The worker has been handed over to another pRuntime",
+            ));
+        }
         let counters = self.runtime_state()?.storage_synchronizer.counters();
         blocks.retain(|b| b.block_header.number >= counters.next_block_number);
 
@@ -281,6 +356,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
                 continue;
             }
             info!("State synced");
+            let dispatch_start = Instant::now();
             state.purge_mq();
             let now_ms = state.chain_storage.timestamp_now();
             let chain_storage = state.chain_storage.snapshot();
@@ -301,6 +377,12 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
 
             self.maybe_apply_cluster_state();
 
+            crate::metrics::record_block_dispatched(
+                dispatch_start.elapsed().as_millis() as u64,
+                now_ms,
+            );
+            crate::metrics::maybe_persist(&self.platform, &self.args.sealing_path);
+
             if let Err(e) = self.maybe_take_checkpoint() {
                 error!("Failed to take checkpoint: {:?}", e);
             }
@@ -317,6 +399,12 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         if self.last_checkpoint.elapsed().as_secs() < self.args.checkpoint_interval {
             return Ok(());
         }
+        if self.args.incremental_checkpoint {
+            if self.take_incremental_checkpoint()? {
+                self.last_checkpoint = Instant::now();
+            }
+            return Ok(());
+        }
         self.take_checkpoint()?;
         Ok(())
     }
@@ -346,12 +434,20 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             return;
         }
         info!("Applying cluster state");
-        system.contract_cluster = Some(cluster_state.cluster.into_owned());
+        let cluster = cluster_state.cluster.into_owned();
+        let cluster_id = cluster.id;
+        let partial = cluster.partial;
+        system.contract_cluster = Some(cluster);
         system.contracts = cluster_state.contracts.into_owned();
 
         for (sender, messages) in cluster_state.pending_messages {
             runtime_state.send_mq.load_state(&sender, messages);
         }
+        if partial {
+            info!("Only selected contracts were restored, not reporting the cluster state synced");
+        } else {
+            system.report_cluster_state_synced(cluster_id);
+        }
         if let Err(e) = self.take_checkpoint() {
             error!("Failed to take checkpoint: {:?}", e);
         }
@@ -440,10 +536,11 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             )
             .expect("Bridge initialize failed");
 
+        let validator = ChainValidator::from(light_client);
         let storage_synchronizer = if is_parachain {
-            Synchronizer::new_parachain(light_client, main_bridge, next_headernum)
+            Synchronizer::new_parachain(validator, main_bridge, next_headernum)
         } else {
-            Synchronizer::new_solochain(light_client, main_bridge)
+            Synchronizer::new_solochain(validator, main_bridge)
         };
 
         let send_mq = MessageSendQueue::default();
@@ -593,13 +690,11 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         system.apply_side_effects(effects, &state.chain_storage);
     }
 
-    fn contract_query(
+    /// Verify and decrypt a contract query request.
+    fn open_contract_query(
         &mut self,
-        req_id: u64,
-        request: pb::ContractQueryRequest,
-    ) -> RpcResult<
-        impl Future<Output = RpcResult<(pb::ContractQueryResponse, Option<ExecSideEffects>)>>,
-    > {
+        request: &pb::ContractQueryRequest,
+    ) -> RpcResult<OpenedQuery> {
         // Validate signature
         let origin = if let Some(sig) = &request.signature {
             let current_block = self.get_info().blocknum - 1;
@@ -637,7 +732,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         let rest = data_cursor.len();
 
         // Origin
-        let accid_origin = match origin {
+        let origin = match origin {
             Some(origin) => {
                 let accid = chain::AccountId::try_from(origin.as_slice())
                     .map_err(|_| from_display("Bad account id"))?;
@@ -646,7 +741,26 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             None => None,
         };
 
+        Ok(OpenedQuery {
+            origin,
+            contract_id: AccountId::unchecked_from(head.id),
+            payload: data[data.len() - rest..].to_vec(),
+            nonce: head.nonce,
+            ecdh_key,
+            pubkey: encrypted_req.pubkey,
+        })
+    }
+
+    fn contract_query(
+        &mut self,
+        req_id: u64,
+        request: pb::ContractQueryRequest,
+    ) -> RpcResult<
+        impl Future<Output = RpcResult<(pb::ContractQueryResponse, Option<ExecSideEffects>)>>,
+    > {
+        let query = self.open_contract_query(&request)?;
         let query_scheduler = self.query_scheduler.clone();
+        let query_start = Instant::now();
         // Dispatch
         let query_future = self
             .system
@@ -654,9 +768,9 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             .expect("system always exists here")
             .make_query(
                 req_id,
-                &AccountId::unchecked_from(head.id),
-                accid_origin.as_ref(),
-                data[data.len() - rest..].to_vec(),
+                &query.contract_id,
+                query.origin.as_ref(),
+                query.payload.clone(),
                 query_scheduler,
                 &self
                     .runtime_state
@@ -666,23 +780,46 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             )?;
 
         Ok(async move {
-            let (response, effects) = query_future.await?;
-            let response = contract::ContractQueryResponse {
-                nonce: head.nonce,
-                result: contract::Data(response),
-            };
-            let response_data = response.encode();
-
-            // Encrypt
-            let encrypted_resp = crypto::EncryptedData::encrypt(
-                &ecdh_key,
-                &encrypted_req.pubkey,
-                crate::generate_random_iv(),
-                &response_data,
-            )
-            .map_err(from_debug)?;
+            let result = query_future.await;
+            crate::metrics::record_query(
+                &query.contract_id,
+                query_start.elapsed().as_millis() as u64,
+                result.is_ok(),
+            );
+            let (response, effects) = result?;
+            Ok((query.seal_response(response)?, effects))
+        })
+    }
 
-            Ok((pb::ContractQueryResponse::new(encrypted_resp), effects))
+    /// Execute an ink message of a contract query request as a transaction would, replying the
+    /// encoded `Result<DryRunResult, QueryError>` with the trace of the call.
+    ///
+    /// The side effects are dropped as well as the storage changes.
+    pub fn contract_dry_run(
+        &mut self,
+        req_id: u64,
+        request: pb::ContractQueryRequest,
+    ) -> RpcResult<impl Future<Output = RpcResult<pb::ContractQueryResponse>>> {
+        let query = self.open_contract_query(&request)?;
+        let dry_run_future = self
+            .system
+            .as_ref()
+            .expect("system always exists here")
+            .make_dry_run(
+                req_id,
+                &query.contract_id,
+                query.origin.as_ref(),
+                query.payload.clone(),
+                self.query_scheduler.clone(),
+                &self
+                    .runtime_state
+                    .as_ref()
+                    .expect("runtime state always exists here")
+                    .chain_storage,
+            )?;
+        Ok(async move {
+            let response = dry_run_future.await;
+            query.seal_response(response)
         })
     }
 
@@ -780,31 +917,49 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
     }
 
     fn sign_endpoints(&mut self) -> RpcResult<pb::GetEndpointResponse> {
+        let versioned_endpoints =
+            self.versioned_endpoints(self.endpoints.values().cloned().collect());
         let system = self.system()?;
         let block_time: u64 = system.now_ms;
         let public_key = system.identity_key.public();
-        let versioned_endpoints =
-            VersionedWorkerEndpoints::V1(self.endpoints.values().cloned().collect());
         let endpoint_payload = WorkerEndpointPayload {
             pubkey: public_key,
             versioned_endpoints,
             signing_time: block_time,
         };
         let signature = self.sign_endpoint_payload(&endpoint_payload)?;
-        let resp = pb::GetEndpointResponse::new(Some(endpoint_payload.clone()), Some(signature));
-        self.signed_endpoints = Some(resp.clone());
-        Ok(resp)
+        Ok(pb::GetEndpointResponse::new(
+            Some(endpoint_payload),
+            Some(signature),
+        ))
     }
 
+    /// The endpoints are re-signed on every call so that the signing time works as a liveness
+    /// heartbeat and the load hint is up to date.
     fn get_endpoint_info(&mut self) -> RpcResult<pb::GetEndpointResponse> {
         if self.endpoints.is_empty() {
             info!("Endpoint not found");
             return Ok(pb::GetEndpointResponse::new(None, None));
         }
-        match &self.signed_endpoints {
-            Some(response) => Ok(response.clone()),
-            None => self.sign_endpoints(),
+        self.sign_endpoints()
+    }
+
+    fn versioned_endpoints(&self, endpoints: Vec<String>) -> VersionedWorkerEndpoints {
+        let mut rpc_features = WorkerCapabilities::RPC_CONTRACT_DRY_RUN;
+        if pb::phactory_api_server::supported_methods().contains(&"PhactoryAPI.ContractQuery") {
+            rpc_features |= WorkerCapabilities::RPC_CONTRACT_QUERY;
         }
+        let capabilities = WorkerCapabilities {
+            rpc_features,
+            pink_runtime_versions: ::pink::runtimes::v1::runtime_versions().to_vec(),
+            sidevm: self.args.cores > 0,
+            region: self.args.region.clone(),
+            load_permill: crate::metrics::load_permill(self.args.cores),
+        };
+        VersionedWorkerEndpoints::V2(WorkerEndpointsV2 {
+            endpoints,
+            capabilities,
+        })
     }
 
     fn sign_endpoint_info(
@@ -938,6 +1093,110 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         Ok(())
     }
 
+    /// Start from the chain state at a synced parachain header instead of the genesis.
+    ///
+    /// Unlike `load_chain_state`, the state is validated against the header right away and the
+    /// worker may already be registered. Its computing state is reconstructed from the snapshot
+    /// and the replayed messages. Gatekeepers and workers in a cluster have to sync from the
+    /// genesis, since their state depends on all the messages before.
+    pub(crate) fn fast_sync(&mut self, request: blocks::FastSyncReq) -> anyhow::Result<()> {
+        let blocks::FastSyncReq {
+            block_number,
+            storage,
+            replay,
+        } = request;
+        info!(block_number, n_replay = replay.len(), "fast_sync");
+        if block_number == 0 {
+            anyhow::bail!("Can not fast sync to block 0");
+        }
+        if system::gk_master_key_exists(&self.args.sealing_path) {
+            anyhow::bail!("Gatekeepers must sync from the genesis");
+        }
+        let Some(system) = &mut self.system else {
+            anyhow::bail!("System is uninitialized");
+        };
+        let Some(state) = &mut self.runtime_state else {
+            anyhow::bail!("Runtime is uninitialized");
+        };
+        let chain_storage = ChainStorage::from_pairs(storage.into_iter());
+        let para_id = chain_storage.para_id();
+        if para_id != state.para_id {
+            anyhow::bail!(
+                "Loading different parachain state, loading={para_id}, init={}",
+                state.para_id
+            );
+        }
+        let pubkey = system.identity_key.public();
+        if system::chain_state::is_gatekeeper(&pubkey, &chain_storage) {
+            anyhow::bail!("Gatekeepers must sync from the genesis");
+        }
+        if chain_storage.get_worker_cluster(&pubkey).is_some() {
+            anyhow::bail!("Workers in a cluster must sync from the genesis");
+        }
+
+        let now_key = light_validation::utils::storage_prefix("Timestamp", "Now");
+        let mut replayed = vec![];
+        let mut next_block = 0;
+        for block in replay {
+            if block.block_number < next_block || block.block_number > block_number {
+                anyhow::bail!(
+                    "Replayed block {} out of order or after the snapshot",
+                    block.block_number
+                );
+            }
+            next_block = block.block_number + 1;
+            let messages = block.messages.encode();
+            let now = block.now_ms.encode();
+            let proved = ["OutboundMessagesV2", "OutboundMessages"]
+                .iter()
+                .any(|name| {
+                    let key = light_validation::utils::storage_prefix("PhalaMq", name);
+                    state
+                        .storage_synchronizer
+                        .validate_block_storage(
+                            block.block_number,
+                            block.proof.clone(),
+                            &[(&key[..], &messages[..]), (&now_key[..], &now[..])],
+                        )
+                        .is_ok()
+                });
+            if !proved {
+                anyhow::bail!("Invalid messages proof of block {}", block.block_number);
+            }
+            for message in block.messages {
+                if !message.sender.is_pallet()
+                    || message.destination.path() != &SystemEvent::topic()
+                {
+                    continue;
+                }
+                let event = SystemEvent::decode(&mut &message.payload[..])
+                    .context("Failed to decode replayed SystemEvent")?;
+                if let SystemEvent::WorkerEvent(event) = event {
+                    if event.pubkey == pubkey {
+                        replayed.push((block.block_number, block.now_ms, event.event));
+                    }
+                }
+            }
+        }
+
+        let session = system::restored_session(&chain_storage, &pubkey, &replayed)?;
+
+        state
+            .storage_synchronizer
+            .fast_sync_to(block_number, chain_storage.root())
+            .context("Failed to validate the chain state")?;
+        state.chain_storage = chain_storage;
+        system.restore_worker_state(&state.chain_storage, block_number, session);
+        // The messages sent by this worker before the snapshot are not replayed.
+        let sender = MessageOrigin::Worker(pubkey);
+        state
+            .send_mq
+            .set_sequence(&sender, state.chain_storage.mq_sequence(&sender));
+        self.can_load_chain_state = false;
+        info!(block_number, "Fast synced");
+        Ok(())
+    }
+
     pub fn stop(&self, remove_checkpoints: bool) -> RpcResult<()> {
         info!("Requested to stop remove_checkpoints={remove_checkpoints}");
         if remove_checkpoints {
@@ -977,6 +1236,9 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         let Some(cluster) = &system.contract_cluster else {
             return Err(from_display("No cluster found"));
         };
+        if cluster.partial {
+            return Err(from_display("The cluster is partially restored"));
+        }
         let block_number = system.block_number;
         if block_number < min_block_number {
             return Err(from_display(format!(
@@ -1049,64 +1311,67 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
     }
 
     fn load_cluster_state(&mut self, filename: &str) -> RpcResult<()> {
+        self.load_cluster_state_selected(filename, None)
+            .map_err(from_debug)
+    }
+
+    /// Load a cluster state dump sent to this worker.
+    ///
+    /// If `contracts` is given, only these contracts and their storage are restored. Such a
+    /// worker does not join the cluster: the contracts are not connected to the message queue,
+    /// and the cluster only serves queries, see [`cluster_state::select_contracts`].
+    pub fn load_cluster_state_selected(
+        &mut self,
+        filename: &str,
+        contracts: Option<&[String]>,
+    ) -> anyhow::Result<()> {
         let Some(system) = &self.system else {
-            return Err(from_display("System is uninitialized"));
+            anyhow::bail!("System is uninitialized");
         };
         if system.contract_cluster.is_some() {
-            return Err(from_display("Already in cluster"));
+            anyhow::bail!("Already in cluster");
         }
         let Some(runtime_state) = &mut self.runtime_state else {
-            return Err(from_display("Runtime is uninitialized"));
+            anyhow::bail!("Runtime is uninitialized");
         };
-
-        let fullname = PathBuf::from(&self.args.storage_path).join(filename);
-        let mut reader = File::open(fullname).map_err(from_debug)?;
-        let mut pubkey: EcdhPublicKey = Default::default();
-        reader.read_exact(&mut pubkey).map_err(from_debug)?;
-
+        let (mut recv_mq, mut send_mq) =
+            (MessageDispatcher::default(), MessageSendQueue::default());
+        let (recv_mq, send_mq) = match contracts {
+            Some(_) => (&mut recv_mq, &mut send_mq),
+            None => (&mut runtime_state.recv_mq, &mut runtime_state.send_mq),
+        };
+        let cluster_state::ClusterStateDump {
+            sender, mut state, ..
+        } = cluster_state::read_dump(
+            &system.ecdh_key,
+            &self.args.storage_path,
+            filename,
+            recv_mq,
+            send_mq,
+        )?;
         // Make sure the pubkey is not fake
         if !runtime_state
             .chain_storage
-            .is_worker_registered(&WorkerPublicKey(pubkey))
+            .is_worker_registered(&WorkerPublicKey(sender))
         {
-            return Err(from_display(format!(
-                "The pubkey {} is not registered",
-                hex_fmt::HexFmt(&pubkey)
-            )));
-        }
-
-        let key = ecdh::agree(&system.ecdh_key, &pubkey).or(Err(from_display(
-            "Failed to derive ECDH key for cluster state",
-        )))?;
-        let key128 = derive_key_for_cluster_state(&key);
-        let mut dec_reader = aead::stream::new_aes128gcm_reader(key128, &mut reader);
-
-        let mut version = [0u8; 4];
-        dec_reader.read_exact(&mut version).map_err(from_debug)?;
-        let version = u32::from_be_bytes(version);
-        info!(version, "Loading cluster state");
-        if version != CHECKPOINT_VERSION {
-            return Err(from_display(format!(
-                "Incompatible cluster state version {version}, expected {CHECKPOINT_VERSION}",
-            )));
+            anyhow::bail!("The pubkey {} is not registered", hex_fmt::HexFmt(&sender));
         }
-        let recv_mq = &mut runtime_state.recv_mq;
-        let send_mq = &mut runtime_state.send_mq;
-        // The recv_mq and send_mq are used to restore the rx/tx handles during the deserilization.
-        let cluster_state: ClusterState =
-            phala_mq::checkpoint_helper::using_dispatcher(recv_mq, move || {
-                phala_mq::checkpoint_helper::using_send_mq(send_mq, move || {
-                    serde_cbor::from_reader(dec_reader)
-                })
-            })
-            .map_err(from_debug)?;
-        if cluster_state.block_number <= system.block_number {
-            return Err(from_display(format!(
+        if state.block_number <= system.block_number {
+            anyhow::bail!(
                 "Staled cluster state, current_block_number={}, state_block_number={}",
-                system.block_number, cluster_state.block_number,
-            )));
+                system.block_number,
+                state.block_number,
+            );
+        }
+        if let Some(contracts) = contracts {
+            cluster_state::select_contracts(&mut state, contracts)?;
         }
-        self.cluster_state_to_apply = Some(cluster_state);
+        info!(
+            block_number = state.block_number,
+            contracts = state.contracts.len(),
+            "Cluster state loaded"
+        );
+        self.cluster_state_to_apply = Some(state);
         Ok(())
     }
 
@@ -1215,6 +1480,18 @@ where
             (code, data)
         }
     }
+
+    /// Serve a contract dry run request, see `Phactory::contract_dry_run`.
+    pub async fn contract_dry_run(
+        &self,
+        req_id: u64,
+        request: pb::ContractQueryRequest,
+    ) -> RpcResult<pb::ContractQueryResponse> {
+        let dry_run = self
+            .lock_phactory(true, false)?
+            .contract_dry_run(req_id, request)?;
+        dry_run.await
+    }
 }
 
 pub struct LogOnDrop<T> {
@@ -1490,8 +1767,9 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
         &mut self,
         request: pb::SignEndpointsRequest,
     ) -> Result<pb::GetEndpointResponse, prpc::server::Error> {
-        self.lock_phactory(true, false)?
-            .sign_endpoint_info(VersionedWorkerEndpoints::V1(request.decode_endpoints()?))
+        let mut phactory = self.lock_phactory(true, false)?;
+        let versioned_endpoints = phactory.versioned_endpoints(request.decode_endpoints()?);
+        phactory.sign_endpoint_info(versioned_endpoints)
     }
 
     async fn derive_phala_i2p_key(&mut self, _: ()) -> RpcResult<pb::DerivePhalaI2pKeyResponse> {
@@ -1529,7 +1807,10 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
         let mut phactory = self.lock_phactory(false, true)?;
         let attestation_provider = phactory.attestation_provider;
         let dev_mode = phactory.dev_mode;
-        let in_sgx = attestation_provider == Some(AttestationProvider::Ias);
+        let in_sgx = matches!(
+            attestation_provider,
+            Some(AttestationProvider::Ias | AttestationProvider::Dcap)
+        );
         let (block_number, now_ms) = phactory.current_block()?;
 
         // 1. verify client RA report to ensure it's in sgx
@@ -1547,6 +1828,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
             // The time from attestation report is generated by IAS, thus trusted. By default, it's valid for **10h**.
             // By ensuring our system timestamp is within the valid period, we know that this pRuntime is not hold back by
             // malicious workers.
+            let dcap_root_ca = phactory.runtime_state()?.chain_storage.dcap_root_ca();
             validate_attestation_report(
                 attn_to_validate.clone(),
                 &payload_hash,
@@ -1554,6 +1836,8 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
                 false,
                 vec![],
                 false,
+                false,
+                dcap_root_ca.as_deref(),
             )
             .map_err(|_| from_display("Invalid client RA report"))?;
             attn_to_validate
@@ -1621,6 +1905,14 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
                         .map_err(|_| from_display("Invalid client RA report"))?;
                     ias_fields.extend_mrenclave()
                 }
+                AttestationReport::SgxDcap { quote, .. } => {
+                    let quote = DcapQuote::parse(&quote)
+                        .map_err(|_| from_display("Invalid client RA report"))?;
+                    quote.report.runtime_hash()
+                }
+                AttestationReport::Mock { .. } => {
+                    return Err(from_display("Mock RA report is not allowed in SGX"));
+                }
             };
             let req_runtime_timestamp = runtime_state
                 .chain_storage
@@ -1675,6 +1967,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
             None
         };
 
+        phactory.start_handover_session();
         Ok(pb::HandoverWorkerKey::new(
             encrypted_worker_key,
             attestation,
@@ -1753,6 +2046,10 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
             let attn_to_validate =
                 Option::<AttestationReport>::decode(&mut &raw_attestation.encoded_report[..])
                     .map_err(|_| from_display("Decode server attestation failed"))?;
+            let dcap_root_ca = phactory
+                .runtime_state()
+                .ok()
+                .and_then(|state| state.chain_storage.dcap_root_ca());
             validate_attestation_report(
                 attn_to_validate,
                 &worker_key_hash,
@@ -1760,6 +2057,8 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
                 false,
                 vec![],
                 false,
+                false,
+                dcap_root_ca.as_deref(),
             )
             .map_err(|_| from_display("Invalid server RA report"))?;
         } else {
@@ -2016,3 +2315,27 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
             .map_err(from_debug)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_platform::MockPlatform;
+
+    fn fast_sync_req(block_number: chain::BlockNumber) -> blocks::FastSyncReq {
+        blocks::FastSyncReq {
+            block_number,
+            storage: vec![],
+            replay: vec![],
+        }
+    }
+
+    #[test]
+    fn fast_sync_requires_an_initialized_runtime() {
+        let mut phactory = Phactory::new(MockPlatform::new(b"fast-sync"));
+        let err = phactory.fast_sync(fast_sync_req(0)).unwrap_err();
+        assert!(err.to_string().contains("block 0"));
+        let err = phactory.fast_sync(fast_sync_req(10)).unwrap_err();
+        assert!(err.to_string().contains("System is uninitialized"));
+        assert!(!phactory.can_load_chain_state);
+    }
+}
@@ -1,10 +1,54 @@
-use crate::light_validation::{storage_proof::StorageProof, LightValidation};
+use crate::light_validation::{
+    beefy::BeefyClient, storage_proof::StorageProof, utils::storage_prefix, LightValidation,
+};
+use parity_scale_codec::Encode;
+use phactory_api::blocks::{BeefyParaHeadProof, BeefyState, InitBeefyReq};
 use phactory_api::storage_sync::{BlockValidator, Error as SyncError, Result};
+use serde::{Deserialize, Serialize};
 use std::string::ToString;
 
 pub use storage_ext::ChainStorage;
 
-impl BlockValidator for LightValidation<chain::Runtime> {
+/// The validator of the chain headers, selected per worker.
+///
+/// A worker validates the relay chain (or solo chain) headers with GRANDPA until `init_beefy`
+/// switches it to BEEFY. From then on, the parachain headers are proved with BEEFY and no GRANDPA
+/// headers are accepted.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum ChainValidator {
+    Grandpa(LightValidation<chain::Runtime>),
+    Beefy(BeefyValidation),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BeefyValidation {
+    bridge_id: u64,
+    /// The GRANDPA light client which the BEEFY authority set was initialized from.
+    grandpa: LightValidation<chain::Runtime>,
+    beefy: BeefyClient,
+}
+
+impl ChainValidator {
+    fn light_validation(&self) -> &LightValidation<chain::Runtime> {
+        match self {
+            ChainValidator::Grandpa(light_validation) => light_validation,
+            ChainValidator::Beefy(beefy) => &beefy.grandpa,
+        }
+    }
+}
+
+impl From<LightValidation<chain::Runtime>> for ChainValidator {
+    fn from(light_validation: LightValidation<chain::Runtime>) -> Self {
+        ChainValidator::Grandpa(light_validation)
+    }
+}
+
+fn beefy_error(message: impl ToString) -> SyncError {
+    SyncError::BeefyValidateFailed(message.to_string())
+}
+
+impl BlockValidator for ChainValidator {
     fn submit_finalized_headers(
         &mut self,
         bridge_id: u64,
@@ -13,14 +57,20 @@ impl BlockValidator for LightValidation<chain::Runtime> {
         grandpa_proof: Vec<u8>,
         authority_set_change: Option<phactory_api::blocks::AuthoritySetChange>,
     ) -> Result<()> {
-        self.submit_finalized_headers(
-            bridge_id,
-            header,
-            ancestry_proof,
-            grandpa_proof,
-            authority_set_change,
-        )
-        .map_err(|e| SyncError::HeaderValidateFailed(e.to_string()))
+        let ChainValidator::Grandpa(light_validation) = self else {
+            return Err(SyncError::HeaderValidateFailed(
+                "GRANDPA headers are not accepted in BEEFY mode".into(),
+            ));
+        };
+        light_validation
+            .submit_finalized_headers(
+                bridge_id,
+                header,
+                ancestry_proof,
+                grandpa_proof,
+                authority_set_change,
+            )
+            .map_err(|e| SyncError::HeaderValidateFailed(e.to_string()))
     }
 
     fn validate_storage_proof(
@@ -29,19 +79,86 @@ impl BlockValidator for LightValidation<chain::Runtime> {
         proof: StorageProof,
         items: &[(&[u8], &[u8])],
     ) -> Result<()> {
-        self.validate_storage_proof(state_root, proof, items)
+        self.light_validation()
+            .validate_storage_proof(state_root, proof, items)
             .map_err(|e| SyncError::StorageProofFailed(e.to_string()))
     }
+
+    /// Switches to BEEFY with the BEEFY authority set at the last GRANDPA finalized block.
+    ///
+    /// BEEFY may be activated later than the genesis block of the bridge, so the authority set is
+    /// read from the last finalized block instead of the genesis block.
+    fn initialize_beefy(&mut self, bridge_id: u64, request: InitBeefyReq) -> Result<()> {
+        let ChainValidator::Grandpa(light_validation) = self else {
+            return Err(beefy_error("BEEFY already initialized"));
+        };
+        let header = light_validation
+            .last_finalized_header(bridge_id)
+            .ok_or_else(|| beefy_error("no such bridge"))?;
+        let authorities_key = storage_prefix("Beefy", "Authorities");
+        let authorities = request.authorities.encode();
+        let set_id_key = storage_prefix("Beefy", "ValidatorSetId");
+        let set_id = request.validator_set_id.encode();
+        light_validation
+            .validate_storage_proof(
+                header.state_root,
+                request.proof,
+                &[
+                    (&authorities_key[..], &authorities[..]),
+                    (&set_id_key[..], &set_id[..]),
+                ],
+            )
+            .map_err(|e| SyncError::StorageProofFailed(e.to_string()))?;
+        let beefy =
+            BeefyClient::new(request.authorities, request.validator_set_id).map_err(beefy_error)?;
+        info!(
+            "Switched to BEEFY at block {} with validator set {}",
+            header.number, request.validator_set_id
+        );
+        *self = ChainValidator::Beefy(BeefyValidation {
+            bridge_id,
+            grandpa: light_validation.clone(),
+            beefy,
+        });
+        Ok(())
+    }
+
+    fn validate_beefy_para_head(
+        &mut self,
+        bridge_id: u64,
+        para_id: u32,
+        para_head: Option<&[u8]>,
+        proof: BeefyParaHeadProof,
+    ) -> Result<()> {
+        let ChainValidator::Beefy(beefy) = self else {
+            return Err(beefy_error("BEEFY not initialized"));
+        };
+        if beefy.bridge_id != bridge_id {
+            return Err(beefy_error("no such bridge"));
+        }
+        beefy
+            .beefy
+            .verify_para_head(para_id, para_head, proof)
+            .map_err(beefy_error)
+    }
+
+    fn beefy_state(&self) -> Option<BeefyState> {
+        match self {
+            ChainValidator::Grandpa(_) => None,
+            ChainValidator::Beefy(beefy) => Some(beefy.beefy.state()),
+        }
+    }
 }
 
 mod storage_ext {
     use crate::{chain, light_validation::utils::storage_prefix};
-    use chain::{pallet_computation, pallet_mq, pallet_phat, pallet_registry};
+    use chain::{pallet_computation, pallet_mq, pallet_phat, pallet_randomness, pallet_registry};
     use log::error;
     use parity_scale_codec::{Decode, Error};
     use phala_mq::{ContractClusterId, Message, MessageOrigin};
     use phala_trie_storage::TrieStorage;
-    use phala_types::messaging::TokenomicParameters;
+    use phala_types::contract::{ClusterInfo, ContractId};
+    use phala_types::messaging::{RandomNumber, TokenomicParameters};
     use serde::{Deserialize, Serialize};
     use sp_state_machine::{Ext, OverlayedChanges, StorageTransactionCache};
 
@@ -170,6 +287,10 @@ mod storage_ext {
             })
         }
 
+        pub(crate) fn dcap_root_ca(&self) -> Option<Vec<u8>> {
+            self.execute_with(pallet_registry::DcapRootCa::<chain::Runtime>::get)
+        }
+
         pub(crate) fn gatekeepers(&self) -> Vec<phala_types::WorkerPublicKey> {
             self.execute_with(pallet_registry::Gatekeeper::<chain::Runtime>::get)
         }
@@ -197,15 +318,69 @@ mod storage_ext {
             false
         }
 
+        /// The randomness beacon generated at the given block, if it is still kept on chain.
+        pub(crate) fn randomness_beacon(
+            &self,
+            block_number: chain::BlockNumber,
+        ) -> Option<RandomNumber> {
+            self.execute_with(|| {
+                pallet_randomness::Pallet::<chain::Runtime>::beacon_at(block_number)
+            })
+        }
+
         pub fn tokenomic_parameters(&self) -> Option<TokenomicParameters> {
             self.execute_with(pallet_computation::TokenomicParameters::<chain::Runtime>::get)
         }
 
+        /// The computing session the worker is bound to.
+        pub(crate) fn worker_session(
+            &self,
+            worker: &phala_types::WorkerPublicKey,
+        ) -> Option<pallet_computation::SessionInfo> {
+            self.execute_with(|| {
+                let session = pallet_computation::WorkerBindings::<chain::Runtime>::get(worker)?;
+                pallet_computation::Sessions::<chain::Runtime>::get(session)
+            })
+        }
+
+        pub(crate) fn next_session_id(&self) -> u32 {
+            self.execute_with(pallet_computation::NextSessionId::<chain::Runtime>::get)
+        }
+
         pub(crate) fn get_worker_cluster(
             &self,
             worker: &phala_types::WorkerPublicKey,
         ) -> Option<ContractClusterId> {
             self.execute_with(|| pallet_phat::ClusterByWorkers::<chain::Runtime>::get(worker))
         }
+
+        pub(crate) fn get_cluster_info(
+            &self,
+            cluster: &ContractClusterId,
+        ) -> Option<ClusterInfo<chain::AccountId>> {
+            self.execute_with(|| pallet_phat::Clusters::<chain::Runtime>::get(cluster))
+        }
+
+        /// The accounts granted the deployer role of the given cluster.
+        pub(crate) fn get_cluster_deployers(
+            &self,
+            cluster: &ContractClusterId,
+        ) -> Vec<chain::AccountId> {
+            self.execute_with(|| {
+                pallet_phat::ClusterDeployers::<chain::Runtime>::iter_key_prefix(cluster).collect()
+            })
+        }
+
+        pub(crate) fn get_cluster_code_allowlist(
+            &self,
+            cluster: &ContractClusterId,
+        ) -> Option<Vec<sp_core::H256>> {
+            self.execute_with(|| pallet_phat::ClusterCodeAllowList::<chain::Runtime>::get(cluster))
+        }
+
+        /// The contracts deployed to the given cluster on-chain.
+        pub(crate) fn get_cluster_contracts(&self, cluster: &ContractClusterId) -> Vec<ContractId> {
+            self.execute_with(|| pallet_phat::ClusterContracts::<chain::Runtime>::get(cluster))
+        }
     }
 }
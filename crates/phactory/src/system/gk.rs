@@ -1,4 +1,7 @@
-use super::{RotatedMasterKey, TransactionError, TypedReceiver, WorkerState};
+use super::{
+    RotatedMasterKey, TransactionError, TypedReceiver, WorkerState,
+    CONSENSUS_VERSION_RANDOMNESS_BEACON,
+};
 use chain::pallet_phat::ClusterRegistryEvent;
 use chain::pallet_registry::GatekeeperRegistryEvent;
 use phala_crypto::{
@@ -14,9 +17,9 @@ use phala_types::{
     },
     messaging::{
         BatchRotateMasterKeyEvent, DispatchMasterKeyHistoryEvent, EncryptedKey, GatekeeperEvent,
-        KeyDistribution, MessageOrigin, RandomNumber, RandomNumberEvent, RotateMasterKeyEvent,
-        SettleInfo, SystemEvent, WorkerEvent, WorkerEventWithKey, WorkingInfoUpdateEvent,
-        WorkingReportEvent,
+        KeyDistribution, MessageOrigin, RandomNumber, RandomNumberEvent, RandomnessBeaconEvent,
+        RotateMasterKeyEvent, SettleInfo, SystemEvent, WorkerEvent, WorkerEventWithKey,
+        WorkingInfoUpdateEvent, WorkingReportEvent,
     },
     wrap_content_to_sign, EcdhPublicKey, SignedContentType, WorkerPublicKey,
 };
@@ -126,6 +129,9 @@ pub(crate) struct Gatekeeper<MsgChan> {
     cluster_events: TypedReceiver<ClusterEvent>,
     // Randomness
     last_random_number: RandomNumber,
+    /// The random number of the last beacon, which the next beacon is derived from
+    #[serde(default)]
+    last_beacon: RandomNumber,
     iv_seq: u64,
     pub(crate) computing_economics: ComputingEconomics<MsgChan>,
 }
@@ -156,6 +162,7 @@ where
             gatekeeper_events: recv_mq.subscribe_bound(),
             cluster_events: recv_mq.subscribe_bound(),
             last_random_number: [0_u8; 32],
+            last_beacon: [0_u8; 32],
             iv_seq: 0,
             computing_economics: ComputingEconomics::new(recv_mq, egress),
         }
@@ -223,33 +230,54 @@ where
     }
 
     /// Append the rotated key to Gatekeeper's master key history, return whether the history is really updated
+    ///
+    /// Since `CONSENSUS_VERSION_MASTER_KEY_QUORUM`, the appended key stays pending until the rotation is confirmed on
+    /// chain by a quorum of the gatekeepers.
     pub fn append_master_key(&mut self, rotated_master_key: RotatedMasterKey) -> bool {
-        if !self.master_key_history.contains(&rotated_master_key) {
-            // the rotation id must be in order
-            assert!(
-                rotated_master_key.rotation_id == self.master_key_history.len() as u64,
-                "Gatekeeper Master key history corrupted"
-            );
-            self.master_key_history.push(rotated_master_key);
-            return true;
+        if self
+            .master_key_history
+            .iter()
+            .any(|key| key.rotation_id >= rotated_master_key.rotation_id)
+        {
+            // The rotation ids are increasing but not continuous since the aborted ones are skipped.
+            return false;
+        }
+        self.master_key_history.push(rotated_master_key);
+        true
+    }
+
+    /// Drop the pending key of an aborted rotation, return whether the history is really updated
+    pub fn drop_master_key(&mut self, rotation_id: u64) -> bool {
+        let current = self.master_key.public();
+        match self.master_key_history.last() {
+            Some(key)
+                if key.rotation_id == rotation_id
+                    && sr25519::Pair::restore_from_secret_key(&key.secret).public() != current =>
+            {
+                self.master_key_history.pop();
+                true
+            }
+            _ => false,
         }
-        false
     }
 
     /// Update the master key and Gatekeeper mq, return whether the key switch succeeds
+    ///
+    /// This is the rotation before `CONSENSUS_VERSION_MASTER_KEY_QUORUM`, the new key takes effect immediately.
     pub fn switch_master_key(
         &mut self,
         rotation_id: u64,
         block_height: chain::BlockNumber,
     ) -> bool {
-        let raw_key = self.master_key_history.get(rotation_id as usize);
-        if raw_key.is_none() {
+        let Some(raw_key) = self
+            .master_key_history
+            .iter()
+            .find(|key| key.rotation_id == rotation_id)
+        else {
             return false;
-        }
-
-        let raw_key = raw_key.expect("checked; qed.");
+        };
         assert!(
-            raw_key.rotation_id == rotation_id && raw_key.block_height == block_height,
+            raw_key.block_height == block_height,
             "Gatekeeper Master key history corrupted"
         );
         let new_master_key = sr25519::Pair::restore_from_secret_key(&raw_key.secret);
@@ -266,6 +294,29 @@ where
         true
     }
 
+    /// Update the master key and Gatekeeper mq once the rotated pubkey is confirmed on chain by a quorum of the
+    /// gatekeepers, return whether the key switch succeeds
+    pub fn confirm_master_key(
+        &mut self,
+        master_pubkey: sr25519::Public,
+        block_height: chain::BlockNumber,
+    ) -> bool {
+        let Some(raw_key) = self
+            .master_key_history
+            .iter_mut()
+            .rev()
+            .find(|key| sr25519::Pair::restore_from_secret_key(&key.secret).public() == master_pubkey)
+        else {
+            return false;
+        };
+        // The key takes effect from the block it is confirmed on chain, which is used to pick the key when verifying
+        // the random numbers.
+        raw_key.block_height = block_height;
+        self.master_key = sr25519::Pair::restore_from_secret_key(&raw_key.secret);
+        self.egress.set_signer(self.master_key.clone().into());
+        true
+    }
+
     pub fn share_master_key(
         &mut self,
         pubkey: &WorkerPublicKey,
@@ -412,7 +463,9 @@ where
         if self.master_pubkey_on_chain {
             self.computing_economics.did_process_block(block, &mut ());
         }
-        self.emit_random_number(block.block_number);
+        let emit_beacon =
+            block.storage.pruntime_consensus_version() >= CONSENSUS_VERSION_RANDOMNESS_BEACON;
+        self.emit_random_number(block.block_number, emit_beacon);
     }
 
     fn process_gatekeeper_event(&mut self, origin: MessageOrigin, event: GatekeeperEvent) {
@@ -557,7 +610,7 @@ where
         }
     }
 
-    pub fn emit_random_number(&mut self, block_number: chain::BlockNumber) {
+    pub fn emit_random_number(&mut self, block_number: chain::BlockNumber, emit_beacon: bool) {
         if block_number % VRF_INTERVAL != 0 {
             return;
         }
@@ -575,8 +628,19 @@ where
                 random_number,
                 self.last_random_number,
             ));
+        if emit_beacon {
+            self.emit_randomness_beacon(block_number);
+        }
         self.last_random_number = random_number;
     }
+
+    /// Publish a beacon to the randomness pallet, with the VRF proof of the master key
+    fn emit_randomness_beacon(&mut self, block_number: chain::BlockNumber) {
+        let (event, random_number) =
+            RandomnessBeaconEvent::sign(&self.master_key, block_number, self.last_beacon);
+        self.egress.push_message(&event);
+        self.last_beacon = random_number;
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -4,7 +4,7 @@ mod master_key;
 use crate::{
     benchmark,
     contracts::{ContractsKeeper, ExecuteEnv, SidevmCode},
-    pink::{Cluster, ClusterContainer},
+    pink::{deploy_permission::DeployPermission, Cluster, ClusterContainer},
     secret_channel::{ecdh_serde, SecretReceiver},
     types::{deopaque_query, BlockInfo, OpaqueError, OpaqueQuery, OpaqueReply},
     ChainStorage,
@@ -64,7 +64,11 @@ use tracing::{error, info};
 
 pub type TransactionResult = Result<Option<pink::types::ExecSideEffects>, TransactionError>;
 
-pub(crate) const MAX_SUPPORTED_CONSENSUS_VERSION: u32 = 0;
+pub(crate) const MAX_SUPPORTED_CONSENSUS_VERSION: u32 = 2;
+/// Since this version, a rotated master key takes effect once a quorum of the gatekeepers has acknowledged it on chain.
+pub(crate) const CONSENSUS_VERSION_MASTER_KEY_QUORUM: u32 = 1;
+/// Since this version, the gatekeepers publish a VRF beacon to the randomness pallet along with each random number.
+pub(crate) const CONSENSUS_VERSION_RANDOMNESS_BEACON: u32 = 2;
 
 #[derive(Encode, Decode, Debug, Clone, thiserror::Error)]
 #[error("TransactionError: {:?}", self)]
@@ -133,7 +137,7 @@ struct BenchState {
     duration: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 enum WorkingState {
     Computing,
     Paused,
@@ -147,6 +151,63 @@ struct WorkingInfo {
     start_iter: u64,
 }
 
+/// The computing session of a worker fast synced from a chain state snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RestoredSession {
+    session_id: u32,
+    state: WorkingState,
+    start_time: u64,
+}
+
+/// Find the computing session of the worker in a chain state snapshot.
+///
+/// The session id is only known from the `Started` event, so the block the session started at
+/// must be replayed. The last replayed `Started` event has to match the `Sessions` entry in the
+/// snapshot, otherwise it belongs to an earlier session.
+pub(crate) fn restored_session(
+    storage: &ChainStorage,
+    pubkey: &WorkerPublicKey,
+    replayed: &[(BlockNumber, u64, WorkerEvent)],
+) -> Result<Option<RestoredSession>> {
+    use chain::pallet_computation::WorkerState as SessionState;
+
+    let Some(info) = storage.worker_session(pubkey) else {
+        return Ok(None);
+    };
+    let state = match info.state {
+        SessionState::WorkerIdle => WorkingState::Computing,
+        SessionState::WorkerUnresponsive => WorkingState::Paused,
+        _ => return Ok(None),
+    };
+    let mut last_start = None;
+    for (block_number, now_ms, event) in replayed {
+        match event {
+            WorkerEvent::Started {
+                session_id,
+                init_v,
+                init_p,
+            } => last_start = Some((*block_number, *now_ms, *session_id, *init_v, *init_p)),
+            WorkerEvent::Stopped => last_start = None,
+            _ => {}
+        }
+    }
+    let Some((block_number, start_time, session_id, init_v, init_p)) = last_start else {
+        return Err(anyhow!(
+            "The worker is computing, the block it started computing must be replayed"
+        ));
+    };
+    if init_v != info.ve || init_p != info.init_p() || session_id >= storage.next_session_id() {
+        return Err(anyhow!(
+            "The Started event replayed at block {block_number} does not match the session on chain"
+        ));
+    }
+    Ok(Some(RestoredSession {
+        session_id,
+        state,
+        start_time,
+    }))
+}
+
 // Minimum worker state machine can be reused to replay in GK.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct WorkerState {
@@ -469,6 +530,15 @@ pub struct System<Platform> {
 
     // If non-zero indicates the block which this worker loaded the chain state from.
     pub(crate) genesis_block: BlockNumber,
+    /// The block of the chain state snapshot this worker was fast synced to, if any.
+    #[serde(default)]
+    pub(crate) fast_synced_at: Option<BlockNumber>,
+
+    /// The contracts whose code hash should be reported at the end of the current block.
+    ///
+    /// Always drained in `did_process_block`, so it never needs to be checkpointed.
+    #[serde(skip)]
+    pending_code_reports: Vec<ContractId>,
 }
 
 thread_local! {
@@ -537,6 +607,27 @@ impl<Platform: pal::Platform> System<Platform> {
             now_ms: 0,
             sidevm_spawner: create_sidevm_service(worker_threads),
             genesis_block: 0,
+            fast_synced_at: None,
+            pending_code_reports: Default::default(),
+        }
+    }
+
+    fn report_contract_codes(&mut self) {
+        let contract_ids = core::mem::take(&mut self.pending_code_reports);
+        let Some(cluster) = &self.contract_cluster else {
+            return;
+        };
+        for contract_id in contract_ids {
+            let code_hash = cluster.code_hash(&contract_id.0.into());
+            info!(
+                "Reporting code hash {code_hash:?} of contract {}",
+                hex_fmt::HexFmt(&contract_id)
+            );
+            self.egress
+                .push_message(&WorkerClusterReport::ContractCodeReported {
+                    contract_id,
+                    code_hash,
+                });
         }
     }
 
@@ -556,18 +647,13 @@ impl<Platform: pal::Platform> System<Platform> {
         self.worker_state.registered
     }
 
-    pub fn make_query(
+    fn query_context(
         &self,
         req_id: u64,
         contract_id: &AccountId,
-        origin: Option<&chain::AccountId>,
-        query: OpaqueQuery,
         query_scheduler: RequestScheduler<AccountId>,
         chain_storage: &ChainStorage,
-    ) -> Result<
-        impl Future<Output = Result<(OpaqueReply, Option<ExecSideEffects>), OpaqueError>>,
-        OpaqueError,
-    > {
+    ) -> Result<(Cluster, contracts::QueryContext), OpaqueError> {
         let contract = self
             .contracts
             .get(contract_id)
@@ -590,6 +676,23 @@ impl<Platform: pal::Platform> System<Platform> {
             chain_storage: chain_storage.snapshot(),
             req_id,
         };
+        Ok((cluster, context))
+    }
+
+    pub fn make_query(
+        &self,
+        req_id: u64,
+        contract_id: &AccountId,
+        origin: Option<&chain::AccountId>,
+        query: OpaqueQuery,
+        query_scheduler: RequestScheduler<AccountId>,
+        chain_storage: &ChainStorage,
+    ) -> Result<
+        impl Future<Output = Result<(OpaqueReply, Option<ExecSideEffects>), OpaqueError>>,
+        OpaqueError,
+    > {
+        let (cluster, context) =
+            self.query_context(req_id, contract_id, query_scheduler, chain_storage)?;
         let origin = origin.cloned();
         let query = deopaque_query(&query)?;
         let contract_id = contract_id.clone();
@@ -608,6 +711,33 @@ impl<Platform: pal::Platform> System<Platform> {
         })
     }
 
+    /// Like `make_query`, but for a `DryRunRequest`, replying the encoded
+    /// `Result<DryRunResult, QueryError>`.
+    pub fn make_dry_run(
+        &self,
+        req_id: u64,
+        contract_id: &AccountId,
+        origin: Option<&chain::AccountId>,
+        request: OpaqueQuery,
+        query_scheduler: RequestScheduler<AccountId>,
+        chain_storage: &ChainStorage,
+    ) -> Result<impl Future<Output = OpaqueReply>, OpaqueError> {
+        let (cluster, context) =
+            self.query_context(req_id, contract_id, query_scheduler, chain_storage)?;
+        let origin = origin.cloned();
+        let request = deopaque_query(&request)?;
+        let contract_id = contract_id.clone();
+        Ok(async move {
+            let result = cluster
+                .handle_dry_run(&contract_id, origin.as_ref(), request, context)
+                .await;
+            if let Err(err) = &result {
+                log::error!("Contract dry run error: {:?}", err);
+            }
+            result.encode()
+        })
+    }
+
     pub fn process_next_message(&mut self, block: &mut BlockInfo) -> anyhow::Result<bool> {
         let ok = phala_mq::select_ignore_errors! {
             (event, origin) = self.system_events => {
@@ -744,6 +874,7 @@ impl<Platform: pal::Platform> System<Platform> {
                 );
             }
         }
+        self.report_contract_codes();
         if self.contracts.weight_changed {
             self.contracts.weight_changed = false;
             self.contracts.apply_local_cache_quotas();
@@ -767,6 +898,34 @@ impl<Platform: pal::Platform> System<Platform> {
         );
     }
 
+    /// Reconstruct the worker state from a chain state snapshot loaded by `fast_sync`.
+    ///
+    /// The registration is read from the snapshot, and the computing session is the one found by
+    /// `restored_session`.
+    pub(crate) fn restore_worker_state(
+        &mut self,
+        storage: &ChainStorage,
+        block_number: BlockNumber,
+        session: Option<RestoredSession>,
+    ) {
+        let working_state = session.map(|session| WorkingInfo {
+            session_id: session.session_id,
+            state: session.state,
+            start_time: session.start_time,
+            start_iter: benchmark::iteration_counter(),
+        });
+        info!("Restored worker state at block {block_number}: working_state={working_state:?}");
+        self.fast_synced_at = Some(block_number);
+        self.worker_state.registered = storage.is_worker_registered(&self.identity_key.public());
+        self.worker_state.bench_state = None;
+        self.worker_state.working_state = working_state;
+        if self.worker_state.need_pause() {
+            benchmark::pause();
+        } else {
+            benchmark::resume();
+        }
+    }
+
     /// Update local sealed master keys if the received history is longer than existing one.
     ///
     /// Panic if `self.gatekeeper` is None since it implies a need for resync from the start as gk
@@ -811,7 +970,7 @@ impl<Platform: pal::Platform> System<Platform> {
             "Init gatekeeper with no master key"
         );
 
-        if self.genesis_block != 0 {
+        if self.genesis_block != 0 || self.fast_synced_at.is_some() {
             panic!("Gatekeeper must be synced start from the first block");
         }
 
@@ -877,10 +1036,56 @@ impl<Platform: pal::Platform> System<Platform> {
                     hex::encode(event.master_pubkey),
                     block.block_number
                 );
+                // Before the quorum rotation, the gatekeepers have switched to the new key at the
+                // BatchRotateMasterKey event.
+                if block.storage.pruntime_consensus_version() >= CONSENSUS_VERSION_MASTER_KEY_QUORUM
+                {
+                    self.process_master_pubkey_rotated(event.master_pubkey);
+                }
+            }
+            GatekeeperLaunch::MasterKeyRotationAborted(event) => {
+                info!(
+                    "Master key rotation round {} aborted in block {}",
+                    event.rotation_id, block.block_number
+                );
+                if let Some(gatekeeper) = &mut self.gatekeeper {
+                    if gatekeeper.drop_master_key(event.rotation_id) {
+                        self.seal_master_key_history();
+                    }
+                }
             }
         }
     }
 
+    /// Switch to the rotated master key once it is acknowledged by a quorum of the gatekeepers on chain
+    fn process_master_pubkey_rotated(&mut self, master_pubkey: sr25519::Public) {
+        let Some(gatekeeper) = &mut self.gatekeeper else {
+            return;
+        };
+        if gatekeeper.confirm_master_key(master_pubkey, self.block_number) {
+            // This is a valid GK in syncing, the needed master key should already be dispatched before the restart this
+            // pRuntime.
+            info!("Worker: rotate master key with received master key history");
+            self.seal_master_key_history();
+        } else {
+            // This is an unregistered GK whose master key is not outdated yet, it 's still sliently syncing. It cannot
+            // do silent syncing anymore since it does not know the rotated key.
+            info!("Worker: master key rotation received, stop unregistered gatekeeper silent syncing and cleanup");
+            self.gatekeeper = None;
+        }
+    }
+
+    fn seal_master_key_history(&self) {
+        if let Some(gatekeeper) = &self.gatekeeper {
+            master_key::seal(
+                self.sealing_path.clone(),
+                gatekeeper.master_key_history(),
+                &self.identity_key,
+                &self.platform,
+            );
+        }
+    }
+
     /// Generate the master key if this is the first gatekeeper
     fn process_first_gatekeeper_event(
         &mut self,
@@ -1087,6 +1292,12 @@ impl<Platform: pal::Platform> System<Platform> {
         origin: MessageOrigin,
         event: ClusterOperation<chain::AccountId>,
     ) -> Result<()> {
+        if let Some(cluster) = &self.contract_cluster {
+            if cluster.partial && !matches!(event, ClusterOperation::DestroyCluster(_)) {
+                info!("The cluster is partially restored, ignoring the cluster operation");
+                return Ok(());
+            }
+        }
         let sender = &origin;
         match event {
             ClusterOperation::DispatchKeys(event) => {
@@ -1131,6 +1342,11 @@ impl<Platform: pal::Platform> System<Platform> {
                     .get_cluster_mut(&cluster_id) else {
                         return Ok(());
                     };
+                if !cluster.config.deploy_permission.can_deploy(&origin) {
+                    anyhow::bail!(
+                        "Failed to upload resource to cluster {cluster_id:?}: {origin:?} is not allowed to deploy"
+                    );
+                }
                 let system_contract = cluster.system_contract().ok_or_else(|| {
                     anyhow!(
                         "Failed to upload resource to cluster {cluster_id:?}: No system contract"
@@ -1213,6 +1429,80 @@ impl<Platform: pal::Platform> System<Platform> {
                 warn!("If you want to keep providing computation power to some cluster, please create a new worker.");
                 std::process::exit(0);
             }
+            ClusterOperation::UpdatePermission {
+                cluster_id,
+                permission,
+            } => {
+                if !sender.is_pallet() {
+                    anyhow::bail!("Invalid origin");
+                }
+                let Some(cluster) = self.contract_cluster.get_cluster_mut(&cluster_id) else {
+                    return Ok(());
+                };
+                info!("Cluster {cluster_id} permission updated to {permission:?}");
+                let deploy_permission = &mut cluster.config.deploy_permission;
+                if deploy_permission.owner.is_none() {
+                    // Clusters deployed before the permission was tracked don't know their owner.
+                    deploy_permission.owner = block
+                        .storage
+                        .get_cluster_info(&cluster_id)
+                        .map(|info| info.owner);
+                }
+                deploy_permission.permission = Some(permission);
+            }
+            ClusterOperation::SetDeployer {
+                cluster_id,
+                account,
+                enabled,
+            } => {
+                if !sender.is_pallet() {
+                    anyhow::bail!("Invalid origin");
+                }
+                let Some(cluster) = self.contract_cluster.get_cluster_mut(&cluster_id) else {
+                    return Ok(());
+                };
+                info!("Cluster {cluster_id} deployer {account:?} enabled={enabled}");
+                cluster
+                    .config
+                    .deploy_permission
+                    .set_deployer(account, enabled);
+            }
+            ClusterOperation::SetCodeAllowList {
+                cluster_id,
+                code_hashes,
+            } => {
+                if !sender.is_pallet() {
+                    anyhow::bail!("Invalid origin");
+                }
+                let Some(cluster) = self.contract_cluster.get_cluster_mut(&cluster_id) else {
+                    return Ok(());
+                };
+                info!("Cluster {cluster_id} code allow-list updated to {code_hashes:?}");
+                cluster.config.deploy_permission.code_allowlist = code_hashes;
+            }
+            ClusterOperation::SettleCluster {
+                cluster_id,
+                accounts,
+            } => {
+                if !sender.is_pallet() {
+                    anyhow::bail!("Invalid origin");
+                }
+                let Some(cluster) = self.contract_cluster.get_cluster(&cluster_id) else {
+                    return Ok(());
+                };
+                let balances = accounts
+                    .into_iter()
+                    .map(|account| {
+                        let balance = cluster.free_balance(&account);
+                        (account, balance)
+                    })
+                    .collect();
+                self.egress
+                    .push_message(&WorkerClusterReport::ClusterSettled {
+                        id: cluster_id,
+                        balances,
+                    });
+            }
         }
         Ok(())
     }
@@ -1241,6 +1531,19 @@ impl<Platform: pal::Platform> System<Platform> {
                     .get_cluster_mut(&cluster_id) else {
                         return Ok(());
                     };
+                let permission = &cluster.config.deploy_permission;
+                if !permission.can_deploy(&contract_info.deployer) {
+                    anyhow::bail!(
+                        "{:?} is not allowed to deploy contracts to cluster {cluster_id:?}",
+                        contract_info.deployer
+                    );
+                }
+                if !permission.can_instantiate(&contract_info.code_index) {
+                    anyhow::bail!(
+                        "Code {:?} is not in the code allow-list of cluster {cluster_id:?}",
+                        contract_info.code_index.code_hash()
+                    );
+                }
                 if cluster.system_contract().is_none() {
                     anyhow::bail!("The system contract is missing, Cannot deploy contract");
                 }
@@ -1278,6 +1581,28 @@ impl<Platform: pal::Platform> System<Platform> {
                     }
                 }
             }
+            ContractOperation::CodeChanged {
+                contract_id,
+                code_hash,
+                rollback,
+            } => {
+                if self.contracts.get(&contract_id.0.into()).is_none() {
+                    return Ok(());
+                }
+                let action = if rollback {
+                    "rolling back"
+                } else {
+                    "upgrading"
+                };
+                info!(
+                    "Contract {} {action} to code {code_hash:?} at block {}",
+                    hex_fmt::HexFmt(&contract_id),
+                    block.block_number
+                );
+                // The migration message is executed after the system messages, so the result is
+                // reported at the end of the block.
+                self.pending_code_reports.push(contract_id);
+            }
         }
         Ok(())
     }
@@ -1356,15 +1681,16 @@ impl<Platform: pal::Platform> System<Platform> {
 
     /// Decrypt the rotated master key
     ///
-    /// The new master key takes effect immediately after the GatekeeperRegistryEvent::RotatedMasterPubkey is sent
+    /// Before `CONSENSUS_VERSION_MASTER_KEY_QUORUM`, the new master key takes effect immediately after the
+    /// GatekeeperRegistryEvent::RotatedMasterPubkey is sent. Since then, the new master key is kept pending and
+    /// acknowledged on chain, it takes effect after the on-chain quorum is reached and
+    /// GatekeeperLaunch::MasterPubkeyRotated is received.
     fn process_batch_rotate_master_key(
         &mut self,
         block: &mut BlockInfo,
         origin: MessageOrigin,
         event: BatchRotateMasterKeyEvent,
     ) -> Result<(), TransactionError> {
-        // ATTENTION.shelven: There would be a mismatch between on-chain and off-chain master key until the on-chain pubkey
-        // is updated, which may cause problem in the future.
         if !origin.is_gatekeeper() {
             error!("Invalid origin {:?} sent a {:?}", origin, event);
             return Err(TransactionError::BadOrigin);
@@ -1397,6 +1723,8 @@ impl<Platform: pal::Platform> System<Platform> {
             return Ok(());
         }
 
+        let quorum_rotation =
+            block.storage.pruntime_consensus_version() >= CONSENSUS_VERSION_MASTER_KEY_QUORUM;
         // for gatekeeper (both active or unregistered)
         if event.secret_keys.contains_key(&my_pubkey) {
             let encrypted_key = &event.secret_keys[&my_pubkey];
@@ -1412,15 +1740,26 @@ impl<Platform: pal::Platform> System<Platform> {
                 block_height: self.block_number,
                 secret: new_master_key.dump_secret_key(),
             }) {
-                master_key::seal(
-                    self.sealing_path.clone(),
-                    gatekeeper.master_key_history(),
-                    &self.identity_key,
-                    &self.platform,
-                );
+                self.seal_master_key_history();
+            }
+            if quorum_rotation
+                && self
+                    .gatekeeper
+                    .as_ref()
+                    .expect("checked; qed.")
+                    .registered_on_chain()
+            {
+                self.egress
+                    .push_message(&RegistryEvent::MasterKeyRotationAck {
+                        rotation_id: event.rotation_id,
+                        master_pubkey: new_master_key.public(),
+                    });
             }
         }
 
+        if quorum_rotation {
+            return Ok(());
+        }
         if self
             .gatekeeper
             .as_mut()
@@ -1499,6 +1838,21 @@ impl<Platform: pal::Platform> System<Platform> {
                 &cluster_key,
                 block.storage.pink_runtime_version(),
             );
+            // The permission messages sent along with the cluster creation arrive before the
+            // cluster key, so take the permission from the chain state.
+            cluster.config.deploy_permission = DeployPermission {
+                permission: block
+                    .storage
+                    .get_cluster_info(&cluster_id)
+                    .map(|info| info.permission),
+                owner: Some(owner.clone()),
+                deployers: block
+                    .storage
+                    .get_cluster_deployers(&cluster_id)
+                    .into_iter()
+                    .collect(),
+                code_allowlist: block.storage.get_cluster_code_allowlist(&cluster_id),
+            };
             let config = ClusterSetupConfig {
                 cluster_id: event.cluster,
                 owner,
@@ -1536,6 +1890,12 @@ impl<Platform: pal::Platform> System<Platform> {
         Ok(())
     }
 
+    /// Reports to the chain that the cluster state handed off by another worker has been applied.
+    pub(crate) fn report_cluster_state_synced(&self, cluster: phala_mq::ContractClusterId) {
+        let message = WorkerClusterReport::ClusterStateSynced { id: cluster };
+        self.egress.push_message(&message);
+    }
+
     pub fn is_registered(&self) -> bool {
         self.worker_state.registered
     }
@@ -1839,6 +2199,22 @@ pub(crate) fn apply_pink_events(
                 cluster.default_runtime_mut().on_runtime_upgrade();
                 info!("Runtime upgraded to {version:?}");
             }
+            PinkEvent::SetHttpEgressPolicy { contract, policy } => {
+                ensure_system!();
+                info!("Set http egress policy for {contract:?} to {policy:?}");
+                cluster
+                    .config
+                    .http_egress_policies
+                    .set(contract.map(|c| c.convert_to()), policy);
+            }
+            PinkEvent::SetQueryPolicy { contract, policy } => {
+                ensure_system!();
+                info!("Set query policy for {contract:?} to {policy:?}");
+                cluster
+                    .config
+                    .query_policies
+                    .set(contract.map(|c| c.convert_to()), policy);
+            }
         }
     }
 }
@@ -1917,3 +2293,298 @@ pub mod chain_state {
         chain_storage.gatekeepers().contains(pubkey)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain::pallet_computation::{NextSessionId, SessionInfo, Sessions, WorkerBindings};
+    use phala_mq::ContractClusterId;
+
+    const INIT_V: u128 = 1 << 40;
+    const INIT_P: u32 = 1000;
+
+    fn pubkey() -> WorkerPublicKey {
+        WorkerPublicKey::from_raw([1; 32])
+    }
+
+    /// A chain state with the worker bound to a session in given state.
+    fn storage_with_session(state: &str) -> ChainStorage {
+        let session = chain::AccountId::new([2; 32]);
+        let info: SessionInfo = serde_json::from_value(serde_json::json!({
+            "state": state,
+            "ve": INIT_V,
+            "v": INIT_V,
+            "v_updated_at": 0,
+            "benchmark": {
+                "p_init": INIT_P,
+                "p_instant": INIT_P,
+                "iterations": 0,
+                "working_start_time": 0,
+                "challenge_time_last": 0,
+            },
+            "cool_down_start": 0,
+            "stats": { "total_reward": 0 },
+        }))
+        .unwrap();
+        ChainStorage::from_pairs(
+            [
+                (
+                    WorkerBindings::<chain::Runtime>::hashed_key_for(pubkey()),
+                    session.encode(),
+                ),
+                (
+                    Sessions::<chain::Runtime>::hashed_key_for(session),
+                    info.encode(),
+                ),
+                (
+                    NextSessionId::<chain::Runtime>::hashed_key().to_vec(),
+                    6u32.encode(),
+                ),
+            ]
+            .into_iter(),
+        )
+    }
+
+    fn started(session_id: u32, init_v: u128) -> WorkerEvent {
+        WorkerEvent::Started {
+            session_id,
+            init_v,
+            init_p: INIT_P,
+        }
+    }
+
+    #[test]
+    fn restored_session_from_replayed_start() {
+        let storage = storage_with_session("WorkerIdle");
+        let replayed = [
+            (10, 60_000, started(1, INIT_V / 2)),
+            (11, 66_000, WorkerEvent::Stopped),
+            (20, 120_000, started(5, INIT_V)),
+        ];
+        assert_eq!(
+            restored_session(&storage, &pubkey(), &replayed).unwrap(),
+            Some(RestoredSession {
+                session_id: 5,
+                state: WorkingState::Computing,
+                start_time: 120_000,
+            })
+        );
+        let storage = storage_with_session("WorkerUnresponsive");
+        assert_eq!(
+            restored_session(&storage, &pubkey(), &replayed)
+                .unwrap()
+                .map(|session| session.state),
+            Some(WorkingState::Paused)
+        );
+        // Not computing
+        let storage = storage_with_session("WorkerCoolingDown");
+        assert_eq!(
+            restored_session(&storage, &pubkey(), &replayed).unwrap(),
+            None
+        );
+        let storage = ChainStorage::default();
+        assert_eq!(
+            restored_session(&storage, &pubkey(), &replayed).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn restored_session_rejects_mismatched_start() {
+        let storage = storage_with_session("WorkerIdle");
+        // Not replayed
+        assert!(restored_session(&storage, &pubkey(), &[]).is_err());
+        // Stopped afterwards
+        let replayed = [
+            (20, 120_000, started(5, INIT_V)),
+            (21, 126_000, WorkerEvent::Stopped),
+        ];
+        assert!(restored_session(&storage, &pubkey(), &replayed).is_err());
+        // An earlier session with different stake
+        let replayed = [(10, 60_000, started(1, INIT_V / 2))];
+        assert!(restored_session(&storage, &pubkey(), &replayed).is_err());
+        // A session id not allocated yet
+        let replayed = [(20, 120_000, started(6, INIT_V))];
+        assert!(restored_session(&storage, &pubkey(), &replayed).is_err());
+    }
+
+    fn system_with_cluster(
+        cluster_id: ContractClusterId,
+        owner: &chain::AccountId,
+        send_mq: &MessageSendQueue,
+        recv_mq: &mut MessageDispatcher,
+    ) -> System<crate::mock_platform::MockPlatform> {
+        let identity_key = sr25519::Pair::from_seed(&[1; 32]);
+        let ecdh_key = identity_key.derive_ecdh_key().unwrap();
+        let mut system = System::new(
+            crate::mock_platform::MockPlatform::new(b"deploy-permission"),
+            false,
+            String::new(),
+            String::new(),
+            identity_key,
+            ecdh_key,
+            send_mq,
+            recv_mq,
+            1,
+        );
+        let mut cluster = Cluster {
+            id: cluster_id,
+            config: Default::default(),
+            storage: Default::default(),
+            partial: false,
+        };
+        cluster.config.deploy_permission.owner = Some(owner.clone());
+        system.contract_cluster = Some(cluster);
+        system
+    }
+
+    fn instantiate(
+        cluster_id: ContractClusterId,
+        deployer: &chain::AccountId,
+        code_hash: chain::Hash,
+    ) -> ContractOperation<chain::Hash, chain::AccountId> {
+        ContractOperation::instantiate_code(
+            contract::ContractInfo {
+                deployer: deployer.clone(),
+                code_index: CodeIndex::WasmCode(code_hash),
+                salt: vec![],
+                cluster_id,
+                instantiate_data: vec![],
+            },
+            0,
+            0,
+            None,
+        )
+    }
+
+    #[test]
+    fn deploy_permission_follows_cluster_operations() {
+        let cluster_id = ContractClusterId::from([3; 32]);
+        let owner = chain::AccountId::new([4; 32]);
+        let deployer = chain::AccountId::new([5; 32]);
+        let allowed_code = chain::Hash::from([6; 32]);
+        let other_code = chain::Hash::from([7; 32]);
+        let pallet = MessageOrigin::Pallet(b"PhalaPhat".to_vec());
+
+        let storage = ChainStorage::default();
+        let send_mq = MessageSendQueue::default();
+        let mut recv_mq = MessageDispatcher::default();
+        let mut system = system_with_cluster(cluster_id, &owner, &send_mq, &mut recv_mq);
+        let mut block = BlockInfo {
+            block_number: 1,
+            now_ms: 0,
+            storage: &storage,
+            send_mq: &send_mq,
+            recv_mq: &mut recv_mq,
+        };
+        let permission = |system: &System<_>| {
+            system
+                .contract_cluster
+                .as_ref()
+                .unwrap()
+                .config
+                .deploy_permission
+                .clone()
+        };
+
+        system
+            .process_cluster_operation_event(
+                &mut block,
+                pallet.clone(),
+                ClusterOperation::UpdatePermission {
+                    cluster_id,
+                    permission: contract::ClusterPermission::AllowList(vec![]),
+                },
+            )
+            .unwrap();
+        let state = permission(&system);
+        assert_eq!(
+            state.permission,
+            Some(contract::ClusterPermission::AllowList(vec![]))
+        );
+        assert!(state.can_deploy(&owner));
+        assert!(!state.can_deploy(&deployer));
+        let err = system
+            .process_contract_operation_event(
+                &mut block,
+                pallet.clone(),
+                instantiate(cluster_id, &deployer, allowed_code),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("not allowed to deploy"));
+        let err = system
+            .process_cluster_operation_event(
+                &mut block,
+                pallet.clone(),
+                ClusterOperation::UploadResource {
+                    origin: deployer.clone(),
+                    cluster_id,
+                    resource_type: ResourceType::InkCode,
+                    resource_data: vec![],
+                },
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("not allowed to deploy"));
+
+        // Only the pallet can change the permission
+        let set_deployer = |enabled| ClusterOperation::SetDeployer {
+            cluster_id,
+            account: deployer.clone(),
+            enabled,
+        };
+        assert!(system
+            .process_cluster_operation_event(
+                &mut block,
+                MessageOrigin::Worker(pubkey()),
+                set_deployer(true),
+            )
+            .is_err());
+        assert!(permission(&system).deployers.is_empty());
+        system
+            .process_cluster_operation_event(&mut block, pallet.clone(), set_deployer(true))
+            .unwrap();
+        let state = permission(&system);
+        assert!(state.deployers.contains(&deployer));
+        assert!(state.can_deploy(&deployer));
+
+        system
+            .process_cluster_operation_event(
+                &mut block,
+                pallet.clone(),
+                ClusterOperation::SetCodeAllowList {
+                    cluster_id,
+                    code_hashes: Some(vec![allowed_code]),
+                },
+            )
+            .unwrap();
+        let state = permission(&system);
+        assert_eq!(state.code_allowlist, Some(vec![allowed_code]));
+        assert!(state.can_instantiate(&CodeIndex::WasmCode(allowed_code)));
+        let err = system
+            .process_contract_operation_event(
+                &mut block,
+                pallet.clone(),
+                instantiate(cluster_id, &deployer, other_code),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("code allow-list"));
+
+        system
+            .process_cluster_operation_event(&mut block, pallet.clone(), set_deployer(false))
+            .unwrap();
+        assert!(!permission(&system).can_deploy(&deployer));
+
+        // Operations on other clusters are ignored
+        system
+            .process_cluster_operation_event(
+                &mut block,
+                pallet,
+                ClusterOperation::UpdatePermission {
+                    cluster_id: ContractClusterId::from([8; 32]),
+                    permission: contract::ClusterPermission::Public,
+                },
+            )
+            .unwrap();
+        assert!(!permission(&system).can_deploy(&deployer));
+    }
+}
@@ -0,0 +1,219 @@
+//! A minimal DER reader, which is enough to walk the certs and the CRLs of the PCK chain.
+
+pub const DER_TAG_BOOLEAN: u8 = 0x01;
+pub const DER_TAG_INTEGER: u8 = 0x02;
+pub const DER_TAG_BIT_STRING: u8 = 0x03;
+pub const DER_TAG_OCTET_STRING: u8 = 0x04;
+pub const DER_TAG_OID: u8 = 0x06;
+pub const DER_TAG_UTC_TIME: u8 = 0x17;
+pub const DER_TAG_GENERALIZED_TIME: u8 = 0x18;
+pub const DER_TAG_SEQUENCE: u8 = 0x30;
+pub const DER_TAG_SET: u8 = 0x31;
+pub const DER_TAG_VERSION: u8 = 0xa0;
+pub const DER_TAG_EXTENSIONS: u8 = 0xa3;
+
+/// A DER element.
+pub struct Tlv<'a> {
+    pub tag: u8,
+    pub value: &'a [u8],
+    /// The whole element, including the tag and the length.
+    pub raw: &'a [u8],
+}
+
+pub struct Der<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    pub fn read(&mut self) -> Option<Tlv<'a>> {
+        let tag = *self.data.first()?;
+        let first = *self.data.get(1)? as usize;
+        let (len, header_len) = if first < 0x80 {
+            (first, 2)
+        } else {
+            let len_bytes = first & 0x7f;
+            if len_bytes == 0 || len_bytes > 4 {
+                return None;
+            }
+            let len = self
+                .data
+                .get(2..2 + len_bytes)?
+                .iter()
+                .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+            (len, 2 + len_bytes)
+        };
+        let raw = self.data.get(..header_len.checked_add(len)?)?;
+        self.data = &self.data[raw.len()..];
+        Some(Tlv {
+            tag,
+            value: &raw[header_len..],
+            raw,
+        })
+    }
+
+    pub fn expect(&mut self, tag: u8) -> Option<Tlv<'a>> {
+        self.read().filter(|tlv| tlv.tag == tag)
+    }
+
+    /// Read the content of a SEQUENCE.
+    pub fn sequence(&mut self) -> Option<Der<'a>> {
+        Some(Der::new(self.expect(DER_TAG_SEQUENCE)?.value))
+    }
+}
+
+pub fn der_uint(value: &[u8]) -> Option<u64> {
+    if value.is_empty() || value[0] & 0x80 != 0 {
+        return None;
+    }
+    let value = match value {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        _ => value,
+    };
+    if value.len() > 8 {
+        return None;
+    }
+    Some(value.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+}
+
+/// Parse a UTCTime or GeneralizedTime in the form of `YYMMDDHHMMSSZ` or `YYYYMMDDHHMMSSZ`.
+pub fn der_time(tlv: &Tlv) -> Option<i64> {
+    let text = core::str::from_utf8(tlv.value).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match tlv.tag {
+        DER_TAG_UTC_TIME => {
+            let year: i32 = text.get(..2)?.parse().ok()?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &text[2..],
+            )
+        }
+        DER_TAG_GENERALIZED_TIME => (text.get(..4)?.parse().ok()?, &text[4..]),
+        _ => return None,
+    };
+    if rest.len() != 10 || !rest.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |i: usize| rest[i..i + 2].parse::<u32>().ok();
+    let time = chrono::NaiveDate::from_ymd_opt(year, field(0)?, field(2)?)?.and_hms_opt(
+        field(4)?,
+        field(6)?,
+        field(8)?,
+    )?;
+    Some(time.timestamp())
+}
+
+/// Split a signed DER structure, a cert or a CRL, into the signed part and the signature.
+pub fn parse_signed(der: &[u8]) -> Option<(Tlv<'_>, &[u8])> {
+    let mut signed = Der::new(der).sequence()?;
+    let tbs = signed.expect(DER_TAG_SEQUENCE)?;
+    // signatureAlgorithm
+    signed.expect(DER_TAG_SEQUENCE)?;
+    // No unused bits in the signature
+    let signature = signed
+        .expect(DER_TAG_BIT_STRING)?
+        .value
+        .strip_prefix(&[0])?;
+    Some((tbs, signature))
+}
+
+/// The fields of an X.509 cert used to verify the DCAP collateral.
+pub struct CertFields<'a> {
+    /// The signed `tbsCertificate`.
+    pub tbs: &'a [u8],
+    pub signature: &'a [u8],
+    pub serial: &'a [u8],
+    /// The DER encoded issuer name.
+    pub issuer: &'a [u8],
+    /// The DER encoded subject name.
+    pub subject: &'a [u8],
+    pub not_before: i64,
+    pub not_after: i64,
+    extensions: Option<&'a [u8]>,
+}
+
+impl<'a> CertFields<'a> {
+    pub fn parse(cert: &'a [u8]) -> Option<Self> {
+        let (tbs, signature) = parse_signed(cert)?;
+        let mut fields = Der::new(tbs.value);
+        if fields.peek_tag() == Some(DER_TAG_VERSION) {
+            fields.read()?;
+        }
+        let serial = fields.expect(DER_TAG_INTEGER)?.value;
+        // signature
+        fields.expect(DER_TAG_SEQUENCE)?;
+        let issuer = fields.expect(DER_TAG_SEQUENCE)?.raw;
+        let mut validity = fields.sequence()?;
+        let not_before = der_time(&validity.read()?)?;
+        let not_after = der_time(&validity.read()?)?;
+        let subject = fields.expect(DER_TAG_SEQUENCE)?.raw;
+        // subjectPublicKeyInfo
+        fields.expect(DER_TAG_SEQUENCE)?;
+        let mut extensions = None;
+        // Skip the optional unique ids
+        while let Some(field) = fields.read() {
+            if field.tag == DER_TAG_EXTENSIONS {
+                extensions = Some(Der::new(field.value).expect(DER_TAG_SEQUENCE)?.value);
+            }
+        }
+        Some(Self {
+            tbs: tbs.raw,
+            signature,
+            serial,
+            issuer,
+            subject,
+            not_before,
+            not_after,
+            extensions,
+        })
+    }
+
+    /// The value of the extension with given OID.
+    pub fn extension(&self, oid: &[u8]) -> Option<&'a [u8]> {
+        let mut extensions = Der::new(self.extensions?);
+        while let Some(extension) = extensions.read() {
+            let mut extension = Der::new(extension.value);
+            if extension.expect(DER_TAG_OID)?.value != oid {
+                continue;
+            }
+            if extension.peek_tag() == Some(DER_TAG_BOOLEAN) {
+                extension.read()?;
+            }
+            return Some(extension.expect(DER_TAG_OCTET_STRING)?.value);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_der_time() {
+        let utc = Tlv {
+            tag: DER_TAG_UTC_TIME,
+            value: b"240601000000Z",
+            raw: &[],
+        };
+        assert_eq!(der_time(&utc), Some(1717200000));
+        let generalized = Tlv {
+            tag: DER_TAG_GENERALIZED_TIME,
+            value: b"20500101000000Z",
+            raw: &[],
+        };
+        assert_eq!(der_time(&generalized), Some(2524608000));
+        let malformed = Tlv {
+            tag: DER_TAG_UTC_TIME,
+            value: b"2406010000Z",
+            raw: &[],
+        };
+        assert_eq!(der_time(&malformed), None);
+    }
+}
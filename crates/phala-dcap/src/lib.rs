@@ -0,0 +1,248 @@
+//! Parsing of SGX DCAP (ECDSA) quotes and PCK certs.
+//!
+//! Only the parsing lives here, so that pRuntime can read its own quotes without linking the
+//! pallets. The verification of the quotes against the collateral is done on chain, see
+//! `phala_pallets::utils::attestation_dcap`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+pub mod der;
+mod pck;
+
+pub use pck::{pck_ca_type, PckExtensions};
+
+const QUOTE_VERSION_V3: u16 = 3;
+const ATTESTATION_KEY_TYPE_ECDSA_P256: u16 = 2;
+const TEE_TYPE_SGX: u32 = 0;
+const CERT_DATA_TYPE_PCK_CERT_CHAIN: u16 = 5;
+
+const HEADER_LEN: usize = 48;
+const REPORT_LEN: usize = 384;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteError {
+    /// The quote is truncated or malformed.
+    Invalid,
+    /// The quote is not a v3 ECDSA P-256 quote of SGX.
+    UnsupportedVersion,
+}
+
+/// The fields we care about in an SGX enclave report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnclaveReport {
+    pub misc_select: u32,
+    pub attributes: [u8; 16],
+    pub mr_enclave: [u8; 32],
+    pub mr_signer: [u8; 32],
+    pub isv_prod_id: [u8; 2],
+    pub isv_svn: [u8; 2],
+    pub report_data: [u8; 64],
+}
+
+impl EnclaveReport {
+    fn parse(raw: &[u8]) -> Self {
+        Self {
+            misc_select: u32::from_le_bytes(raw[16..20].try_into().expect("Report length checked")),
+            attributes: raw[48..64].try_into().expect("Report length checked"),
+            mr_enclave: raw[64..96].try_into().expect("Report length checked"),
+            mr_signer: raw[128..160].try_into().expect("Report length checked"),
+            isv_prod_id: raw[256..258].try_into().expect("Report length checked"),
+            isv_svn: raw[258..260].try_into().expect("Report length checked"),
+            report_data: raw[320..384].try_into().expect("Report length checked"),
+        }
+    }
+
+    /// Same layout as `IasFields::extend_mrenclave`.
+    pub fn runtime_hash(&self) -> Vec<u8> {
+        let mut hash = Vec::new();
+        hash.extend_from_slice(&self.mr_enclave);
+        hash.extend_from_slice(&self.isv_prod_id);
+        hash.extend_from_slice(&self.isv_svn);
+        hash.extend_from_slice(&self.mr_signer);
+        hash
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], QuoteError> {
+        if self.data.len() < len {
+            return Err(QuoteError::Invalid);
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, QuoteError> {
+        Ok(u16::from_le_bytes(
+            self.take(2)?.try_into().expect("Length checked"),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32, QuoteError> {
+        Ok(u32::from_le_bytes(
+            self.take(4)?.try_into().expect("Length checked"),
+        ))
+    }
+}
+
+/// A parsed v3 ECDSA quote.
+pub struct Quote<'a> {
+    /// The header and the enclave report, signed by the attestation key.
+    pub signed_data: &'a [u8],
+    pub report: EnclaveReport,
+    pub signature: &'a [u8],
+    pub attestation_key: &'a [u8],
+    pub raw_qe_report: &'a [u8],
+    pub qe_report: EnclaveReport,
+    pub qe_report_signature: &'a [u8],
+    pub qe_auth_data: &'a [u8],
+    /// PEM encoded PCK cert chain.
+    pub pck_cert_chain: &'a [u8],
+}
+
+impl<'a> Quote<'a> {
+    pub fn parse(raw: &'a [u8]) -> Result<Self, QuoteError> {
+        let mut reader = Reader { data: raw };
+        let mut header = Reader {
+            data: reader.take(HEADER_LEN)?,
+        };
+        let version = header.u16()?;
+        let attestation_key_type = header.u16()?;
+        let tee_type = header.u32()?;
+        if version != QUOTE_VERSION_V3
+            || attestation_key_type != ATTESTATION_KEY_TYPE_ECDSA_P256
+            || tee_type != TEE_TYPE_SGX
+        {
+            return Err(QuoteError::UnsupportedVersion);
+        }
+        let report = EnclaveReport::parse(reader.take(REPORT_LEN)?);
+        let signed_data = &raw[..HEADER_LEN + REPORT_LEN];
+
+        let signature_data_len = reader.u32()? as usize;
+        let mut reader = Reader {
+            data: reader.take(signature_data_len)?,
+        };
+        let signature = reader.take(64)?;
+        let attestation_key = reader.take(64)?;
+        let raw_qe_report = reader.take(REPORT_LEN)?;
+        let qe_report_signature = reader.take(64)?;
+        let qe_auth_data_len = reader.u16()? as usize;
+        let qe_auth_data = reader.take(qe_auth_data_len)?;
+        let cert_data_type = reader.u16()?;
+        let cert_data_len = reader.u32()? as usize;
+        let pck_cert_chain = reader.take(cert_data_len)?;
+        if cert_data_type != CERT_DATA_TYPE_PCK_CERT_CHAIN {
+            return Err(QuoteError::Invalid);
+        }
+
+        Ok(Self {
+            signed_data,
+            report,
+            signature,
+            attestation_key,
+            raw_qe_report,
+            qe_report: EnclaveReport::parse(raw_qe_report),
+            qe_report_signature,
+            qe_auth_data,
+            pck_cert_chain,
+        })
+    }
+}
+
+/// Decode the DER certs from a PEM encoded cert chain.
+pub fn pem_to_der_chain(pem: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let pem = core::str::from_utf8(pem).ok()?;
+    let mut certs = Vec::new();
+    for block in pem.split("-----BEGIN CERTIFICATE-----").skip(1) {
+        let body = block.split("-----END CERTIFICATE-----").next()?;
+        let body: Vec<u8> = body.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        certs.push(base64::decode(body).ok()?);
+    }
+    if certs.is_empty() {
+        return None;
+    }
+    Some(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn build_quote(version: u16, cert_data_type: u16) -> Vec<u8> {
+        let mut quote = Vec::new();
+        quote.extend_from_slice(&version.to_le_bytes());
+        quote.extend_from_slice(&ATTESTATION_KEY_TYPE_ECDSA_P256.to_le_bytes());
+        quote.extend_from_slice(&TEE_TYPE_SGX.to_le_bytes());
+        quote.resize(HEADER_LEN, 0);
+        let mut report = [0u8; REPORT_LEN];
+        report[64..96].copy_from_slice(&[1u8; 32]);
+        report[128..160].copy_from_slice(&[2u8; 32]);
+        report[256..258].copy_from_slice(&[3, 0]);
+        report[258..260].copy_from_slice(&[4, 0]);
+        report[320..384].copy_from_slice(&[5u8; 64]);
+        quote.extend_from_slice(&report);
+
+        let cert_data = b"-----BEGIN CERTIFICATE-----\nAQID\n-----END CERTIFICATE-----\n";
+        let mut signature_data = Vec::new();
+        signature_data.extend_from_slice(&[6u8; 64]);
+        signature_data.extend_from_slice(&[7u8; 64]);
+        signature_data.extend_from_slice(&report);
+        signature_data.extend_from_slice(&[8u8; 64]);
+        signature_data.extend_from_slice(&2u16.to_le_bytes());
+        signature_data.extend_from_slice(&[9u8; 2]);
+        signature_data.extend_from_slice(&cert_data_type.to_le_bytes());
+        signature_data.extend_from_slice(&(cert_data.len() as u32).to_le_bytes());
+        signature_data.extend_from_slice(cert_data);
+        quote.extend_from_slice(&(signature_data.len() as u32).to_le_bytes());
+        quote.extend_from_slice(&signature_data);
+        quote
+    }
+
+    #[test]
+    fn test_parse_quote() {
+        let raw = build_quote(QUOTE_VERSION_V3, CERT_DATA_TYPE_PCK_CERT_CHAIN);
+        let quote = Quote::parse(&raw).unwrap();
+        assert_eq!(quote.report.mr_enclave, [1u8; 32]);
+        assert_eq!(quote.report.mr_signer, [2u8; 32]);
+        assert_eq!(quote.report.report_data, [5u8; 64]);
+        assert_eq!(quote.signed_data.len(), HEADER_LEN + REPORT_LEN);
+        assert_eq!(quote.signature, &[6u8; 64][..]);
+        assert_eq!(quote.attestation_key, &[7u8; 64][..]);
+        assert_eq!(quote.qe_report, quote.report);
+        assert_eq!(quote.qe_auth_data, &[9u8; 2][..]);
+        assert_eq!(
+            pem_to_der_chain(quote.pck_cert_chain),
+            Some(vec![vec![1, 2, 3]])
+        );
+        assert_eq!(
+            quote.report.runtime_hash(),
+            [&[1u8; 32][..], &[3, 0], &[4, 0], &[2u8; 32]].concat()
+        );
+    }
+
+    #[test]
+    fn test_reject_malformed_quote() {
+        let raw = build_quote(4, CERT_DATA_TYPE_PCK_CERT_CHAIN);
+        assert!(matches!(
+            Quote::parse(&raw),
+            Err(QuoteError::UnsupportedVersion)
+        ));
+        let raw = build_quote(QUOTE_VERSION_V3, 1);
+        assert!(matches!(Quote::parse(&raw), Err(QuoteError::Invalid)));
+        let raw = build_quote(QUOTE_VERSION_V3, CERT_DATA_TYPE_PCK_CERT_CHAIN);
+        assert!(matches!(
+            Quote::parse(&raw[..raw.len() - 1]),
+            Err(QuoteError::Invalid)
+        ));
+    }
+}
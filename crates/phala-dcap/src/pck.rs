@@ -0,0 +1,99 @@
+use alloc::vec::Vec;
+
+use crate::der::*;
+
+/// OID of the SGX extensions in PCK certs: 1.2.840.113741.1.13.1
+const SGX_EXTENSION_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf8, 0x4d, 0x01, 0x0d, 0x01];
+const SGX_TCB_OID_SUFFIX: u8 = 0x02;
+const SGX_FMSPC_OID_SUFFIX: u8 = 0x04;
+const SGX_PCE_SVN_OID_SUFFIX: u8 = 17;
+/// OID of the common name attribute: 2.5.4.3
+const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+/// Iterate the `(OID, value)` items in the SGX extension or its TCB item.
+fn sgx_items<'a>(sequence: &'a [u8]) -> Option<Vec<(&'a [u8], Tlv<'a>)>> {
+    let mut items = Der::new(sequence);
+    let mut output = Vec::new();
+    while items.peek_tag().is_some() {
+        let mut item = items.sequence()?;
+        let oid = item.expect(DER_TAG_OID)?.value;
+        output.push((oid.strip_prefix(SGX_EXTENSION_OID)?, item.read()?));
+    }
+    Some(output)
+}
+
+/// The SGX extensions of a PCK cert.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PckExtensions {
+    pub fmspc: [u8; 6],
+    pub tcb_components: [u8; 16],
+    pub pce_svn: u16,
+}
+
+impl PckExtensions {
+    pub fn parse(cert: &[u8]) -> Option<Self> {
+        let cert = CertFields::parse(cert)?;
+        let extension = cert.extension(SGX_EXTENSION_OID)?;
+        let extension = Der::new(extension).expect(DER_TAG_SEQUENCE)?.value;
+        let mut fmspc = None;
+        let mut tcb = None;
+        for (oid, value) in sgx_items(extension)? {
+            match (oid, value.tag) {
+                ([SGX_FMSPC_OID_SUFFIX], DER_TAG_OCTET_STRING) => fmspc = Some(value.value),
+                ([SGX_TCB_OID_SUFFIX], DER_TAG_SEQUENCE) => tcb = Some(value.value),
+                _ => {}
+            }
+        }
+        let mut tcb_components = [None; 16];
+        let mut pce_svn = None;
+        for (oid, value) in sgx_items(tcb?)? {
+            let [SGX_TCB_OID_SUFFIX, index] = *oid else {
+                continue;
+            };
+            if value.tag != DER_TAG_INTEGER {
+                continue;
+            }
+            match index {
+                1..=16 => {
+                    tcb_components[index as usize - 1] =
+                        Some(u8::try_from(der_uint(value.value)?).ok()?)
+                }
+                SGX_PCE_SVN_OID_SUFFIX => {
+                    pce_svn = Some(u16::try_from(der_uint(value.value)?).ok()?)
+                }
+                _ => {}
+            }
+        }
+        let mut components = [0u8; 16];
+        for (component, parsed) in components.iter_mut().zip(tcb_components) {
+            *component = parsed?;
+        }
+        Some(Self {
+            fmspc: fmspc?.try_into().ok()?,
+            tcb_components: components,
+            pce_svn: pce_svn?,
+        })
+    }
+}
+
+/// The CA issuing the PCK cert, `processor` or `platform`, as named in the PCCS API to fetch the
+/// PCK CRL.
+pub fn pck_ca_type(pck_cert: &[u8]) -> Option<&'static str> {
+    let cert = CertFields::parse(pck_cert)?;
+    let mut names = Der::new(Der::new(cert.issuer).expect(DER_TAG_SEQUENCE)?.value);
+    while names.peek_tag().is_some() {
+        let mut attributes = Der::new(names.expect(DER_TAG_SET)?.value);
+        while attributes.peek_tag().is_some() {
+            let mut attribute = attributes.sequence()?;
+            if attribute.expect(DER_TAG_OID)?.value != COMMON_NAME_OID {
+                continue;
+            }
+            return match attribute.read()?.value {
+                b"Intel SGX PCK Processor CA" => Some("processor"),
+                b"Intel SGX PCK Platform CA" => Some("platform"),
+                _ => None,
+            };
+        }
+    }
+    None
+}
@@ -11,6 +11,13 @@ pub struct Channel {
     dummy: bool,
 }
 
+impl Channel {
+    /// The messages waiting to be sent to the chain.
+    pub fn messages(&self) -> &[SignedMessage] {
+        &self.messages
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct MessageSendQueue {
     inner: Arc<Mutex<BTreeMap<SenderId, Channel>>>,
@@ -128,6 +135,16 @@ impl MessageSendQueue {
         }
     }
 
+    /// Continue the sequence of the sender from the given one, dropping the pending messages.
+    ///
+    /// Used when the previous messages of the sender are not replayed locally.
+    pub fn set_sequence(&self, sender: &SenderId, sequence: u64) {
+        let mut inner = self.inner.lock();
+        let entry = inner.entry(sender.clone()).or_default();
+        entry.messages.clear();
+        entry.sequence = sequence;
+    }
+
     pub fn dump_state(&self, sender: &SenderId) -> Option<Channel> {
         let inner = self.inner.lock();
         inner.get(sender).cloned()
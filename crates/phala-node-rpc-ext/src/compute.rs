@@ -0,0 +1,113 @@
+use super::*;
+use pallet_compute_runtime_api::{
+    OwnerRewards, PendingWithdrawal, PerformanceHistory, PhalaComputeApi, PoolSummary, StakeValue,
+    WorkerPublicKey,
+};
+use std::convert::TryInto;
+use thiserror::Error;
+
+pub use sp_runtime::AccountId32 as AccountId;
+pub type Balance = u128;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid worker public key")]
+    InvalidWorker,
+    #[error("{0}")]
+    ApiError(#[from] sp_api::ApiError),
+}
+
+impl From<Error> for JsonRpseeError {
+    fn from(e: Error) -> Self {
+        JsonRpseeError::Call(CallError::Custom(ErrorObject::owned(
+            CUSTOM_RPC_ERROR,
+            e.to_string(),
+            Option::<()>::None,
+        )))
+    }
+}
+
+fn at_or_best<Client, Block>(client: &Client, at: Option<Block::Hash>) -> Block::Hash
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block>,
+{
+    at.unwrap_or_else(|| client.info().best_hash)
+}
+
+pub(super) fn pool_summary<Client, Block>(
+    client: &Client,
+    pid: u64,
+    at: Option<Block::Hash>,
+) -> Result<Option<PoolSummary<AccountId, Balance>>, Error>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + ProvideRuntimeApi<Block>,
+    Client::Api: PhalaComputeApi<Block, AccountId, Balance>,
+{
+    let at = at_or_best(client, at);
+    Ok(client.runtime_api().pool_summary(at, pid)?)
+}
+
+pub(super) fn account_stakes<Client, Block>(
+    client: &Client,
+    account: AccountId,
+    at: Option<Block::Hash>,
+) -> Result<Vec<StakeValue<Balance>>, Error>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + ProvideRuntimeApi<Block>,
+    Client::Api: PhalaComputeApi<Block, AccountId, Balance>,
+{
+    let at = at_or_best(client, at);
+    Ok(client.runtime_api().account_stakes(at, account)?)
+}
+
+pub(super) fn pending_withdrawals<Client, Block>(
+    client: &Client,
+    account: AccountId,
+    at: Option<Block::Hash>,
+) -> Result<Vec<PendingWithdrawal<Balance>>, Error>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + ProvideRuntimeApi<Block>,
+    Client::Api: PhalaComputeApi<Block, AccountId, Balance>,
+{
+    let at = at_or_best(client, at);
+    Ok(client.runtime_api().pending_withdrawals(at, account)?)
+}
+
+pub(super) fn owner_rewards<Client, Block>(
+    client: &Client,
+    pid: u64,
+    at: Option<Block::Hash>,
+) -> Result<Option<OwnerRewards<Balance>>, Error>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + ProvideRuntimeApi<Block>,
+    Client::Api: PhalaComputeApi<Block, AccountId, Balance>,
+{
+    let at = at_or_best(client, at);
+    Ok(client.runtime_api().owner_rewards(at, pid)?)
+}
+
+pub(super) fn worker_performance<Client, Block>(
+    client: &Client,
+    worker_hex: String,
+    at: Option<Block::Hash>,
+) -> Result<Option<PerformanceHistory>, Error>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + ProvideRuntimeApi<Block>,
+    Client::Api: PhalaComputeApi<Block, AccountId, Balance>,
+{
+    let worker_hex = worker_hex.trim_start_matches("0x");
+    let raw: [u8; 32] = hex::decode(worker_hex)
+        .ok()
+        .and_then(|raw| raw.try_into().ok())
+        .ok_or(Error::InvalidWorker)?;
+    let at = at_or_best(client, at);
+    Ok(client
+        .runtime_api()
+        .worker_performance(at, WorkerPublicKey::from_raw(raw))?)
+}
@@ -2,12 +2,16 @@ use std::marker::PhantomData;
 use std::sync::Arc;
 
 use codec::Encode;
+use compute::{AccountId, Balance};
 use jsonrpsee::{
     core::{async_trait, Error as JsonRpseeError, RpcResult},
     proc_macros::rpc,
     types::error::{CallError, ErrorObject},
     RpcModule,
 };
+use pallet_compute_runtime_api::{
+    OwnerRewards, PendingWithdrawal, PerformanceHistory, PhalaComputeApi, PoolSummary, StakeValue,
+};
 use pallet_mq_runtime_api::MqApi;
 use sc_client_api::blockchain::{HeaderBackend, HeaderMetadata};
 use sc_client_api::{backend, Backend, BlockBackend, StorageProvider};
@@ -20,6 +24,7 @@ use storage_changes::Error as StorageChangesError;
 
 pub use storage_changes::{GetStorageChangesResponse, MakeInto, StorageChanges};
 
+mod compute;
 mod mq_seq;
 mod storage_changes;
 
@@ -46,6 +51,49 @@ pub trait NodeRpcExtApi<BlockHash> {
     /// Return the next mq sequence number for given sender which take the ready transactions in count.
     #[method(name = "pha_getMqNextSequence")]
     fn get_mq_seq(&self, sender_hex: String) -> RpcResult<u64>;
+
+    /// Return the summary of the given stake pool or vault, including the share price, free and
+    /// releasing stake and the withdraw queue.
+    #[method(name = "pha_getPoolSummary")]
+    fn get_pool_summary(
+        &self,
+        pid: u64,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Option<PoolSummary<AccountId, Balance>>>;
+
+    /// Return the shares and their current value the account holds in each pool.
+    #[method(name = "pha_getAccountStakes")]
+    fn get_account_stakes(
+        &self,
+        account: AccountId,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Vec<StakeValue<Balance>>>;
+
+    /// Return the withdraw requests of the account waiting in the withdraw queues, with the
+    /// estimated release time.
+    #[method(name = "pha_getPendingWithdrawals")]
+    fn get_pending_withdrawals(
+        &self,
+        account: AccountId,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Vec<PendingWithdrawal<Balance>>>;
+
+    /// Return the claimable and pending rewards of the owner of the given pool.
+    #[method(name = "pha_getOwnerRewards")]
+    fn get_owner_rewards(
+        &self,
+        pid: u64,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Option<OwnerRewards<Balance>>>;
+
+    /// Return the uptime, offline time, heartbeats and slashed V of the given worker in the
+    /// recent epochs.
+    #[method(name = "pha_getWorkerPerformance")]
+    fn get_worker_performance(
+        &self,
+        worker_hex: String,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Option<PerformanceHistory>>;
 }
 
 /// Stuffs for custom RPC
@@ -82,6 +130,7 @@ where
     Client::Api:
         sp_api::Metadata<Block> + ApiExt<Block, StateBackend = backend::StateBackendFor<BE, Block>>,
     Client::Api: MqApi<Block>,
+    Client::Api: PhalaComputeApi<Block, AccountId, Balance>,
     Block: BlockT + 'static,
     <<Block as BlockT>::Header as Header>::Number: Into<u64>,
     P: TransactionPool + 'static,
@@ -119,6 +168,46 @@ where
 
         Ok(result?)
     }
+
+    fn get_pool_summary(
+        &self,
+        pid: u64,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<Option<PoolSummary<AccountId, Balance>>> {
+        Ok(compute::pool_summary(&*self.client, pid, at)?)
+    }
+
+    fn get_account_stakes(
+        &self,
+        account: AccountId,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<Vec<StakeValue<Balance>>> {
+        Ok(compute::account_stakes(&*self.client, account, at)?)
+    }
+
+    fn get_pending_withdrawals(
+        &self,
+        account: AccountId,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<Vec<PendingWithdrawal<Balance>>> {
+        Ok(compute::pending_withdrawals(&*self.client, account, at)?)
+    }
+
+    fn get_owner_rewards(
+        &self,
+        pid: u64,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<Option<OwnerRewards<Balance>>> {
+        Ok(compute::owner_rewards(&*self.client, pid, at)?)
+    }
+
+    fn get_worker_performance(
+        &self,
+        worker_hex: String,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<Option<PerformanceHistory>> {
+        Ok(compute::worker_performance(&*self.client, worker_hex, at)?)
+    }
 }
 
 pub fn extend_rpc<Client, BE, Block, P>(
@@ -139,6 +228,7 @@ pub fn extend_rpc<Client, BE, Block, P>(
     Client::Api:
         sp_api::Metadata<Block> + ApiExt<Block, StateBackend = backend::StateBackendFor<BE, Block>>,
     Client::Api: MqApi<Block>,
+    Client::Api: PhalaComputeApi<Block, AccountId, Balance>,
     <<Block as BlockT>::Header as Header>::Number: Into<u64>,
     P: TransactionPool + 'static,
 {
@@ -0,0 +1,168 @@
+//! Framing of signed body chunks used to sign large or streamed response bodies.
+//!
+//! The body is streamed as a sequence of frames, each carrying a chunk of at most `chunk_size`
+//! bytes and the signature of the chunk:
+//!
+//! ```text
+//! frame = chunk_len: u32 LE || chunk || last: u8 || signature_len: u16 LE || signature
+//! ```
+//!
+//! The signature covers [`chunk_message`], which binds the chunk to the response (by a stream id
+//! sent in the `X-Phactory-Body-Stream-Id` header), to its position and to whether it is the last
+//! one. So a client can verify every chunk as soon as it arrives, and detects reordered, spliced
+//! or truncated bodies. The last frame may carry an empty chunk.
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+pub type StreamId = [u8; 16];
+
+/// The value of the `X-Phactory-Signature-Scheme` header for the chunk-framed bodies.
+pub const SCHEME: &str = "signed-chunks-v1";
+
+const MESSAGE_PREFIX: &[u8] = b"phala-signed-chunks-v1:";
+
+/// The message that is signed for a chunk.
+pub fn chunk_message(stream_id: &StreamId, index: u64, last: bool, chunk: &[u8]) -> Vec<u8> {
+    let chunk_hash: Hash = Sha256::digest(chunk).into();
+    let mut message = MESSAGE_PREFIX.to_vec();
+    message.extend_from_slice(stream_id);
+    message.extend_from_slice(&index.to_le_bytes());
+    message.push(last as u8);
+    message.extend_from_slice(&chunk_hash);
+    message
+}
+
+/// Encode a chunk and its signature into a frame.
+pub fn encode_frame(chunk: &[u8], last: bool, signature: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(chunk.len() + signature.len() + 7);
+    frame.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+    frame.extend_from_slice(chunk);
+    frame.push(last as u8);
+    frame.extend_from_slice(&(signature.len() as u16).to_le_bytes());
+    frame.extend_from_slice(signature);
+    frame
+}
+
+/// A frame decoded from a chunk-framed body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub chunk: &'a [u8],
+    pub last: bool,
+    pub signature: &'a [u8],
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if buf.len() < len {
+        return None;
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Some(head)
+}
+
+/// Decode all the frames of a body. Returns `None` if the body is malformed.
+pub fn decode_frames(mut body: &[u8]) -> Option<Vec<Frame<'_>>> {
+    let mut frames = vec![];
+    while !body.is_empty() {
+        let chunk_len = u32::from_le_bytes(take(&mut body, 4)?.try_into().ok()?);
+        let chunk = take(&mut body, chunk_len as usize)?;
+        let last = match take(&mut body, 1)? {
+            [0] => false,
+            [1] => true,
+            _ => return None,
+        };
+        let signature_len = u16::from_le_bytes(take(&mut body, 2)?.try_into().ok()?);
+        let signature = take(&mut body, signature_len as usize)?;
+        frames.push(Frame {
+            chunk,
+            last,
+            signature,
+        });
+    }
+    Some(frames)
+}
+
+/// Verify a chunk-framed body and return the original body.
+///
+/// `verify_signature` is called with the signed message and the signature of each chunk.
+/// Returns `None` if any signature is invalid, or if the body is not complete.
+pub fn verify(
+    body: &[u8],
+    stream_id: &StreamId,
+    verify_signature: impl Fn(&[u8], &[u8]) -> bool,
+) -> Option<Vec<u8>> {
+    let frames = decode_frames(body)?;
+    let (last, init) = frames.split_last()?;
+    if !last.last || init.iter().any(|frame| frame.last) {
+        return None;
+    }
+    let mut output = vec![];
+    for (index, frame) in frames.iter().enumerate() {
+        let message = chunk_message(stream_id, index as u64, frame.last, frame.chunk);
+        if !verify_signature(&message, frame.signature) {
+            return None;
+        }
+        output.extend_from_slice(frame.chunk);
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_sign(message: &[u8]) -> Vec<u8> {
+        Sha256::digest(message).to_vec()
+    }
+
+    fn fake_verify(message: &[u8], signature: &[u8]) -> bool {
+        fake_sign(message) == signature
+    }
+
+    fn encode(stream_id: &StreamId, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut body = vec![];
+        for (index, chunk) in chunks.iter().enumerate() {
+            let last = index + 1 == chunks.len();
+            let signature = fake_sign(&chunk_message(stream_id, index as u64, last, chunk));
+            body.extend(encode_frame(chunk, last, &signature));
+        }
+        body
+    }
+
+    #[test]
+    fn framed_body_can_be_verified() {
+        let stream_id = [1u8; 16];
+        let body = encode(&stream_id, &[b"hello", b" ", b"world", b""]);
+        assert_eq!(
+            verify(&body, &stream_id, fake_verify).as_deref(),
+            Some(&b"hello world"[..])
+        );
+        assert_eq!(verify(&body, &[2u8; 16], fake_verify), None);
+    }
+
+    #[test]
+    fn tampered_bodies_are_rejected() {
+        let stream_id = [1u8; 16];
+        let body = encode(&stream_id, &[b"hello", b"world"]);
+        let frames = decode_frames(&body).unwrap();
+        assert_eq!(frames.len(), 2);
+
+        // Truncated
+        let first = encode_frame(frames[0].chunk, frames[0].last, frames[0].signature);
+        assert_eq!(verify(&first, &stream_id, fake_verify), None);
+        assert_eq!(
+            verify(&body[..body.len() - 1], &stream_id, fake_verify),
+            None
+        );
+
+        // Reordered
+        let mut reordered = encode_frame(frames[1].chunk, false, frames[1].signature);
+        reordered.extend(encode_frame(frames[0].chunk, true, frames[0].signature));
+        assert_eq!(verify(&reordered, &stream_id, fake_verify), None);
+
+        // Modified
+        let mut modified = body.clone();
+        modified[4] ^= 1;
+        assert_eq!(verify(&modified, &stream_id, fake_verify), None);
+    }
+}
@@ -1,7 +1,8 @@
 pub use request_tracer::{RequestTracer, TraceId};
-pub use response_signer::ResponseSigner;
+pub use response_signer::{ResponseSigner, ACCEPT_SCHEME_HEADER};
 pub use time_meter::TimeMeter;
 
+pub mod chunked_signature;
 mod request_tracer;
 mod response_signer;
 mod time_meter;
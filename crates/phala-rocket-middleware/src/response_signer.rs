@@ -1,11 +1,37 @@
-use std::io::Cursor;
+use std::io::{self, Cursor};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::tokio::io::{AsyncRead, ReadBuf};
 use rocket::{Request, Response};
+use sha2::{Digest, Sha256};
+
+use crate::chunked_signature::{self, StreamId};
+
+/// The request header with which a client accepts the chunk-framed bodies.
+pub const ACCEPT_SCHEME_HEADER: &str = "X-Phactory-Accept-Signature-Scheme";
+
+type ChunkSignFn = dyn Fn(&[u8]) -> Option<String> + Send + Sync;
+
+struct ChunkedSigning {
+    chunk_size: usize,
+    sign_fn: Arc<ChunkSignFn>,
+}
 
 /// A signer to sign the response with given signing fn.
+///
+/// Bodies up to `max_signing_body_size` are signed as a whole. Larger or streamed bodies are
+/// streamed in signed frames if chunked signing is enabled and the client accepts it with the
+/// `X-Phactory-Accept-Signature-Scheme` header, see [`chunked_signature`] for the scheme.
+/// Otherwise they are sent unsigned.
 pub struct ResponseSigner<SignFn> {
     max_signing_body_size: usize,
+    chunked: Option<ChunkedSigning>,
     sign_fn: SignFn,
 }
 
@@ -13,9 +39,129 @@ impl<SignFn> ResponseSigner<SignFn> {
     pub fn new(max_signing_body_size: usize, sign_fn: SignFn) -> Self {
         Self {
             max_signing_body_size,
+            chunked: None,
             sign_fn,
         }
     }
+
+    /// Enable chunked signing with chunks of `chunk_size` bytes.
+    ///
+    /// `sign_fn` signs the [`chunked_signature::chunk_message`] of each chunk. It must sign in a
+    /// different domain than the whole-body signing fn, so that a signed chunk message can not
+    /// be taken as a signed body.
+    pub fn with_chunked_signing(
+        mut self,
+        chunk_size: usize,
+        sign_fn: impl Fn(&[u8]) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.chunked = Some(ChunkedSigning {
+            chunk_size: chunk_size.clamp(1, u32::MAX as usize),
+            sign_fn: Arc::new(sign_fn),
+        });
+        self
+    }
+}
+
+/// A unique id for each chunk-framed response.
+fn new_stream_id() -> StreamId {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut hasher = Sha256::new();
+    hasher.update(now.to_le_bytes());
+    hasher.update(COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    let hash: [u8; 32] = hasher.finalize().into();
+    let mut id = StreamId::default();
+    id.copy_from_slice(&hash[..16]);
+    id
+}
+
+/// Reads the inner body chunk by chunk and emits the signed frames.
+///
+/// Errors reading the inner body or signing a chunk are passed to the reader, so the response is
+/// aborted rather than truncated.
+struct SignedChunks<R> {
+    inner: Pin<Box<R>>,
+    sign_fn: Arc<ChunkSignFn>,
+    stream_id: StreamId,
+    chunk_size: usize,
+    chunk: Vec<u8>,
+    index: u64,
+    frame: Vec<u8>,
+    frame_pos: usize,
+    finished: bool,
+}
+
+impl<R> SignedChunks<R> {
+    fn new(inner: R, signing: &ChunkedSigning, stream_id: StreamId) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            sign_fn: signing.sign_fn.clone(),
+            stream_id,
+            chunk_size: signing.chunk_size,
+            chunk: Vec::with_capacity(signing.chunk_size),
+            index: 0,
+            frame: vec![],
+            frame_pos: 0,
+            finished: false,
+        }
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let message =
+            chunked_signature::chunk_message(&self.stream_id, self.index, last, &self.chunk);
+        let signature = (self.sign_fn)(&message)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Failed to sign body chunk"))?;
+        self.frame = chunked_signature::encode_frame(&self.chunk, last, signature.as_bytes());
+        self.frame_pos = 0;
+        self.chunk.clear();
+        self.index += 1;
+        self.finished = last;
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for SignedChunks<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.frame_pos < this.frame.len() {
+                let n = out.remaining().min(this.frame.len() - this.frame_pos);
+                out.put_slice(&this.frame[this.frame_pos..this.frame_pos + n]);
+                this.frame_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            if this.finished {
+                return Poll::Ready(Ok(()));
+            }
+            let eof = loop {
+                let filled = this.chunk.len();
+                if filled == this.chunk_size {
+                    break false;
+                }
+                this.chunk.resize(this.chunk_size, 0);
+                let mut buf = ReadBuf::new(&mut this.chunk[filled..]);
+                let poll = this.inner.as_mut().poll_read(cx, &mut buf);
+                let n = buf.filled().len();
+                this.chunk.truncate(filled + n);
+                match poll {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Ready(Ok(())) if n == 0 => break true,
+                    Poll::Ready(Ok(())) => {}
+                }
+            };
+            if let Err(err) = this.seal(eof) {
+                return Poll::Ready(Err(err));
+            }
+        }
+    }
 }
 
 #[rocket::async_trait]
@@ -30,23 +176,111 @@ where
         }
     }
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        // TODO.kevin: use `let else` instead when the next rustc released
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
         let body_size = response.body().preset_size();
-        let body = match body_size {
-            Some(body_size) if body_size <= self.max_signing_body_size => {
-                let mut body = response.body_mut().take();
-                if let Ok(body) = body.to_bytes().await {
-                    body
-                } else {
+        if matches!(body_size, Some(size) if size <= self.max_signing_body_size) {
+            let body = match response.body_mut().take().to_bytes().await {
+                Ok(body) => body,
+                Err(err) => {
+                    log::error!("Failed to read the response body to sign: {err}");
+                    response.set_status(Status::InternalServerError);
+                    response.set_sized_body(0, Cursor::new(vec![]));
                     return;
                 }
+            };
+            if let Some(signature) = (self.sign_fn)(&body) {
+                response.set_raw_header("X-Phactory-Signature", signature);
             }
-            _ => return,
+            response.set_sized_body(body.len(), Cursor::new(body));
+            return;
+        }
+        let Some(chunked) = &self.chunked else {
+            return;
         };
-        if let Some(signature) = (self.sign_fn)(&body) {
-            response.set_raw_header("X-Phactory-Signature", signature);
+        let accepted = request
+            .headers()
+            .get(ACCEPT_SCHEME_HEADER)
+            .any(|scheme| scheme == chunked_signature::SCHEME);
+        if !accepted {
+            return;
         }
-        response.set_sized_body(body.len(), Cursor::new(body));
+        let stream_id = new_stream_id();
+        let body = response.body_mut().take();
+        response.set_raw_header("X-Phactory-Signature-Scheme", chunked_signature::SCHEME);
+        response.set_raw_header("X-Phactory-Body-Stream-Id", hex::encode(stream_id));
+        response.set_streamed_body(SignedChunks::new(body, chunked, stream_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio::io::AsyncReadExt;
+
+    fn fake_sign(message: &[u8]) -> Option<String> {
+        Some(hex::encode(Sha256::digest(message)))
+    }
+
+    fn fake_verify(message: &[u8], signature: &[u8]) -> bool {
+        fake_sign(message).as_deref().map(str::as_bytes) == Some(signature)
+    }
+
+    fn signing(chunk_size: usize) -> ChunkedSigning {
+        ChunkedSigning {
+            chunk_size,
+            sign_fn: Arc::new(fake_sign),
+        }
+    }
+
+    async fn read_all(reader: impl AsyncRead) -> io::Result<Vec<u8>> {
+        let mut output = vec![];
+        Box::pin(reader).read_to_end(&mut output).await?;
+        Ok(output)
+    }
+
+    #[rocket::async_test]
+    async fn body_is_streamed_in_signed_frames() {
+        let body: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let stream_id = new_stream_id();
+        for chunk_size in [1, 7, 1000, 4096] {
+            let signed =
+                SignedChunks::new(Cursor::new(body.clone()), &signing(chunk_size), stream_id);
+            let framed = read_all(signed).await.unwrap();
+            let frames = chunked_signature::decode_frames(&framed).unwrap();
+            assert_eq!(frames.len(), body.len() / chunk_size + 1);
+            assert!(frames.iter().all(|frame| frame.chunk.len() <= chunk_size));
+            assert_eq!(
+                chunked_signature::verify(&framed, &stream_id, fake_verify),
+                Some(body.clone())
+            );
+        }
+    }
+
+    #[rocket::async_test]
+    async fn errors_abort_the_stream() {
+        struct Broken;
+        impl AsyncRead for Broken {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+                _buf: &mut ReadBuf<'_>,
+            ) -> Poll<io::Result<()>> {
+                Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+            }
+        }
+        let signed = SignedChunks::new(Broken, &signing(16), new_stream_id());
+        assert!(read_all(signed).await.is_err());
+
+        let failing = ChunkedSigning {
+            chunk_size: 16,
+            sign_fn: Arc::new(|_: &[u8]| None),
+        };
+        let signed = SignedChunks::new(Cursor::new(vec![0u8; 32]), &failing, new_stream_id());
+        assert!(read_all(signed).await.is_err());
+    }
+
+    #[test]
+    fn stream_ids_are_unique() {
+        assert_ne!(new_stream_id(), new_stream_id());
     }
 }
@@ -1,6 +1,9 @@
 use super::*;
 /// A logger that only allow our codes to print logs
-struct SanitizedLogger(Logger);
+struct SanitizedLogger {
+    logger: Logger,
+    rules: Rules,
+}
 
 pub fn init_env_logger(sanitized: bool) {
     let sanitized = crate::get_env("RUST_LOG_SANITIZED", sanitized);
@@ -8,9 +11,13 @@ pub fn init_env_logger(sanitized: bool) {
     let mut builder = env_logger::Builder::from_env(env);
     builder.format_timestamp_micros();
     if sanitized {
+        let rules = Rules::from_env().expect("Invalid log sanitizing rules");
         let env_logger = builder.build();
         let max_level = env_logger.filter();
-        let logger = SanitizedLogger(env_logger);
+        let logger = SanitizedLogger {
+            logger: env_logger,
+            rules,
+        };
         log::set_boxed_logger(Box::new(logger)).expect("Failed to install sanitized logger");
         log::set_max_level(max_level);
     } else {
@@ -20,16 +27,33 @@ pub fn init_env_logger(sanitized: bool) {
 
 impl log::Log for SanitizedLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        self.0.enabled(metadata) && target_allowed(metadata.target())
+        self.logger.enabled(metadata)
+            && self
+                .rules
+                .enabled(metadata.target(), level_from_log(metadata.level()))
     }
 
     fn log(&self, record: &log::Record) {
-        if self.enabled(record.metadata()) {
-            self.0.log(record)
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if !self.rules.has_redactions() {
+            return self.logger.log(record);
         }
+        let message = record.args().to_string();
+        let message = self.rules.redact_text(&message);
+        self.logger.log(
+            &log::Record::builder()
+                .args(format_args!("{message}"))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        )
     }
 
     fn flush(&self) {
-        self.0.flush()
+        self.logger.flush()
     }
 }
@@ -0,0 +1,275 @@
+//! Filter and redaction rules of the sanitized logger.
+//!
+//! Rules are written one per line, empty lines and lines starting with `#` are ignored. When given
+//! inline in an environment variable, rules can also be separated by `;`:
+//!
+//! ```text
+//! # Include the builtin whitelist
+//! defaults
+//! # Allow records from hyper at info or more severe level
+//! allow hyper* info
+//! # Deny debug and trace records from an exact target
+//! deny phactory::storage debug
+//! # Replace the value of fields named `origin` with `<redacted>`
+//! redact key origin
+//! # Replace text matching the regex with `<redacted>`
+//! redact regex 0x[0-9a-fA-F]{64}
+//! ```
+//!
+//! Target patterns ending with `*` match by prefix, otherwise they must be equal to the target.
+//! The first filter rule matching a record decides whether the record is logged. Records
+//! matching no rule are dropped.
+use regex::Regex;
+use std::borrow::Cow;
+use tracing_core::Level;
+
+/// The text that redacted values are replaced with.
+pub const REDACTED: &str = "<redacted>";
+
+/// The builtin whitelist. Keep more frequently targets in the front.
+const DEFAULT_RULES: &str = "
+allow phactory*
+allow rocket::launch*
+allow rocket::server
+allow pink*
+allow sidevm*
+allow prpc_measuring
+allow gk_computing
+allow phala_*
+allow pruntime*
+";
+
+#[derive(Debug, Clone)]
+enum TargetPattern {
+    Prefix(String),
+    Eq(String),
+}
+
+impl TargetPattern {
+    fn parse(pattern: &str) -> Self {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => Self::Prefix(prefix.into()),
+            None => Self::Eq(pattern.into()),
+        }
+    }
+
+    fn matches(&self, target: &str) -> bool {
+        match self {
+            Self::Prefix(prefix) => target.starts_with(prefix.as_str()),
+            Self::Eq(rule) => rule == target,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FilterRule {
+    allow: bool,
+    target: TargetPattern,
+    /// For allow rules, records at this level or more severe are matched.
+    /// For deny rules, records at this level or more verbose are matched.
+    level: Level,
+}
+
+impl FilterRule {
+    fn matches(&self, target: &str, level: Level) -> bool {
+        let level_matches = if self.allow {
+            level <= self.level
+        } else {
+            level >= self.level
+        };
+        level_matches && self.target.matches(target)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct KeyRule {
+    name: String,
+    /// Matches `name=value` or `name: value` in formatted text.
+    pattern: Regex,
+}
+
+/// A set of filter and redaction rules.
+#[derive(Debug, Clone)]
+pub struct Rules {
+    filters: Vec<FilterRule>,
+    keys: Vec<KeyRule>,
+    patterns: Vec<Regex>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self::parse(DEFAULT_RULES).expect("Invalid builtin log rules")
+    }
+}
+
+fn parse_level(level: &str) -> Result<Level, String> {
+    level
+        .parse()
+        .map_err(|_| format!("invalid log level `{level}`"))
+}
+
+impl Rules {
+    /// Rules that allow everything and redact nothing.
+    pub fn permissive() -> Self {
+        Self::parse("allow *").expect("Invalid builtin log rules")
+    }
+
+    /// Load the rules from `RUST_LOG_SANITIZE_RULES` or the file given by
+    /// `RUST_LOG_SANITIZE_RULES_FILE`. Falls back to the builtin rules if neither is set.
+    pub fn from_env() -> Result<Self, String> {
+        if let Ok(rules) = std::env::var("RUST_LOG_SANITIZE_RULES") {
+            return Self::parse(&rules.replace(';', "\n"));
+        }
+        if let Ok(path) = std::env::var("RUST_LOG_SANITIZE_RULES_FILE") {
+            let rules = std::fs::read_to_string(&path)
+                .map_err(|err| format!("failed to read {path}: {err}"))?;
+            return Self::parse(&rules);
+        }
+        Ok(Self::default())
+    }
+
+    /// Parse rules from text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rules = Self {
+            filters: vec![],
+            keys: vec![],
+            patterns: vec![],
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            rules
+                .parse_line(line)
+                .map_err(|err| format!("rule {} `{line}`: {err}", i + 1))?;
+        }
+        Ok(rules)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        match directive {
+            "defaults" => {
+                let defaults = Self::default();
+                self.filters.extend(defaults.filters);
+                self.keys.extend(defaults.keys);
+                self.patterns.extend(defaults.patterns);
+            }
+            "allow" | "deny" => {
+                let allow = directive == "allow";
+                let mut parts = rest.split_whitespace();
+                let target = parts.next().ok_or("missing target")?;
+                let level = match parts.next() {
+                    Some(level) => parse_level(level)?,
+                    None if allow => Level::TRACE,
+                    None => Level::ERROR,
+                };
+                if parts.next().is_some() {
+                    return Err("too many arguments".into());
+                }
+                self.filters.push(FilterRule {
+                    allow,
+                    target: TargetPattern::parse(target),
+                    level,
+                });
+            }
+            "redact" => {
+                let (kind, arg) = rest.split_once(' ').ok_or("missing redaction argument")?;
+                let arg = arg.trim();
+                match kind {
+                    "key" => {
+                        let pattern = format!(
+                            r#"(\b{}\s*[=:]\s*)("[^"]*"|[^\s,}})]+)"#,
+                            regex::escape(arg)
+                        );
+                        self.keys.push(KeyRule {
+                            name: arg.into(),
+                            pattern: Regex::new(&pattern).map_err(|err| err.to_string())?,
+                        });
+                    }
+                    "regex" => {
+                        self.patterns
+                            .push(Regex::new(arg).map_err(|err| err.to_string())?);
+                    }
+                    _ => return Err(format!("unknown redaction kind `{kind}`")),
+                }
+            }
+            _ => return Err(format!("unknown directive `{directive}`")),
+        }
+        Ok(())
+    }
+
+    /// Whether records of the given target and level should be logged.
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        self.filters
+            .iter()
+            .find(|rule| rule.matches(target, level))
+            .map(|rule| rule.allow)
+            .unwrap_or(false)
+    }
+
+    /// Whether records of the given target could be logged at any level.
+    pub fn target_allowed(&self, target: &str) -> bool {
+        [
+            Level::ERROR,
+            Level::WARN,
+            Level::INFO,
+            Level::DEBUG,
+            Level::TRACE,
+        ]
+        .into_iter()
+        .any(|level| self.enabled(target, level))
+    }
+
+    /// Whether there is any redaction rule.
+    pub fn has_redactions(&self) -> bool {
+        !self.keys.is_empty() || !self.patterns.is_empty()
+    }
+
+    /// Whether values of the field with given name should be redacted entirely.
+    pub fn is_redacted_key(&self, key: &str) -> bool {
+        self.keys.iter().any(|rule| rule.name == key)
+    }
+
+    /// Redact the value of a field.
+    pub fn redact_field<'a>(&self, key: &str, value: &'a str) -> Cow<'a, str> {
+        if self.is_redacted_key(key) {
+            return Cow::Borrowed(REDACTED);
+        }
+        self.redact_text(value)
+    }
+
+    /// Redact `key=value` pairs and regex matches in formatted text.
+    pub fn redact_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let key_replacement = format!("${{1}}{REDACTED}");
+        let mut text = Cow::Borrowed(text);
+        for rule in &self.keys {
+            replace_all(&mut text, &rule.pattern, &key_replacement);
+        }
+        for pattern in &self.patterns {
+            replace_all(&mut text, pattern, REDACTED);
+        }
+        text
+    }
+}
+
+fn replace_all(text: &mut Cow<str>, pattern: &Regex, replacement: &str) {
+    let replaced = match pattern.replace_all(text, replacement) {
+        Cow::Owned(replaced) => replaced,
+        Cow::Borrowed(_) => return,
+    };
+    *text = Cow::Owned(replaced);
+}
+
+/// Convert a log level to the tracing one.
+pub(crate) fn level_from_log(level: log::Level) -> Level {
+    match level {
+        log::Level::Error => Level::ERROR,
+        log::Level::Warn => Level::WARN,
+        log::Level::Info => Level::INFO,
+        log::Level::Debug => Level::DEBUG,
+        log::Level::Trace => Level::TRACE,
+    }
+}
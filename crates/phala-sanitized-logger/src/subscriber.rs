@@ -1,97 +1,271 @@
-use std::io::Stderr;
-use tracing_core::{span, Event, LevelFilter, Metadata, Subscriber};
+use crate::rules::{Rules, REDACTED};
+use serde_json::{json, Map, Value};
+use std::{fmt, io::Stderr, sync::Arc};
+use tracing_core::{
+    field::{Field, Visit},
+    span, Event, Interest, LevelFilter, Metadata, Subscriber,
+};
 use tracing_subscriber::{
+    field::RecordFields,
     fmt::{
-        format::{DefaultFields, Format, Full},
-        Subscriber as FmtSubscriber,
+        format::Writer,
+        time::{FormatTime, SystemTime},
+        FmtContext, FormatEvent, FormatFields, FormattedFields, Subscriber as FmtSubscriber,
     },
+    registry::LookupSpan,
     util::SubscriberInitExt,
     EnvFilter,
 };
 
-type EnvFilterSubscriber = FmtSubscriber<DefaultFields, Format<Full>, EnvFilter, fn() -> Stderr>;
-
 /// A tracing subscriber that only allow our codes to print logs
-struct SanitizedSubscriber(EnvFilterSubscriber);
+struct SanitizedSubscriber<S> {
+    inner: S,
+    rules: Arc<Rules>,
+}
 
-impl Subscriber for SanitizedSubscriber {
+impl<S: Subscriber> Subscriber for SanitizedSubscriber<S> {
     fn enabled(&self, metadata: &Metadata<'_>) -> bool {
-        self.0.enabled(metadata) && super::target_allowed(metadata.target())
+        self.inner.enabled(metadata) && self.rules.enabled(metadata.target(), *metadata.level())
     }
 
     fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
-        self.0.new_span(span)
+        self.inner.new_span(span)
     }
 
     fn record(&self, span: &span::Id, values: &span::Record<'_>) {
-        self.0.record(span, values)
+        self.inner.record(span, values)
     }
 
     fn record_follows_from(&self, span: &span::Id, follows: &span::Id) {
-        self.0.record_follows_from(span, follows)
+        self.inner.record_follows_from(span, follows)
     }
 
     fn event(&self, event: &Event<'_>) {
-        self.0.event(event)
+        self.inner.event(event)
     }
 
     fn enter(&self, span: &span::Id) {
-        self.0.enter(span)
+        self.inner.enter(span)
     }
 
     fn exit(&self, span: &span::Id) {
-        self.0.exit(span)
+        self.inner.exit(span)
     }
 
     fn event_enabled(&self, event: &Event<'_>) -> bool {
-        self.0.event_enabled(event)
+        self.inner.event_enabled(event)
     }
 
     fn current_span(&self) -> span::Current {
-        self.0.current_span()
+        self.inner.current_span()
     }
 
     fn clone_span(&self, id: &span::Id) -> span::Id {
-        self.0.clone_span(id)
+        self.inner.clone_span(id)
     }
 
     fn try_close(&self, id: span::Id) -> bool {
-        self.0.try_close(id)
+        self.inner.try_close(id)
     }
 
     fn max_level_hint(&self) -> Option<LevelFilter> {
-        self.0.max_level_hint()
+        self.inner.max_level_hint()
     }
 
-    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> tracing_core::Interest {
-        self.0.register_callsite(metadata)
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if !self.rules.enabled(metadata.target(), *metadata.level()) {
+            return Interest::never();
+        }
+        self.inner.register_callsite(metadata)
     }
 
     fn drop_span(&self, id: span::Id) {
         #[allow(deprecated)]
-        self.0.drop_span(id);
+        self.inner.drop_span(id);
+    }
+}
+
+/// Formats fields like the default formatter does, with the redaction rules applied.
+struct RedactedFields {
+    rules: Arc<Rules>,
+}
+
+impl<'writer> FormatFields<'writer> for RedactedFields {
+    fn format_fields<R: RecordFields>(&self, writer: Writer<'writer>, fields: R) -> fmt::Result {
+        let mut visitor = TextVisitor {
+            writer,
+            rules: &self.rules,
+            is_empty: true,
+            result: Ok(()),
+        };
+        fields.record(&mut visitor);
+        visitor.result
     }
 }
 
+struct TextVisitor<'a, 'writer> {
+    writer: Writer<'writer>,
+    rules: &'a Rules,
+    is_empty: bool,
+    result: fmt::Result,
+}
+
+impl Visit for TextVisitor<'_, '_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let name = field.name();
+        if self.result.is_err() || name.starts_with("log.") {
+            return;
+        }
+        let value = format!("{value:?}");
+        let value = self.rules.redact_field(name, &value);
+        let sep = if self.is_empty { "" } else { " " };
+        self.is_empty = false;
+        self.result = if name == "message" {
+            write!(self.writer, "{sep}{value}")
+        } else {
+            write!(self.writer, "{sep}{name}={value}")
+        };
+    }
+}
+
+/// Formats events as JSON lines, with the redaction rules applied.
+struct JsonFormat {
+    rules: Arc<Rules>,
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut visitor = JsonVisitor {
+            fields: Map::new(),
+            rules: &self.rules,
+        };
+        event.record(&mut visitor);
+        let mut spans = vec![];
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let fields = extensions
+                    .get::<FormattedFields<N>>()
+                    .map(|fields| fields.fields.as_str())
+                    .unwrap_or_default();
+                spans.push(json!({ "name": span.name(), "fields": fields }));
+            }
+        }
+        let line = json!({
+            "timestamp": timestamp,
+            "level": metadata.level().to_string(),
+            "target": metadata.target(),
+            "fields": visitor.fields,
+            "spans": spans,
+        });
+        writeln!(writer, "{line}")
+    }
+}
+
+struct JsonVisitor<'a> {
+    fields: Map<String, Value>,
+    rules: &'a Rules,
+}
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        let name = field.name();
+        if name.starts_with("log.") {
+            return;
+        }
+        let value = if self.rules.is_redacted_key(name) {
+            REDACTED.into()
+        } else {
+            value
+        };
+        self.fields.insert(name.into(), value);
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = self.rules.redact_text(value).into_owned();
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = format!("{value:?}");
+        let value = self.rules.redact_text(&value).into_owned();
+        self.insert(field, value.into());
+    }
+}
+
+fn install<S>(subscriber: S, rules: Option<Arc<Rules>>)
+where
+    S: Subscriber + Send + Sync + 'static,
+{
+    let result = match rules {
+        Some(rules) => SanitizedSubscriber {
+            inner: subscriber,
+            rules,
+        }
+        .try_init(),
+        None => subscriber.try_init(),
+    };
+    result.expect("Failed to init tracing subscriber");
+}
+
+/// Install the global tracing subscriber.
+///
+/// When sanitized, logs are filtered and redacted by the rules loaded by [`Rules::from_env`].
+/// Set `RUST_LOG_JSON=true` to output logs as JSON lines.
 pub fn init_subscriber(sanitized: bool) {
     let builder = FmtSubscriber::builder();
     let filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::INFO.into())
         .from_env_lossy();
     let ansi = crate::get_env("RUST_LOG_ANSI_COLOR", false);
+    let json = crate::get_env("RUST_LOG_JSON", false);
     let sanitized = crate::get_env("RUST_LOG_SANITIZED", sanitized);
+    let rules = if sanitized {
+        Rules::from_env().expect("Invalid log sanitizing rules")
+    } else {
+        Rules::permissive()
+    };
+    let rules = Arc::new(rules);
     let builder = builder
         .with_env_filter(filter)
-        .with_ansi(ansi)
-        .with_writer(std::io::stderr as fn() -> Stderr);
-    let subscriber = builder.finish();
-    if sanitized {
-        SanitizedSubscriber(subscriber)
-            .try_init()
-            .expect("Failed to init tracing subscriber");
+        .with_ansi(ansi && !json)
+        .with_writer(std::io::stderr as fn() -> Stderr)
+        .fmt_fields(RedactedFields {
+            rules: rules.clone(),
+        });
+    let sanitizing_rules = sanitized.then(|| rules.clone());
+    if json {
+        install(
+            builder.event_format(JsonFormat { rules }).finish(),
+            sanitizing_rules,
+        );
     } else {
-        subscriber
-            .try_init()
-            .expect("Failed to init tracing subscriber");
+        install(builder.finish(), sanitizing_rules);
     }
 }
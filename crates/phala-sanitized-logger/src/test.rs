@@ -2,9 +2,10 @@ use super::*;
 
 #[test]
 fn whitelist_works() {
+    let rules = Rules::default();
     let allowed: Vec<_> = include_str!("all-log-targets.txt")
         .split('\n')
-        .filter(|t| target_allowed(t))
+        .filter(|t| rules.target_allowed(t))
         .collect();
     assert_eq!(
         allowed,
@@ -48,6 +49,60 @@ fn whitelist_works() {
     );
 }
 
+#[test]
+fn filter_rules_work() {
+    use tracing_core::Level;
+
+    let rules = Rules::parse(
+        "
+        # comment
+        deny phactory::storage debug
+        allow phactory*
+        allow hyper* info
+        ",
+    )
+    .unwrap();
+    assert!(rules.enabled("phactory::storage", Level::INFO));
+    assert!(!rules.enabled("phactory::storage", Level::DEBUG));
+    assert!(rules.enabled("phactory::system", Level::TRACE));
+    assert!(rules.enabled("hyper::client", Level::WARN));
+    assert!(!rules.enabled("hyper::client", Level::DEBUG));
+    assert!(!rules.enabled("other", Level::ERROR));
+
+    let rules = Rules::parse("defaults\nallow hyper*").unwrap();
+    assert!(rules.target_allowed("pink"));
+    assert!(rules.target_allowed("hyper"));
+}
+
+#[test]
+fn invalid_rules_are_rejected() {
+    assert!(Rules::parse("allow").is_err());
+    assert!(Rules::parse("allow pink loud").is_err());
+    assert!(Rules::parse("redact regex (").is_err());
+    assert!(Rules::parse("permit pink").is_err());
+}
+
+#[test]
+fn redaction_works() {
+    let rules = Rules::parse(
+        r"
+        redact key origin
+        redact regex 0x[0-9a-f]{8,}
+        ",
+    )
+    .unwrap();
+    assert_eq!(rules.redact_field("origin", "alice"), REDACTED);
+    assert_eq!(rules.redact_field("nonce", "1"), "1");
+    assert_eq!(
+        rules.redact_text("query origin=alice, data: 0xdeadbeef00"),
+        "query origin=<redacted>, data: <redacted>"
+    );
+    assert_eq!(
+        rules.redact_text(r#"Query { origin: "alice" }"#),
+        "Query { origin: <redacted> }"
+    );
+}
+
 #[test]
 fn see_log() {
     use log::info;
@@ -54,11 +54,11 @@ pub mod messaging {
     use core::fmt::Debug;
     use scale_info::TypeInfo;
 
-    use super::{ContractClusterId, ContractInfo};
+    use super::{ClusterPermission, ContractClusterId, ContractId, ContractInfo};
     use crate::messaging::EncryptedKey;
     use crate::{ClusterPublicKey, WorkerIdentity, WorkerPublicKey};
     use phala_mq::bind_topic;
-    use sp_core::crypto::AccountId32;
+    use sp_core::{crypto::AccountId32, H256};
 
     bind_topic!(ClusterEvent, b"phala/cluster/event");
     #[derive(Encode, Decode, Debug)]
@@ -85,6 +85,16 @@ pub mod messaging {
             gas_limit: u64,
             storage_deposit_limit: Option<u128>,
         },
+        /// The code of a contract is being upgraded or rolled back on-chain.
+        ///
+        /// The code is actually changed by the migration message sent to the contract, the workers
+        /// report the resulting code hash with `WorkerClusterReport::ContractCodeReported` at the
+        /// end of the block.
+        CodeChanged {
+            contract_id: ContractId,
+            code_hash: CodeHash,
+            rollback: bool,
+        },
     }
 
     impl<CodeHash, AccountId> ContractOperation<CodeHash, AccountId> {
@@ -121,6 +131,20 @@ pub mod messaging {
         ClusterDeploymentFailed {
             id: ContractClusterId,
         },
+        /// The worker has loaded the cluster state handed off by another worker of the cluster.
+        ClusterStateSynced {
+            id: ContractClusterId,
+        },
+        /// The in-cluster free balances of the accounts asked by `ClusterOperation::SettleCluster`.
+        ClusterSettled {
+            id: ContractClusterId,
+            balances: Vec<(AccountId32, u128)>,
+        },
+        /// The code hash of a contract after the `ContractOperation::CodeChanged` block.
+        ContractCodeReported {
+            contract_id: ContractId,
+            code_hash: Option<H256>,
+        },
     }
 
     #[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, Debug)]
@@ -163,6 +187,28 @@ pub mod messaging {
             cluster_id: ContractClusterId,
             worker: WorkerPublicKey,
         },
+        /// The deploy permission of the cluster has been changed.
+        UpdatePermission {
+            cluster_id: ContractClusterId,
+            permission: ClusterPermission<AccountId>,
+        },
+        /// An account has been granted or revoked the deployer role of the cluster.
+        SetDeployer {
+            cluster_id: ContractClusterId,
+            account: AccountId,
+            enabled: bool,
+        },
+        /// The code hashes allowed to be instantiated in the cluster. `None` means no restriction.
+        SetCodeAllowList {
+            cluster_id: ContractClusterId,
+            code_hashes: Option<Vec<H256>>,
+        },
+        /// The cluster is going to be destroyed, the workers report the in-cluster balances of the
+        /// given accounts so that they can be refunded on-chain.
+        SettleCluster {
+            cluster_id: ContractClusterId,
+            accounts: Vec<AccountId>,
+        },
     }
 
     impl<AccountId> ClusterOperation<AccountId> {
@@ -194,7 +240,15 @@ pub mod messaging {
 #[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, TypeInfo)]
 pub enum ClusterPermission<AccountId> {
     Public,
+    /// Only the cluster owner can deploy.
     OnlyOwner(AccountId),
+    /// Only the cluster owner, the cluster deployers and the listed accounts can deploy.
+    AllowList(Vec<AccountId>),
+    /// Anyone except the listed accounts can deploy.
+    DenyList(Vec<AccountId>),
+    /// The cluster is managed by the governance rather than the owner and only the cluster
+    /// deployers can deploy.
+    Governance,
 }
 
 #[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, TypeInfo)]
@@ -193,6 +193,8 @@ pub mod messaging {
         MasterPubkeyOnChain(MasterPubkeyEvent),
         RotateMasterKey(RotateMasterKeyEvent),
         MasterPubkeyRotated(MasterPubkeyEvent),
+        /// The rotation didn't reach the quorum in time, the rotated key should be dropped.
+        MasterKeyRotationAborted(MasterKeyRotationAbortedEvent),
     }
 
     impl GatekeeperLaunch {
@@ -223,6 +225,12 @@ pub mod messaging {
         pub fn master_pubkey_rotated(master_pubkey: MasterPublicKey) -> GatekeeperLaunch {
             GatekeeperLaunch::MasterPubkeyRotated(MasterPubkeyEvent { master_pubkey })
         }
+
+        pub fn master_key_rotation_aborted(rotation_id: u64) -> GatekeeperLaunch {
+            GatekeeperLaunch::MasterKeyRotationAborted(MasterKeyRotationAbortedEvent {
+                rotation_id,
+            })
+        }
     }
 
     #[derive(Encode, Decode, Clone, Debug, PartialEq, Eq, TypeInfo)]
@@ -250,6 +258,11 @@ pub mod messaging {
         pub gk_identities: Vec<WorkerIdentity>,
     }
 
+    #[derive(Encode, Decode, Clone, Debug, PartialEq, Eq, TypeInfo)]
+    pub struct MasterKeyRotationAbortedEvent {
+        pub rotation_id: u64,
+    }
+
     // Messages: Gatekeeper change
     bind_topic!(GatekeeperChange, b"phala/gatekeeper/change");
     #[derive(Encode, Decode, Clone, Debug, PartialEq, Eq, TypeInfo)]
@@ -408,6 +421,81 @@ pub mod messaging {
         pub last_random_number: RandomNumber,
     }
 
+    // Messages: Gatekeeper -> Randomness pallet
+    bind_topic!(RandomnessBeaconEvent, b"^phala/randomness/beacon");
+    /// The randomness beacon published on chain
+    ///
+    /// The random number is the output of a schnorrkel VRF keyed by the master key, over the block
+    /// number and the previous random number. Anyone can check it against the master pubkey with
+    /// the proof, see [`RandomnessBeaconEvent::verify`].
+    #[derive(Encode, Decode, Clone, Debug, PartialEq, Eq, TypeInfo)]
+    pub struct RandomnessBeaconEvent {
+        pub block_number: u32,
+        pub last_random_number: RandomNumber,
+        /// The encoded schnorrkel `VRFOutput`
+        pub vrf_output: [u8; 32],
+        /// The encoded schnorrkel `VRFProof` of `vrf_output`
+        pub vrf_proof: [u8; 64],
+    }
+
+    #[derive(Encode)]
+    pub(crate) struct RandomnessBeaconData<'a> {
+        pub(crate) block_number: u32,
+        pub(crate) last_random_number: &'a RandomNumber,
+    }
+
+    const BEACON_VRF_CONTEXT: &[u8] = b"phala-randomness-beacon";
+
+    impl RandomnessBeaconEvent {
+        fn vrf_transcript(
+            block_number: u32,
+            last_random_number: &RandomNumber,
+        ) -> impl schnorrkel::context::SigningTranscript {
+            let data = RandomnessBeaconData {
+                block_number,
+                last_random_number,
+            }
+            .encode();
+            let data =
+                crate::wrap_content_to_sign(&data, crate::SignedContentType::RandomnessBeacon);
+            schnorrkel::context::signing_context(BEACON_VRF_CONTEXT).bytes(&data)
+        }
+
+        /// Evaluate the VRF with the master key, returning the beacon and the random number.
+        #[cfg(feature = "full_crypto")]
+        pub fn sign(
+            master_key: &sp_core::sr25519::Pair,
+            block_number: u32,
+            last_random_number: RandomNumber,
+        ) -> (Self, RandomNumber) {
+            let keypair: &schnorrkel::Keypair = master_key.as_ref();
+            let (inout, proof, _) =
+                keypair.vrf_sign(Self::vrf_transcript(block_number, &last_random_number));
+            let event = Self {
+                block_number,
+                last_random_number,
+                vrf_output: inout.to_output().to_bytes(),
+                vrf_proof: proof.to_bytes(),
+            };
+            (event, inout.make_bytes(BEACON_VRF_CONTEXT))
+        }
+
+        /// Verify the VRF proof against the master pubkey, returning the random number.
+        pub fn verify(&self, master_pubkey: &MasterPublicKey) -> Option<RandomNumber> {
+            let pubkey = schnorrkel::PublicKey::from_bytes(&master_pubkey.0).ok()?;
+            let output = schnorrkel::vrf::VRFOutput::from_bytes(&self.vrf_output).ok()?;
+            let proof = schnorrkel::vrf::VRFProof::from_bytes(&self.vrf_proof).ok()?;
+            let (inout, _) = pubkey
+                .vrf_verify(
+                    Self::vrf_transcript(self.block_number, &self.last_random_number),
+                    &output,
+                    &proof,
+                )
+                .ok()?;
+            Some(inout.make_bytes(BEACON_VRF_CONTEXT))
+        }
+    }
+
     #[cfg_attr(feature = "enable_serde", derive(Serialize, Deserialize))]
     #[derive(Encode, Decode, Clone, Debug, PartialEq, Eq, TypeInfo)]
     pub struct TokenomicParameters {
@@ -441,6 +529,45 @@ pub enum AttestationReport {
         signature: Vec<u8>,
         raw_signing_cert: Vec<u8>,
     },
+    SgxDcap {
+        quote: Vec<u8>,
+        collateral: Collateral,
+    },
+    /// Produced by the software platform of phactory, only accepted by dev chains.
+    Mock {
+        runtime_hash: Vec<u8>,
+        report_data: Vec<u8>,
+        /// Unix timestamp in seconds when the report was created.
+        timestamp: u64,
+    },
+}
+
+/// The collateral used to verify a DCAP quote, fetched from the PCCS.
+#[derive(Encode, Decode, TypeInfo, Debug, Clone, PartialEq, Eq)]
+pub enum Collateral {
+    SgxV30(SgxV30QuoteCollateral),
+}
+
+#[derive(Encode, Decode, TypeInfo, Debug, Clone, PartialEq, Eq)]
+pub struct SgxV30QuoteCollateral {
+    /// PEM encoded certificate chain of the TCB info signing cert.
+    pub tcb_info_issuer_chain: String,
+    /// The raw JSON of the `tcbInfo` field.
+    pub tcb_info: String,
+    /// The raw ECDSA signature (r || s) of `tcb_info`.
+    pub tcb_info_signature: Vec<u8>,
+    /// PEM encoded certificate chain of the QE identity signing cert.
+    pub qe_identity_issuer_chain: String,
+    /// The raw JSON of the `enclaveIdentity` field.
+    pub qe_identity: String,
+    /// The raw ECDSA signature (r || s) of `qe_identity`.
+    pub qe_identity_signature: Vec<u8>,
+    /// DER encoded CRL of the root CA.
+    pub root_ca_crl: Vec<u8>,
+    /// PEM encoded certificate chain of the CA issuing the PCK cert.
+    pub pck_crl_issuer_chain: String,
+    /// DER encoded CRL of the CA issuing the PCK cert.
+    pub pck_crl: Vec<u8>,
 }
 
 #[cfg_attr(feature = "enable_serde", derive(Serialize, Deserialize))]
@@ -450,6 +577,10 @@ pub enum AttestationProvider {
     Root,
     #[cfg_attr(feature = "enable_serde", serde(rename = "ias"))]
     Ias,
+    #[cfg_attr(feature = "enable_serde", serde(rename = "dcap"))]
+    Dcap,
+    #[cfg_attr(feature = "enable_serde", serde(rename = "mock"))]
+    Mock,
 }
 
 #[derive(Encode, Decode, PartialEq, Eq, Debug, Default, Clone, TypeInfo)]
@@ -564,6 +695,56 @@ pub struct WorkerRegistrationInfoV2<AccountId> {
 #[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, TypeInfo)]
 pub enum VersionedWorkerEndpoints {
     V1(Vec<String>),
+    V2(WorkerEndpointsV2),
+}
+
+impl VersionedWorkerEndpoints {
+    pub fn endpoints(&self) -> &[String] {
+        match self {
+            VersionedWorkerEndpoints::V1(endpoints) => endpoints,
+            VersionedWorkerEndpoints::V2(v2) => &v2.endpoints,
+        }
+    }
+
+    pub fn capabilities(&self) -> Option<&WorkerCapabilities> {
+        match self {
+            VersionedWorkerEndpoints::V1(_) => None,
+            VersionedWorkerEndpoints::V2(v2) => Some(&v2.capabilities),
+        }
+    }
+}
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, TypeInfo)]
+pub struct WorkerEndpointsV2 {
+    pub endpoints: Vec<String>,
+    pub capabilities: WorkerCapabilities,
+}
+
+/// What a worker can serve, reported by the pRuntime along with its endpoints.
+#[derive(Encode, Decode, Debug, Default, Clone, PartialEq, Eq, TypeInfo)]
+pub struct WorkerCapabilities {
+    /// The supported RPC services, a bitmask of the `WorkerCapabilities::RPC_*` flags
+    pub rpc_features: u32,
+    /// The supported pink runtime versions as (major, minor)
+    pub pink_runtime_versions: Vec<(u32, u32)>,
+    /// Whether the worker can run sidevm instances
+    pub sidevm: bool,
+    /// The region hint given by the operator, e.g. `eu-west`
+    pub region: Option<String>,
+    /// The recent workload of the contract queries in permill of the worker capacity
+    pub load_permill: u32,
+}
+
+impl WorkerCapabilities {
+    /// Serves `PhactoryAPI.ContractQuery`
+    pub const RPC_CONTRACT_QUERY: u32 = 1 << 0;
+    /// Serves the `/contract/dry_run` API
+    pub const RPC_CONTRACT_DRY_RUN: u32 = 1 << 1;
+
+    /// Whether all the given `RPC_*` flags are set
+    pub fn supports_rpc(&self, features: u32) -> bool {
+        self.rpc_features & features == features
+    }
 }
 
 #[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, TypeInfo)]
@@ -619,6 +800,8 @@ pub enum SignedContentType {
     MasterKeyRotation = 3,
     MasterKeyStore = 4,
     ClusterStateRequest = 5,
+    RandomnessBeacon = 6,
+    RpcResponseChunk = 7,
 }
 
 pub fn wrap_content_to_sign(data: &[u8], sigtype: SignedContentType) -> Cow<[u8]> {
@@ -131,11 +131,36 @@ impl ChainApi {
         Ok(Some(block_number as _))
     }
 
+    /// Whether the worker is computing at the given block, that is its session is in the
+    /// `WorkerIdle` or `WorkerUnresponsive` state.
+    pub async fn worker_computing_at(&self, hash: Hash, worker: &WorkerPublicKey) -> Result<bool> {
+        let Some(session): Option<[u8; 32]> = self
+            .fetch_at(Some(hash), "PhalaComputation", "WorkerBindings", Some(worker))
+            .await? else {
+                return Ok(false);
+            };
+        // The state is the first field of `SessionInfo`, it's enough to decode the variant index.
+        let state: Option<u8> = self
+            .fetch_at(Some(hash), "PhalaComputation", "Sessions", Some(session))
+            .await?;
+        Ok(matches!(state, Some(1 | 3)))
+    }
+
     async fn fetch<K: Encode, V: Decode>(
         &self,
         pallet: &str,
         name: &str,
         key: Option<K>,
+    ) -> Result<Option<V>> {
+        self.fetch_at(None, pallet, name, key).await
+    }
+
+    async fn fetch_at<K: Encode, V: Decode>(
+        &self,
+        hash: Option<Hash>,
+        pallet: &str,
+        name: &str,
+        key: Option<K>,
     ) -> Result<Option<V>> {
         let mut args = vec![];
         if let Some(key) = key {
@@ -144,12 +169,11 @@ impl ChainApi {
         }
         let address = subxt::dynamic::storage(pallet, name, args);
         let Some(data) = self
-            .storage()
-            .at_latest()
+            .storage_at(hash)
             .await?
             .fetch(&address)
             .await
-            .context("Failed to get worker endpoints")? else {
+            .with_context(|| format!("Failed to get {pallet}.{name}"))? else {
                 return Ok(None);
             };
         Ok(Some(Decode::decode(&mut &data.encoded()[..])?))
@@ -163,13 +187,26 @@ impl ChainApi {
     }
 
     pub async fn get_endpoints(&self, worker: &WorkerPublicKey) -> Result<Vec<String>> {
-        let result = self
-            .fetch("PhalaRegistry", "Endpoints", Some(worker))
+        let result = self.get_endpoint_info(worker).await?;
+        Ok(result
+            .map(|info| info.endpoints().to_vec())
+            .unwrap_or_default())
+    }
+
+    pub async fn get_endpoint_info(
+        &self,
+        worker: &WorkerPublicKey,
+    ) -> Result<Option<VersionedWorkerEndpoints>> {
+        self.fetch("PhalaRegistry", "Endpoints", Some(worker)).await
+    }
+
+    /// The signing time in milliseconds of the latest endpoint payload accepted on chain.
+    pub async fn get_endpoint_signing_time(&self, worker: &WorkerPublicKey) -> Result<Option<u64>> {
+        // It's the first field of `EndpointHeartbeat`.
+        let heartbeat: Option<(u64, BlockNumber)> = self
+            .fetch("PhalaRegistry", "EndpointHeartbeats", Some(worker))
             .await?;
-        let Some(VersionedWorkerEndpoints::V1(endpoints)) = result else {
-            return Ok(vec![])
-        };
-        Ok(endpoints)
+        Ok(heartbeat.map(|(signing_time, _)| signing_time))
     }
 
     pub async fn storage_keys(&self, prefix: &[u8], hash: Option<Hash>) -> Result<Vec<Vec<u8>>> {
@@ -13,7 +13,7 @@ mod system {
     use alloc::vec::Vec;
     use ink::{codegen::Env, storage::Mapping};
     use pink::system::{CodeType, ContractDeposit, ContractDepositRef, DriverError, Error, Result};
-    use pink::{HookPoint, PinkEnvironment};
+    use pink::{HookPoint, HttpEgressPolicy, PinkEnvironment, QueryPolicy};
 
     use this_crate::{version_tuple, VersionTuple};
 
@@ -199,6 +199,30 @@ mod system {
             Ok(())
         }
 
+        #[ink(message)]
+        fn set_http_egress_policy(
+            &self,
+            contract_id: Option<AccountId>,
+            policy: Option<HttpEgressPolicy>,
+        ) -> Result<()> {
+            self.ensure_admin()?;
+            self.ensure_min_runtime_version((1, 2))?;
+            pink::set_http_egress_policy(contract_id, policy);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn set_query_policy(
+            &self,
+            contract_id: Option<AccountId>,
+            policy: Option<QueryPolicy>,
+        ) -> Result<()> {
+            self.ensure_admin()?;
+            self.ensure_min_runtime_version((1, 2))?;
+            pink::set_query_policy(contract_id, policy);
+            Ok(())
+        }
+
         #[ink(message)]
         fn total_balance_of(&self, account: AccountId) -> Balance {
             pink::ext().balance_of(account).0
@@ -11,7 +11,7 @@ pub type Index = u64;
 pub type Address = AccountId32;
 pub type Weight = u64;
 
-pub use pink_extension::{HookPoint, PinkEvent};
+pub use pink_extension::{HookPoint, HttpEgressPolicy, PinkEvent, QueryPolicy};
 
 #[derive(Decode, Encode, Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub enum ExecutionMode {
@@ -96,3 +96,108 @@ impl ExecSideEffects {
         }
     }
 }
+
+/// Result of a contract call executed in dry-run mode.
+#[derive(Decode, Encode, Debug, Clone)]
+pub struct DryRunResult {
+    /// The encoded `ContractExecResult`, including the gas consumed, the gas required, the storage
+    /// deposit and the debug messages.
+    pub result: Vec<u8>,
+    /// The side effects the call would have as a transaction.
+    pub effects: ExecSideEffects,
+    /// What happened during the call.
+    pub trace: CallTrace,
+}
+
+/// Trace of a contract call executed in dry-run mode.
+#[derive(Decode, Encode, Debug, Clone, Default)]
+pub struct CallTrace {
+    /// The call frames entered at the top level, normally only the one of the called contract,
+    /// with the nested calls inside.
+    pub calls: Vec<TracedFrame>,
+    /// The gas consumed by the whole call.
+    pub gas_consumed: Weight,
+    /// The gas required to run the call successfully, which can be more than the gas consumed.
+    pub gas_required: Weight,
+    /// The storage deposit charged from the caller.
+    pub storage_deposit_charged: Balance,
+    /// The storage deposit refunded to the caller.
+    pub storage_deposit_refunded: Balance,
+    /// The chain extension invocations, in the order they were made.
+    pub ext_calls: Vec<TracedExtCall>,
+    /// Number of trie nodes read from the cluster storage.
+    pub storage_reads: u32,
+    /// Total size of the trie nodes read from the cluster storage.
+    pub storage_read_bytes: u64,
+    /// The storage changes made by the call, excluding the bookkeeping of the runtime.
+    pub storage_writes: Vec<TracedStorageWrite>,
+    /// Messages logged by the contracts, as `(contract, level, message)`.
+    pub logs: Vec<(AccountId, u8, String)>,
+}
+
+/// A call frame, i.e. the execution of a contract message or constructor.
+#[derive(Decode, Encode, Debug, Clone, PartialEq, Eq)]
+pub struct TracedFrame {
+    pub kind: FrameKind,
+    /// The contract executed by the frame.
+    ///
+    /// `None` if the frame failed before the contract could be told, which happens to a failed
+    /// delegate call or a failed call to a contract already in the call stack.
+    pub contract: Option<AccountId>,
+    /// Whether the changes of the frame were rolled back, because the contract reverted or
+    /// trapped. The events and nested calls of a reverted frame are kept in the trace.
+    pub reverted: bool,
+    /// The beneficiary if the contract terminated itself.
+    pub terminated_to: Option<AccountId>,
+    /// The events emitted by the contract in the frame.
+    pub events: Vec<TracedEvent>,
+    /// The changes to the contract storage made in the frame, which the storage deposit is
+    /// charged for.
+    pub storage: StorageUsage,
+    /// The nested calls made by the frame, in the order they were entered.
+    pub calls: Vec<TracedFrame>,
+}
+
+#[derive(Decode, Encode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Call,
+    DelegateCall,
+    Instantiate,
+}
+
+#[derive(Decode, Encode, Debug, Clone, PartialEq, Eq)]
+pub struct TracedEvent {
+    pub topics: Vec<Hash>,
+    pub data: Vec<u8>,
+}
+
+/// Storage items and bytes added to and removed from the contract storage.
+#[derive(Decode, Encode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageUsage {
+    pub items_added: u32,
+    pub items_removed: u32,
+    pub bytes_added: u64,
+    pub bytes_removed: u64,
+}
+
+#[derive(Decode, Encode, Debug, Clone, PartialEq, Eq)]
+pub struct TracedExtCall {
+    /// The contract invoking the chain extension.
+    pub contract: AccountId,
+    /// The function id, see `pink_extension::chain_extension::func_ids`.
+    pub func_id: u32,
+    /// Size of the encoded input.
+    pub input_len: u32,
+    /// Size of the encoded output. `None` if the invocation failed.
+    pub output_len: Option<u32>,
+}
+
+#[derive(Decode, Encode, Debug, Clone, PartialEq, Eq)]
+pub struct TracedStorageWrite {
+    /// The child trie the key belongs to, which is the `trie_id` of the contract for contract
+    /// storage. `None` for the runtime storage.
+    pub child_trie: Option<Vec<u8>>,
+    pub key: Vec<u8>,
+    /// The new value. `None` if the key is removed.
+    pub value: Option<Vec<u8>>,
+}
@@ -34,7 +34,7 @@ pub trait OCall: CrossCall {}
 
 pub mod ecall {
     use super::{CrossCallMut, ECall, Executing};
-    use crate::types::{AccountId, Balance, ExecutionMode, Hash, Weight};
+    use crate::types::{AccountId, Balance, DryRunResult, ExecutionMode, Hash, Weight};
     use pink_macro::cross_call;
     use scale::{Decode, Encode};
     pub trait EventCallbacks {
@@ -118,6 +118,16 @@ pub mod ecall {
         fn on_genesis(&mut self);
         #[xcall(id = 23)]
         fn on_runtime_upgrade(&mut self);
+        /// Execute a contract call in estimating mode and trace it, discarding the changes.
+        ///
+        /// Available since runtime 1.2.
+        #[xcall(id = 24)]
+        fn contract_dry_run(
+            &self,
+            contract: AccountId,
+            input_data: Vec<u8>,
+            tx_args: TransactionArguments,
+        ) -> DryRunResult;
     }
 }
 
@@ -128,7 +138,8 @@ pub mod ocall {
     use scale::{Decode, Encode};
 
     pub use pink_extension::chain_extension::{
-        BatchHttpResult, HttpRequest, HttpRequestError, HttpResponse, StorageQuotaExceeded,
+        BatchHttpResult, BatchHttpResultV2, HttpRequest, HttpRequestError, HttpRequestV2,
+        HttpResponse, HttpResponseV2, StorageQuotaExceeded,
     };
     pub type StorageChanges = Vec<(Vec<u8>, (Vec<u8>, i32))>;
 
@@ -208,5 +219,23 @@ pub mod ocall {
 
         #[xcall(id = 18)]
         fn entry_contract(&self) -> Option<AccountId>;
+
+        #[xcall(id = 19)]
+        fn http_request_v2(
+            &self,
+            contract: AccountId,
+            request: HttpRequestV2,
+        ) -> Result<HttpResponseV2, HttpRequestError>;
+
+        #[xcall(id = 20)]
+        fn batch_http_request_v2(
+            &self,
+            contract: AccountId,
+            requests: Vec<HttpRequestV2>,
+            timeout_ms: u64,
+        ) -> BatchHttpResultV2;
+
+        #[xcall(id = 21)]
+        fn randomness_beacon(&self, block_number: u32) -> Option<[u8; 32]>;
     }
 }
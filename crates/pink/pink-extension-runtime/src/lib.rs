@@ -3,12 +3,17 @@ use std::io::Write;
 use std::{
     fmt::Display,
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, SystemTime},
 };
 
 use pink_extension::{
     chain_extension::{
-        self as ext, HttpRequest, HttpRequestError, HttpResponse, PinkExtBackend, SigType,
+        self as ext, HttpRequest, HttpRequestError, HttpRequestV2, HttpResponse,
+        HttpResponseMeta, HttpResponseV2, PinkExtBackend, ResponseBodyMode, SigType,
         StorageQuotaExceeded,
     },
     Balance, EcdhPublicKey, EcdsaPublicKey, EcdsaSignature, Hash,
@@ -53,13 +58,101 @@ fn block_on<F: core::future::Future>(f: F) -> F::Output {
 }
 
 pub fn batch_http_request(requests: Vec<HttpRequest>, timeout_ms: u64) -> ext::BatchHttpResult {
-    const MAX_CONCURRENT_REQUESTS: usize = 5;
+    let requests = requests.into_iter().map(Into::into).collect();
+    Ok(batch_http_request_v2(requests, timeout_ms)?
+        .into_iter()
+        .map(|result| match result {
+            Ok(response) => Ok(response.into()),
+            Err(err) => v1_1_compatible_error(err),
+        })
+        .collect())
+}
+
+/// Convert an error to the result of `http_request` known by contracts built against runtime v1.0.
+pub fn v1_0_compatible_error(err: HttpRequestError) -> Result<HttpResponse, HttpRequestError> {
+    use HttpRequestError::*;
+    match err {
+        // runtime v1.0 supported errors
+        InvalidUrl | InvalidMethod | InvalidHeaderName | InvalidHeaderValue
+        | FailedToCreateClient | Timeout => Err(err),
+        // Runtime v1.0 reported redirect loops as unreachable
+        TooManyRedirects => Ok(HttpResponse {
+            status_code: 523,
+            reason_phrase: "Unreachable".into(),
+            body: format!("{err:?}").into_bytes(),
+            headers: vec![],
+        }),
+        _ => {
+            // To be compatible with runtime v1.0, we need to convert the v1.1 extended errors
+            // to an HTTP response with status code 524.
+            log::error!("chain_ext: http request failed: {}", err.display());
+            Ok(HttpResponse {
+                status_code: 524,
+                reason_phrase: "IO Error".into(),
+                body: format!("{err:?}").into_bytes(),
+                headers: vec![],
+            })
+        }
+    }
+}
+
+/// Convert an error to the result of `batch_http_request` known by contracts built against
+/// runtime v1.1.
+pub fn v1_1_compatible_error(err: HttpRequestError) -> Result<HttpResponse, HttpRequestError> {
+    if err.is_v1_1_error() {
+        return Err(err);
+    }
+    // Contracts built for runtime v1.1 don't know the v1.2 extended errors, convert them
+    // to a 523 response.
+    Ok(HttpResponse {
+        status_code: 523,
+        reason_phrase: "Unreachable".into(),
+        body: format!("{err:?}").into_bytes(),
+        headers: vec![],
+    })
+}
+
+/// The maximum number of requests in a batch HTTP request.
+pub const MAX_CONCURRENT_REQUESTS: usize = 5;
+
+/// Filter deciding whether a host may be requested, applied to the URL and every redirect hop.
+pub type HostFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Response body bytes left to download, shared by the requests of a batch.
+///
+/// Requests fail with `HttpRequestError::QuotaExceeded` once the budget runs out.
+pub type ByteBudget = Arc<AtomicU64>;
+
+/// The maximum number of body bytes downloaded by a request in `ResponseBodyMode::Streamed`.
+pub const MAX_DOWNLOAD_SIZE: u64 = 1024 * 1024 * 64; // 64MB
+
+pub fn batch_http_request_v2(
+    requests: Vec<HttpRequestV2>,
+    timeout_ms: u64,
+) -> ext::BatchHttpResultV2 {
+    batch_http_request_v2_filtered(requests, timeout_ms, None, None)
+}
+
+/// Like [`batch_http_request_v2`], but rejects requests redirected to a host not allowed by
+/// `host_filter` with `HttpRequestError::DestinationNotAllowed`, and stops downloading once the
+/// responses exceed `byte_budget`.
+pub fn batch_http_request_v2_filtered(
+    requests: Vec<HttpRequestV2>,
+    timeout_ms: u64,
+    host_filter: Option<HostFilter>,
+    byte_budget: Option<ByteBudget>,
+) -> ext::BatchHttpResultV2 {
     if requests.len() > MAX_CONCURRENT_REQUESTS {
         return Err(ext::HttpRequestError::TooManyRequests);
     }
-    let futs = requests
-        .into_iter()
-        .map(|request| async_http_request(request, timeout_ms));
+    let futs = requests.into_iter().map(|request| {
+        async_http_request(
+            request,
+            timeout_ms,
+            host_filter.clone(),
+            byte_budget.clone(),
+        )
+    });
     block_on(tokio::time::timeout(
         Duration::from_millis(timeout_ms + 200),
         futures::future::join_all(futs),
@@ -71,40 +164,86 @@ pub fn http_request(
     request: HttpRequest,
     timeout_ms: u64,
 ) -> Result<HttpResponse, HttpRequestError> {
-    use HttpRequestError::*;
-    match block_on(async_http_request(request, timeout_ms)) {
-        Ok(resp) => Ok(resp),
-        Err(err) => match err {
-            // runtime v1.0 supported errors
-            InvalidUrl | InvalidMethod | InvalidHeaderName | InvalidHeaderValue
-            | FailedToCreateClient | Timeout => Err(err),
-            _ => {
-                // To be compatible with runtime v1.0, we need to convert the v1.1 extended errors
-                // to an HTTP response with status code 524.
-                log::error!("chain_ext: http request failed: {}", err.display());
-                Ok(HttpResponse {
-                    status_code: 524,
-                    reason_phrase: "IO Error".into(),
-                    body: format!("{err:?}").into_bytes(),
-                    headers: vec![],
-                })
-            }
-        },
+    match block_on(async_http_request(request.into(), timeout_ms, None, None)) {
+        Ok(resp) => Ok(resp.into()),
+        Err(err) => v1_0_compatible_error(err),
     }
 }
 
+pub fn http_request_v2(
+    request: HttpRequestV2,
+    timeout_ms: u64,
+) -> Result<HttpResponseV2, HttpRequestError> {
+    http_request_v2_filtered(request, timeout_ms, None, None)
+}
+
+/// Like [`http_request_v2`], but rejects requests redirected to a host not allowed by
+/// `host_filter` with `HttpRequestError::DestinationNotAllowed`, and stops downloading once the
+/// response exceeds `byte_budget`.
+///
+/// The initial URL is not checked here, callers are expected to check it before sending.
+pub fn http_request_v2_filtered(
+    request: HttpRequestV2,
+    timeout_ms: u64,
+    host_filter: Option<HostFilter>,
+    byte_budget: Option<ByteBudget>,
+) -> Result<HttpResponseV2, HttpRequestError> {
+    block_on(async_http_request(
+        request,
+        timeout_ms,
+        host_filter,
+        byte_budget,
+    ))
+}
+
 async fn async_http_request(
-    request: HttpRequest,
+    request: HttpRequestV2,
     timeout_ms: u64,
-) -> Result<HttpResponse, HttpRequestError> {
+    host_filter: Option<HostFilter>,
+    byte_budget: Option<ByteBudget>,
+) -> Result<HttpResponseV2, HttpRequestError> {
+    const MAX_BODY_SIZE: usize = 1024 * 1024 * 2; // 2MB
+    const DEFAULT_MAX_REDIRECTS: u32 = 10;
+
+    let options = request.options;
+    let timeout_ms = match options.timeout_ms {
+        Some(request_timeout) => request_timeout.min(timeout_ms),
+        None => timeout_ms,
+    };
     if timeout_ms == 0 {
         return Err(HttpRequestError::Timeout);
     }
     let timeout = Duration::from_millis(timeout_ms);
     let url: reqwest::Url = request.url.parse().or(Err(HttpRequestError::InvalidUrl))?;
+    let max_redirects = options.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS) as usize;
+    let redirects = Arc::new(AtomicU32::new(0));
+    let blocked = Arc::new(AtomicBool::new(false));
+    let redirect_policy = if max_redirects == 0 {
+        reqwest::redirect::Policy::none()
+    } else {
+        let redirects = redirects.clone();
+        let blocked = blocked.clone();
+        reqwest::redirect::Policy::custom(move |attempt| {
+            let host_allowed = match &host_filter {
+                Some(filter) => filter(attempt.url().host_str().unwrap_or_default()),
+                None => true,
+            };
+            if !host_allowed {
+                blocked.store(true, Ordering::Relaxed);
+                attempt.error("redirected to a destination not allowed")
+            } else if attempt.previous().len() > max_redirects {
+                attempt.error("too many redirects")
+            } else {
+                redirects.store(attempt.previous().len() as u32, Ordering::Relaxed);
+                attempt.follow()
+            }
+        })
+    };
     let client = reqwest::Client::builder()
         .trust_dns(true)
         .timeout(timeout)
+        .redirect(redirect_policy)
+        .tls_info(true)
         .env_proxy(url.host_str().unwrap_or_default())
         .build()
         .or(Err(HttpRequestError::FailedToCreateClient))?;
@@ -128,40 +267,99 @@ async fn async_http_request(
 
     let mut response = match result {
         Ok(response) => response,
+        Err(err) if err.is_redirect() => {
+            if blocked.load(Ordering::Relaxed) {
+                return Err(HttpRequestError::DestinationNotAllowed);
+            }
+            return Err(HttpRequestError::TooManyRedirects);
+        }
         Err(err) => {
             // If there is somthing wrong with the network, we can not inspect the reason too
             // much here. Let it return a non-standard 523 here.
-            return Ok(HttpResponse {
+            return Ok(HttpResponseV2 {
                 status_code: 523,
                 reason_phrase: "Unreachable".into(),
                 body: format!("{err:?}").into_bytes(),
                 headers: vec![],
+                meta: Default::default(),
             });
         }
     };
 
+    let peer_cert_sha256 = response
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .map(sha256);
+    if !options.pinned_certs.is_empty() {
+        match &peer_cert_sha256 {
+            Some(hash) if options.pinned_certs.contains(hash) => {}
+            _ => return Err(HttpRequestError::CertificateNotPinned),
+        }
+    }
+
     let headers: Vec<_> = response
         .headers()
         .iter()
         .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().into()))
         .collect();
+    let final_url = response.url().to_string();
 
-    const MAX_BODY_SIZE: usize = 1024 * 1024 * 2; // 2MB
-
+    let body_limit = match options.max_body_size {
+        Some(size) => (size as usize).min(MAX_BODY_SIZE),
+        None => MAX_BODY_SIZE,
+    };
+    let download_limit = match options.max_download_size {
+        Some(size) => size.min(MAX_DOWNLOAD_SIZE),
+        None => MAX_DOWNLOAD_SIZE,
+    };
     let mut body = Vec::new();
-    let mut writer = LimitedWriter::new(&mut body, MAX_BODY_SIZE);
+    let mut writer = LimitedWriter::new(&mut body, body_limit);
+    let mut hasher = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut body_size = 0_u64;
+    let mut body_truncated = false;
 
     while let Some(chunk) = response
         .chunk()
         .await
         .or(Err(HttpRequestError::NetworkError))?
     {
-        writer
-            .write_all(&chunk)
-            .or(Err(HttpRequestError::ResponseTooLarge))?;
+        let chunk_size = chunk.len() as u64;
+        if let Some(budget) = &byte_budget {
+            budget
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                    left.checked_sub(chunk_size)
+                })
+                .or(Err(HttpRequestError::QuotaExceeded))?;
+        }
+        body_size += chunk_size;
+        if body_size > download_limit {
+            return Err(HttpRequestError::ResponseTooLarge);
+        }
+        hasher.update(&chunk);
+        match options.body_mode {
+            ResponseBodyMode::Buffered => {
+                writer
+                    .write_all(&chunk)
+                    .or(Err(HttpRequestError::ResponseTooLarge))?;
+            }
+            ResponseBodyMode::Streamed => {
+                if body_truncated {
+                    continue;
+                }
+                let room = writer.remaining().min(chunk.len());
+                writer
+                    .write_all(&chunk[..room])
+                    .or(Err(HttpRequestError::ResponseTooLarge))?;
+                body_truncated = room < chunk.len();
+            }
+        }
     }
 
-    let response = HttpResponse {
+    let mut body_sha256 = [0u8; 32];
+    body_sha256.copy_from_slice(hasher.finish().as_ref());
+
+    let response = HttpResponseV2 {
         status_code: response.status().as_u16(),
         reason_phrase: response
             .status()
@@ -170,10 +368,24 @@ async fn async_http_request(
             .into(),
         body,
         headers,
+        meta: HttpResponseMeta {
+            final_url,
+            redirects: redirects.load(Ordering::Relaxed),
+            body_size,
+            body_sha256,
+            body_truncated,
+            peer_cert_sha256,
+        },
     };
     Ok(response)
 }
 
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, data).as_ref());
+    hash
+}
+
 impl<T: PinkRuntimeEnv, E: From<&'static str>> PinkExtBackend for DefaultPinkExtension<'_, T, E> {
     type Error = E;
     fn http_request(&self, request: HttpRequest) -> Result<HttpResponse, Self::Error> {
@@ -188,6 +400,21 @@ impl<T: PinkRuntimeEnv, E: From<&'static str>> PinkExtBackend for DefaultPinkExt
         Ok(batch_http_request(requests, timeout_ms))
     }
 
+    fn http_request_v2(
+        &self,
+        request: HttpRequestV2,
+    ) -> Result<Result<HttpResponseV2, HttpRequestError>, Self::Error> {
+        Ok(http_request_v2(request, 10 * 1000))
+    }
+
+    fn batch_http_request_v2(
+        &self,
+        requests: Vec<HttpRequestV2>,
+        timeout_ms: u64,
+    ) -> Result<ext::BatchHttpResultV2, Self::Error> {
+        Ok(batch_http_request_v2(requests, timeout_ms))
+    }
+
     fn sign(
         &self,
         sigtype: SigType,
@@ -368,6 +595,10 @@ impl<T: PinkRuntimeEnv, E: From<&'static str>> PinkExtBackend for DefaultPinkExt
     fn current_event_chain_head(&self) -> Result<(u64, Hash), Self::Error> {
         Ok((0, Default::default()))
     }
+
+    fn randomness_beacon(&self, _block_number: u32) -> Result<Option<[u8; 32]>, Self::Error> {
+        Ok(None)
+    }
 }
 
 struct LimitedWriter<W> {
@@ -384,6 +615,10 @@ impl<W> LimitedWriter<W> {
             limit,
         }
     }
+
+    fn remaining(&self) -> usize {
+        self.limit - self.written
+    }
 }
 
 impl<W: std::io::Write> std::io::Write for LimitedWriter<W> {
@@ -403,3 +638,120 @@ impl<W: std::io::Write> std::io::Write for LimitedWriter<W> {
         self.writer.flush()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_request_v2_timeout_is_capped() {
+        let request = HttpRequestV2::new("https://example.com", "GET", vec![], vec![]);
+        let result = http_request_v2(request.clone().timeout_ms(1000), 0);
+        assert!(matches!(result, Err(HttpRequestError::Timeout)));
+        let result = http_request_v2(request.timeout_ms(0), 1000);
+        assert!(matches!(result, Err(HttpRequestError::Timeout)));
+    }
+
+    #[test]
+    fn http_request_v2_rejects_invalid_url() {
+        let request = HttpRequestV2::new("not a url", "GET", vec![], vec![]);
+        let result = http_request_v2(request, 1000);
+        assert!(matches!(result, Err(HttpRequestError::InvalidUrl)));
+    }
+
+    #[test]
+    fn v1_0_redirect_loop_is_unreachable() {
+        let response = v1_0_compatible_error(HttpRequestError::TooManyRedirects).unwrap();
+        assert_eq!(response.status_code, 523);
+        let response = v1_0_compatible_error(HttpRequestError::ResponseTooLarge).unwrap();
+        assert_eq!(response.status_code, 524);
+    }
+
+    #[test]
+    fn redirect_to_blocked_host_is_rejected() {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 302 Found\r\nLocation: http://blocked.invalid/\r\nContent-Length: 0\r\n\r\n",
+                )
+                .unwrap();
+        });
+        let filter: HostFilter = Arc::new(|host: &str| host == "127.0.0.1");
+        let url = format!("http://127.0.0.1:{port}/");
+        let request = HttpRequestV2::new(&url, "GET", vec![], vec![]);
+        let result = http_request_v2_filtered(request, 5000, Some(filter), None);
+        assert!(matches!(
+            result,
+            Err(HttpRequestError::DestinationNotAllowed)
+        ));
+        server.join().unwrap();
+    }
+
+    fn serve_body(body_size: usize) -> (String, std::thread::JoinHandle<()>) {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).unwrap();
+            let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {body_size}\r\n\r\n");
+            stream.write_all(header.as_bytes()).unwrap();
+            // The client may hang up early.
+            let _ = stream.write_all(&vec![b'x'; body_size]);
+        });
+        (format!("http://127.0.0.1:{port}/"), server)
+    }
+
+    #[test]
+    fn streamed_download_is_capped() {
+        let (url, server) = serve_body(1000);
+        let request = HttpRequestV2::new(&url, "GET", vec![], vec![])
+            .body_mode(ResponseBodyMode::Streamed)
+            .max_body_size(10)
+            .max_download_size(999);
+        let result = http_request_v2(request, 5000);
+        assert!(matches!(result, Err(HttpRequestError::ResponseTooLarge)));
+        server.join().unwrap();
+
+        let (url, server) = serve_body(1000);
+        let request = HttpRequestV2::new(&url, "GET", vec![], vec![])
+            .body_mode(ResponseBodyMode::Streamed)
+            .max_body_size(10);
+        let response = http_request_v2(request, 5000).unwrap();
+        assert_eq!(response.body.len(), 10);
+        assert_eq!(response.meta.body_size, 1000);
+        assert!(response.meta.body_truncated);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn download_stops_when_budget_runs_out() {
+        let (url, server) = serve_body(1000);
+        let request = HttpRequestV2::new(&url, "GET", vec![], vec![])
+            .body_mode(ResponseBodyMode::Streamed)
+            .max_body_size(10);
+        let budget: ByteBudget = Arc::new(AtomicU64::new(999));
+        let result = http_request_v2_filtered(request, 5000, None, Some(budget.clone()));
+        assert!(matches!(result, Err(HttpRequestError::QuotaExceeded)));
+        assert!(budget.load(Ordering::Relaxed) < 999);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn limited_writer_remaining() {
+        let mut buf = Vec::new();
+        let mut writer = LimitedWriter::new(&mut buf, 4);
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(writer.remaining(), 1);
+        assert!(writer.write_all(b"de").is_err());
+    }
+}
@@ -34,6 +34,21 @@ impl ext::PinkExtBackend for MockExtension {
         super::DefaultPinkExtension::new(self).batch_http_request(requests, timeout_ms)
     }
 
+    fn http_request_v2(
+        &self,
+        request: ext::HttpRequestV2,
+    ) -> Result<Result<ext::HttpResponseV2, ext::HttpRequestError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).http_request_v2(request)
+    }
+
+    fn batch_http_request_v2(
+        &self,
+        requests: Vec<ext::HttpRequestV2>,
+        timeout_ms: u64,
+    ) -> Result<ext::BatchHttpResultV2, Self::Error> {
+        super::DefaultPinkExtension::new(self).batch_http_request_v2(requests, timeout_ms)
+    }
+
     fn sign(
         &self,
         sigtype: SigType,
@@ -152,6 +167,10 @@ impl ext::PinkExtBackend for MockExtension {
     fn current_event_chain_head(&self) -> Result<(u64, Hash), Self::Error> {
         Ok((0, Default::default()))
     }
+
+    fn randomness_beacon(&self, block_number: u32) -> Result<Option<[u8; 32]>, Self::Error> {
+        super::DefaultPinkExtension::new(self).randomness_beacon(block_number)
+    }
 }
 
 thread_local! {
@@ -2,7 +2,10 @@ use alloc::borrow::Cow;
 use alloc::vec::Vec;
 use ink::ChainExtensionInstance;
 
-pub use http_request::{HttpRequest, HttpRequestError, HttpResponse};
+pub use http_request::{
+    HttpRequest, HttpRequestError, HttpRequestOptions, HttpRequestV2, HttpResponse,
+    HttpResponseMeta, HttpResponseV2, ResponseBodyMode,
+};
 pub use ink::primitives::AccountId;
 pub use signing::SigType;
 
@@ -101,6 +104,8 @@ impl ink::env::chain_extension::FromStatusCode for ErrorCode {
 }
 
 pub type BatchHttpResult = Result<Vec<Result<HttpResponse, HttpRequestError>>, HttpRequestError>;
+pub type BatchHttpResultV2 =
+    Result<Vec<Result<HttpResponseV2, HttpRequestError>>, HttpRequestError>;
 
 /// Extensions for the ink runtime defined by phat contract.
 #[pink_extension_macro::chain_extension]
@@ -635,6 +640,74 @@ pub trait PinkExt {
     /// Returns (next event block number, last event block hash)
     #[ink(extension = 23, handle_status = false)]
     fn current_event_chain_head() -> (u64, Hash);
+
+    /// Make a HTTP request with extended options.
+    ///
+    /// Compared to [`http_request`](Self::http_request), the request can control the timeout,
+    /// redirects, response size limit, certificate pinning and whether the body is streamed.
+    /// The response carries metadata such as the final URL after redirects, the digest of the
+    /// full body and the digest of the certificate presented by the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `request`: A `HttpRequestV2` struct containing all the details for the HTTP request.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<HttpResponseV2, HttpRequestError>` - The response with its metadata, or the
+    ///   reason why the request failed.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let request = HttpRequestV2::new("https://httpbin.org/get", "GET", Default::default(), Default::default())
+    ///     .timeout_ms(5000)
+    ///     .max_redirects(0);
+    /// let response = pink::ext().http_request_v2(request)?;
+    /// let body_hash = response.meta.body_sha256;
+    /// ```
+    ///
+    /// # Availability
+    /// any contract | query only | runtime v1.2+
+    #[ink(extension = 24, handle_status = true)]
+    fn http_request_v2(request: HttpRequestV2) -> Result<HttpResponseV2, HttpRequestError>;
+
+    /// Batch HTTP request with extended options.
+    ///
+    /// The same as [`batch_http_request`](Self::batch_http_request), but takes `HttpRequestV2`
+    /// and returns `HttpResponseV2`. The timeout of each request is capped by `timeout_ms`.
+    ///
+    /// # Availability
+    /// any contract | query only | runtime v1.2+
+    #[ink(extension = 25, handle_status = true)]
+    fn batch_http_request_v2(requests: Vec<HttpRequestV2>, timeout_ms: u64) -> BatchHttpResultV2;
+
+    /// Get the randomness beacon generated by the gatekeepers at the given block.
+    ///
+    /// The gatekeepers publish a beacon every few blocks on chain, as the output of a VRF keyed by
+    /// the master key. The VRF proof is verified on chain, so the gatekeepers cannot choose the
+    /// beacon. The beacon is the same for all the workers in the cluster, so it can be used in
+    /// transactions to build lotteries or fair ordering. Only the recent beacons are kept on chain.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_number`: The block number at which the beacon is generated.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<[u8; 32]>` - The beacon, or `None` if there is no beacon generated at the block
+    ///   or it has not reached the chain yet.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let beacon = pink::ext().randomness_beacon(draw_at_block);
+    /// ```
+    ///
+    /// # Availability
+    /// any contract | query | transaction | runtime v1.2+
+    #[ink(extension = 26, handle_status = false)]
+    fn randomness_beacon(block_number: u32) -> Option<[u8; 32]>;
 }
 
 pub fn pink_extension_instance() -> <PinkExt as ChainExtensionInstance>::Instance {
@@ -38,6 +38,173 @@ pub struct HttpResponse {
     pub body: Vec<u8>,
 }
 
+/// How the body of a response should be delivered to the contract.
+#[derive(scale::Encode, scale::Decode, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum ResponseBodyMode {
+    /// Keep the whole body in memory.
+    ///
+    /// The request fails with `ResponseTooLarge` if the body exceeds `max_body_size`.
+    #[default]
+    Buffered,
+    /// Stream the body through a SHA-256 hasher and keep only the first `max_body_size` bytes.
+    ///
+    /// The returned `HttpResponseMeta` contains the size and digest of the full body, so
+    /// large responses can be attested without holding them in memory. The request fails with
+    /// `ResponseTooLarge` if the full body exceeds `max_download_size`.
+    Streamed,
+}
+
+/// Options to control the behavior of a `HttpRequestV2`.
+#[derive(scale::Encode, scale::Decode, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct HttpRequestOptions {
+    /// Timeout of this request in milliseconds.
+    ///
+    /// It is capped by the time remaining for the current call. `None` uses the remaining time.
+    pub timeout_ms: Option<u64>,
+    /// Maximum number of redirects to follow. `Some(0)` returns the redirect responses as they are.
+    ///
+    /// `None` follows up to 10 redirects.
+    pub max_redirects: Option<u32>,
+    /// Maximum number of body bytes returned to the contract.
+    ///
+    /// It is capped by the runtime limit (2MB). `None` uses the runtime limit.
+    pub max_body_size: Option<u32>,
+    /// Maximum number of body bytes downloaded in `Streamed` mode.
+    ///
+    /// It is capped by the runtime limit (64MB). `None` uses the runtime limit.
+    pub max_download_size: Option<u64>,
+    /// SHA-256 digests of the DER encoded leaf certificates that the server is allowed to present.
+    ///
+    /// Leave it empty to accept any certificate trusted by the worker. When not empty, the
+    /// request fails with `CertificateNotPinned` if the server presents a certificate out of the
+    /// list. Note that the request has already been sent when the check happens, so do not put
+    /// secrets in a request that relies on pinning to authenticate the server.
+    pub pinned_certs: Vec<[u8; 32]>,
+    /// How the response body should be delivered.
+    pub body_mode: ResponseBodyMode,
+}
+
+/// A HTTP request with extended options.
+#[derive(scale::Encode, scale::Decode, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct HttpRequestV2 {
+    pub url: String,
+    pub method: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub options: HttpRequestOptions,
+}
+
+impl HttpRequestV2 {
+    /// Create a new http request with default options.
+    pub fn new(
+        url: impl Into<String>,
+        method: impl Into<String>,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> Self {
+        Self {
+            url: url.into(),
+            method: method.into(),
+            headers,
+            body,
+            options: Default::default(),
+        }
+    }
+
+    /// Set the timeout of the request in milliseconds.
+    pub fn timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.options.timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Set the maximum number of redirects to follow.
+    pub fn max_redirects(mut self, max_redirects: u32) -> Self {
+        self.options.max_redirects = Some(max_redirects);
+        self
+    }
+
+    /// Set the maximum number of body bytes returned to the contract.
+    pub fn max_body_size(mut self, max_body_size: u32) -> Self {
+        self.options.max_body_size = Some(max_body_size);
+        self
+    }
+
+    /// Set the maximum number of body bytes downloaded in `Streamed` mode.
+    pub fn max_download_size(mut self, max_download_size: u64) -> Self {
+        self.options.max_download_size = Some(max_download_size);
+        self
+    }
+
+    /// Accept a server certificate with the given SHA-256 digest of its DER encoding.
+    pub fn pin_cert(mut self, cert_sha256: [u8; 32]) -> Self {
+        self.options.pinned_certs.push(cert_sha256);
+        self
+    }
+
+    /// Set how the response body should be delivered.
+    pub fn body_mode(mut self, body_mode: ResponseBodyMode) -> Self {
+        self.options.body_mode = body_mode;
+        self
+    }
+}
+
+impl From<HttpRequest> for HttpRequestV2 {
+    fn from(request: HttpRequest) -> Self {
+        Self {
+            url: request.url,
+            method: request.method,
+            headers: request.headers,
+            body: request.body,
+            options: Default::default(),
+        }
+    }
+}
+
+/// Metadata about how a `HttpResponseV2` was fetched.
+#[derive(scale::Encode, scale::Decode, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct HttpResponseMeta {
+    /// The URL of the final response after following redirects.
+    pub final_url: String,
+    /// The number of redirects followed.
+    pub redirects: u32,
+    /// The size of the full body received from the server.
+    pub body_size: u64,
+    /// The SHA-256 digest of the full body received from the server.
+    pub body_sha256: [u8; 32],
+    /// Whether the `body` in the response is truncated. Only possible in `Streamed` mode.
+    pub body_truncated: bool,
+    /// The SHA-256 digest of the DER encoded certificate presented by the server.
+    ///
+    /// `None` for plain HTTP requests.
+    pub peer_cert_sha256: Option<[u8; 32]>,
+}
+
+/// A HTTP response with metadata about how it was fetched.
+#[derive(scale::Encode, scale::Decode, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct HttpResponseV2 {
+    pub status_code: u16,
+    pub reason_phrase: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub meta: HttpResponseMeta,
+}
+
+impl From<HttpResponseV2> for HttpResponse {
+    fn from(response: HttpResponseV2) -> Self {
+        Self {
+            status_code: response.status_code,
+            reason_phrase: response.reason_phrase,
+            headers: response.headers,
+            body: response.body,
+        }
+    }
+}
+
 #[derive(scale::Encode, scale::Decode, TryFromPrimitive, IntoPrimitive, Clone, Copy, Debug)]
 #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
 #[repr(u32)]
@@ -52,6 +219,10 @@ pub enum HttpRequestError {
     TooManyRequests,
     NetworkError,
     ResponseTooLarge,
+    TooManyRedirects,
+    CertificateNotPinned,
+    QuotaExceeded,
+    DestinationNotAllowed,
 }
 
 impl super::sealed::Sealed for HttpRequestError {}
@@ -85,6 +256,11 @@ impl From<scale::Error> for HttpRequestError {
 }
 
 impl HttpRequestError {
+    /// Whether the error is known by contracts built against runtime v1.1.
+    pub fn is_v1_1_error(&self) -> bool {
+        (*self as u32) <= (Self::ResponseTooLarge as u32)
+    }
+
     pub fn display(&self) -> &'static str {
         match self {
             Self::InvalidUrl => "Invalid URL",
@@ -97,6 +273,10 @@ impl HttpRequestError {
             Self::TooManyRequests => "Too many requests",
             Self::NetworkError => "Network error",
             Self::ResponseTooLarge => "Response too large",
+            Self::TooManyRedirects => "Too many redirects",
+            Self::CertificateNotPinned => "Certificate not pinned",
+            Self::QuotaExceeded => "Quota exceeded",
+            Self::DestinationNotAllowed => "Destination not allowed",
         }
     }
 }
@@ -4,6 +4,7 @@
 
 extern crate alloc;
 
+use alloc::string::String;
 use alloc::vec::Vec;
 use ink::env::{emit_event, topics::state::HasRemainingTopics, Environment, Topics};
 
@@ -161,6 +162,32 @@ pub enum PinkEvent {
     /// System contract
     #[codec(index = 10)]
     UpgradeRuntimeTo { version: (u32, u32) },
+    /// Set the HTTP egress policy of a contract, or the default policy of the cluster
+    ///
+    /// Please do not use this event directly, use [`set_http_egress_policy()`] instead.
+    ///
+    /// # Availability
+    /// System contract
+    #[codec(index = 11)]
+    SetHttpEgressPolicy {
+        /// The target contract address. `None` to set the cluster default policy.
+        contract: Option<AccountId>,
+        /// The policy to apply. `None` to remove the policy.
+        policy: Option<HttpEgressPolicy>,
+    },
+    /// Set the query policy of a contract, or the default policy of the cluster
+    ///
+    /// Please do not use this event directly, use [`set_query_policy()`] instead.
+    ///
+    /// # Availability
+    /// System contract
+    #[codec(index = 12)]
+    SetQueryPolicy {
+        /// The target contract address. `None` to set the cluster default policy.
+        contract: Option<AccountId>,
+        /// The policy to apply. `None` to remove the policy.
+        policy: Option<QueryPolicy>,
+    },
 }
 
 impl PinkEvent {
@@ -175,6 +202,8 @@ impl PinkEvent {
             PinkEvent::SetLogHandler(_) => false,
             PinkEvent::SetContractWeight { .. } => false,
             PinkEvent::UpgradeRuntimeTo { .. } => false,
+            PinkEvent::SetHttpEgressPolicy { .. } => false,
+            PinkEvent::SetQueryPolicy { .. } => false,
         }
     }
 
@@ -189,6 +218,8 @@ impl PinkEvent {
             PinkEvent::SetLogHandler(_) => "SetLogHandler",
             PinkEvent::SetContractWeight { .. } => "SetContractWeight",
             PinkEvent::UpgradeRuntimeTo { .. } => "UpgradeRuntimeTo",
+            PinkEvent::SetHttpEgressPolicy { .. } => "SetHttpEgressPolicy",
+            PinkEvent::SetQueryPolicy { .. } => "SetQueryPolicy",
         }
     }
 
@@ -203,10 +234,89 @@ impl PinkEvent {
             PinkEvent::SetLogHandler(_) => false,
             PinkEvent::SetContractWeight { .. } => false,
             PinkEvent::UpgradeRuntimeTo { .. } => false,
+            PinkEvent::SetHttpEgressPolicy { .. } => false,
+            PinkEvent::SetQueryPolicy { .. } => false,
         }
     }
 }
 
+/// Limits applied to the outgoing HTTP requests of a contract.
+///
+/// The quotas are counted by each worker in fixed windows of one minute. Requests exceeding
+/// the quotas fail with `HttpRequestError::QuotaExceeded`, and requests to a destination not
+/// allowed by the domain lists fail with `HttpRequestError::DestinationNotAllowed`.
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct HttpEgressPolicy {
+    /// Maximum number of requests per minute. `None` for unlimited.
+    pub max_requests_per_minute: Option<u32>,
+    /// Maximum number of request and response body bytes per minute. `None` for unlimited.
+    ///
+    /// Responses still downloading when the quota runs out fail with `QuotaExceeded`.
+    pub max_bytes_per_minute: Option<u64>,
+    /// Domains the contract is allowed to access. Empty to allow any domain not denied.
+    ///
+    /// A domain also matches its subdomains, e.g. `example.com` matches `api.example.com`.
+    pub allowed_domains: Vec<String>,
+    /// Domains the contract is not allowed to access. Takes precedence over `allowed_domains`.
+    pub denied_domains: Vec<String>,
+}
+
+impl HttpEgressPolicy {
+    /// Check whether the given host is allowed by the domain lists of the policy.
+    pub fn is_host_allowed(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let matches = |domain: &String| {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            host == domain
+                || (host.len() > domain.len()
+                    && host.ends_with(&domain)
+                    && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+        };
+        if self.denied_domains.iter().any(matches) {
+            return false;
+        }
+        self.allowed_domains.is_empty() || self.allowed_domains.iter().any(matches)
+    }
+}
+
+/// Limits applied to the queries to a contract.
+///
+/// The quotas are counted per origin by each worker in fixed windows of one minute. Queries from
+/// an origin not allowed fail with `QueryError::Forbidden`, queries exceeding the quotas fail
+/// with `QueryError::QuotaExceeded`, and ink queries from an origin short of the required balance
+/// fail with `QueryError::PaymentRequired`.
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct QueryPolicy {
+    /// Origins allowed to query the contract. Empty to allow any origin, including unsigned
+    /// queries to the sidevm.
+    pub allowed_origins: Vec<AccountId>,
+    /// Maximum number of queries per minute of each origin. `None` for unlimited.
+    pub max_queries_per_minute: Option<u32>,
+    /// Maximum gas (ref time) per minute of each origin. `None` for unlimited.
+    pub max_gas_per_minute: Option<u64>,
+    /// The minimum free balance the origin must hold in the cluster to send ink queries. `None`
+    /// for no requirement.
+    ///
+    /// This is a balance gate, not a charge: queries are never committed, so nothing is paid. If
+    /// set, the origin can not fund the query with a `deposit`, and the gas of the query is priced
+    /// against its balance, so the origin must also afford the gas.
+    pub min_balance_required: Option<Balance>,
+}
+
+impl QueryPolicy {
+    /// Check whether the given origin, `None` for unsigned queries, is allowed to query.
+    pub fn is_origin_allowed(&self, origin: Option<&[u8; 32]>) -> bool {
+        if self.allowed_origins.is_empty() {
+            return true;
+        }
+        origin.map_or(false, |origin| {
+            self.allowed_origins.iter().any(|a| a.as_ref() == origin)
+        })
+    }
+}
+
 /// Instructions to manipulate the cache.
 #[derive(Encode, Decode, Debug, Clone)]
 #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
@@ -350,6 +460,22 @@ pub fn upgrade_runtime(version: (u32, u32)) {
     emit_event::<PinkEnvironment, _>(PinkEvent::UpgradeRuntimeTo { version });
 }
 
+/// Set the HTTP egress policy of a contract. (system only)
+///
+/// Pass `None` as the contract to set the default policy applied to contracts without their own
+/// policy, and `None` as the policy to remove it.
+pub fn set_http_egress_policy(contract: Option<AccountId>, policy: Option<HttpEgressPolicy>) {
+    emit_event::<PinkEnvironment, _>(PinkEvent::SetHttpEgressPolicy { contract, policy });
+}
+
+/// Set the query policy of a contract. (system only)
+///
+/// Pass `None` as the contract to set the default policy applied to contracts without their own
+/// policy, and `None` as the policy to remove it.
+pub fn set_query_policy(contract: Option<AccountId>, policy: Option<QueryPolicy>) {
+    emit_event::<PinkEnvironment, _>(PinkEvent::SetQueryPolicy { contract, policy });
+}
+
 /// Pink defined environment. This environment is used to access the phat contract extended runtime features.
 ///
 /// # Example
@@ -402,4 +528,31 @@ mod tests {
     fn test_event_topics() {
         insta::assert_debug_snapshot!(super::PinkEvent::event_topic());
     }
+
+    #[test]
+    fn test_http_egress_policy_domains() {
+        let policy = super::HttpEgressPolicy {
+            allowed_domains: vec!["example.com".into()],
+            denied_domains: vec!["evil.example.com".into()],
+            ..Default::default()
+        };
+        assert!(policy.is_host_allowed("example.com"));
+        assert!(policy.is_host_allowed("API.Example.com."));
+        assert!(!policy.is_host_allowed("badexample.com"));
+        assert!(!policy.is_host_allowed("evil.example.com"));
+        assert!(!policy.is_host_allowed("x.evil.example.com"));
+        assert!(super::HttpEgressPolicy::default().is_host_allowed("any.org"));
+    }
+
+    #[test]
+    fn test_query_policy_origins() {
+        let policy = super::QueryPolicy {
+            allowed_origins: vec![[1; 32].into()],
+            ..Default::default()
+        };
+        assert!(policy.is_origin_allowed(Some(&[1; 32])));
+        assert!(!policy.is_origin_allowed(Some(&[2; 32])));
+        assert!(!policy.is_origin_allowed(None));
+        assert!(super::QueryPolicy::default().is_origin_allowed(None));
+    }
 }
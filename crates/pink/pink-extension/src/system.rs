@@ -114,6 +114,26 @@ pub trait System {
     #[ink(message)]
     fn set_contract_weight(&self, contract_id: AccountId, weight: u32) -> Result<()>;
 
+    /// Sets the HTTP egress policy of a contract, or the default policy of the cluster if
+    /// `contract_id` is `None`. Pass `None` as the policy to remove it. Must be called by an
+    /// administrator.
+    #[ink(message)]
+    fn set_http_egress_policy(
+        &self,
+        contract_id: Option<AccountId>,
+        policy: Option<crate::HttpEgressPolicy>,
+    ) -> Result<()>;
+
+    /// Sets the query policy of a contract, or the default policy of the cluster if
+    /// `contract_id` is `None`. Pass `None` as the policy to remove it. Must be called by an
+    /// administrator.
+    #[ink(message)]
+    fn set_query_policy(
+        &self,
+        contract_id: Option<AccountId>,
+        policy: Option<crate::QueryPolicy>,
+    ) -> Result<()>;
+
     /// Returns the total balance of a given account.
     #[ink(message)]
     fn total_balance_of(&self, account: AccountId) -> Balance;
@@ -21,6 +21,16 @@ impl ClusterStorage {
         self.kv_store.get(key)
     }
 
+    /// Iterate over the trie nodes, in `(hash, (ref_count, node))` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &(i32, Vec<u8>))> {
+        self.kv_store.iter()
+    }
+
+    /// Keep only the trie nodes whose hashes satisfy the predicate.
+    pub fn retain(&mut self, mut f: impl FnMut(&[u8]) -> bool) {
+        self.kv_store.retain(|key, _| f(key));
+    }
+
     fn update(&mut self, key: Vec<u8>, value: Vec<u8>, rc: i32) {
         if rc == 0 {
             return;
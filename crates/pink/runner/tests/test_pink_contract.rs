@@ -3,11 +3,12 @@ use phala_crypto::sr25519::Persistence;
 use pink_capi::v1::ecall::{ECalls, TransactionArguments};
 use pink_runner::{
     local_cache,
-    types::{AccountId, Balance, ExecutionMode, Weight},
+    types::{AccountId, Balance, ExecutionMode, FrameKind, Weight},
 };
+use scale::Decode;
 use sp_runtime::{app_crypto::sr25519, AccountId32};
 
-use test_cluster::TestCluster;
+use test_cluster::{ContractExecResult, TestCluster};
 
 pub const ALICE: AccountId32 = AccountId32::new([1u8; 32]);
 pub const BOB: AccountId32 = AccountId32::new([2u8; 32]);
@@ -90,6 +91,96 @@ fn test_ink_flip() {
     assert_matches!(result, Ok((42, 24)));
 }
 
+#[test]
+fn test_dry_run() {
+    let mut cluster = TestCluster::for_test();
+
+    let code_hash = cluster
+        .upload_code(
+            alice(),
+            include_bytes!("./fixtures/flip/flip.wasm").to_vec(),
+            true,
+        )
+        .unwrap();
+    let contract = cluster
+        .instantiate_typed(
+            code_hash,
+            0x9bae9d5e,
+            true,
+            vec![],
+            cluster.mode(),
+            tx_args(),
+        )
+        .unwrap();
+
+    let flip = 0x633aa551u32.to_be_bytes().to_vec();
+    let output = cluster.contract_dry_run(contract.clone(), flip, tx_args());
+    let result = ContractExecResult::decode(&mut &output.result[..]).unwrap();
+    assert!(result.result.is_ok());
+    let [frame] = &output.trace.calls[..] else {
+        panic!("Unexpected frames: {:?}", output.trace.calls);
+    };
+    assert_eq!(frame.kind, FrameKind::Call);
+    assert_eq!(frame.contract, Some(contract.clone()));
+    assert!(!frame.reverted);
+    assert!(frame.calls.is_empty());
+    assert_eq!(output.trace.gas_consumed, result.gas_consumed.ref_time());
+    assert!(output
+        .trace
+        .storage_writes
+        .iter()
+        .any(|write| write.child_trie.is_some()));
+
+    // The changes are not committed
+    let result = cluster.call_typed::<_, bool>(
+        &contract,
+        0x2f865bd9, // get
+        (),
+        cluster.mode(),
+        tx_args(),
+    );
+    assert_matches!(result, Ok(true));
+}
+
+#[test]
+fn test_dry_run_nested_calls() {
+    let mut cluster = TestCluster::for_test();
+
+    cluster
+        .upload_code(
+            alice(),
+            include_bytes!("./fixtures/flip/flip.wasm").to_vec(),
+            true,
+        )
+        .unwrap();
+    let code_hash = cluster
+        .upload_code(
+            alice(),
+            include_bytes!("./fixtures/cross/cross.wasm").to_vec(),
+            true,
+        )
+        .unwrap();
+    let mut args = tx_args();
+    args.transfer = ENOUGH / 100;
+    let contract = cluster
+        .instantiate_typed(code_hash, 0x9bae9d5e, (), vec![], cluster.mode(), args)
+        .unwrap();
+
+    let call_other_contract = 0xc3220014u32.to_be_bytes().to_vec();
+    let output = cluster.contract_dry_run(contract.clone(), call_other_contract, tx_args());
+    let [frame] = &output.trace.calls[..] else {
+        panic!("Unexpected frames: {:?}", output.trace.calls);
+    };
+    assert_eq!(frame.contract, Some(contract.clone()));
+    let [nested] = &frame.calls[..] else {
+        panic!("Unexpected nested frames: {:?}", frame.calls);
+    };
+    assert_eq!(nested.kind, FrameKind::Call);
+    assert!(nested.contract.is_some());
+    assert_ne!(nested.contract, frame.contract);
+    assert!(!nested.reverted);
+}
+
 #[test]
 fn test_ink_cross_contract_instantiate() {
     let mut cluster = TestCluster::for_test();
@@ -256,8 +347,8 @@ mod test_cluster {
     use pink_capi::v1::{
         ecall::ECalls,
         ocall::{
-            BatchHttpResult, ExecContext, HttpRequest, HttpRequestError, HttpResponse, OCalls,
-            StorageChanges,
+            BatchHttpResult, BatchHttpResultV2, ExecContext, HttpRequest, HttpRequestError,
+            HttpRequestV2, HttpResponse, HttpResponseV2, OCalls, StorageChanges,
         },
         CrossCall, CrossCallMut, ECall,
     };
@@ -476,6 +567,10 @@ mod test_cluster {
             vec![]
         }
 
+        fn randomness_beacon(&self, _block_number: u32) -> Option<[u8; 32]> {
+            None
+        }
+
         fn http_request(
             &self,
             _contract: AccountId,
@@ -493,6 +588,23 @@ mod test_cluster {
             pink_extension_runtime::batch_http_request(requests, timeout_ms)
         }
 
+        fn http_request_v2(
+            &self,
+            _contract: AccountId,
+            request: HttpRequestV2,
+        ) -> Result<HttpResponseV2, HttpRequestError> {
+            pink_extension_runtime::http_request_v2(request, 10 * 1000)
+        }
+
+        fn batch_http_request_v2(
+            &self,
+            _: AccountId,
+            requests: Vec<HttpRequestV2>,
+            timeout_ms: u64,
+        ) -> BatchHttpResultV2 {
+            pink_extension_runtime::batch_http_request_v2(requests, timeout_ms)
+        }
+
         fn emit_system_event_block(&self, number: u64, _encoded_block: Vec<u8>) {
             log::info!("emit_system_event_block: number={}", number,);
         }
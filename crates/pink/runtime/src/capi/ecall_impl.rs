@@ -1,9 +1,10 @@
 use frame_support::{traits::Currency, weights::constants::WEIGHT_REF_TIME_PER_SECOND};
 use log::info;
 use pallet_contracts::{AddressGenerator, Determinism};
+use pallet_contracts_primitives::StorageDeposit;
 use phala_crypto::sr25519::Sr25519SecretKey;
 use pink_capi::{
-    types::{AccountId, Balance, ExecutionMode, Hash, Weight},
+    types::{AccountId, Balance, DryRunResult, ExecutionMode, Hash, Weight},
     v1::{
         ecall::{self, ClusterSetupConfig, TransactionArguments},
         ocall::OCalls,
@@ -274,6 +275,34 @@ impl ecall::ECalls for ECallImpl {
         result.encode()
     }
 
+    fn contract_dry_run(
+        &self,
+        address: AccountId,
+        input_data: Vec<u8>,
+        tx_args: TransactionArguments,
+    ) -> DryRunResult {
+        let mode = ExecutionMode::Estimating;
+        let tx_args = sanitize_args(tx_args, mode);
+        let context = OCallImpl.exec_context();
+        // Run in a storage of its own to get the changes, which are dropped afterwards.
+        let ((result, mut trace), effects, changes) = storage().execute_traced(&context, || {
+            handle_deposit(&tx_args);
+            crate::contract::bare_call(address, input_data, mode, tx_args)
+        });
+        trace.gas_consumed = result.gas_consumed.ref_time();
+        trace.gas_required = result.gas_required.ref_time();
+        match result.storage_deposit {
+            StorageDeposit::Charge(v) => trace.storage_deposit_charged = v,
+            StorageDeposit::Refund(v) => trace.storage_deposit_refunded = v,
+        }
+        trace.storage_writes = crate::trace::storage_writes(&changes);
+        DryRunResult {
+            result: result.encode(),
+            effects,
+            trace,
+        }
+    }
+
     fn git_revision(&self) -> String {
         phala_git_revision::git_revision().to_string()
     }
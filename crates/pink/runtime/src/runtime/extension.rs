@@ -9,7 +9,8 @@ use phala_crypto::sr25519::{Persistence, KDF};
 use phala_types::contract::ConvertTo;
 use pink_extension::{
     chain_extension::{
-        self as ext, HttpRequest, HttpResponse, PinkExtBackend, SigType, StorageQuotaExceeded,
+        self as ext, HttpRequest, HttpRequestError, HttpRequestV2, HttpResponse, HttpResponseV2,
+        PinkExtBackend, SigType, StorageQuotaExceeded,
     },
     dispatch_ext_call, CacheOp, EcdhPublicKey, EcdsaPublicKey, EcdsaSignature, Hash, PinkEvent,
 };
@@ -102,6 +103,7 @@ impl ChainExtension<super::PinkRuntime> for PinkExtension {
         }
 
         let address = env.ext().address().clone();
+        let trace_index = crate::trace::add_ext_call(&address, env.func_id(), env.in_len());
         let call_in_query = CallInQuery { address };
         let mode = OCallImpl.exec_context().mode;
         let result = if mode.is_query() {
@@ -121,6 +123,7 @@ impl ChainExtension<super::PinkRuntime> for PinkExtension {
                 ));
             }
         };
+        crate::trace::set_ext_call_output(trace_index, output.len() as u32);
         env.write(&output, false, None)
             .or(Err(DispatchError::Other(
                 "PinkExtension::call: failed to write output",
@@ -175,6 +178,21 @@ impl PinkExtBackend for CallInQuery {
         Ok(OCallImpl.batch_http_request(self.address.clone(), requests, timeout_ms))
     }
 
+    fn http_request_v2(
+        &self,
+        request: HttpRequestV2,
+    ) -> Result<Result<HttpResponseV2, HttpRequestError>, Self::Error> {
+        Ok(OCallImpl.http_request_v2(self.address.clone(), request))
+    }
+
+    fn batch_http_request_v2(
+        &self,
+        requests: Vec<HttpRequestV2>,
+        timeout_ms: u64,
+    ) -> Result<ext::BatchHttpResultV2, Self::Error> {
+        Ok(OCallImpl.batch_http_request_v2(self.address.clone(), requests, timeout_ms))
+    }
+
     fn sign(
         &self,
         sigtype: SigType,
@@ -232,6 +250,7 @@ impl PinkExtBackend for CallInQuery {
     }
 
     fn log(&self, level: u8, message: Cow<str>) -> Result<(), Self::Error> {
+        crate::trace::add_log(&self.address, level, &message);
         OCallImpl.log_to_server(self.address.clone(), level, message.as_ref().into());
         DefaultPinkExtension::new(self).log(level, message)
     }
@@ -327,6 +346,10 @@ impl PinkExtBackend for CallInQuery {
             PalletPink::last_event_block_hash().into(),
         ))
     }
+
+    fn randomness_beacon(&self, block_number: u32) -> Result<Option<[u8; 32]>, Self::Error> {
+        Ok(OCallImpl.randomness_beacon(block_number))
+    }
 }
 
 struct CallInCommand {
@@ -354,6 +377,19 @@ impl PinkExtBackend for CallInCommand {
     ) -> Result<ext::BatchHttpResult, Self::Error> {
         Ok(Err(ext::HttpRequestError::NotAllowed))
     }
+    fn http_request_v2(
+        &self,
+        _request: HttpRequestV2,
+    ) -> Result<Result<HttpResponseV2, HttpRequestError>, Self::Error> {
+        Ok(Err(HttpRequestError::NotAllowed))
+    }
+    fn batch_http_request_v2(
+        &self,
+        _requests: Vec<HttpRequestV2>,
+        _timeout_ms: u64,
+    ) -> Result<ext::BatchHttpResultV2, Self::Error> {
+        Ok(Err(HttpRequestError::NotAllowed))
+    }
     fn sign(
         &self,
         sigtype: SigType,
@@ -491,4 +527,10 @@ impl PinkExtBackend for CallInCommand {
     fn current_event_chain_head(&self) -> Result<(u64, Hash), Self::Error> {
         self.as_in_query.current_event_chain_head()
     }
+
+    fn randomness_beacon(&self, block_number: u32) -> Result<Option<[u8; 32]>, Self::Error> {
+        // The beacons are read from the chain storage at the block being processed, so the result
+        // is deterministic across the workers.
+        self.as_in_query.randomness_beacon(block_number)
+    }
 }
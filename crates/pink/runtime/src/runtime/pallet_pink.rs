@@ -100,7 +100,10 @@ pub mod pallet {
                 cluster_id.as_ref(),
                 salt,
             );
-            UncheckedFrom::unchecked_from(<T as frame_system::Config>::Hashing::hash(&buf))
+            let address: T::AccountId =
+                UncheckedFrom::unchecked_from(<T as frame_system::Config>::Hashing::hash(&buf));
+            crate::trace::set_instantiating(AsRef::<[u8]>::as_ref(&address));
+            address
         }
 
         fn deposit_address(contract_addr: &T::AccountId) -> T::AccountId {
@@ -19,7 +19,11 @@ impl TrieBackendStorage<Hashing> for ExternalDB {
     type Overlay = MemoryDB<Hashing>;
 
     fn get(&self, key: &Hash, _prefix: Prefix) -> Result<Option<DBValue>, DefaultError> {
-        Ok(OCallImpl.storage_get(key.as_ref().to_vec()))
+        let value = OCallImpl.storage_get(key.as_ref().to_vec());
+        if let Some(value) = &value {
+            crate::trace::add_storage_read(value.len());
+        }
+        Ok(value)
     }
 }
 
@@ -1,10 +1,12 @@
 use crate::{
     capi::OCallImpl,
     runtime::{ExecSideEffects, Pink as PalletPink, System, SystemEvents, Timestamp},
+    trace::TracingExt,
     types::{EventsBlock, EventsBlockBody, EventsBlockHeader, Hash, Hashing},
 };
-use pink_capi::v1::ocall::ExecContext;
+use pink_capi::{types::CallTrace, v1::ocall::ExecContext};
 use scale::Encode;
+use sp_externalities::Externalities;
 use sp_state_machine::{
     backend::AsTrieBackend, Backend as StorageBackend, Ext, OverlayedChanges,
     StorageTransactionCache,
@@ -34,6 +36,24 @@ where
         &self,
         exec_context: &ExecContext,
         f: impl FnOnce() -> R,
+    ) -> (R, ExecSideEffects, OverlayedChanges) {
+        self.execute_in(exec_context, false, f)
+    }
+
+    /// Like `execute_with`, but trace the contract calls made by `f`.
+    pub fn execute_traced<R>(
+        &self,
+        exec_context: &ExecContext,
+        f: impl FnOnce() -> R,
+    ) -> ((R, CallTrace), ExecSideEffects, OverlayedChanges) {
+        self.execute_in(exec_context, true, || crate::trace::using(f))
+    }
+
+    fn execute_in<R>(
+        &self,
+        exec_context: &ExecContext,
+        traced: bool,
+        f: impl FnOnce() -> R,
     ) -> (R, ExecSideEffects, OverlayedChanges) {
         let backend = self.backend.as_trie_backend();
 
@@ -41,7 +61,14 @@ where
         overlay.start_transaction();
         let mut cache = StorageTransactionCache::default();
         let mut ext = Ext::new(&mut overlay, &mut cache, backend, None);
-        let (rv, effects) = sp_externalities::set_and_run_with_externalities(&mut ext, move || {
+        let mut tracing_ext;
+        let ext: &mut dyn Externalities = if traced {
+            tracing_ext = TracingExt::new(&mut ext);
+            &mut tracing_ext
+        } else {
+            &mut ext
+        };
+        let (rv, effects) = sp_externalities::set_and_run_with_externalities(ext, move || {
             Timestamp::set_timestamp(exec_context.now_ms);
             System::set_block_number(exec_context.block_number);
             System::reset_events();
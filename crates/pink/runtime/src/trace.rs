@@ -0,0 +1,407 @@
+//! Tracing of the contract calls executed in dry-run mode.
+//!
+//! pallet-contracts has no hooks into its call stack, so the call frames are told from what they
+//! do to the storage: every frame runs in a storage transaction, which is committed if the frame
+//! returns successfully and rolled back otherwise, and deposits its events into `System::Events`.
+//! [`TracingExt`] wraps the externalities of the dry run to follow them.
+
+use pink_capi::types::{
+    AccountId, CallTrace, FrameKind, StorageUsage, TracedEvent, TracedExtCall, TracedFrame,
+    TracedStorageWrite,
+};
+use scale::Decode;
+use sp_core::{
+    hashing::twox_128,
+    storage::{ChildInfo, StateVersion, TrackedStorageKey},
+};
+use sp_externalities::{Extension, ExtensionStore, Externalities, MultiRemovalResults};
+use sp_state_machine::OverlayedChanges;
+use std::any::{Any, TypeId};
+
+use crate::{runtime::RuntimeEvent, types::Hash};
+
+type EventRecord = frame_system::EventRecord<RuntimeEvent, Hash>;
+
+#[derive(Default)]
+struct Tracer {
+    trace: CallTrace,
+    /// The frames entered and not returned yet, the innermost last.
+    stack: Vec<TracedFrame>,
+    /// The contract of the next frame, as told by the storage reads made to enter it.
+    entering: Option<(FrameKind, AccountId)>,
+}
+
+impl Tracer {
+    fn enter_frame(&mut self) {
+        let (kind, contract) = match self.entering.take() {
+            Some((kind, contract)) => (kind, Some(contract)),
+            None => (FrameKind::Call, None),
+        };
+        self.stack.push(TracedFrame {
+            kind,
+            contract,
+            reverted: false,
+            terminated_to: None,
+            events: vec![],
+            storage: Default::default(),
+            calls: vec![],
+        });
+    }
+
+    fn exit_frame(&mut self, reverted: bool) {
+        self.entering = None;
+        let Some(mut frame) = self.stack.pop() else {
+            return;
+        };
+        frame.reverted = reverted;
+        match self.stack.last_mut() {
+            Some(parent) => parent.calls.push(frame),
+            None => self.trace.calls.push(frame),
+        }
+    }
+
+    fn on_event(&mut self, record: EventRecord) {
+        use pallet_contracts::Event;
+        let RuntimeEvent::Contracts(event) = record.event else {
+            return;
+        };
+        let Some(frame) = self.stack.last_mut() else {
+            return;
+        };
+        // Called, DelegateCalled and Instantiated are deposited by the frame right before it
+        // returns successfully, so they tell the frame for sure.
+        match event {
+            Event::ContractEmitted { data, .. } => frame.events.push(TracedEvent {
+                topics: record.topics,
+                data,
+            }),
+            Event::Called { contract, .. } => {
+                frame.kind = FrameKind::Call;
+                frame.contract = Some(contract);
+            }
+            Event::DelegateCalled { contract, .. } => {
+                frame.kind = FrameKind::DelegateCall;
+                frame.contract = Some(contract);
+            }
+            Event::Instantiated { contract, .. } => {
+                frame.kind = FrameKind::Instantiate;
+                frame.contract = Some(contract);
+            }
+            Event::Terminated { beneficiary, .. } => frame.terminated_to = Some(beneficiary),
+            _ => {}
+        }
+    }
+}
+
+environmental::environmental!(tracer: Tracer);
+
+/// Run `f` with the tracing enabled and return the trace recorded.
+pub fn using<R>(f: impl FnOnce() -> R) -> (R, CallTrace) {
+    let mut tracer = Tracer::default();
+    let rv = tracer::using(&mut tracer, f);
+    // The frames left open were aborted, and so rolled back.
+    while !tracer.stack.is_empty() {
+        tracer.exit_frame(true);
+    }
+    (rv, tracer.trace)
+}
+
+/// Record something into the trace. Does nothing if the tracing is not enabled.
+pub fn with(f: impl FnOnce(&mut CallTrace)) {
+    tracer::with(|tracer| f(&mut tracer.trace));
+}
+
+pub fn add_storage_read(size: usize) {
+    with(|trace| {
+        trace.storage_reads += 1;
+        trace.storage_read_bytes += size as u64;
+    });
+}
+
+/// Record a chain extension invocation, returning its index to set the output later.
+pub fn add_ext_call(contract: &AccountId, func_id: u32, input_len: u32) -> Option<usize> {
+    tracer::with(|tracer| {
+        let ext_calls = &mut tracer.trace.ext_calls;
+        ext_calls.push(TracedExtCall {
+            contract: contract.clone(),
+            func_id,
+            input_len,
+            output_len: None,
+        });
+        ext_calls.len() - 1
+    })
+}
+
+pub fn set_ext_call_output(index: Option<usize>, output_len: u32) {
+    let Some(index) = index else {
+        return;
+    };
+    with(|trace| {
+        if let Some(call) = trace.ext_calls.get_mut(index) {
+            call.output_len = Some(output_len);
+        }
+    });
+}
+
+pub fn add_log(contract: &AccountId, level: u8, message: &str) {
+    with(|trace| trace.logs.push((contract.clone(), level, message.into())));
+}
+
+/// Record the address of a contract going to be instantiated.
+pub fn set_instantiating(address: &[u8]) {
+    let Ok(address) = AccountId::try_from(address) else {
+        return;
+    };
+    tracer::with(|tracer| tracer.entering = Some((FrameKind::Instantiate, address)));
+}
+
+fn contract_info_prefix() -> Vec<u8> {
+    [twox_128(b"Contracts"), twox_128(b"ContractInfoOf")].concat()
+}
+
+fn events_key() -> Vec<u8> {
+    [twox_128(b"System"), twox_128(b"Events")].concat()
+}
+
+fn on_storage_read(key: &[u8]) {
+    // ContractInfoOf is a Twox64Concat map, so the account follows the prefix and the hash.
+    let prefix = contract_info_prefix();
+    if key.len() != prefix.len() + 8 + 32 || !key.starts_with(&prefix) {
+        return;
+    }
+    let Ok(contract) = AccountId::try_from(&key[prefix.len() + 8..]) else {
+        return;
+    };
+    tracer::with(|tracer| {
+        // Instantiating a contract checks its ContractInfoOf, which is not a call.
+        if let Some((FrameKind::Instantiate, address)) = &tracer.entering {
+            if *address == contract {
+                return;
+            }
+        }
+        tracer.entering = Some((FrameKind::Call, contract));
+    });
+}
+
+fn on_child_storage_write(old_len: Option<usize>, new_len: Option<usize>) {
+    tracer::with(|tracer| {
+        let Some(frame) = tracer.stack.last_mut() else {
+            return;
+        };
+        let usage: &mut StorageUsage = &mut frame.storage;
+        match (old_len, new_len) {
+            (None, Some(new)) => {
+                usage.items_added += 1;
+                usage.bytes_added += new as u64;
+            }
+            (Some(old), None) => {
+                usage.items_removed += 1;
+                usage.bytes_removed += old as u64;
+            }
+            (Some(old), Some(new)) if new > old => usage.bytes_added += (new - old) as u64,
+            (Some(old), Some(new)) => usage.bytes_removed += (old - new) as u64,
+            (None, None) => {}
+        }
+    });
+}
+
+/// Externalities following the storage transactions, the events and the contract storage writes
+/// of the contract calls into the trace.
+pub struct TracingExt<'a> {
+    inner: &'a mut dyn Externalities,
+    events_key: Vec<u8>,
+}
+
+impl<'a> TracingExt<'a> {
+    pub fn new(inner: &'a mut dyn Externalities) -> Self {
+        Self {
+            inner,
+            events_key: events_key(),
+        }
+    }
+}
+
+impl ExtensionStore for TracingExt<'_> {
+    fn extension_by_type_id(&mut self, type_id: TypeId) -> Option<&mut dyn Any> {
+        self.inner.extension_by_type_id(type_id)
+    }
+
+    fn register_extension_with_type_id(
+        &mut self,
+        type_id: TypeId,
+        extension: Box<dyn Extension>,
+    ) -> Result<(), sp_externalities::Error> {
+        self.inner
+            .register_extension_with_type_id(type_id, extension)
+    }
+
+    fn deregister_extension_by_type_id(
+        &mut self,
+        type_id: TypeId,
+    ) -> Result<(), sp_externalities::Error> {
+        self.inner.deregister_extension_by_type_id(type_id)
+    }
+}
+
+impl Externalities for TracingExt<'_> {
+    fn set_offchain_storage(&mut self, key: &[u8], value: Option<&[u8]>) {
+        self.inner.set_offchain_storage(key, value)
+    }
+
+    fn storage(&self, key: &[u8]) -> Option<Vec<u8>> {
+        on_storage_read(key);
+        self.inner.storage(key)
+    }
+
+    fn storage_hash(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.storage_hash(key)
+    }
+
+    fn child_storage_hash(&self, child_info: &ChildInfo, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.child_storage_hash(child_info, key)
+    }
+
+    fn child_storage(&self, child_info: &ChildInfo, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.child_storage(child_info, key)
+    }
+
+    fn next_storage_key(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.next_storage_key(key)
+    }
+
+    fn next_child_storage_key(&self, child_info: &ChildInfo, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.next_child_storage_key(child_info, key)
+    }
+
+    fn kill_child_storage(
+        &mut self,
+        child_info: &ChildInfo,
+        maybe_limit: Option<u32>,
+        maybe_cursor: Option<&[u8]>,
+    ) -> MultiRemovalResults {
+        self.inner
+            .kill_child_storage(child_info, maybe_limit, maybe_cursor)
+    }
+
+    fn clear_prefix(
+        &mut self,
+        prefix: &[u8],
+        maybe_limit: Option<u32>,
+        maybe_cursor: Option<&[u8]>,
+    ) -> MultiRemovalResults {
+        self.inner.clear_prefix(prefix, maybe_limit, maybe_cursor)
+    }
+
+    fn clear_child_prefix(
+        &mut self,
+        child_info: &ChildInfo,
+        prefix: &[u8],
+        maybe_limit: Option<u32>,
+        maybe_cursor: Option<&[u8]>,
+    ) -> MultiRemovalResults {
+        self.inner
+            .clear_child_prefix(child_info, prefix, maybe_limit, maybe_cursor)
+    }
+
+    fn place_storage(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        self.inner.place_storage(key, value)
+    }
+
+    fn place_child_storage(
+        &mut self,
+        child_info: &ChildInfo,
+        key: Vec<u8>,
+        value: Option<Vec<u8>>,
+    ) {
+        let old_len = self.inner.child_storage(child_info, &key).map(|v| v.len());
+        on_child_storage_write(old_len, value.as_ref().map(|v| v.len()));
+        self.inner.place_child_storage(child_info, key, value)
+    }
+
+    fn storage_root(&mut self, state_version: StateVersion) -> Vec<u8> {
+        self.inner.storage_root(state_version)
+    }
+
+    fn child_storage_root(
+        &mut self,
+        child_info: &ChildInfo,
+        state_version: StateVersion,
+    ) -> Vec<u8> {
+        self.inner.child_storage_root(child_info, state_version)
+    }
+
+    fn storage_append(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        if key == self.events_key {
+            if let Ok(record) = EventRecord::decode(&mut &value[..]) {
+                tracer::with(|tracer| tracer.on_event(record));
+            }
+        }
+        self.inner.storage_append(key, value)
+    }
+
+    fn storage_start_transaction(&mut self) {
+        self.inner.storage_start_transaction();
+        tracer::with(|tracer| tracer.enter_frame());
+    }
+
+    fn storage_rollback_transaction(&mut self) -> Result<(), ()> {
+        tracer::with(|tracer| tracer.exit_frame(true));
+        self.inner.storage_rollback_transaction()
+    }
+
+    fn storage_commit_transaction(&mut self) -> Result<(), ()> {
+        tracer::with(|tracer| tracer.exit_frame(false));
+        self.inner.storage_commit_transaction()
+    }
+
+    fn wipe(&mut self) {
+        self.inner.wipe()
+    }
+
+    fn commit(&mut self) {
+        self.inner.commit()
+    }
+
+    fn read_write_count(&self) -> (u32, u32, u32, u32) {
+        self.inner.read_write_count()
+    }
+
+    fn reset_read_write_count(&mut self) {
+        self.inner.reset_read_write_count()
+    }
+
+    fn get_whitelist(&self) -> Vec<TrackedStorageKey> {
+        self.inner.get_whitelist()
+    }
+
+    fn set_whitelist(&mut self, new: Vec<TrackedStorageKey>) {
+        self.inner.set_whitelist(new)
+    }
+
+    fn get_read_and_written_keys(&self) -> Vec<(Vec<u8>, u32, u32, bool)> {
+        self.inner.get_read_and_written_keys()
+    }
+}
+
+/// Convert the storage changes of a call to the trace, skipping the block bookkeeping of the
+/// `System` and `Timestamp` pallets.
+pub fn storage_writes(changes: &OverlayedChanges) -> Vec<TracedStorageWrite> {
+    let skipped = [twox_128(b"System"), twox_128(b"Timestamp")];
+    let mut writes: Vec<_> = changes
+        .changes()
+        .filter(|(key, _)| !skipped.iter().any(|prefix| key.starts_with(prefix)))
+        .map(|(key, value)| TracedStorageWrite {
+            child_trie: None,
+            key: key.clone(),
+            value: value.value().cloned(),
+        })
+        .collect();
+    for (child_changes, info) in changes.children() {
+        writes.extend(child_changes.map(|(key, value)| TracedStorageWrite {
+            child_trie: Some(info.storage_key().to_vec()),
+            key: key.clone(),
+            value: value.value().cloned(),
+        }));
+    }
+    writes
+}
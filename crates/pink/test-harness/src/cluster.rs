@@ -0,0 +1,395 @@
+use std::sync::{Arc, Mutex};
+
+use phala_crypto::sr25519::{self, Persistence};
+use pink_capi::v1::{
+    ecall::{ECalls, TransactionArguments},
+    ocall::{
+        BatchHttpResult, BatchHttpResultV2, ExecContext, HttpRequest, HttpRequestError,
+        HttpRequestV2, HttpResponse, HttpResponseV2, OCalls, StorageChanges,
+    },
+    CrossCall, CrossCallMut, ECall,
+};
+use pink_runner::{
+    local_cache::{self, StorageQuotaExceeded},
+    runtimes::v1::{using_ocalls, Runtime},
+    storage::ClusterStorage,
+    types::{AccountId, Balance, BlockNumber, ExecSideEffects, ExecutionMode, Hash, Weight},
+};
+use scale::{Decode, Encode};
+use sp_runtime::{AccountId32, DispatchError};
+
+use crate::http::HttpRecorder;
+
+pub use pink::{ContractExecResult, ContractInstantiateResult};
+
+pub const ALICE: AccountId32 = AccountId32::new([1u8; 32]);
+pub const BOB: AccountId32 = AccountId32::new([2u8; 32]);
+pub const CHARLIE: AccountId32 = AccountId32::new([3u8; 32]);
+
+/// The balance the well-known accounts are funded with.
+pub const ENOUGH: Balance = u128::MAX / 4;
+
+/// The time [`TestCluster::advance_blocks`] moves forward for each block.
+pub const BLOCK_TIME_MS: u64 = 12_000;
+
+/// The seed of the cluster key. Fixed so that the contract derived keys are the same on every run.
+const CLUSTER_KEY_SEED: [u8; 32] = [42; 32];
+
+/// A log line emitted by a contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractLog {
+    pub contract: AccountId,
+    pub level: u8,
+    pub message: String,
+}
+
+/// An in-memory cluster running the latest pink runtime.
+///
+/// The block number and the clock only change when the test asks to, so a test produces the
+/// same results on every run. Combined with a replaying [`HttpRecorder`] nothing reaches the
+/// outside world.
+pub struct TestCluster {
+    storage: ClusterStorage,
+    context: ExecContext,
+    runtime: Runtime,
+    worker_pubkey: [u8; 32],
+    http: Arc<HttpRecorder>,
+    logs: Arc<Mutex<Vec<ContractLog>>>,
+    effects: Option<ExecSideEffects>,
+}
+
+impl Clone for TestCluster {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+            context: self.context.clone(),
+            runtime: unsafe { self.runtime.dup() },
+            worker_pubkey: self.worker_pubkey,
+            http: self.http.clone(),
+            logs: self.logs.clone(),
+            effects: None,
+        }
+    }
+}
+
+impl Default for TestCluster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestCluster {
+    /// Create a cluster with ALICE, BOB and CHARLIE funded, sending the HTTP requests to the
+    /// network.
+    pub fn new() -> Self {
+        let runtime = Runtime::from_fn(
+            pink::capi::__pink_runtime_init,
+            std::ptr::null_mut(),
+            pink::version(),
+        );
+        let mut me = Self {
+            storage: ClusterStorage::default(),
+            context: ExecContext {
+                mode: ExecutionMode::Transaction,
+                block_number: 1,
+                now_ms: 1,
+                req_id: None,
+            },
+            runtime,
+            worker_pubkey: [1; 32],
+            http: Arc::new(HttpRecorder::live()),
+            logs: Default::default(),
+            effects: None,
+        };
+        let key = sr25519::Pair::restore_from_seed(&CLUSTER_KEY_SEED);
+        me.set_key(key.dump_secret_key());
+        for account in [ALICE, BOB, CHARLIE] {
+            me.fund(account, ENOUGH);
+        }
+        me
+    }
+
+    /// Route the HTTP requests of the contracts through the given recorder.
+    pub fn with_http(mut self, recorder: HttpRecorder) -> Self {
+        self.http = Arc::new(recorder);
+        self
+    }
+
+    pub fn http(&self) -> &HttpRecorder {
+        &self.http
+    }
+
+    pub fn fund(&mut self, who: AccountId, amount: Balance) {
+        self.deposit(who, amount);
+    }
+
+    pub fn block_number(&self) -> BlockNumber {
+        self.context.block_number
+    }
+
+    pub fn set_block_number(&mut self, block_number: BlockNumber) {
+        self.context.block_number = block_number;
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.context.now_ms
+    }
+
+    pub fn set_time(&mut self, now_ms: u64) {
+        self.context.now_ms = now_ms;
+    }
+
+    pub fn advance_time(&mut self, ms: u64) {
+        self.context.now_ms += ms;
+    }
+
+    /// Move forward by `n` blocks, advancing the clock by [`BLOCK_TIME_MS`] per block.
+    pub fn advance_blocks(&mut self, n: BlockNumber) {
+        self.context.block_number += n;
+        self.context.now_ms += BLOCK_TIME_MS * n as u64;
+    }
+
+    /// The logs emitted by the contracts so far.
+    pub fn take_logs(&mut self) -> Vec<ContractLog> {
+        std::mem::take(&mut *self.logs.lock().unwrap())
+    }
+
+    /// The side effects of the last transaction.
+    pub fn take_effects(&mut self) -> Option<ExecSideEffects> {
+        self.effects.take()
+    }
+
+    fn execute_mut<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        using_ocalls(self, f)
+    }
+
+    fn execute<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut tmp = self.clone();
+        using_ocalls(&mut tmp, f)
+    }
+
+    /// Upload the code and instantiate a contract from it with the given constructor.
+    pub fn deploy<I: Encode>(
+        &mut self,
+        origin: AccountId,
+        wasm: &[u8],
+        constructor: u32,
+        input: I,
+        salt: Vec<u8>,
+    ) -> Result<AccountId, DispatchError> {
+        let code_hash = self
+            .upload_code(origin.clone(), wasm.to_vec(), true)
+            .map_err(|_| DispatchError::Other("Failed to upload code"))?;
+        self.instantiate(origin, code_hash, constructor, input, salt)
+    }
+
+    pub fn instantiate<I: Encode>(
+        &mut self,
+        origin: AccountId,
+        code_hash: Hash,
+        constructor: u32,
+        input: I,
+        salt: Vec<u8>,
+    ) -> Result<AccountId, DispatchError> {
+        let input_data = Encode::encode(&(constructor.to_be_bytes(), input));
+        let result = self.contract_instantiate(
+            code_hash,
+            input_data,
+            salt,
+            ExecutionMode::Transaction,
+            tx_args(origin),
+        );
+        let result = ContractInstantiateResult::decode(&mut &result[..])
+            .map_err(|_| DispatchError::Other("Failed to decode instantiate result"))?;
+        let ret = result.result?;
+        if ret.result.did_revert() {
+            return Err(DispatchError::Other("Contract instantiation reverted"));
+        }
+        Ok(ret.account_id)
+    }
+
+    /// Call a contract message in a transaction. The state changes are kept.
+    pub fn call<I: Encode, T: Decode>(
+        &mut self,
+        origin: AccountId,
+        contract: &AccountId,
+        selector: u32,
+        input: I,
+    ) -> Result<T, DispatchError> {
+        let input_data = Encode::encode(&(selector.to_be_bytes(), input));
+        let result = self.contract_call(
+            contract.clone(),
+            input_data,
+            ExecutionMode::Transaction,
+            tx_args(origin),
+        );
+        decode_exec_result(&result)
+    }
+
+    /// Query a contract message. The state changes are discarded, as in pRuntime.
+    pub fn query<I: Encode, T: Decode>(
+        &self,
+        origin: AccountId,
+        contract: &AccountId,
+        selector: u32,
+        input: I,
+    ) -> Result<T, DispatchError> {
+        let mut cluster = self.clone();
+        cluster.context.mode = ExecutionMode::Query;
+        let input_data = Encode::encode(&(selector.to_be_bytes(), input));
+        let result = cluster.contract_call(
+            contract.clone(),
+            input_data,
+            ExecutionMode::Query,
+            tx_args(origin),
+        );
+        decode_exec_result(&result)
+    }
+}
+
+fn tx_args(origin: AccountId) -> TransactionArguments {
+    TransactionArguments {
+        origin,
+        transfer: 0,
+        gas_limit: Weight::MAX,
+        gas_free: true,
+        storage_deposit_limit: None,
+        deposit: 0,
+    }
+}
+
+fn decode_exec_result<T: Decode>(result: &[u8]) -> Result<T, DispatchError> {
+    let result = ContractExecResult::decode(&mut &result[..])
+        .map_err(|_| DispatchError::Other("Failed to decode exec result"))?;
+    let ret = result.result?;
+    if ret.did_revert() {
+        return Err(DispatchError::Other("Contract reverted"));
+    }
+    Decode::decode(&mut &ret.data[..])
+        .map_err(|_| DispatchError::Other("Failed to decode contract return value"))
+}
+
+impl OCalls for TestCluster {
+    fn storage_root(&self) -> Option<Hash> {
+        self.storage.root()
+    }
+
+    fn storage_get(&self, key: Vec<u8>) -> Option<Vec<u8>> {
+        self.storage.get(&key).map(|(_rc, val)| val.clone())
+    }
+
+    fn storage_commit(&mut self, root: Hash, changes: StorageChanges) {
+        self.storage.commit(root, changes);
+    }
+
+    fn log_to_server(&self, contract: AccountId, level: u8, message: String) {
+        log::info!("contract={contract:?}, level={level}, message={message}");
+        self.logs.lock().unwrap().push(ContractLog {
+            contract,
+            level,
+            message,
+        });
+    }
+
+    fn emit_side_effects(&mut self, effects: ExecSideEffects) {
+        self.effects = Some(effects)
+    }
+
+    fn exec_context(&self) -> ExecContext {
+        self.context.clone()
+    }
+
+    fn worker_pubkey(&self) -> [u8; 32] {
+        self.worker_pubkey
+    }
+
+    fn cache_get(&self, contract: Vec<u8>, key: Vec<u8>) -> Option<Vec<u8>> {
+        local_cache::get(&contract, &key)
+    }
+
+    fn cache_set(
+        &self,
+        contract: Vec<u8>,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<(), StorageQuotaExceeded> {
+        local_cache::set(&contract, &key, &value)
+    }
+
+    fn cache_set_expiration(&self, contract: Vec<u8>, key: Vec<u8>, expiration: u64) {
+        local_cache::set_expiration(&contract, &key, expiration)
+    }
+
+    fn cache_remove(&self, contract: Vec<u8>, key: Vec<u8>) -> Option<Vec<u8>> {
+        local_cache::remove(&contract, &key)
+    }
+
+    fn latest_system_code(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn randomness_beacon(&self, _block_number: u32) -> Option<[u8; 32]> {
+        None
+    }
+
+    fn http_request(
+        &self,
+        _contract: AccountId,
+        request: HttpRequest,
+    ) -> Result<HttpResponse, HttpRequestError> {
+        self.http.http_request(request)
+    }
+
+    fn batch_http_request(
+        &self,
+        _contract: AccountId,
+        requests: Vec<HttpRequest>,
+        timeout_ms: u64,
+    ) -> BatchHttpResult {
+        self.http.batch_http_request(requests, timeout_ms)
+    }
+
+    fn http_request_v2(
+        &self,
+        _contract: AccountId,
+        request: HttpRequestV2,
+    ) -> Result<HttpResponseV2, HttpRequestError> {
+        self.http.http_request_v2(request)
+    }
+
+    fn batch_http_request_v2(
+        &self,
+        _contract: AccountId,
+        requests: Vec<HttpRequestV2>,
+        timeout_ms: u64,
+    ) -> BatchHttpResultV2 {
+        self.http.batch_http_request_v2(requests, timeout_ms)
+    }
+
+    fn emit_system_event_block(&self, number: u64, _encoded_block: Vec<u8>) {
+        log::info!("emit_system_event_block: number={number}");
+    }
+
+    fn contract_call_nonce(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn entry_contract(&self) -> Option<AccountId> {
+        None
+    }
+}
+
+impl CrossCall for TestCluster {
+    fn cross_call(&self, call_id: u32, data: &[u8]) -> Vec<u8> {
+        self.execute(move || self.runtime.ecall(call_id, data))
+    }
+}
+
+impl CrossCallMut for TestCluster {
+    fn cross_call_mut(&mut self, call_id: u32, data: &[u8]) -> Vec<u8> {
+        let runtime = unsafe { self.runtime.dup() };
+        self.execute_mut(move || runtime.ecall(call_id, data))
+    }
+}
+
+impl ECall for TestCluster {}
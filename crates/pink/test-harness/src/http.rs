@@ -0,0 +1,421 @@
+//! Recording and replaying the HTTP requests made by the contracts under test.
+//!
+//! A [`HttpRecorder`] sits behind the `http_request` family of ocalls. In `Live` mode the requests
+//! go to the network. In `Record` mode they go to the network as well, and each call together with
+//! its result is appended to a fixture file. In `Replay` mode the results are served from the
+//! fixture file, so the tests run offline and produce the same results on every run.
+//!
+//! Requests and results are kept in the fixture SCALE encoded as hex. The method, the url and the
+//! response status are written alongside to make the fixture readable and reviewable.
+//!
+//! The values of the credential headers, e.g. `Authorization` or `X-API-Key`, are replaced with
+//! `<redacted>` before the requests are written, so the fixtures can be committed. The requests are
+//! matched on the redacted form when replayed, which also lets the tests use dummy credentials.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use pink_capi::v1::ocall::{
+    BatchHttpResult, BatchHttpResultV2, HttpRequest, HttpRequestError, HttpRequestV2, HttpResponse,
+    HttpResponseV2,
+};
+use scale::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// Setting this env var to `1` makes [`HttpRecorder::from_env`] re-record the fixtures.
+pub const RECORD_ENV: &str = "PINK_HTTP_RECORD";
+
+/// Timeout for the single requests, the same as the one used in pRuntime.
+const HTTP_TIMEOUT_MS: u64 = 10 * 1000;
+/// Maximum number of requests in a batch, the same as the one used in pRuntime.
+const MAX_BATCH_REQUESTS: usize = 5;
+/// The value written in place of the credential headers.
+const REDACTED: &str = "<redacted>";
+/// Headers always treated as credentials, besides the ones matched by [`is_sensitive_header`].
+const SENSITIVE_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpMode {
+    /// Send the requests to the network without recording.
+    Live,
+    /// Send the requests to the network and save them with their results to the fixture file.
+    Record(PathBuf),
+    /// Serve the requests from the fixture file. No request reaches the network.
+    Replay(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CallKind {
+    Single,
+    Batch,
+}
+
+/// A recorded `http_request` or `batch_http_request` call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    kind: CallKind,
+    /// `METHOD url` of each request, informational only.
+    summary: Vec<String>,
+    /// The status code of each response, `None` for failed requests. Informational only.
+    status: Vec<Option<u16>>,
+    /// The hex encoded `Vec<HttpRequestV2>`.
+    requests: String,
+    /// The hex encoded `Result<HttpResponseV2, HttpRequestError>` or `BatchHttpResultV2`.
+    result: String,
+    #[serde(skip)]
+    used: bool,
+}
+
+impl Interaction {
+    fn new(kind: CallKind, requests: &[HttpRequestV2], result: Vec<u8>) -> Self {
+        Self {
+            kind,
+            summary: requests
+                .iter()
+                .map(|req| format!("{} {}", req.method, req.url))
+                .collect(),
+            status: vec![],
+            requests: encode_redacted(requests),
+            result: hex::encode(result),
+            used: false,
+        }
+    }
+}
+
+fn is_sensitive_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SENSITIVE_HEADERS.contains(&name.as_str())
+        || ["key", "token", "secret", "signature"]
+            .iter()
+            .any(|word| name.contains(word))
+}
+
+/// Hex encode the requests with the values of the credential headers redacted.
+fn encode_redacted(requests: &[HttpRequestV2]) -> String {
+    let redacted: Vec<_> = requests
+        .iter()
+        .cloned()
+        .map(|mut request| {
+            for (name, value) in request.headers.iter_mut() {
+                if is_sensitive_header(name) {
+                    *value = REDACTED.into();
+                }
+            }
+            request
+        })
+        .collect();
+    hex::encode(redacted.encode())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Fixture {
+    interactions: Vec<Interaction>,
+}
+
+/// Routes the HTTP requests of the contracts according to the [`HttpMode`].
+pub struct HttpRecorder {
+    mode: HttpMode,
+    fixture: Mutex<Fixture>,
+}
+
+impl HttpRecorder {
+    pub fn live() -> Self {
+        Self {
+            mode: HttpMode::Live,
+            fixture: Default::default(),
+        }
+    }
+
+    /// Record the requests to the given file. The file is written when the recorder is dropped or
+    /// when [`save`](Self::save) is called.
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self {
+            mode: HttpMode::Record(path.into()),
+            fixture: Default::default(),
+        }
+    }
+
+    /// Replay the requests recorded in the given file.
+    pub fn replay(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let content = fs::read(&path)?;
+        let fixture: Fixture = serde_json::from_slice(&content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Self {
+            mode: HttpMode::Replay(path),
+            fixture: Mutex::new(fixture),
+        })
+    }
+
+    /// Record to the given file if `PINK_HTTP_RECORD=1`, otherwise replay from it.
+    pub fn from_env(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if std::env::var(RECORD_ENV).as_deref() == Ok("1") {
+            Ok(Self::record(path))
+        } else {
+            Self::replay(path).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!(
+                        "failed to load http fixture {}: {err}, run with {RECORD_ENV}=1 to record it",
+                        path.display()
+                    ),
+                )
+            })
+        }
+    }
+
+    pub fn mode(&self) -> &HttpMode {
+        &self.mode
+    }
+
+    /// Number of recorded calls not replayed yet.
+    pub fn unused(&self) -> usize {
+        let fixture = self.fixture.lock().unwrap();
+        fixture.interactions.iter().filter(|i| !i.used).count()
+    }
+
+    /// Write the recorded calls to the fixture file. Does nothing if not in `Record` mode.
+    pub fn save(&self) -> io::Result<()> {
+        let HttpMode::Record(path) = &self.mode else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let fixture = self.fixture.lock().unwrap();
+        let content = serde_json::to_vec_pretty(&*fixture)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, content)
+    }
+
+    /// Serve the given result for the request as if it was recorded.
+    ///
+    /// The stub is saved along with the recorded calls in `Record` mode.
+    pub fn stub(&self, request: HttpRequestV2, result: Result<HttpResponseV2, HttpRequestError>) {
+        let mut interaction = Interaction::new(CallKind::Single, &[request], result.encode());
+        interaction.status = vec![result.as_ref().ok().map(|resp| resp.status_code)];
+        self.push(interaction);
+    }
+
+    fn push(&self, interaction: Interaction) {
+        self.fixture.lock().unwrap().interactions.push(interaction);
+    }
+
+    fn take<T: Decode>(&self, kind: CallKind, requests: &[HttpRequestV2]) -> T {
+        let encoded = encode_redacted(requests);
+        let mut fixture = self.fixture.lock().unwrap();
+        let Some(interaction) = fixture
+            .interactions
+            .iter_mut()
+            .find(|i| !i.used && i.kind == kind && i.requests == encoded)
+        else {
+            let summary: Vec<_> = requests
+                .iter()
+                .map(|req| format!("{} {}", req.method, req.url))
+                .collect();
+            panic!(
+                "no recorded response for {summary:?} in {}, run with {RECORD_ENV}=1 to re-record",
+                self.fixture_path().display()
+            );
+        };
+        interaction.used = true;
+        let result = hex::decode(&interaction.result).expect("invalid hex in http fixture");
+        T::decode(&mut &result[..]).expect("invalid result in http fixture")
+    }
+
+    fn fixture_path(&self) -> &Path {
+        match &self.mode {
+            HttpMode::Live => Path::new(""),
+            HttpMode::Record(path) | HttpMode::Replay(path) => path,
+        }
+    }
+
+    pub fn http_request_v2(
+        &self,
+        request: HttpRequestV2,
+    ) -> Result<HttpResponseV2, HttpRequestError> {
+        match &self.mode {
+            HttpMode::Live => pink_extension_runtime::http_request_v2(request, HTTP_TIMEOUT_MS),
+            HttpMode::Record(_) => {
+                let result =
+                    pink_extension_runtime::http_request_v2(request.clone(), HTTP_TIMEOUT_MS);
+                self.stub(request, result.clone());
+                result
+            }
+            HttpMode::Replay(_) => self.take(CallKind::Single, &[request]),
+        }
+    }
+
+    pub fn batch_http_request_v2(
+        &self,
+        requests: Vec<HttpRequestV2>,
+        timeout_ms: u64,
+    ) -> BatchHttpResultV2 {
+        match &self.mode {
+            HttpMode::Live => pink_extension_runtime::batch_http_request_v2(requests, timeout_ms),
+            HttpMode::Record(_) => {
+                let result =
+                    pink_extension_runtime::batch_http_request_v2(requests.clone(), timeout_ms);
+                let mut interaction = Interaction::new(CallKind::Batch, &requests, result.encode());
+                if let Ok(responses) = &result {
+                    interaction.status = responses
+                        .iter()
+                        .map(|r| r.as_ref().ok().map(|resp| resp.status_code))
+                        .collect();
+                }
+                self.push(interaction);
+                result
+            }
+            HttpMode::Replay(_) => {
+                if requests.len() > MAX_BATCH_REQUESTS {
+                    return Err(HttpRequestError::TooManyRequests);
+                }
+                self.take(CallKind::Batch, &requests)
+            }
+        }
+    }
+
+    pub fn http_request(&self, request: HttpRequest) -> Result<HttpResponse, HttpRequestError> {
+        match self.http_request_v2(request.into()) {
+            Ok(response) => Ok(response.into()),
+            Err(err) => pink_extension_runtime::v1_0_compatible_error(err),
+        }
+    }
+
+    pub fn batch_http_request(
+        &self,
+        requests: Vec<HttpRequest>,
+        timeout_ms: u64,
+    ) -> BatchHttpResult {
+        let requests = requests.into_iter().map(Into::into).collect();
+        Ok(self
+            .batch_http_request_v2(requests, timeout_ms)?
+            .into_iter()
+            .map(|result| match result {
+                Ok(response) => Ok(response.into()),
+                Err(err) => pink_extension_runtime::v1_1_compatible_error(err),
+            })
+            .collect())
+    }
+}
+
+impl Drop for HttpRecorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.save() {
+            log::error!(
+                "Failed to save http fixture {}: {err}",
+                self.fixture_path().display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded(path: &Path, requests: &[HttpRequestV2], result: BatchHttpResultV2) {
+        let recorder = HttpRecorder::record(path);
+        recorder.push(Interaction::new(CallKind::Batch, requests, result.encode()));
+    }
+
+    fn response(status_code: u16, body: &[u8]) -> HttpResponseV2 {
+        HttpResponseV2 {
+            status_code,
+            reason_phrase: "OK".into(),
+            headers: vec![],
+            body: body.to_vec(),
+            meta: Default::default(),
+        }
+    }
+
+    #[test]
+    fn replay_recorded_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fixtures/http.json");
+        let requests = vec![
+            HttpRequestV2::new("https://example.com/a", "GET", vec![], vec![]),
+            HttpRequestV2::new("https://example.com/b", "POST", vec![], b"hi".to_vec()),
+        ];
+        recorded(
+            &path,
+            &requests,
+            Ok(vec![
+                Ok(response(200, b"a")),
+                Err(HttpRequestError::Timeout),
+            ]),
+        );
+
+        let recorder = HttpRecorder::replay(&path).unwrap();
+        assert_eq!(recorder.unused(), 1);
+        let result = recorder
+            .batch_http_request_v2(requests.clone(), 1000)
+            .unwrap();
+        assert_eq!(result[0].as_ref().unwrap().body, b"a");
+        assert!(matches!(result[1], Err(HttpRequestError::Timeout)));
+        assert_eq!(recorder.unused(), 0);
+
+        // The v1 api is served from the same fixture
+        let recorder = HttpRecorder::replay(&path).unwrap();
+        let v1_requests = requests
+            .into_iter()
+            .map(|req| HttpRequest::new(req.url, req.method, req.headers, req.body))
+            .collect();
+        let result = recorder.batch_http_request(v1_requests, 1000).unwrap();
+        assert_eq!(result[0].as_ref().unwrap().status_code, 200);
+    }
+
+    #[test]
+    fn credentials_are_redacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("http.json");
+        let request = |token: &str| {
+            HttpRequestV2::new(
+                "https://example.com",
+                "GET",
+                vec![
+                    ("Authorization".into(), format!("Bearer {token}")),
+                    ("X-Api-Key".into(), token.into()),
+                    ("Accept".into(), "application/json".into()),
+                ],
+                vec![],
+            )
+        };
+        HttpRecorder::record(&path).stub(request("s3cr3t"), Ok(response(200, b"ok")));
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains(&hex::encode("s3cr3t")));
+        assert!(content.contains(&hex::encode("application/json")));
+
+        // Any credential matches the redacted request
+        let recorder = HttpRecorder::replay(&path).unwrap();
+        let response = recorder.http_request_v2(request("dummy")).unwrap();
+        assert_eq!(response.body, b"ok");
+    }
+
+    #[test]
+    #[should_panic(expected = "no recorded response")]
+    fn replay_unknown_request_panics() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("http.json");
+        recorded(&path, &[], Ok(vec![]));
+        let recorder = HttpRecorder::replay(&path).unwrap();
+        let _ = recorder.http_request_v2(HttpRequestV2::new(
+            "https://example.com",
+            "GET",
+            vec![],
+            vec![],
+        ));
+    }
+
+    #[test]
+    fn from_env_requires_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(HttpRecorder::from_env(dir.path().join("missing.json")).is_err());
+    }
+}
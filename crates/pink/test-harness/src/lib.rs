@@ -0,0 +1,27 @@
+//! A test harness running pink contracts in an in-memory cluster.
+//!
+//! The [`TestCluster`] runs the latest pink runtime in process with a few funded accounts. It
+//! provides helpers to deploy, call and query contracts and lets the test drive the block number
+//! and the clock. The HTTP requests made by the contracts can be recorded to fixture files and
+//! replayed offline with an [`HttpRecorder`]:
+//!
+//! ```ignore
+//! let recorder = HttpRecorder::from_env("tests/fixtures/price_feed.json")?;
+//! let mut cluster = TestCluster::new().with_http(recorder);
+//! let contract = cluster.deploy(ALICE, WASM, NEW_SELECTOR, (), vec![])?;
+//! let price: u128 = cluster.query(ALICE, &contract, FETCH_PRICE_SELECTOR, ())?;
+//! ```
+//!
+//! Run the tests with `PINK_HTTP_RECORD=1` to send the requests to the network and (re)write the
+//! fixtures.
+
+pub use cluster::{
+    ContractExecResult, ContractInstantiateResult, ContractLog, TestCluster, ALICE, BLOCK_TIME_MS,
+    BOB, CHARLIE, ENOUGH,
+};
+pub use http::{HttpMode, HttpRecorder, RECORD_ENV};
+pub use pink_capi::v1::ecall::ECalls;
+pub use pink_runner::types;
+
+mod cluster;
+pub mod http;
@@ -0,0 +1,39 @@
+(module
+	(import "seal0" "seal_input" (func $seal_input (param i32 i32)))
+	(import "seal0" "seal_return" (func $seal_return (param i32 i32 i32)))
+	(import "seal0" "seal_call_chain_extension"
+		(func $seal_call_chain_extension (param i32 i32 i32 i32 i32) (result i32))
+	)
+	(import "env" "memory" (memory 1 1))
+
+	;; [0, 4) size of the input buffer
+	;; [4, 8) size of the output buffer
+	;; [8, 16392) input buffer
+	;; [16392, 65536) output buffer
+
+	(func (export "deploy"))
+
+	;; Forwards the input, without the 4 bytes of the selector, to the `http_request` extension
+	;; (func_id 1) and returns its output.
+	;;
+	;; The input is the SCALE encoded `HttpRequest` and the output the SCALE encoded `HttpResponse`.
+	(func (export "call")
+		(i32.store (i32.const 0) (i32.const 16384))
+		(call $seal_input (i32.const 8) (i32.const 0))
+		(i32.store (i32.const 4) (i32.const 49144))
+		(drop
+			(call $seal_call_chain_extension
+				(i32.const 1) ;; id
+				(i32.const 12) ;; input_ptr
+				(i32.sub (i32.load (i32.const 0)) (i32.const 4)) ;; input_len
+				(i32.const 16392) ;; output_ptr
+				(i32.const 4) ;; output_len_ptr
+			)
+		)
+		(call $seal_return
+			(i32.const 0) ;; flags
+			(i32.const 16392) ;; data_ptr
+			(i32.load (i32.const 4)) ;; data_len
+		)
+	)
+)
@@ -0,0 +1,83 @@
+use pink_capi::v1::ocall::{HttpRequest, HttpResponse, HttpResponseV2};
+use pink_test_harness::{HttpRecorder, TestCluster, ALICE, BLOCK_TIME_MS, BOB};
+
+const FLIP_WASM: &[u8] = include_bytes!("../../runner/tests/fixtures/flip/flip.wasm");
+const NEW: u32 = 0x9bae9d5e;
+const GET: u32 = 0x2f865bd9;
+const FLIP: u32 = 0x633aa551;
+/// Any selector, the proxy contract ignores it.
+const FETCH: u32 = 0;
+
+fn http_proxy_wasm() -> Vec<u8> {
+    wat::parse_str(include_str!("fixtures/http_proxy.wat")).unwrap()
+}
+
+#[test]
+fn deploy_call_and_query() {
+    let mut cluster = TestCluster::new();
+    let contract = cluster.deploy(ALICE, FLIP_WASM, NEW, true, vec![]).unwrap();
+
+    assert_eq!(cluster.query::<_, bool>(BOB, &contract, GET, ()), Ok(true));
+
+    cluster.call::<_, ()>(BOB, &contract, FLIP, ()).unwrap();
+    assert_eq!(
+        cluster.query::<_, bool>(ALICE, &contract, GET, ()),
+        Ok(false)
+    );
+
+    // Queries don't change the state
+    cluster.query::<_, ()>(ALICE, &contract, FLIP, ()).unwrap();
+    assert_eq!(
+        cluster.query::<_, bool>(ALICE, &contract, GET, ()),
+        Ok(false)
+    );
+}
+
+#[test]
+fn time_control() {
+    let mut cluster = TestCluster::new();
+    cluster.set_time(1_000);
+    cluster.advance_time(500);
+    assert_eq!(cluster.now_ms(), 1_500);
+
+    cluster.set_block_number(10);
+    cluster.advance_blocks(2);
+    assert_eq!(cluster.block_number(), 12);
+    assert_eq!(cluster.now_ms(), 1_500 + 2 * BLOCK_TIME_MS);
+}
+
+#[test]
+fn replay_without_network() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("http.json");
+    let request = |token: &str| {
+        HttpRequest::new(
+            "https://api.example.com/price",
+            "GET",
+            vec![("Authorization".into(), format!("Bearer {token}"))],
+            vec![],
+        )
+    };
+    HttpRecorder::record(&path).stub(
+        request("s3cr3t").into(),
+        Ok(HttpResponseV2 {
+            status_code: 200,
+            reason_phrase: "OK".into(),
+            headers: vec![],
+            body: b"42".to_vec(),
+            meta: Default::default(),
+        }),
+    );
+
+    let mut cluster = TestCluster::new().with_http(HttpRecorder::replay(&path).unwrap());
+    let contract = cluster
+        .deploy(ALICE, &http_proxy_wasm(), NEW, (), vec![])
+        .unwrap();
+    assert_eq!(cluster.http().unused(), 1);
+    let response: HttpResponse = cluster
+        .query(ALICE, &contract, FETCH, request("dummy"))
+        .unwrap();
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body, b"42");
+    assert_eq!(cluster.http().unused(), 0);
+}
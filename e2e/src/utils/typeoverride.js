@@ -236,7 +236,10 @@ const types = {
     "ClusterPermission": {
         "_enum": {
             "Public": null,
-            "OnlyOwner": "AccountId"
+            "OnlyOwner": "AccountId",
+            "AllowList": "Vec<AccountId>",
+            "DenyList": "Vec<AccountId>",
+            "Governance": null
         }
     }
 };
@@ -0,0 +1,30 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use sp_std::vec::Vec;
+
+pub use phala_pallets::compute::{
+	computation::{PerformanceEpoch, PerformanceHistory},
+	valuation::{OwnerRewards, PendingWithdrawal, PoolSummary, StakeValue},
+};
+pub use phala_types::WorkerPublicKey;
+
+sp_api::decl_runtime_apis! {
+	/// Valuation of the stake pools and vaults, and the track record of the workers.
+	pub trait PhalaComputeApi<AccountId, Balance>
+	where
+		AccountId: Codec,
+		Balance: Codec,
+	{
+		/// The summary of the given stake pool or vault.
+		fn pool_summary(pid: u64) -> Option<PoolSummary<AccountId, Balance>>;
+		/// The stake the account holds in each pool.
+		fn account_stakes(account: AccountId) -> Vec<StakeValue<Balance>>;
+		/// The withdraw requests of the account waiting in the withdraw queues.
+		fn pending_withdrawals(account: AccountId) -> Vec<PendingWithdrawal<Balance>>;
+		/// The rewards of the owner of the given pool.
+		fn owner_rewards(pid: u64) -> Option<OwnerRewards<Balance>>;
+		/// The performance of the given worker in the recent epochs.
+		fn worker_performance(worker: WorkerPublicKey) -> Option<PerformanceHistory>;
+	}
+}
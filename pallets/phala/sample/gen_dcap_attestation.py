@@ -0,0 +1,264 @@
+#!/usr/bin/env python3
+"""Generates `dcap_attestation.json`, a DCAP quote with its collateral for the tests.
+
+The quote is NOT produced by an SGX platform. The whole PKI is issued by a test root CA that
+mimics the Intel SGX Root CA: a PCK Platform CA issuing the PCK cert with the SGX extensions, a TCB
+signing cert signing the TCB info and the QE identity, and the CRLs of both CAs. The structures
+follow the Intel SGX ECDSA quote library and the PCS v3 API, so the fixture exercises the same code
+path as a real quote, but it is only trusted with the test root CA in the fixture.
+
+Requires `cryptography`. Usage: python3 gen_dcap_attestation.py > dcap_attestation.json
+"""
+
+import datetime
+import hashlib
+import json
+import struct
+
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import ec
+from cryptography.hazmat.primitives.asymmetric.utils import decode_dss_signature
+from cryptography.x509.oid import NameOID
+
+NOT_BEFORE = datetime.datetime(2023, 1, 1, tzinfo=datetime.timezone.utc)
+NOT_AFTER = datetime.datetime(2049, 12, 31, tzinfo=datetime.timezone.utc)
+NOW = datetime.datetime(2024, 6, 1, tzinfo=datetime.timezone.utc)
+
+SGX_OID = "1.2.840.113741.1.13.1"
+FMSPC = bytes.fromhex("00606a000000")
+TCB_COMPONENTS = [5] * 16
+PCE_SVN = 13
+
+QE_MR_SIGNER = bytes.fromhex("8c4f5775d796503e96137f77c68a829a0056ac8ded70140b081b094490c57bff")
+QE_PROD_ID = 1
+QE_SVN = 8
+
+MR_ENCLAVE = bytes(range(32))
+MR_SIGNER = bytes(range(32, 64))
+ISV_PROD_ID = 0
+ISV_SVN = 1
+USER_DATA_HASH = hashlib.sha256(b"phala dcap sample").digest()
+
+
+def der(tag, value):
+    if len(value) < 0x80:
+        length = bytes([len(value)])
+    else:
+        encoded = len(value).to_bytes((len(value).bit_length() + 7) // 8, "big")
+        length = bytes([0x80 | len(encoded)]) + encoded
+    return bytes([tag]) + length + value
+
+
+def der_oid(oid):
+    parts = [int(p) for p in oid.split(".")]
+    body = bytes([parts[0] * 40 + parts[1]])
+    for part in parts[2:]:
+        chunk = [part & 0x7F]
+        part >>= 7
+        while part:
+            chunk.insert(0, 0x80 | (part & 0x7F))
+            part >>= 7
+        body += bytes(chunk)
+    return der(0x06, body)
+
+
+def der_int(value):
+    body = value.to_bytes(max(1, (value.bit_length() + 8) // 8), "big")
+    return der(0x02, body)
+
+
+def sgx_item(suffix, value):
+    return der(0x30, der_oid(f"{SGX_OID}.{suffix}") + value)
+
+
+def sgx_extension():
+    tcb = b"".join(sgx_item(f"2.{i + 1}", der_int(svn)) for i, svn in enumerate(TCB_COMPONENTS))
+    tcb += sgx_item("2.17", der_int(PCE_SVN))
+    tcb += sgx_item("2.18", der(0x04, bytes(TCB_COMPONENTS)))
+    return der(
+        0x30,
+        sgx_item("1", der(0x04, bytes(16)))
+        + sgx_item("2", der(0x30, tcb))
+        + sgx_item("3", der(0x04, bytes(2)))
+        + sgx_item("4", der(0x04, FMSPC))
+        + sgx_item("5", der(0x0A, b"\x00")),
+    )
+
+
+def name(common_name):
+    return x509.Name(
+        [
+            x509.NameAttribute(NameOID.COMMON_NAME, common_name),
+            x509.NameAttribute(NameOID.ORGANIZATION_NAME, "Phala Test"),
+        ]
+    )
+
+
+def issue(subject, key, issuer, issuer_key, ca, extensions=()):
+    builder = (
+        x509.CertificateBuilder()
+        .subject_name(subject)
+        .issuer_name(issuer)
+        .public_key(key.public_key())
+        .serial_number(x509.random_serial_number())
+        .not_valid_before(NOT_BEFORE)
+        .not_valid_after(NOT_AFTER)
+        .add_extension(x509.BasicConstraints(ca=ca, path_length=None), critical=True)
+    )
+    for extension in extensions:
+        builder = builder.add_extension(extension, critical=False)
+    return builder.sign(issuer_key, hashes.SHA256())
+
+
+def crl(issuer, issuer_key, revoked_serials):
+    builder = (
+        x509.CertificateRevocationListBuilder()
+        .issuer_name(issuer)
+        .last_update(NOT_BEFORE)
+        .next_update(NOT_AFTER)
+    )
+    for serial in revoked_serials:
+        builder = builder.add_revoked_certificate(
+            x509.RevokedCertificateBuilder()
+            .serial_number(serial)
+            .revocation_date(NOT_BEFORE)
+            .build()
+        )
+    return builder.sign(issuer_key, hashes.SHA256()).public_bytes(serialization.Encoding.DER)
+
+
+def pem(*certs):
+    return "".join(cert.public_bytes(serialization.Encoding.PEM).decode() for cert in certs)
+
+
+def raw_sign(key, message):
+    r, s = decode_dss_signature(key.sign(message, ec.ECDSA(hashes.SHA256())))
+    return r.to_bytes(32, "big") + s.to_bytes(32, "big")
+
+
+def raw_public(key):
+    return key.public_key().public_bytes(
+        serialization.Encoding.X962, serialization.PublicFormat.UncompressedPoint
+    )[1:]
+
+
+def report(mr_enclave, mr_signer, prod_id, svn, report_data, attributes=bytes(16)):
+    body = bytearray(384)
+    body[48:64] = attributes
+    body[64:96] = mr_enclave
+    body[128:160] = mr_signer
+    body[256:260] = struct.pack("<HH", prod_id, svn)
+    body[320:384] = report_data.ljust(64, b"\0")
+    return bytes(body)
+
+
+def main():
+    new_key = lambda: ec.generate_private_key(ec.SECP256R1())
+    root_key, platform_key, pck_key, tcb_key, attestation_key = (new_key() for _ in range(5))
+    root_name = name("Phala Test SGX Root CA")
+    platform_name = name("Phala Test SGX PCK Platform CA")
+    root = issue(root_name, root_key, root_name, root_key, True)
+    platform = issue(platform_name, platform_key, root_name, root_key, True)
+    tcb_signer = issue(name("Phala Test SGX TCB Signing"), tcb_key, root_name, root_key, False)
+    pck = issue(
+        name("Phala Test SGX PCK Certificate"),
+        pck_key,
+        platform_name,
+        platform_key,
+        False,
+        [x509.UnrecognizedExtension(x509.ObjectIdentifier(SGX_OID), sgx_extension())],
+    )
+
+    # Quote v3 with an ECDSA P-256 attestation key
+    header = struct.pack("<HHIHH", 3, 2, 0, 0, 0) + bytes(16) + bytes(20)
+    enclave_report = report(MR_ENCLAVE, MR_SIGNER, ISV_PROD_ID, ISV_SVN, USER_DATA_HASH)
+    qe_auth_data = bytes(range(32))
+    key_hash = hashlib.sha256(raw_public(attestation_key) + qe_auth_data).digest()
+    qe_report = report(bytes(32), QE_MR_SIGNER, QE_PROD_ID, QE_SVN, key_hash, b"\x11" + bytes(15))
+    cert_data = pem(pck, platform, root).encode()
+    signature_data = (
+        raw_sign(attestation_key, header + enclave_report)
+        + raw_public(attestation_key)
+        + qe_report
+        + raw_sign(pck_key, qe_report)
+        + struct.pack("<H", len(qe_auth_data))
+        + qe_auth_data
+        + struct.pack("<HI", 5, len(cert_data))
+        + cert_data
+    )
+    quote = header + enclave_report + struct.pack("<I", len(signature_data)) + signature_data
+
+    tcb_level = lambda svn, pce_svn, status, advisories: {
+        "tcb": {"sgxtcbcomponents": [{"svn": svn}] * 16, "pcesvn": pce_svn},
+        "tcbDate": "2023-01-01T00:00:00Z",
+        "tcbStatus": status,
+        "advisoryIDs": advisories,
+    }
+    tcb_info = json.dumps(
+        {
+            "id": "SGX",
+            "version": 3,
+            "issueDate": "2024-01-01T00:00:00Z",
+            "nextUpdate": "2049-01-01T00:00:00Z",
+            "fmspc": FMSPC.hex(),
+            "pceId": "0000",
+            "tcbType": 0,
+            "tcbEvaluationDataNumber": 16,
+            "tcbLevels": [
+                tcb_level(7, PCE_SVN, "UpToDate", []),
+                tcb_level(5, PCE_SVN, "SWHardeningNeeded", ["INTEL-SA-00334"]),
+                tcb_level(1, 10, "OutOfDate", ["INTEL-SA-00615"]),
+            ],
+        },
+        separators=(",", ":"),
+    )
+    qe_identity = json.dumps(
+        {
+            "id": "QE",
+            "version": 2,
+            "issueDate": "2024-01-01T00:00:00Z",
+            "nextUpdate": "2049-01-01T00:00:00Z",
+            "tcbEvaluationDataNumber": 16,
+            "miscselect": "00000000",
+            "miscselectMask": "FFFFFFFF",
+            "attributes": "11000000000000000000000000000000",
+            "attributesMask": "FBFFFFFFFFFFFFFF0000000000000000",
+            "mrsigner": QE_MR_SIGNER.hex().upper(),
+            "isvprodid": QE_PROD_ID,
+            "tcbLevels": [
+                {"tcb": {"isvsvn": QE_SVN}, "tcbDate": "2023-01-01T00:00:00Z", "tcbStatus": "UpToDate"},
+                {"tcb": {"isvsvn": 6}, "tcbDate": "2022-01-01T00:00:00Z", "tcbStatus": "OutOfDate"},
+            ],
+        },
+        separators=(",", ":"),
+    )
+
+    sample = {
+        "note": "Generated by gen_dcap_attestation.py with a test root CA, not by an SGX platform",
+        "now": int(NOW.timestamp()),
+        "root_ca": root.public_bytes(serialization.Encoding.DER).hex(),
+        "quote": quote.hex(),
+        "user_data_hash": USER_DATA_HASH.hex(),
+        "runtime_hash": (
+            MR_ENCLAVE + struct.pack("<HH", ISV_PROD_ID, ISV_SVN) + MR_SIGNER
+        ).hex(),
+        "collateral": {
+            "tcb_info_issuer_chain": pem(tcb_signer, root),
+            "tcb_info": tcb_info,
+            "tcb_info_signature": raw_sign(tcb_key, tcb_info.encode()).hex(),
+            "qe_identity_issuer_chain": pem(tcb_signer, root),
+            "qe_identity": qe_identity,
+            "qe_identity_signature": raw_sign(tcb_key, qe_identity.encode()).hex(),
+            "root_ca_crl": crl(root_name, root_key, [x509.random_serial_number()]).hex(),
+            "pck_crl_issuer_chain": pem(platform, root),
+            "pck_crl": crl(platform_name, platform_key, [x509.random_serial_number()]).hex(),
+            # The same CRL, but revoking the PCK cert
+            "revoking_pck_crl": crl(platform_name, platform_key, [pck.serial_number]).hex(),
+        },
+    }
+    print(json.dumps(sample, indent=2))
+
+
+if __name__ == "__main__":
+    main()
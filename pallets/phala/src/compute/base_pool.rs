@@ -47,6 +47,10 @@ pub mod pallet {
 	const MAX_RECURSIONS: u32 = 1;
 	const MAX_WHITELIST_LEN: u32 = 100;
 	const RESERVE_CID_START: CollectionId = 10000;
+	/// Max number of pools visited by `on_idle` in a block
+	const MAX_IDLE_POOLS: u64 = 16;
+	/// Max number of withdrawals fulfilled for a single pool by `on_idle` in a block
+	const MAX_IDLE_WITHDRAWALS_PER_POOL: u32 = 8;
 	type DescMaxLen = ConstU32<4400>;
 	pub type DescStr = BoundedVec<u8, DescMaxLen>;
 
@@ -95,6 +99,10 @@ pub mod pallet {
 	#[pallet::storage]
 	pub type LockIterateStartPos<T> = StorageValue<_, Option<LockKey>, ValueQuery>;
 
+	/// The pid where `on_idle` continues to process the withdraw queues
+	#[pallet::storage]
+	pub type WithdrawQueueIterateStartPos<T> = StorageValue<_, u64, ValueQuery>;
+
 	/// The number of total pools
 	#[pallet::storage]
 	#[pallet::getter(fn pool_count)]
@@ -131,6 +139,15 @@ pub mod pallet {
 	#[pallet::getter(fn pool_descriptions)]
 	pub type PoolDescriptions<T: Config> = StorageMap<_, Twox64Concat, u64, DescStr>;
 
+	/// Mapping from the nfts of the queued withdrawals to the minimum shares a partial
+	/// fulfillment must reach
+	///
+	/// Withdrawals without an entry accept a partial fulfillment of any size. The entry is removed
+	/// along with the nft when the withdrawal is fulfilled, cancelled or replaced by a new one.
+	#[pallet::storage]
+	pub type WithdrawMinFills<T: Config> =
+		StorageMap<_, Twox64Concat, (CollectionId, NftId), BalanceOf<T>>;
+
 	#[pallet::event]
 	#[pallet::generate_deposit(pub(super) fn deposit_event)]
 	pub enum Event<T: Config> {
@@ -183,6 +200,31 @@ pub mod pallet {
 
 		/// A staker is removed from the pool contribution whitelist
 		PoolWhitelistStakerRemoved { pid: u64, staker: T::AccountId },
+
+		/// A queued withdrawal is cancelled and its shares are returned to the user
+		///
+		/// Affected states:
+		/// - the withdrawal is removed from the withdraw queue in [`Pools`]
+		WithdrawalCancelled {
+			pid: u64,
+			user: T::AccountId,
+			shares: BalanceOf<T>,
+			as_vault: Option<u64>,
+		},
+
+		/// A queued withdrawal is amended
+		///
+		/// Affected states:
+		/// - the withdrawal in the withdraw queue in [`Pools`], moved to the end of the queue if
+		///   the shares are increased
+		/// - the minimum fill in [`WithdrawMinFills`]
+		WithdrawalReplaced {
+			pid: u64,
+			user: T::AccountId,
+			shares: BalanceOf<T>,
+			min_fill: BalanceOf<T>,
+			as_vault: Option<u64>,
+		},
 	}
 
 	#[pallet::error]
@@ -230,6 +272,12 @@ pub mod pallet {
 		BurnNftFailed,
 
 		TransferSharesAmountInvalid,
+		/// The user doesn't have a withdrawal in the withdraw queue of the pool
+		NoWithdrawalInQueue,
+		/// The minimum fill is larger than the shares to withdraw
+		InvalidMinFill,
+		/// The vault is locked for a forced withdrawal
+		VaultIsLocked,
 	}
 
 	#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, RuntimeDebug)]
@@ -416,6 +464,19 @@ pub mod pallet {
 			.expect("Decoding zero-padded account id should always succeed; qed")
 	}
 
+	#[pallet::hooks]
+	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T>
+	where
+		BalanceOf<T>: sp_runtime::traits::AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+		T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+		T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+		T: Config + wrapped_balances::Config + vault::Config,
+	{
+		fn on_idle(_n: BlockNumberFor<T>, remaining_weight: Weight) -> Weight {
+			Self::process_withdraw_queues(remaining_weight)
+		}
+	}
+
 	#[pallet::call]
 	impl<T: Config> Pallet<T>
 	where
@@ -581,6 +642,142 @@ pub mod pallet {
 
 			Ok(())
 		}
+
+		/// Cancels the withdrawal of the caller in the withdraw queue of a pool
+		///
+		/// The shares left in the withdrawal are returned to the caller's nft. The vault owner can
+		/// cancel the withdrawal of the vault by specifying `as_vault`.
+		#[pallet::call_index(5)]
+		#[pallet::weight({0})]
+		#[frame_support::transactional]
+		pub fn cancel_withdrawal(
+			origin: OriginFor<T>,
+			pid: u64,
+			as_vault: Option<u64>,
+		) -> DispatchResult {
+			let who = Self::ensure_withdrawer(ensure_signed(origin)?, as_vault)?;
+			let mut pool_proxy = Pools::<T>::get(pid).ok_or(Error::<T>::PoolDoesNotExist)?;
+			let pool_info = match &mut pool_proxy {
+				PoolProxy::Vault(res) => &mut res.basepool,
+				PoolProxy::StakePool(res) => &mut res.basepool,
+			};
+			let pos = pool_info
+				.withdraw_queue
+				.iter()
+				.position(|withdraw| withdraw.user == who)
+				.ok_or(Error::<T>::NoWithdrawalInQueue)?;
+			let withdraw = pool_info
+				.withdraw_queue
+				.remove(pos)
+				.expect("position exists as just checked; qed.");
+			let withdraw_nft_guard = Self::get_nft_attr_guard(pool_info.cid, withdraw.nft_id)?;
+			let shares = withdraw_nft_guard.attr.shares;
+			withdraw_nft_guard.unlock();
+			Self::burn_nft(&pallet_id(), pool_info.cid, withdraw.nft_id)?;
+			Self::mint_nft(pool_info.cid, who.clone(), shares, pid)?;
+			Self::merge_nft_for_staker(pool_info.cid, who.clone(), pid)?;
+			Pools::<T>::insert(pid, pool_proxy);
+			Self::deposit_event(Event::<T>::WithdrawalCancelled {
+				pid,
+				user: who,
+				shares,
+				as_vault,
+			});
+
+			Ok(())
+		}
+
+		/// Amends the withdrawal of the caller in the withdraw queue of a pool
+		///
+		/// Sets the shares to withdraw to `shares`, moving the difference between the withdrawal
+		/// and the caller's nft. A partial fulfillment smaller than `min_fill` shares is skipped
+		/// until enough free stake shows up, and `min_fill` of zero accepts any partial
+		/// fulfillment. The withdrawal keeps its place in the queue unless the shares are
+		/// increased, in which case it's moved to the end of the queue as a new request.
+		///
+		/// Note that a new withdrawal by `withdraw` replaces the queued one and resets `min_fill`.
+		#[pallet::call_index(6)]
+		#[pallet::weight({0})]
+		#[frame_support::transactional]
+		pub fn replace_withdrawal(
+			origin: OriginFor<T>,
+			pid: u64,
+			shares: BalanceOf<T>,
+			min_fill: BalanceOf<T>,
+			as_vault: Option<u64>,
+		) -> DispatchResult {
+			let who = Self::ensure_withdrawer(ensure_signed(origin)?, as_vault)?;
+			let mut pool_proxy = Pools::<T>::get(pid).ok_or(Error::<T>::PoolDoesNotExist)?;
+			let pool_info = match &mut pool_proxy {
+				PoolProxy::Vault(res) => &mut res.basepool,
+				PoolProxy::StakePool(res) => &mut res.basepool,
+			};
+			let pos = pool_info
+				.withdraw_queue
+				.iter()
+				.position(|withdraw| withdraw.user == who)
+				.ok_or(Error::<T>::NoWithdrawalInQueue)?;
+			let withdraw_nft_id = pool_info.withdraw_queue[pos].nft_id;
+			let mut withdraw_nft_guard = Self::get_nft_attr_guard(pool_info.cid, withdraw_nft_id)?;
+			let queued_shares = withdraw_nft_guard.attr.shares;
+			let maybe_nft_id = Self::merge_nft_for_staker(pool_info.cid, who.clone(), pid)?;
+			let maybe_nft_guard = match maybe_nft_id {
+				Some(nft_id) => Some(Self::get_nft_attr_guard(pool_info.cid, nft_id)?),
+				None => None,
+			};
+			let total_shares = queued_shares
+				+ maybe_nft_guard
+					.as_ref()
+					.map(|guard| guard.attr.shares)
+					.unwrap_or_else(Zero::zero);
+			ensure!(
+				is_nondust_balance(shares) && shares <= total_shares,
+				Error::<T>::InvalidWithdrawalAmount
+			);
+			ensure!(min_fill <= shares, Error::<T>::InvalidMinFill);
+
+			let remaining_shares = total_shares - shares;
+			match maybe_nft_guard {
+				Some(mut nft_guard) => {
+					nft_guard.attr.shares = remaining_shares;
+					nft_guard.save()?;
+				}
+				None if remaining_shares > Zero::zero() => {
+					Self::mint_nft(pool_info.cid, who.clone(), remaining_shares, pid)?;
+				}
+				None => (),
+			}
+			withdraw_nft_guard.attr.shares = shares;
+			withdraw_nft_guard.save()?;
+			if shares > queued_shares {
+				// Otherwise a small withdrawal could be queued early and enlarged later to
+				// expire sooner than a new one.
+				let mut withdraw = pool_info
+					.withdraw_queue
+					.remove(pos)
+					.expect("position exists as just checked; qed.");
+				withdraw.start_time = <T as registry::Config>::UnixTime::now()
+					.as_secs()
+					.saturated_into::<u64>();
+				pool_info.withdraw_queue.push_back(withdraw);
+			}
+			if min_fill.is_zero() {
+				WithdrawMinFills::<T>::remove((pool_info.cid, withdraw_nft_id));
+			} else {
+				WithdrawMinFills::<T>::insert((pool_info.cid, withdraw_nft_id), min_fill);
+			}
+			Self::deposit_event(Event::<T>::WithdrawalReplaced {
+				pid,
+				user: who,
+				shares,
+				min_fill,
+				as_vault,
+			});
+			Self::try_process_withdraw_queue(pool_info);
+			Pools::<T>::insert(pid, pool_proxy);
+
+			Ok(())
+		}
 	}
 
 	impl<T: Config> Pallet<T>
@@ -730,6 +927,28 @@ pub mod pallet {
 			Ok(())
 		}
 
+		/// Returns the account that owns the withdrawals of the caller
+		///
+		/// It's the pool account of the vault if the caller acts as the owner of the vault.
+		fn ensure_withdrawer(
+			who: T::AccountId,
+			as_vault: Option<u64>,
+		) -> Result<T::AccountId, DispatchError> {
+			let Some(vault_pid) = as_vault else {
+				return Ok(who);
+			};
+			let vault_info = ensure_vault::<T>(vault_pid)?;
+			ensure!(
+				!vault::VaultLocks::<T>::contains_key(vault_pid),
+				Error::<T>::VaultIsLocked
+			);
+			ensure!(
+				who == vault_info.basepool.owner,
+				Error::<T>::UnauthorizedPoolOwner
+			);
+			Ok(vault_info.basepool.pool_account_id)
+		}
+
 		/// Checks if there has expired withdraw request in the withdraw queue
 		///
 		/// Releasing stakes (stakes in workers that is cooling down (stakepool case), or shares in withdraw_queue (vault case))
@@ -888,6 +1107,7 @@ pub mod pallet {
 			);
 			Self::remove_properties(cid, nft_id);
 			pallet_rmrk_core::pallet::Lock::<T>::remove((cid, nft_id));
+			WithdrawMinFills::<T>::remove((cid, nft_id));
 			Ok(())
 		}
 
@@ -926,8 +1146,8 @@ pub mod pallet {
 			Ok(Some(nft_id))
 		}
 
-		/// Gets nft attr, can only be called in the pallet
-		fn get_nft_attr(
+		/// Gets nft attr without locking it, can only be called in the crate
+		pub(crate) fn get_nft_attr(
 			cid: CollectionId,
 			nft_id: NftId,
 		) -> Result<NftAttr<BalanceOf<T>>, DispatchError> {
@@ -1061,25 +1281,42 @@ pub mod pallet {
 
 		/// Tries to fulfill the withdraw queue with the newly freed stake
 		pub fn try_process_withdraw_queue(pool_info: &mut BasePool<T::AccountId, BalanceOf<T>>) {
+			Self::process_withdraw_queue(pool_info, u32::MAX);
+		}
+
+		/// Fulfills at most `max_withdrawals` withdrawals in the withdraw queue with the free stake
+		///
+		/// The queue is processed in order. A withdrawal whose partial fulfillment would be below
+		/// its minimum fill is skipped and keeps its place, so that it doesn't block the
+		/// withdrawals behind it. Returns the number of the visited withdrawals, including the
+		/// skipped ones and the ones removed as dust.
+		pub fn process_withdraw_queue(
+			pool_info: &mut BasePool<T::AccountId, BalanceOf<T>>,
+			max_withdrawals: u32,
+		) -> u32 {
 			// The share price shouldn't change at any point in this function. So we can calculate
 			// only once at the beginning.
 			let price = match pool_info.share_price() {
 				Some(price) => price,
-				None => return,
+				None => return 0,
 			};
 
 			let wpha_min = wrapped_balances::Pallet::<T>::min_balance();
-			while pool_info.get_free_stakes::<T>() > wpha_min {
-				if let Some(withdraw) = pool_info.withdraw_queue.front().cloned() {
+			let mut processed = 0;
+			// The position of the next withdrawal to process, the skipped ones are before it
+			let mut index = 0;
+			while processed < max_withdrawals && pool_info.get_free_stakes::<T>() > wpha_min {
+				if let Some(withdraw) = pool_info.withdraw_queue.get(index).cloned() {
 					// Must clear the pending reward before any stake change
 					let mut withdraw_nft_guard =
 						Self::get_nft_attr_guard(pool_info.cid, withdraw.nft_id)
 							.expect("get nftattr should always success; qed.");
 					let mut withdraw_nft = withdraw_nft_guard.attr.clone();
 					if Self::maybe_remove_dust(pool_info, &withdraw_nft) {
-						pool_info.withdraw_queue.pop_front();
+						pool_info.withdraw_queue.remove(index);
 						Self::burn_nft(&pallet_id(), pool_info.cid, withdraw.nft_id)
 							.expect("burn nft should always success");
+						processed += 1;
 						continue;
 					}
 					// Try to fulfill the withdraw requests as much as possible
@@ -1092,6 +1329,14 @@ pub mod pallet {
 					// because we ensure (1) `free_shares` is not dust earlier, and (2) the shares
 					// in any withdraw request mustn't be dust when inserting and updating it.
 					let withdrawing_shares = free_shares.min(withdraw_nft.shares);
+					// Leave the partial fulfillment below the minimum fill to the later free stake
+					let min_fill = WithdrawMinFills::<T>::get((pool_info.cid, withdraw.nft_id))
+						.unwrap_or_else(Zero::zero);
+					if withdrawing_shares < withdraw_nft.shares && withdrawing_shares < min_fill {
+						index += 1;
+						processed += 1;
+						continue;
+					}
 					debug_assert!(
 						is_nondust_balance(withdrawing_shares),
 						"withdrawing_shares must be positive"
@@ -1113,19 +1358,74 @@ pub mod pallet {
 					if withdraw_nft.shares == Zero::zero()
 						|| Self::maybe_remove_dust(pool_info, &withdraw_nft)
 					{
-						pool_info.withdraw_queue.pop_front();
+						pool_info.withdraw_queue.remove(index);
 						Self::burn_nft(&pallet_id(), pool_info.cid, withdraw.nft_id)
 							.expect("burn nft should always success");
 					} else {
 						*pool_info
 							.withdraw_queue
-							.front_mut()
-							.expect("front exists as just checked; qed.") = withdraw;
+							.get_mut(index)
+							.expect("withdrawal exists as just checked; qed.") = withdraw;
 					}
+					processed += 1;
 				} else {
 					break;
 				}
 			}
+			processed
+		}
+
+		/// Processes the withdraw queues of the pools in turn within `max_weight`
+		///
+		/// Free stake can show up without anyone touching the pool, e.g. when a vault gets its
+		/// stake back from a stake pool. Processing the queues in the idle time lets such
+		/// withdrawals mature without waiting for the next interaction with the pool. Returns the
+		/// consumed weight.
+		pub fn process_withdraw_queues(max_weight: Weight) -> Weight {
+			let db_weight = T::DbWeight::get();
+			// Reads the pool, its free stake and the minimum balance of W-PHA
+			let visit_weight = db_weight.reads(3);
+			// A conservative estimation of the nft, asset and pool updates of a withdrawal
+			let withdrawal_weight = db_weight.reads_writes(16, 16);
+			// Reads the pool count, reads and writes the iterate position
+			let mut used = db_weight.reads_writes(2, 1);
+			if !(used + visit_weight + withdrawal_weight).all_lte(max_weight) {
+				return Weight::zero();
+			}
+			let pool_count = PoolCount::<T>::get();
+			if pool_count == 0 {
+				return used;
+			}
+			let mut pid = WithdrawQueueIterateStartPos::<T>::get() % pool_count;
+			for _ in 0..pool_count.min(MAX_IDLE_POOLS) {
+				if !(used + visit_weight + withdrawal_weight).all_lte(max_weight) {
+					break;
+				}
+				used += visit_weight;
+				if let Some(mut pool_proxy) = Pools::<T>::get(pid) {
+					let pool_info = match &mut pool_proxy {
+						PoolProxy::Vault(res) => &mut res.basepool,
+						PoolProxy::StakePool(res) => &mut res.basepool,
+					};
+					if !pool_info.withdraw_queue.is_empty() {
+						let mut max_withdrawals = 1;
+						while max_withdrawals < MAX_IDLE_WITHDRAWALS_PER_POOL
+							&& (used + withdrawal_weight.saturating_mul(max_withdrawals as u64 + 1))
+								.all_lte(max_weight)
+						{
+							max_withdrawals += 1;
+						}
+						let processed = Self::process_withdraw_queue(pool_info, max_withdrawals);
+						if processed > 0 {
+							used += withdrawal_weight.saturating_mul(processed as u64);
+							Pools::<T>::insert(pid, pool_proxy);
+						}
+					}
+				}
+				pid = (pid + 1) % pool_count;
+			}
+			WithdrawQueueIterateStartPos::<T>::put(pid);
+			used
 		}
 	}
 
@@ -33,7 +33,7 @@ pub mod pallet {
 		traits::{AccountIdConversion, Zero},
 		AccountId32, SaturatedConversion,
 	};
-	use sp_std::cmp;
+	use sp_std::{cmp, vec::Vec};
 
 	use crate::balance_convert::FixedPointConvert;
 	use codec::{Decode, Encode};
@@ -48,6 +48,10 @@ pub mod pallet {
 	const COMPUTING_PALLETID: PalletId = PalletId(*b"phala/pp");
 	// Multiplier that adjust the maxbudgetlimit to compensate budget miss-caculated during the last nonce.
 	const BUDGET_SAFE_MULTIPLIER: u128 = 2;
+	/// The length of a worker performance epoch, in seconds
+	const PERFORMANCE_EPOCH_SECS: u64 = 24 * 3600;
+	/// The number of the latest performance epochs kept for each worker
+	const MAX_PERFORMANCE_EPOCHS: usize = 30;
 
 	#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 	#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, RuntimeDebug)]
@@ -156,6 +160,16 @@ pub mod pallet {
 	}
 
 	impl SessionInfo {
+		/// The unix timestamp of the cool down starting time
+		pub fn cool_down_start(&self) -> u64 {
+			self.cool_down_start
+		}
+
+		/// The initial performance score of the current computing session
+		pub fn init_p(&self) -> u32 {
+			self.benchmark.p_init
+		}
+
 		/// Calculates the final final returned and slashed stake
 		fn calc_final_stake<Balance>(&self, orig_stake: Balance) -> (Balance, Balance)
 		where
@@ -200,6 +214,95 @@ pub mod pallet {
 		}
 	}
 
+	/// The performance of a worker in an epoch
+	#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+	#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+	#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+	pub struct PerformanceEpoch {
+		/// The index of the epoch, i.e. the unix timestamp of its start divided by the epoch length
+		pub epoch: u64,
+		/// Seconds the worker was computing and responsive
+		pub uptime: u64,
+		/// Seconds the worker was computing but unresponsive
+		pub offline_time: u64,
+		/// Times the gatekeeper reported the worker unresponsive for missing the heartbeats
+		pub offline_count: u32,
+		/// Number of the received heartbeats
+		pub heartbeats: u32,
+		/// Sum of the `p_instant` of the received heartbeats
+		pub p_instant_sum: u64,
+		/// The net decrease of V over the settlements, in `U64F64` bits
+		///
+		/// Computed from the V reported by consecutive settlements, so it is the slash net of the
+		/// V gained in between, not the slash amount itself. Increases of V are not counted.
+		pub v_decreased: u128,
+	}
+
+	impl PerformanceEpoch {
+		/// The average `p_instant` of the heartbeats received in the epoch
+		pub fn p_instant_avg(&self) -> Option<u32> {
+			if self.heartbeats == 0 {
+				return None;
+			}
+			Some((self.p_instant_sum / self.heartbeats as u64) as u32)
+		}
+	}
+
+	/// The recent performance of a worker, kept across its computing sessions
+	#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+	#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+	#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+	pub struct PerformanceHistory {
+		/// The latest epochs with any activity, the oldest first
+		pub epochs: Vec<PerformanceEpoch>,
+		/// The unix timestamp until which the uptime and offline time have been accounted
+		pub accounted_until: u64,
+	}
+
+	impl PerformanceHistory {
+		/// Returns the epoch containing `now`, creating it and dropping the oldest one if needed
+		fn epoch_mut(&mut self, now: u64) -> &mut PerformanceEpoch {
+			let epoch = now / PERFORMANCE_EPOCH_SECS;
+			if self.epochs.last().map(|e| e.epoch) != Some(epoch) {
+				if self.epochs.len() >= MAX_PERFORMANCE_EPOCHS {
+					self.epochs.remove(0);
+				}
+				self.epochs.push(PerformanceEpoch {
+					epoch,
+					..Default::default()
+				});
+			}
+			self.epochs.last_mut().expect("pushed above; qed.")
+		}
+
+		/// Accounts the time since the last update as uptime or offline time according to the
+		/// state the worker has been in
+		fn accrue(&mut self, state: &WorkerState, now: u64) {
+			let last = self.accounted_until;
+			self.accounted_until = now.max(last);
+			// Nothing is known about the time before the history is created
+			if last == 0 {
+				return;
+			}
+			// Only the time in the kept epochs matters
+			let earliest = (now / PERFORMANCE_EPOCH_SECS)
+				.saturating_sub(MAX_PERFORMANCE_EPOCHS as u64 - 1)
+				* PERFORMANCE_EPOCH_SECS;
+			let mut from = last.max(earliest);
+			while from < now {
+				let to = ((from / PERFORMANCE_EPOCH_SECS + 1) * PERFORMANCE_EPOCH_SECS).min(now);
+				match state {
+					WorkerState::WorkerIdle => self.epoch_mut(from).uptime += to - from,
+					WorkerState::WorkerUnresponsive => {
+						self.epoch_mut(from).offline_time += to - from
+					}
+					_ => return,
+				}
+				from = to;
+			}
+		}
+	}
+
 	#[pallet::config]
 	pub trait Config: frame_system::Config + PhalaConfig + mq::Config + registry::Config {
 		type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
@@ -324,6 +427,14 @@ pub mod pallet {
 	#[pallet::getter(fn stakes)]
 	pub type Stakes<T: Config> = StorageMap<_, Twox64Concat, T::AccountId, BalanceOf<T>>;
 
+	/// The recent performance of the workers
+	///
+	/// Unlike [`Sessions`], it's kept when the worker is unbound so that the track record follows
+	/// the worker.
+	#[pallet::storage]
+	pub type WorkerPerformance<T> =
+		StorageMap<_, Twox64Concat, WorkerPublicKey, PerformanceHistory>;
+
 	#[pallet::event]
 	#[pallet::generate_deposit(pub(super) fn deposit_event)]
 	pub enum Event<T: Config> {
@@ -712,9 +823,14 @@ pub mod pallet {
 							.benchmark
 							.update(now, iterations, challenge_time_sec)
 							.expect("Benchmark report must be valid; qed.");
+						let p_instant = session_info.benchmark.p_instant;
+						Self::update_performance(&worker, &session_info.state, now, |epoch| {
+							epoch.heartbeats += 1;
+							epoch.p_instant_sum += p_instant as u64;
+						});
 						Self::deposit_event(Event::<T>::BenchmarkUpdated {
 							session: session.clone(),
-							p_instant: session_info.benchmark.p_instant,
+							p_instant,
 						});
 						Sessions::<T>::insert(&session, session_info);
 					}
@@ -746,6 +862,12 @@ pub mod pallet {
 						if !session_info.state.is_computing() {
 							continue;
 						}
+						let was_idle = session_info.state == WorkerState::WorkerIdle;
+						Self::update_performance(&worker, &session_info.state, now, |epoch| {
+							if was_idle {
+								epoch.offline_count += 1;
+							}
+						});
 						session_info.state = WorkerState::WorkerUnresponsive;
 						Sessions::<T>::insert(&account, &session_info);
 						Self::deposit_event(Event::<T>::WorkerEnterUnresponsive {
@@ -769,6 +891,7 @@ pub mod pallet {
 						if !session_info.state.is_computing() {
 							continue;
 						}
+						Self::update_performance(&worker, &session_info.state, now, |_| ());
 						session_info.state = WorkerState::WorkerIdle;
 						Sessions::<T>::insert(&account, &session_info);
 						Self::deposit_event(Event::<T>::WorkerExitUnresponsive {
@@ -817,6 +940,10 @@ pub mod pallet {
 					return Ok(());
 				}
 				// Otherwise it's a normal update. Let's proceed.
+				let v_decreased = session_info.v.saturating_sub(info.v);
+				Self::update_performance(&info.pubkey, &session_info.state, now, |epoch| {
+					epoch.v_decreased += v_decreased;
+				});
 				session_info.v = info.v; // in bits
 				session_info.v_updated_at = now;
 				session_info.stats.on_reward(info.payout);
@@ -1007,6 +1134,7 @@ pub mod pallet {
 			let now = Self::now_sec();
 
 			Stakes::<T>::insert(&session, stake);
+			Self::update_performance(&worker, &WorkerState::Ready, now, |_| ());
 			Sessions::<T>::mutate(&session, |info| {
 				if let Some(info) = info {
 					info.state = WorkerState::WorkerIdle;
@@ -1052,6 +1180,7 @@ pub mod pallet {
 			);
 
 			let now = Self::now_sec();
+			Self::update_performance(&worker, &session_info.state, now, |_| ());
 			session_info.state = WorkerState::WorkerCoolingDown;
 			session_info.cool_down_start = now;
 			Sessions::<T>::insert(&session, &session_info);
@@ -1107,6 +1236,23 @@ pub mod pallet {
 			Ok(Tokenomic::<T>::new(params))
 		}
 
+		/// Updates the performance history of a worker
+		///
+		/// The time since the last update is accounted according to `state`, the state the
+		/// worker has been in, and then `f` is applied to the current epoch.
+		fn update_performance(
+			worker: &WorkerPublicKey,
+			state: &WorkerState,
+			now: u64,
+			f: impl FnOnce(&mut PerformanceEpoch),
+		) {
+			WorkerPerformance::<T>::mutate(worker, |history| {
+				let history = history.get_or_insert_with(Default::default);
+				history.accrue(state, now);
+				f(history.epoch_mut(now));
+			});
+		}
+
 		fn now_sec() -> u64 {
 			<T as registry::Config>::UnixTime::now()
 				.as_secs()
@@ -1598,6 +1744,126 @@ pub mod pallet {
 			);
 		}
 
+		#[test]
+		fn test_worker_performance() {
+			use phala_types::messaging::{Topic, WorkingReportEvent};
+			new_test_ext().execute_with(|| {
+				let gk_update = |timestamp_ms: u64,
+				                 offline: Vec<WorkerPublicKey>,
+				                 recovered_to_online: Vec<WorkerPublicKey>,
+				                 settle: Vec<SettleInfo>| {
+					PhalaComputation::on_gk_message_received(DecodedMessage::<
+						WorkingInfoUpdateEvent<BlockNumber>,
+					> {
+						sender: MessageOrigin::Gatekeeper,
+						destination: Topic::new(*b"^phala/mining/update"),
+						payload: WorkingInfoUpdateEvent::<BlockNumber> {
+							block_number: 1,
+							timestamp_ms,
+							offline,
+							recovered_to_online,
+							settle,
+						},
+					})
+				};
+				set_block_1();
+				setup_workers(1);
+				PhalaRegistry::internal_set_benchmark(&worker_pubkey(1), Some(600));
+				assert_ok!(PhalaComputation::bind(1, worker_pubkey(1)));
+				elapse_seconds(100);
+				assert_ok!(PhalaComputation::start_computing(1, 3000 * DOLLARS));
+				let ve = PhalaComputation::sessions(1).unwrap().v;
+
+				// Computing for 100 secs
+				elapse_seconds(100);
+				assert_ok!(PhalaComputation::on_working_message_received(
+					DecodedMessage::<WorkingReportEvent> {
+						sender: MessageOrigin::Worker(worker_pubkey(1)),
+						destination: Topic::new(*b"phala/mining/report"),
+						payload: WorkingReportEvent::Heartbeat {
+							session_id: 0,
+							challenge_block: 2,
+							challenge_time: 150_000,
+							iterations: 12000,
+						},
+					}
+				));
+				// Computing for 50 secs, and then offline for 30 secs with some V slashed
+				elapse_seconds(50);
+				assert_ok!(gk_update(250_000, vec![worker_pubkey(1)], vec![], vec![]));
+				elapse_seconds(30);
+				assert_ok!(gk_update(
+					280_000,
+					vec![],
+					vec![worker_pubkey(1)],
+					vec![SettleInfo {
+						pubkey: worker_pubkey(1),
+						v: 1,
+						payout: 0,
+						treasury: 0,
+					}]
+				));
+				// Computing until 20 secs after the first epoch
+				elapse_seconds(PERFORMANCE_EPOCH_SECS - 280 + 20);
+				assert_ok!(PhalaComputation::stop_computing(1));
+
+				let history = WorkerPerformance::<Test>::get(worker_pubkey(1)).unwrap();
+				assert_eq!(history.accounted_until, PERFORMANCE_EPOCH_SECS + 20);
+				assert_eq!(
+					history.epochs,
+					vec![
+						PerformanceEpoch {
+							epoch: 0,
+							uptime: PERFORMANCE_EPOCH_SECS - 100 - 30,
+							offline_time: 30,
+							offline_count: 1,
+							heartbeats: 1,
+							p_instant_sum: 360,
+							v_decreased: ve - 1,
+						},
+						PerformanceEpoch {
+							epoch: 1,
+							uptime: 20,
+							..Default::default()
+						}
+					]
+				);
+				assert_eq!(history.epochs[0].p_instant_avg(), Some(360));
+				assert_eq!(history.epochs[1].p_instant_avg(), None);
+
+				// Cooling down doesn't count as uptime
+				elapse_seconds(100);
+				PhalaComputation::update_performance(
+					&worker_pubkey(1),
+					&WorkerState::WorkerCoolingDown,
+					PhalaComputation::now_sec(),
+					|_| (),
+				);
+				let history = WorkerPerformance::<Test>::get(worker_pubkey(1)).unwrap();
+				assert_eq!(history.epochs[1].uptime, 20);
+			});
+		}
+
+		#[test]
+		fn test_performance_history_bounded() {
+			let mut history = PerformanceHistory {
+				epochs: vec![],
+				accounted_until: 1,
+			};
+			history.accrue(&WorkerState::WorkerIdle, 40 * PERFORMANCE_EPOCH_SECS);
+			assert_eq!(history.epochs.len(), MAX_PERFORMANCE_EPOCHS - 1);
+			assert_eq!(history.epochs[0].epoch, 11);
+			assert!(history
+				.epochs
+				.iter()
+				.all(|epoch| epoch.uptime == PERFORMANCE_EPOCH_SECS));
+			history.epoch_mut(40 * PERFORMANCE_EPOCH_SECS);
+			history.epoch_mut(41 * PERFORMANCE_EPOCH_SECS);
+			assert_eq!(history.epochs.len(), MAX_PERFORMANCE_EPOCHS);
+			assert_eq!(history.epochs[0].epoch, 12);
+			assert_eq!(history.epochs.last().unwrap().epoch, 41);
+		}
+
 		#[test]
 		fn drop_late_arrived_update() {
 			new_test_ext().execute_with(|| {
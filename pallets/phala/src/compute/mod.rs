@@ -2,5 +2,6 @@ pub mod base_pool;
 pub mod computation;
 pub mod pool_proxy;
 pub mod stake_pool_v2;
+pub mod valuation;
 pub mod vault;
 pub mod wrapped_balances;
@@ -92,6 +92,7 @@ pub enum PoolProxy<AccountId, Balance> {
 	Vault(Vault<AccountId, Balance>),
 }
 
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, RuntimeDebug)]
 pub enum PoolType {
 	StakePool,
@@ -0,0 +1,482 @@
+//! Read-only valuation of the stake pools and vaults
+//!
+//! Computes the value of pools, stakers' shares, pending withdrawals and owner rewards with the
+//! same fixed-point math as the pallets, so that clients don't need to re-implement it. Served
+//! to the clients by the `PhalaComputeApi` runtime api.
+
+use crate::balance_convert::{div as bdiv, mul as bmul, FixedPointConvert};
+use crate::base_pool::{self, BasePool, CollectionId, NftId, WithdrawInfo};
+use crate::computation;
+use crate::pool_proxy::{PoolProxy, PoolType, StakePool, Vault};
+use crate::registry;
+use crate::stake_pool_v2;
+use crate::vault;
+use crate::BalanceOf;
+
+use codec::{Decode, Encode};
+use frame_support::{
+	traits::{tokens::nonfungibles::InspectEnumerable, Get, UnixTime},
+	RuntimeDebug,
+};
+use scale_info::TypeInfo;
+use sp_runtime::{
+	traits::{AtLeast32BitUnsigned, Zero},
+	Permill, SaturatedConversion,
+};
+use sp_std::{collections::btree_map::BTreeMap, fmt::Display, prelude::*, vec};
+
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+
+/// A snapshot of a stake pool or vault
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct PoolSummary<AccountId, Balance> {
+	pub pid: u64,
+	pub pool_type: PoolType,
+	pub owner: AccountId,
+	pub total_shares: Balance,
+	pub total_value: Balance,
+	/// The value of 1 PHA worth of shares (10^12 units), `None` if the pool has no share
+	pub share_price: Option<Balance>,
+	/// The stake available to fulfill the withdraw requests immediately
+	pub free_stake: Balance,
+	/// The stake to be released to the pool (cooling down workers for a stake pool, queued
+	/// withdrawals from the upstream stake pools for a vault)
+	pub releasing_stake: Balance,
+	/// The shares in the withdraw queue
+	pub withdrawing_shares: Balance,
+	pub withdraw_queue_len: u32,
+	pub commission: Option<Permill>,
+	/// The capacity of a stake pool, always `None` for a vault
+	pub cap: Option<Balance>,
+	pub workers: u32,
+	pub cd_workers: u32,
+	/// The upstream stake pools a vault has delegated to, always empty for a stake pool
+	pub invest_pools: Vec<u64>,
+}
+
+/// The stake an account holds in a pool, across all its nfts in the pool collection
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct StakeValue<Balance> {
+	pub pid: u64,
+	pub nft_ids: Vec<NftId>,
+	pub shares: Balance,
+	/// The current value of the shares
+	pub value: Balance,
+}
+
+/// A withdraw request of an account waiting in the withdraw queue of a pool
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct PendingWithdrawal<Balance> {
+	pub pid: u64,
+	pub nft_id: NftId,
+	pub shares: Balance,
+	/// The current value of the shares
+	pub value: Balance,
+	/// The unix timestamp when the request was queued, in seconds
+	pub start_time: u64,
+	/// The estimated unix timestamp when the request gets fulfilled, in seconds
+	///
+	/// Assumes the share price and the other requests don't change, and the pool is triggered
+	/// (e.g. by `check_and_maybe_force_withdraw` or `reclaim_pool_worker`) as soon as possible.
+	/// If the releasing stake can't cover the request, it's the time when the pool can be forced
+	/// to release the stake (grace period or vault queue period) plus the cool down period.
+	/// `None` if the pool is bankrupt.
+	pub estimated_release_at: Option<u64>,
+}
+
+/// The rewards of a pool owner
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct OwnerRewards<Balance> {
+	pub pid: u64,
+	/// The rewards the owner can claim now
+	pub claimable: Balance,
+	/// The value of the owner shares a vault would gain by `maybe_gain_owner_shares` now, always
+	/// zero for a stake pool
+	pub pending: Balance,
+}
+
+struct QueueEstimate<AccountId, Balance> {
+	request: WithdrawInfo<AccountId>,
+	shares: Balance,
+	value: Balance,
+	release_at: Option<u64>,
+}
+
+fn now_sec<T: registry::Config>() -> u64 {
+	<T as registry::Config>::UnixTime::now()
+		.as_secs()
+		.saturated_into::<u64>()
+}
+
+/// The sum of the values of the requests queued by `user` in the given stake pool.
+fn queued_value<T>(pool: &BasePool<T::AccountId, BalanceOf<T>>, user: &T::AccountId) -> BalanceOf<T>
+where
+	BalanceOf<T>: AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+	T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+	T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+	T: vault::Config,
+{
+	let price = match pool.share_price() {
+		Some(price) => price,
+		None => return Zero::zero(),
+	};
+	pool.withdraw_queue
+		.iter()
+		.filter(|request| &request.user == user)
+		.filter_map(|request| base_pool::Pallet::<T>::get_nft_attr(pool.cid, request.nft_id).ok())
+		.fold(Zero::zero(), |acc: BalanceOf<T>, nft| {
+			acc + bmul(nft.shares, &price)
+		})
+}
+
+/// Simulates the withdraw queue of a pool
+///
+/// `releases` is the stake to be released to the pool with the time it's released. The requests
+/// not covered by the free stake and `releases` are fulfilled `force_delay` seconds after they
+/// become overdue at `start_time + overdue_period`.
+fn estimate_queue<T>(
+	pool: &BasePool<T::AccountId, BalanceOf<T>>,
+	mut releases: Vec<(u64, BalanceOf<T>)>,
+	overdue_period: u64,
+	force_delay: u64,
+	now: u64,
+) -> Vec<QueueEstimate<T::AccountId, BalanceOf<T>>>
+where
+	BalanceOf<T>: AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+	T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+	T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+	T: vault::Config,
+{
+	let price = pool.share_price();
+	releases.sort_by_key(|(time, _)| *time);
+	let mut releases = releases.into_iter().peekable();
+	let mut free = pool.get_free_stakes::<T>();
+	// Requests are fulfilled in order, none of them is fulfilled before the previous one.
+	let mut last_release = now;
+	pool.withdraw_queue
+		.iter()
+		.map(|request| {
+			let shares = base_pool::Pallet::<T>::get_nft_attr(pool.cid, request.nft_id)
+				.map(|nft| nft.shares)
+				.unwrap_or_else(|_| Zero::zero());
+			let (value, release_at) = match price {
+				Some(price) => {
+					let value = bmul(shares, &price);
+					let mut remaining = value;
+					let covered = free.min(remaining);
+					free -= covered;
+					remaining -= covered;
+					while !remaining.is_zero() {
+						let Some((time, amount)) = releases.peek_mut() else {
+							break;
+						};
+						let covered = (*amount).min(remaining);
+						*amount -= covered;
+						remaining -= covered;
+						last_release = last_release.max(*time);
+						if amount.is_zero() {
+							releases.next();
+						}
+					}
+					if !remaining.is_zero() {
+						let forced_at = (request.start_time + overdue_period).max(now);
+						last_release = last_release.max(forced_at + force_delay);
+					}
+					(value, Some(last_release))
+				}
+				None => (Zero::zero(), None),
+			};
+			QueueEstimate {
+				request: request.clone(),
+				shares,
+				value,
+				release_at,
+			}
+		})
+		.collect()
+}
+
+fn stake_pool_releases<T>(pool: &StakePool<T::AccountId, BalanceOf<T>>) -> Vec<(u64, BalanceOf<T>)>
+where
+	T: stake_pool_v2::Config,
+{
+	let cool_down_period = computation::Pallet::<T>::cool_down_period();
+	pool.cd_workers
+		.iter()
+		.filter_map(|worker| {
+			let session: T::AccountId = stake_pool_v2::pool_sub_account(pool.basepool.pid, worker);
+			let stake = computation::pallet::Stakes::<T>::get(&session)?;
+			let info = computation::Pallet::<T>::sessions(&session)?;
+			Some((info.cool_down_start() + cool_down_period, stake))
+		})
+		.collect()
+}
+
+fn estimate_stake_pool<T>(
+	pool: &StakePool<T::AccountId, BalanceOf<T>>,
+	now: u64,
+) -> Vec<QueueEstimate<T::AccountId, BalanceOf<T>>>
+where
+	BalanceOf<T>: AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+	T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+	T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+	T: vault::Config,
+{
+	// Overdue requests allow anyone to stop all the workers, which then cool down.
+	estimate_queue::<T>(
+		&pool.basepool,
+		stake_pool_releases::<T>(pool),
+		<T as stake_pool_v2::Config>::GracePeriod::get(),
+		computation::Pallet::<T>::cool_down_period(),
+		now,
+	)
+}
+
+/// The withdrawals the vault queued in its upstream stake pools, with their estimated release
+/// time.
+fn vault_releases<T>(
+	vault: &Vault<T::AccountId, BalanceOf<T>>,
+	now: u64,
+) -> Vec<(u64, BalanceOf<T>)>
+where
+	BalanceOf<T>: AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+	T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+	T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+	T: vault::Config,
+{
+	let vault_account = &vault.basepool.pool_account_id;
+	vault
+		.invest_pools
+		.iter()
+		.filter_map(|pid| crate::pool_proxy::ensure_stake_pool::<T>(*pid).ok())
+		.flat_map(|pool| estimate_stake_pool::<T>(&pool, now))
+		.filter(|estimate| &estimate.request.user == vault_account)
+		.filter_map(|estimate| Some((estimate.release_at?, estimate.value)))
+		.collect()
+}
+
+fn estimate_vault<T>(
+	vault: &Vault<T::AccountId, BalanceOf<T>>,
+	now: u64,
+) -> Vec<QueueEstimate<T::AccountId, BalanceOf<T>>>
+where
+	BalanceOf<T>: AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+	T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+	T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+	T: vault::Config,
+{
+	// Overdue requests allow anyone to withdraw all the vault's shares from the upstream stake
+	// pools, which then go through the withdraw queues of the stake pools.
+	estimate_queue::<T>(
+		&vault.basepool,
+		vault_releases::<T>(vault, now),
+		<T as vault::Config>::VaultQueuePeriod::get(),
+		<T as stake_pool_v2::Config>::GracePeriod::get()
+			+ computation::Pallet::<T>::cool_down_period(),
+		now,
+	)
+}
+
+/// Returns the summary of a stake pool or vault
+pub fn pool_summary<T>(pid: u64) -> Option<PoolSummary<T::AccountId, BalanceOf<T>>>
+where
+	BalanceOf<T>: AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+	T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+	T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+	T: vault::Config,
+{
+	let proxy = base_pool::Pallet::<T>::pool_collection(pid)?;
+	let (basepool, pool_type) = match &proxy {
+		PoolProxy::StakePool(pool) => (&pool.basepool, PoolType::StakePool),
+		PoolProxy::Vault(vault) => (&vault.basepool, PoolType::Vault),
+	};
+	let withdrawing_shares = basepool
+		.withdraw_queue
+		.iter()
+		.filter_map(|request| {
+			base_pool::Pallet::<T>::get_nft_attr(basepool.cid, request.nft_id).ok()
+		})
+		.fold(Zero::zero(), |acc: BalanceOf<T>, nft| acc + nft.shares);
+	let mut summary = PoolSummary {
+		pid,
+		pool_type,
+		owner: basepool.owner.clone(),
+		total_shares: basepool.total_shares,
+		total_value: basepool.total_value,
+		share_price: basepool
+			.share_price()
+			.map(|price| BalanceOf::<T>::from_fixed(&price)),
+		free_stake: basepool.get_free_stakes::<T>(),
+		releasing_stake: Zero::zero(),
+		withdrawing_shares,
+		withdraw_queue_len: basepool.withdraw_queue.len() as u32,
+		commission: None,
+		cap: None,
+		workers: 0,
+		cd_workers: 0,
+		invest_pools: vec![],
+	};
+	match proxy {
+		PoolProxy::StakePool(pool) => {
+			summary.releasing_stake = stake_pool_releases::<T>(&pool)
+				.into_iter()
+				.fold(Zero::zero(), |acc: BalanceOf<T>, (_, stake)| acc + stake);
+			summary.commission = pool.payout_commission;
+			summary.cap = pool.cap;
+			summary.workers = pool.workers.len() as u32;
+			summary.cd_workers = pool.cd_workers.len() as u32;
+		}
+		PoolProxy::Vault(vault) => {
+			summary.releasing_stake = vault
+				.invest_pools
+				.iter()
+				.filter_map(|pid| crate::pool_proxy::ensure_stake_pool::<T>(*pid).ok())
+				.fold(Zero::zero(), |acc: BalanceOf<T>, pool| {
+					acc + queued_value::<T>(&pool.basepool, &vault.basepool.pool_account_id)
+				});
+			summary.commission = vault.commission;
+			summary.invest_pools = vault.invest_pools;
+		}
+	}
+	Some(summary)
+}
+
+/// Returns the stake the account holds in each pool
+pub fn account_stakes<T>(account: &T::AccountId) -> Vec<StakeValue<BalanceOf<T>>>
+where
+	BalanceOf<T>: AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+	T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+	T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+	T: vault::Config,
+{
+	let mut nfts_by_pid: BTreeMap<u64, (CollectionId, Vec<NftId>)> = BTreeMap::new();
+	for (cid, nft_id) in pallet_uniques::Pallet::<T>::owned(account) {
+		if let Some(pid) = base_pool::pallet::PoolCollections::<T>::get(cid) {
+			nfts_by_pid
+				.entry(pid)
+				.or_insert_with(|| (cid, vec![]))
+				.1
+				.push(nft_id);
+		}
+	}
+	nfts_by_pid
+		.into_iter()
+		.filter_map(|(pid, (cid, nft_ids))| {
+			let proxy = base_pool::Pallet::<T>::pool_collection(pid)?;
+			let basepool = match &proxy {
+				PoolProxy::StakePool(pool) => &pool.basepool,
+				PoolProxy::Vault(vault) => &vault.basepool,
+			};
+			let shares = nft_ids
+				.iter()
+				.filter_map(|nft_id| base_pool::Pallet::<T>::get_nft_attr(cid, *nft_id).ok())
+				.fold(Zero::zero(), |acc: BalanceOf<T>, nft| acc + nft.shares);
+			let value = basepool
+				.share_price()
+				.map(|price| bmul(shares, &price))
+				.unwrap_or_else(Zero::zero);
+			Some(StakeValue {
+				pid,
+				nft_ids,
+				shares,
+				value,
+			})
+		})
+		.collect()
+}
+
+/// Returns the withdraw requests of the account waiting in the withdraw queues of all the pools
+pub fn pending_withdrawals<T>(account: &T::AccountId) -> Vec<PendingWithdrawal<BalanceOf<T>>>
+where
+	BalanceOf<T>: AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+	T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+	T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+	T: vault::Config,
+{
+	let now = now_sec::<T>();
+	base_pool::pallet::Pools::<T>::iter()
+		.flat_map(|(pid, proxy)| {
+			let basepool = match &proxy {
+				PoolProxy::StakePool(pool) => &pool.basepool,
+				PoolProxy::Vault(vault) => &vault.basepool,
+			};
+			if !basepool
+				.withdraw_queue
+				.iter()
+				.any(|request| &request.user == account)
+			{
+				return vec![];
+			}
+			let estimates = match &proxy {
+				PoolProxy::StakePool(pool) => estimate_stake_pool::<T>(pool, now),
+				PoolProxy::Vault(vault) => estimate_vault::<T>(vault, now),
+			};
+			estimates
+				.into_iter()
+				.filter(|estimate| &estimate.request.user == account)
+				.map(|estimate| PendingWithdrawal {
+					pid,
+					nft_id: estimate.request.nft_id,
+					shares: estimate.shares,
+					value: estimate.value,
+					start_time: estimate.request.start_time,
+					estimated_release_at: estimate.release_at,
+				})
+				.collect()
+		})
+		.collect()
+}
+
+/// Returns the rewards of the pool owner
+pub fn owner_rewards<T>(pid: u64) -> Option<OwnerRewards<BalanceOf<T>>>
+where
+	BalanceOf<T>: AtLeast32BitUnsigned + Copy + FixedPointConvert + Display,
+	T: pallet_uniques::Config<CollectionId = CollectionId, ItemId = NftId>,
+	T: pallet_assets::Config<AssetId = u32, Balance = BalanceOf<T>>,
+	T: vault::Config,
+{
+	let rewards = match base_pool::Pallet::<T>::pool_collection(pid)? {
+		PoolProxy::StakePool(pool) => OwnerRewards {
+			pid,
+			claimable: pool.get_owner_stakes::<T>(),
+			pending: Zero::zero(),
+		},
+		PoolProxy::Vault(vault) => {
+			let Some(price) = vault.basepool.share_price() else {
+				return Some(OwnerRewards {
+					pid,
+					claimable: Zero::zero(),
+					pending: Zero::zero(),
+				});
+			};
+			// The same as `maybe_gain_owner_shares`
+			let current_price = BalanceOf::<T>::from_fixed(&price);
+			let checkpoint = vault.last_share_price_checkpoint;
+			let pending = if checkpoint.is_zero() || current_price <= checkpoint {
+				Zero::zero()
+			} else {
+				let delta_price =
+					vault.commission.unwrap_or_default() * (current_price - checkpoint);
+				let new_price = (current_price - delta_price).to_fixed();
+				let adjust_shares = bdiv(vault.basepool.total_value, &new_price)
+					.saturating_sub(vault.basepool.total_shares);
+				bmul(adjust_shares, &new_price)
+			};
+			OwnerRewards {
+				pid,
+				claimable: bmul(vault.owner_shares, &price),
+				pending,
+			}
+		}
+	};
+	Some(rewards)
+}
@@ -6,6 +6,8 @@
 
 #[cfg(target_arch = "wasm32")]
 extern crate webpki_wasm as webpki;
+#[cfg(target_arch = "wasm32")]
+extern crate ring_wasm as ring;
 
 #[cfg(not(feature = "std"))]
 extern crate alloc;
@@ -20,6 +22,7 @@ pub mod compute;
 pub mod mq;
 pub mod phat;
 pub mod puppets;
+pub mod randomness;
 pub mod registry;
 pub mod stake_pool;
 
@@ -48,6 +51,7 @@ pub use compute::wrapped_balances as pallet_wrapped_balances;
 pub use mq as pallet_mq;
 pub use phat as pallet_phat;
 pub use phat_tokenomic as pallet_phat_tokenomic;
+pub use randomness as pallet_randomness;
 pub use registry as pallet_registry;
 pub use stake_pool as pallet_stake_pool;
 pub mod phat_tokenomic;
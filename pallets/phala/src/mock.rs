@@ -1,5 +1,5 @@
 use crate::{
-	base_pool, computation, mq, registry, stake_pool, stake_pool_v2,
+	base_pool, computation, mq, randomness, registry, stake_pool, stake_pool_v2,
 	utils::attestation_legacy::{
 		Attestation, AttestationValidator, Error as AttestationError, IasFields,
 	},
@@ -47,6 +47,7 @@ frame_support::construct_runtime!(
 		// Pallets to test
 		PhalaMq: mq::{Pallet, Call},
 		PhalaRegistry: registry::{Pallet, Event<T>, Storage, Config<T>},
+		PhalaRandomness: randomness::{Pallet, Event<T>, Storage},
 		PhalaComputation: computation::{Pallet, Event<T>, Storage, Config},
 		PhalaStakePoolv2: stake_pool_v2::{Pallet, Event<T>},
 		PhalaVault: vault::{Pallet, Event<T>},
@@ -74,6 +75,7 @@ parameter_types! {
 	pub const MinInitP: u32 = 1;
 	pub const MaxPoolWorkers: u32 = 10;
 	pub const NoneAttestationEnabled: bool = true;
+	pub const MockAttestationEnabled: bool = true;
 	pub const VerifyPRuntime: bool = false;
 	pub const VerifyRelaychainGenesisBlockHash: bool = true;
 	pub const CheckWorkerRegisterTime: bool = true;
@@ -176,12 +178,19 @@ impl registry::Config for Test {
 	type LegacyAttestationValidator = MockValidator;
 	type UnixTime = Timestamp;
 	type NoneAttestationEnabled = NoneAttestationEnabled;
+	type MockAttestationEnabled = MockAttestationEnabled;
 	type VerifyPRuntime = VerifyPRuntime;
 	type VerifyRelaychainGenesisBlockHash = VerifyRelaychainGenesisBlockHash;
 	type GovernanceOrigin = EnsureRoot<Self::AccountId>;
 	type ParachainId = ConstU32<0>;
 }
 
+impl randomness::Config for Test {
+	type RuntimeEvent = RuntimeEvent;
+	type MaxBeaconHistory = ConstU32<4>;
+	type BeaconInterval = ConstU32<5>;
+}
+
 parameter_types! {
 	pub const CollectionDeposit: Balance = 0; // 1 UNIT deposit to create collection
 	pub const ItemDeposit: Balance = 0; // 1/100 UNIT deposit to create item
@@ -16,8 +16,9 @@ pub mod pallet {
 	use sp_core::crypto::UncheckedFrom;
 	use sp_core::H256;
 	use sp_runtime::{
-		traits::{UniqueSaturatedInto, Zero},
-		AccountId32,
+		helpers_128bit::multiply_by_rational_with_rounding,
+		traits::{Saturating, UniqueSaturatedInto, Zero},
+		AccountId32, Rounding,
 	};
 	use sp_std::prelude::*;
 
@@ -33,19 +34,51 @@ pub mod pallet {
 				WorkerClusterReport,
 			},
 			ClusterInfo, ClusterPermission, CodeIndex, ContractClusterId, ContractId, ContractInfo,
+			InkCommand,
 		},
-		messaging::{bind_topic, DecodedMessage, MessageOrigin},
+		messaging::{bind_topic, CommandPayload, DecodedMessage, MessageOrigin},
 		ClusterPublicKey, ContractPublicKey, WorkerIdentity, WorkerPublicKey,
 	};
 
 	type BalanceOf<T> =
 		<<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+
+	pub trait OnClusterDestroyed {
+		/// Called when a cluster is being destroyed, before its storage is cleared.
+		///
+		/// `contracts` are the contracts deployed in the cluster.
+		fn on_cluster_destroyed(_cluster: ContractClusterId, _contracts: &[ContractId]) {}
+	}
+
+	impl OnClusterDestroyed for () {}
+
 	#[derive(Encode, Decode, Clone, Debug, TypeInfo)]
 	pub struct BasicContractInfo {
 		pub deployer: AccountId32,
 		pub cluster: ContractClusterId,
 	}
 
+	/// A code change of a contract recorded on-chain.
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, TypeInfo)]
+	pub struct CodeHistoryEntry<BlockNumber> {
+		pub code_hash: H256,
+		/// The block since which the code is in use.
+		pub since: BlockNumber,
+		/// Whether the change is a rollback to the previous code.
+		pub rollback: bool,
+	}
+
+	/// A contract upgrade waiting to be applied.
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, TypeInfo)]
+	pub struct ScheduledUpgrade<BlockNumber> {
+		pub code_hash: H256,
+		/// The block at which the upgrade is applied.
+		pub at: BlockNumber,
+		/// The ink message sent to the contract to switch the code and migrate the storage.
+		pub migration: Vec<u8>,
+		pub gas_limit: u64,
+	}
+
 	bind_topic!(ClusterRegistryEvent, b"^phala/registry/cluster");
 	#[derive(Encode, Decode, Clone, Debug)]
 	pub enum ClusterRegistryEvent {
@@ -71,6 +104,9 @@ pub mod pallet {
 		type InkCodeSizeLimit: Get<u32>;
 		type SidevmCodeSizeLimit: Get<u32>;
 		type Currency: Currency<Self::AccountId>;
+		type OnClusterDestroyed: OnClusterDestroyed;
+		/// The number of blocks after a contract upgrade during which it can be rolled back.
+		type CodeRollbackWindow: Get<BlockNumberFor<Self>>;
 	}
 
 	const STORAGE_VERSION: StorageVersion = StorageVersion::new(7);
@@ -117,6 +153,101 @@ pub mod pallet {
 	#[pallet::storage]
 	pub type NextPinkSystemCode<T> = StorageValue<_, Vec<u8>, OptionQuery>;
 
+	/// Accounts granted the deployer role in a cluster.
+	#[pallet::storage]
+	pub type ClusterDeployers<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		ContractClusterId,
+		Blake2_128Concat,
+		T::AccountId,
+		(),
+		OptionQuery,
+	>;
+
+	/// The workers which have deployed the cluster or loaded its state from another worker.
+	#[pallet::storage]
+	pub type ClusterReadyWorkers<T> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		ContractClusterId,
+		Twox64Concat,
+		WorkerPublicKey,
+		(),
+		OptionQuery,
+	>;
+
+	/// The minimum number of ready workers a cluster must keep when removing workers.
+	#[pallet::storage]
+	pub type ClusterMinReplicas<T> =
+		StorageMap<_, Twox64Concat, ContractClusterId, u32, ValueQuery>;
+
+	/// The clusters being drained, with the block number when the draining started.
+	///
+	/// A draining cluster doesn't accept new contracts, resources, deposits or workers, and can
+	/// then be destroyed.
+	#[pallet::storage]
+	pub type DrainingClusters<T: Config> =
+		StorageMap<_, Twox64Concat, ContractClusterId, BlockNumberFor<T>>;
+
+	/// The clusters being destroyed, waiting for a worker to report the in-cluster balances of
+	/// the accounts to refund, with the block number when the destroying started.
+	#[pallet::storage]
+	pub type SettlingClusters<T: Config> =
+		StorageMap<_, Twox64Concat, ContractClusterId, BlockNumberFor<T>>;
+
+	/// The amount transferred into a cluster to each in-cluster account.
+	///
+	/// These accounts are refunded by their in-cluster balances when the cluster is destroyed.
+	/// The accounts funded before the deposits were recorded can be added by
+	/// `register_cluster_refund`.
+	#[pallet::storage]
+	pub type ClusterDeposits<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		ContractClusterId,
+		Blake2_128Concat,
+		T::AccountId,
+		BalanceOf<T>,
+		ValueQuery,
+	>;
+
+	/// The code history of the contracts, in the order of the changes.
+	///
+	/// Only the changes confirmed by the workers are recorded, and only the last
+	/// `MAX_CODE_HISTORY_LEN` of them are kept.
+	#[pallet::storage]
+	pub type ContractCodeHistory<T: Config> = StorageMap<
+		_,
+		Twox64Concat,
+		ContractId,
+		Vec<CodeHistoryEntry<BlockNumberFor<T>>>,
+		ValueQuery,
+	>;
+
+	/// The code changes sent to the contracts and waiting for the workers to report the actual
+	/// code hash.
+	#[pallet::storage]
+	pub type PendingCodeChanges<T: Config> =
+		StorageMap<_, Twox64Concat, ContractId, CodeHistoryEntry<BlockNumberFor<T>>>;
+
+	/// The pending upgrade of the contracts.
+	#[pallet::storage]
+	pub type ScheduledUpgrades<T: Config> =
+		StorageMap<_, Twox64Concat, ContractId, ScheduledUpgrade<BlockNumberFor<T>>>;
+
+	/// The contracts to upgrade at a given block, at most `MAX_UPGRADES_PER_BLOCK`.
+	#[pallet::storage]
+	pub type UpgradesAt<T: Config> =
+		StorageMap<_, Twox64Concat, BlockNumberFor<T>, Vec<ContractId>, ValueQuery>;
+
+	/// The code hashes allowed to be instantiated in a cluster.
+	///
+	/// No restriction is applied if there is no entry for the cluster.
+	#[pallet::storage]
+	pub type ClusterCodeAllowList<T> =
+		StorageMap<_, Twox64Concat, ContractClusterId, Vec<H256>, OptionQuery>;
+
 	#[pallet::event]
 	#[pallet::generate_deposit(pub(super) fn deposit_event)]
 	pub enum Event<T: Config> {
@@ -168,6 +299,70 @@ pub mod pallet {
 			worker: WorkerPublicKey,
 			cluster: ContractClusterId,
 		},
+		ClusterPermissionUpdated {
+			cluster: ContractClusterId,
+			permission: ClusterPermission<T::AccountId>,
+		},
+		ClusterDeployerAdded {
+			cluster: ContractClusterId,
+			deployer: T::AccountId,
+		},
+		ClusterDeployerRemoved {
+			cluster: ContractClusterId,
+			deployer: T::AccountId,
+		},
+		ClusterCodeAllowListUpdated {
+			cluster: ContractClusterId,
+			code_hashes: Option<Vec<H256>>,
+		},
+		ClusterStateSynced {
+			cluster: ContractClusterId,
+			worker: WorkerPublicKey,
+		},
+		ClusterMinReplicasUpdated {
+			cluster: ContractClusterId,
+			min_replicas: u32,
+		},
+		ClusterDraining {
+			cluster: ContractClusterId,
+		},
+		ClusterDepositRefunded {
+			cluster: ContractClusterId,
+			account: T::AccountId,
+			amount: BalanceOf<T>,
+		},
+		ClusterSettling {
+			cluster: ContractClusterId,
+		},
+		ContractUpgradeScheduled {
+			contract: ContractId,
+			code_hash: H256,
+			at: BlockNumberFor<T>,
+		},
+		ContractUpgradeCancelled {
+			contract: ContractId,
+		},
+		ContractUpgraded {
+			contract: ContractId,
+			code_hash: H256,
+		},
+		ContractRolledBack {
+			contract: ContractId,
+			code_hash: H256,
+		},
+		/// The workers have confirmed the code change of the contract.
+		ContractCodeChanged {
+			contract: ContractId,
+			code_hash: H256,
+			rollback: bool,
+		},
+		/// The code of the contract reported by the workers is not the requested one, e.g. the
+		/// migration message failed.
+		ContractCodeChangeFailed {
+			contract: ContractId,
+			expected: H256,
+			actual: Option<H256>,
+		},
 	}
 
 	#[pallet::error]
@@ -185,17 +380,66 @@ pub mod pallet {
 		NoPinkSystemCode,
 		ContractNotFound,
 		WorkerIsBusy,
+		DeployerNotFound,
+		CodeNotAllowed,
+		PermissionListTooLong,
+		ClusterIsDraining,
+		ClusterNotDraining,
+		NotEnoughReplicas,
+		ContractPermissionDenied,
+		InvalidUpgradeBlock,
+		UpgradeAlreadyScheduled,
+		NoScheduledUpgrade,
+		NothingToRollback,
+		RollbackWindowExpired,
+		ClusterIsSettling,
+		ClusterNotSettling,
+		TooManyUpgradesAtBlock,
+		CodeChangePending,
 	}
 
+	/// The max number of entries in the account lists of a `ClusterPermission` and in the code
+	/// allow-list of a cluster.
+	pub const MAX_PERMISSION_LIST_LEN: usize = 256;
+
+	/// The max number of contract upgrades scheduled at the same block.
+	pub const MAX_UPGRADES_PER_BLOCK: usize = 16;
+
+	/// The max number of entries kept in the code history of a contract.
+	pub const MAX_CODE_HISTORY_LEN: usize = 16;
+
 	type CodeHash<T> = <T as frame_system::Config>::Hash;
 
 	fn check_cluster_permission<T: Config>(
 		deployer: &T::AccountId,
+		cluster_id: ContractClusterId,
 		cluster: &ClusterInfo<T::AccountId>,
 	) -> bool {
+		let is_deployer = || ClusterDeployers::<T>::contains_key(cluster_id, deployer);
 		match &cluster.permission {
 			ClusterPermission::Public => true,
 			ClusterPermission::OnlyOwner(owner) => deployer == owner,
+			ClusterPermission::AllowList(accounts) => {
+				deployer == &cluster.owner || accounts.contains(deployer) || is_deployer()
+			}
+			ClusterPermission::DenyList(accounts) => !accounts.contains(deployer),
+			ClusterPermission::Governance => is_deployer(),
+		}
+	}
+
+	fn check_code_permission<T: Config>(cluster_id: ContractClusterId, code_hash: &[u8]) -> bool {
+		let Some(allowed) = ClusterCodeAllowList::<T>::get(cluster_id) else {
+			return true;
+		};
+		allowed.iter().any(|hash| hash.as_bytes() == code_hash)
+	}
+
+	fn check_permission_list_len<T: Config>(permission: &ClusterPermission<T::AccountId>) -> bool {
+		match permission {
+			ClusterPermission::AllowList(accounts) | ClusterPermission::DenyList(accounts) => {
+				accounts.len() <= MAX_PERMISSION_LIST_LEN
+			}
+			_ => true,
 		}
 	}
 
@@ -203,7 +447,7 @@ pub mod pallet {
 	impl<T: Config> Pallet<T>
 	where
 		T: crate::mq::Config + crate::registry::Config,
-		T: frame_system::Config<AccountId = AccountId32>,
+		T: frame_system::Config<AccountId = AccountId32, Hash = H256>,
 	{
 		/// Create a new cluster
 		///
@@ -232,6 +476,10 @@ pub mod pallet {
 			T::GovernanceOrigin::ensure_origin(origin)?;
 
 			ensure!(!deploy_workers.is_empty(), Error::<T>::NoWorkerSpecified);
+			ensure!(
+				check_permission_list_len::<T>(&permission),
+				Error::<T>::PermissionListTooLong
+			);
 			let workers = deploy_workers
 				.iter()
 				.map(|worker| {
@@ -317,9 +565,10 @@ pub mod pallet {
 			let origin: T::AccountId = ensure_signed(origin)?;
 			let cluster_info = Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
 			ensure!(
-				check_cluster_permission::<T>(&origin, &cluster_info),
+				check_cluster_permission::<T>(&origin, cluster_id, &cluster_info),
 				Error::<T>::ClusterPermissionDenied
 			);
+			Self::ensure_not_draining(cluster_id)?;
 
 			let size_limit = match resource_type {
 				ResourceType::InkCode => T::InkCodeSizeLimit::get(),
@@ -350,12 +599,16 @@ pub mod pallet {
 			dest_account: AccountId32,
 		) -> DispatchResult {
 			let user = ensure_signed(origin)?;
+			Self::ensure_not_draining(cluster_id)?;
 			<T as Config>::Currency::transfer(
 				&user,
 				&cluster_account(&cluster_id),
 				amount,
 				ExistenceRequirement::KeepAlive,
 			)?;
+			ClusterDeposits::<T>::mutate(cluster_id, &dest_account, |deposit| {
+				*deposit = deposit.saturating_add(amount)
+			});
 			Self::push_message(ClusterOperation::Deposit {
 				cluster_id,
 				account: dest_account.clone().into_h256(),
@@ -403,9 +656,14 @@ pub mod pallet {
 			let deployer = ensure_signed(origin.clone())?;
 			let cluster_info = Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
 			ensure!(
-				check_cluster_permission::<T>(&deployer, &cluster_info),
+				check_cluster_permission::<T>(&deployer, cluster_id, &cluster_info),
 				Error::<T>::ClusterPermissionDenied
 			);
+			ensure!(
+				check_code_permission::<T>(cluster_id, &code_index.code_hash()),
+				Error::<T>::CodeNotAllowed
+			);
+			Self::ensure_not_draining(cluster_id)?;
 
 			if !deposit.is_zero() {
 				Self::transfer_to_cluster(origin.clone(), deposit, cluster_id, deployer.clone())?;
@@ -430,6 +688,15 @@ pub mod pallet {
 					cluster: contract_info.cluster_id,
 				},
 			);
+			let CodeIndex::WasmCode(code_hash) = &contract_info.code_index;
+			ContractCodeHistory::<T>::append(
+				contract_id,
+				CodeHistoryEntry {
+					code_hash: *code_hash,
+					since: frame_system::Pallet::<T>::block_number(),
+					rollback: false,
+				},
+			);
 
 			Self::push_message(ContractOperation::instantiate_code(
 				contract_info.clone(),
@@ -446,14 +713,37 @@ pub mod pallet {
 			Ok(())
 		}
 
+		/// Destroy a drained cluster
+		///
+		/// The workers of the cluster are asked to report the in-cluster balances of the accounts
+		/// in `ClusterDeposits`. Once the first report arrives, the accounts are refunded from the
+		/// cluster account by their reported balances, the stakes on the contracts of the cluster
+		/// are handed to `OnClusterDestroyed` and the storage of the cluster is cleared. A cluster
+		/// no worker reports for can be destroyed by `force_destroy_cluster`.
 		#[pallet::call_index(5)]
 		#[pallet::weight({0})]
 		pub fn cluster_destroy(origin: OriginFor<T>, cluster: ContractClusterId) -> DispatchResult {
 			ensure_root(origin)?;
 
-			Clusters::<T>::take(cluster).ok_or(Error::<T>::ClusterNotFound)?;
-			Self::push_message(ClusterOperation::<T::AccountId>::DestroyCluster(cluster));
-			Self::deposit_event(Event::ClusterDestroyed { cluster });
+			ensure!(
+				Clusters::<T>::contains_key(cluster),
+				Error::<T>::ClusterNotFound
+			);
+			ensure!(
+				DrainingClusters::<T>::contains_key(cluster),
+				Error::<T>::ClusterNotDraining
+			);
+			ensure!(
+				!SettlingClusters::<T>::contains_key(cluster),
+				Error::<T>::ClusterIsSettling
+			);
+			SettlingClusters::<T>::insert(cluster, frame_system::Pallet::<T>::block_number());
+			let accounts = ClusterDeposits::<T>::iter_key_prefix(cluster).collect();
+			Self::push_message(ClusterOperation::SettleCluster {
+				cluster_id: cluster,
+				accounts,
+			});
+			Self::deposit_event(Event::ClusterSettling { cluster });
 			Ok(())
 		}
 
@@ -494,6 +784,7 @@ pub mod pallet {
 				cluster_info.owner == origin,
 				Error::<T>::ClusterPermissionDenied
 			);
+			Self::ensure_not_draining(cluster_id)?;
 			ensure!(
 				registry::Workers::<T>::get(worker_pubkey).is_some(),
 				Error::<T>::WorkerNotFound
@@ -515,6 +806,11 @@ pub mod pallet {
 		}
 
 		/// Remove a new worker from a cluster
+		///
+		/// At least `ClusterMinReplicas` (and no less than one) other workers must have deployed
+		/// the cluster or synced its state, so that the state is handed off before the worker
+		/// leaves. A draining cluster only needs to keep one of them, to settle its balances when
+		/// it is destroyed.
 		#[pallet::call_index(9)]
 		#[pallet::weight({0})]
 		pub fn remove_worker_from_cluster(
@@ -523,7 +819,8 @@ pub mod pallet {
 			cluster_id: ContractClusterId,
 		) -> DispatchResult {
 			let origin = ensure_signed(origin)?;
-			let cluster_info = Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
+			let mut cluster_info =
+				Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
 			ensure!(
 				cluster_info.owner == origin,
 				Error::<T>::ClusterPermissionDenied
@@ -532,11 +829,25 @@ pub mod pallet {
 				ClusterByWorkers::<T>::get(worker_pubkey) == Some(cluster_id),
 				Error::<T>::WorkerNotFound
 			);
+			// A draining cluster still needs a worker to report the balances when it is destroyed.
+			let min_replicas = if DrainingClusters::<T>::contains_key(cluster_id) {
+				1
+			} else {
+				ClusterMinReplicas::<T>::get(cluster_id).max(1) as usize
+			};
+			let replicas = ClusterReadyWorkers::<T>::iter_key_prefix(cluster_id)
+				.filter(|worker| worker != &worker_pubkey)
+				.take(min_replicas)
+				.count();
+			ensure!(replicas >= min_replicas, Error::<T>::NotEnoughReplicas);
 			// Put the worker to cluster 0 to avoid it to be added to some cluster again.
 			ClusterByWorkers::<T>::insert(worker_pubkey, ContractClusterId::from_low_u64_be(0));
 			ClusterWorkers::<T>::mutate(cluster_id, |workers| {
 				workers.retain(|key| key != &worker_pubkey)
 			});
+			ClusterReadyWorkers::<T>::remove(cluster_id, worker_pubkey);
+			cluster_info.workers.retain(|key| key != &worker_pubkey);
+			Clusters::<T>::insert(cluster_id, cluster_info);
 			Self::deposit_event(Event::WorkerRemovedFromCluster {
 				worker: worker_pubkey,
 				cluster: cluster_id,
@@ -547,12 +858,606 @@ pub mod pallet {
 			});
 			Ok(())
 		}
+
+		/// Change who can deploy contracts in a cluster
+		///
+		/// Can be called by the cluster owner, or by `GovernanceOrigin`. Once the cluster is
+		/// switched to `ClusterPermission::Governance`, only `GovernanceOrigin` can manage it.
+		#[pallet::call_index(10)]
+		#[pallet::weight({0})]
+		pub fn set_cluster_permission(
+			origin: OriginFor<T>,
+			cluster_id: ContractClusterId,
+			permission: ClusterPermission<T::AccountId>,
+		) -> DispatchResult {
+			let mut cluster_info =
+				Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
+			Self::ensure_cluster_admin(origin, &cluster_info)?;
+			ensure!(
+				check_permission_list_len::<T>(&permission),
+				Error::<T>::PermissionListTooLong
+			);
+			cluster_info.permission = permission.clone();
+			Clusters::<T>::insert(cluster_id, cluster_info);
+			Self::push_message(ClusterOperation::UpdatePermission {
+				cluster_id,
+				permission: permission.clone(),
+			});
+			Self::deposit_event(Event::ClusterPermissionUpdated {
+				cluster: cluster_id,
+				permission,
+			});
+			Ok(())
+		}
+
+		/// Grant the deployer role of a cluster to an account
+		///
+		/// The role lets the account deploy contracts in a cluster with the `AllowList` or the
+		/// `Governance` permission. It has no effect on the other permissions.
+		#[pallet::call_index(11)]
+		#[pallet::weight({0})]
+		pub fn add_cluster_deployer(
+			origin: OriginFor<T>,
+			cluster_id: ContractClusterId,
+			deployer: T::AccountId,
+		) -> DispatchResult {
+			let cluster_info = Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
+			Self::ensure_cluster_admin(origin, &cluster_info)?;
+			ClusterDeployers::<T>::insert(cluster_id, &deployer, ());
+			Self::push_message(ClusterOperation::SetDeployer {
+				cluster_id,
+				account: deployer.clone(),
+				enabled: true,
+			});
+			Self::deposit_event(Event::ClusterDeployerAdded {
+				cluster: cluster_id,
+				deployer,
+			});
+			Ok(())
+		}
+
+		/// Revoke the deployer role of a cluster from an account
+		#[pallet::call_index(12)]
+		#[pallet::weight({0})]
+		pub fn remove_cluster_deployer(
+			origin: OriginFor<T>,
+			cluster_id: ContractClusterId,
+			deployer: T::AccountId,
+		) -> DispatchResult {
+			let cluster_info = Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
+			Self::ensure_cluster_admin(origin, &cluster_info)?;
+			ensure!(
+				ClusterDeployers::<T>::take(cluster_id, &deployer).is_some(),
+				Error::<T>::DeployerNotFound
+			);
+			Self::push_message(ClusterOperation::SetDeployer {
+				cluster_id,
+				account: deployer.clone(),
+				enabled: false,
+			});
+			Self::deposit_event(Event::ClusterDeployerRemoved {
+				cluster: cluster_id,
+				deployer,
+			});
+			Ok(())
+		}
+
+		/// Restrict the code hashes that can be instantiated in a cluster
+		///
+		/// Passing `None` removes the restriction.
+		#[pallet::call_index(13)]
+		#[pallet::weight({0})]
+		pub fn set_cluster_code_allowlist(
+			origin: OriginFor<T>,
+			cluster_id: ContractClusterId,
+			code_hashes: Option<Vec<H256>>,
+		) -> DispatchResult {
+			let cluster_info = Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
+			Self::ensure_cluster_admin(origin, &cluster_info)?;
+			if let Some(code_hashes) = &code_hashes {
+				ensure!(
+					code_hashes.len() <= MAX_PERMISSION_LIST_LEN,
+					Error::<T>::PermissionListTooLong
+				);
+			}
+			ClusterCodeAllowList::<T>::set(cluster_id, code_hashes.clone());
+			Self::push_message(ClusterOperation::<T::AccountId>::SetCodeAllowList {
+				cluster_id,
+				code_hashes: code_hashes.clone(),
+			});
+			Self::deposit_event(Event::ClusterCodeAllowListUpdated {
+				cluster: cluster_id,
+				code_hashes,
+			});
+			Ok(())
+		}
+
+		/// Set the minimum number of ready workers the cluster must keep when removing workers
+		#[pallet::call_index(14)]
+		#[pallet::weight({0})]
+		pub fn set_cluster_min_replicas(
+			origin: OriginFor<T>,
+			cluster_id: ContractClusterId,
+			min_replicas: u32,
+		) -> DispatchResult {
+			let cluster_info = Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
+			Self::ensure_cluster_admin(origin, &cluster_info)?;
+			ClusterMinReplicas::<T>::insert(cluster_id, min_replicas);
+			Self::deposit_event(Event::ClusterMinReplicasUpdated {
+				cluster: cluster_id,
+				min_replicas,
+			});
+			Ok(())
+		}
+
+		/// Start draining a cluster so that it can be destroyed later
+		///
+		/// A draining cluster stops accepting new contracts, resources, deposits, stakes and
+		/// workers. The draining can not be reverted.
+		#[pallet::call_index(15)]
+		#[pallet::weight({0})]
+		pub fn drain_cluster(
+			origin: OriginFor<T>,
+			cluster_id: ContractClusterId,
+		) -> DispatchResult {
+			let cluster_info = Clusters::<T>::get(cluster_id).ok_or(Error::<T>::ClusterNotFound)?;
+			Self::ensure_cluster_admin(origin, &cluster_info)?;
+			Self::ensure_not_draining(cluster_id)?;
+			DrainingClusters::<T>::insert(cluster_id, frame_system::Pallet::<T>::block_number());
+			Self::deposit_event(Event::ClusterDraining {
+				cluster: cluster_id,
+			});
+			Ok(())
+		}
+
+		/// Schedule an upgrade of a contract at block `at`
+		///
+		/// At the given block, `migration` is sent to the contract as an ink message from the
+		/// deployer. It is expected to switch the contract to `code_hash` by `set_code_hash` and
+		/// to migrate the storage. Only one upgrade can be scheduled for a contract at a time.
+		#[pallet::call_index(16)]
+		#[pallet::weight({0})]
+		pub fn schedule_contract_upgrade(
+			origin: OriginFor<T>,
+			contract_id: ContractId,
+			code_hash: H256,
+			at: BlockNumberFor<T>,
+			migration: Vec<u8>,
+			gas_limit: u64,
+		) -> DispatchResult {
+			let contract_info = Self::ensure_contract_deployer(origin, &contract_id)?;
+			let cluster_id = contract_info.cluster;
+			Self::ensure_not_draining(cluster_id)?;
+			ensure!(
+				check_code_permission::<T>(cluster_id, code_hash.as_bytes()),
+				Error::<T>::CodeNotAllowed
+			);
+			ensure!(
+				at > frame_system::Pallet::<T>::block_number(),
+				Error::<T>::InvalidUpgradeBlock
+			);
+			ensure!(
+				!ScheduledUpgrades::<T>::contains_key(contract_id),
+				Error::<T>::UpgradeAlreadyScheduled
+			);
+			ensure!(
+				UpgradesAt::<T>::decode_len(at).unwrap_or(0) < MAX_UPGRADES_PER_BLOCK,
+				Error::<T>::TooManyUpgradesAtBlock
+			);
+			ensure!(
+				migration.len() <= T::InkCodeSizeLimit::get() as usize,
+				Error::<T>::PayloadTooLarge
+			);
+			ScheduledUpgrades::<T>::insert(
+				contract_id,
+				ScheduledUpgrade {
+					code_hash,
+					at,
+					migration,
+					gas_limit,
+				},
+			);
+			UpgradesAt::<T>::append(at, contract_id);
+			Self::deposit_event(Event::ContractUpgradeScheduled {
+				contract: contract_id,
+				code_hash,
+				at,
+			});
+			Ok(())
+		}
+
+		/// Cancel the scheduled upgrade of a contract
+		#[pallet::call_index(17)]
+		#[pallet::weight({0})]
+		pub fn cancel_contract_upgrade(
+			origin: OriginFor<T>,
+			contract_id: ContractId,
+		) -> DispatchResult {
+			Self::ensure_contract_deployer(origin, &contract_id)?;
+			let upgrade =
+				ScheduledUpgrades::<T>::take(contract_id).ok_or(Error::<T>::NoScheduledUpgrade)?;
+			UpgradesAt::<T>::mutate(upgrade.at, |contracts| {
+				contracts.retain(|id| id != &contract_id)
+			});
+			Self::deposit_event(Event::ContractUpgradeCancelled {
+				contract: contract_id,
+			});
+			Ok(())
+		}
+
+		/// Roll back the last upgrade of a contract to the previous code
+		///
+		/// It is only allowed within `CodeRollbackWindow` blocks after the upgrade. `message` is
+		/// sent to the contract as an ink message from the deployer to switch the code back.
+		#[pallet::call_index(18)]
+		#[pallet::weight({0})]
+		pub fn rollback_contract_upgrade(
+			origin: OriginFor<T>,
+			contract_id: ContractId,
+			message: Vec<u8>,
+			gas_limit: u64,
+		) -> DispatchResult {
+			let contract_info = Self::ensure_contract_deployer(origin, &contract_id)?;
+			Self::ensure_not_draining(contract_info.cluster)?;
+			ensure!(
+				message.len() <= T::InkCodeSizeLimit::get() as usize,
+				Error::<T>::PayloadTooLarge
+			);
+			ensure!(
+				!PendingCodeChanges::<T>::contains_key(contract_id),
+				Error::<T>::CodeChangePending
+			);
+			let history = ContractCodeHistory::<T>::get(contract_id);
+			let (last, previous) = match history.as_slice() {
+				[.., previous, last] if !last.rollback => (last, previous),
+				_ => return Err(Error::<T>::NothingToRollback.into()),
+			};
+			let now = frame_system::Pallet::<T>::block_number();
+			ensure!(
+				now <= last.since.saturating_add(T::CodeRollbackWindow::get()),
+				Error::<T>::RollbackWindowExpired
+			);
+			let code_hash = previous.code_hash;
+			Self::change_contract_code(
+				&contract_info,
+				contract_id,
+				code_hash,
+				message,
+				gas_limit,
+				true,
+			);
+			Self::deposit_event(Event::ContractRolledBack {
+				contract: contract_id,
+				code_hash,
+			});
+			Ok(())
+		}
+
+		/// Register the caller's in-cluster account to be refunded when the cluster is destroyed
+		///
+		/// The accounts funded by `transfer_to_cluster` are registered automatically, this is for
+		/// the accounts funded before the deposits were recorded.
+		#[pallet::call_index(19)]
+		#[pallet::weight({0})]
+		pub fn register_cluster_refund(
+			origin: OriginFor<T>,
+			cluster_id: ContractClusterId,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(
+				Clusters::<T>::contains_key(cluster_id),
+				Error::<T>::ClusterNotFound
+			);
+			ensure!(
+				!SettlingClusters::<T>::contains_key(cluster_id),
+				Error::<T>::ClusterIsSettling
+			);
+			if !ClusterDeposits::<T>::contains_key(cluster_id, &who) {
+				ClusterDeposits::<T>::insert(cluster_id, &who, BalanceOf::<T>::zero());
+			}
+			Ok(())
+		}
+
+		/// Destroy a cluster stuck in settlement without waiting for the workers
+		///
+		/// For the clusters left without any worker to report the in-cluster balances. The
+		/// depositors are not refunded, the whole balance of the cluster account goes to the
+		/// cluster owner.
+		#[pallet::call_index(20)]
+		#[pallet::weight({0})]
+		pub fn force_destroy_cluster(
+			origin: OriginFor<T>,
+			cluster: ContractClusterId,
+		) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(
+				Clusters::<T>::contains_key(cluster),
+				Error::<T>::ClusterNotFound
+			);
+			ensure!(
+				SettlingClusters::<T>::contains_key(cluster),
+				Error::<T>::ClusterNotSettling
+			);
+			Self::settle_cluster(cluster, vec![]);
+			Ok(())
+		}
 	}
 
 	impl<T: Config> Pallet<T>
 	where
 		T: crate::mq::Config + crate::registry::Config,
 	{
+		/// Ensures the origin can manage the permissions of the cluster.
+		///
+		/// `GovernanceOrigin` can always manage a cluster, while the owner can only manage it if
+		/// the cluster is not in the governance mode.
+		fn ensure_cluster_admin(
+			origin: OriginFor<T>,
+			cluster_info: &ClusterInfo<T::AccountId>,
+		) -> DispatchResult {
+			let origin = match T::GovernanceOrigin::try_origin(origin) {
+				Ok(_) => return Ok(()),
+				Err(origin) => origin,
+			};
+			let who = ensure_signed(origin)?;
+			ensure!(
+				cluster_info.permission != ClusterPermission::Governance
+					&& cluster_info.owner == who,
+				Error::<T>::ClusterPermissionDenied
+			);
+			Ok(())
+		}
+
+		fn ensure_contract_deployer(
+			origin: OriginFor<T>,
+			contract_id: &ContractId,
+		) -> Result<BasicContractInfo, DispatchError> {
+			let who = ensure_signed(origin)?;
+			let contract_info =
+				Contracts::<T>::get(contract_id).ok_or(Error::<T>::ContractNotFound)?;
+			ensure!(
+				contract_info.deployer.encode() == who.encode(),
+				Error::<T>::ContractPermissionDenied
+			);
+			Ok(contract_info)
+		}
+
+		/// Sends the message switching the code to the contract.
+		///
+		/// The change is recorded once the workers report the code hash of the contract after
+		/// executing the message.
+		fn change_contract_code(
+			contract_info: &BasicContractInfo,
+			contract_id: ContractId,
+			code_hash: H256,
+			message: Vec<u8>,
+			gas_limit: u64,
+			rollback: bool,
+		) {
+			let command = CommandPayload::Plain(InkCommand::InkMessage {
+				nonce: Default::default(),
+				message,
+				transfer: 0,
+				gas_limit,
+				storage_deposit_limit: None,
+			});
+			PalletMq::<T>::push_message_to(
+				command_topic(contract_id),
+				MessageOrigin::AccountId(contract_info.deployer.clone().into_h256()),
+				command,
+			);
+			PendingCodeChanges::<T>::insert(
+				contract_id,
+				CodeHistoryEntry {
+					code_hash,
+					since: frame_system::Pallet::<T>::block_number(),
+					rollback,
+				},
+			);
+			Self::push_message(ContractOperation::<H256, T::AccountId>::CodeChanged {
+				contract_id,
+				code_hash,
+				rollback,
+			});
+		}
+
+		/// Applies the contract upgrades scheduled at block `now`.
+		///
+		/// An upgrade is postponed to the next block if the previous code change of the contract
+		/// has not been confirmed yet.
+		fn apply_scheduled_upgrades(now: BlockNumberFor<T>) -> Weight {
+			let db = T::DbWeight::get();
+			let contracts = UpgradesAt::<T>::take(now);
+			let mut weight = db.reads_writes(1, 1);
+			let mut postponed = vec![];
+			for contract_id in contracts {
+				weight = weight.saturating_add(db.reads_writes(3, 1));
+				let Some(upgrade) = ScheduledUpgrades::<T>::get(contract_id) else {
+					continue;
+				};
+				let Some(contract_info) = Contracts::<T>::get(contract_id) else {
+					ScheduledUpgrades::<T>::remove(contract_id);
+					continue;
+				};
+				if PendingCodeChanges::<T>::contains_key(contract_id) {
+					postponed.push(contract_id);
+					continue;
+				}
+				ScheduledUpgrades::<T>::remove(contract_id);
+				// Pushing the messages and recording the pending change
+				weight = weight.saturating_add(db.reads_writes(2, 4));
+				Self::change_contract_code(
+					&contract_info,
+					contract_id,
+					upgrade.code_hash,
+					upgrade.migration,
+					upgrade.gas_limit,
+					false,
+				);
+				Self::deposit_event(Event::ContractUpgraded {
+					contract: contract_id,
+					code_hash: upgrade.code_hash,
+				});
+			}
+			if !postponed.is_empty() {
+				let next = now + 1u32.into();
+				weight = weight.saturating_add(db.reads_writes(1, 1 + postponed.len() as u64));
+				for contract_id in postponed {
+					ScheduledUpgrades::<T>::mutate(contract_id, |upgrade| {
+						if let Some(upgrade) = upgrade {
+							upgrade.at = next;
+						}
+					});
+					UpgradesAt::<T>::append(next, contract_id);
+				}
+			}
+			weight
+		}
+
+		/// Records the code change of a contract once a worker has reported the actual code hash.
+		fn confirm_code_change(contract_id: ContractId, actual: Option<H256>) {
+			let Some(change) = PendingCodeChanges::<T>::take(contract_id) else {
+				return;
+			};
+			if actual != Some(change.code_hash) {
+				Self::deposit_event(Event::ContractCodeChangeFailed {
+					contract: contract_id,
+					expected: change.code_hash,
+					actual,
+				});
+				return;
+			}
+			let code_hash = change.code_hash;
+			let rollback = change.rollback;
+			ContractCodeHistory::<T>::mutate(contract_id, |history| {
+				history.push(change);
+				if history.len() > MAX_CODE_HISTORY_LEN {
+					let excess = history.len() - MAX_CODE_HISTORY_LEN;
+					history.drain(..excess);
+				}
+			});
+			Self::deposit_event(Event::ContractCodeChanged {
+				contract: contract_id,
+				code_hash,
+				rollback,
+			});
+		}
+
+		fn ensure_not_draining(cluster_id: ContractClusterId) -> DispatchResult {
+			ensure!(
+				!Self::is_cluster_draining(&cluster_id),
+				Error::<T>::ClusterIsDraining
+			);
+			Ok(())
+		}
+
+		/// Refunds the in-cluster balances reported by a worker of the cluster from the cluster
+		/// account, then destroys the cluster.
+		///
+		/// Only the accounts in `ClusterDeposits` are refunded, in proportion to their balances if
+		/// the cluster account can not cover all of them. The rest, e.g. the balances of the
+		/// contracts or the refunds failed to transfer, goes to the cluster owner.
+		fn settle_cluster(cluster: ContractClusterId, balances: Vec<(AccountId32, u128)>) {
+			let owner = match Clusters::<T>::get(cluster) {
+				Some(info) => info.owner,
+				None => return,
+			};
+			let account = cluster_account(&cluster);
+			let refunds: Vec<(T::AccountId, BalanceOf<T>)> = balances
+				.into_iter()
+				.filter_map(|(account, balance)| {
+					let account = T::AccountId::decode(&mut account.as_ref()).ok()?;
+					ClusterDeposits::<T>::contains_key(cluster, &account)
+						.then(|| (account, balance.unique_saturated_into()))
+				})
+				.collect();
+			let _ = ClusterDeposits::<T>::clear_prefix(cluster, u32::MAX, None);
+			let total = refunds
+				.iter()
+				.fold(BalanceOf::<T>::zero(), |acc, (_, amount)| {
+					acc.saturating_add(*amount)
+				});
+			let available = <T as Config>::Currency::free_balance(&account);
+			for (depositor, amount) in refunds {
+				let refund: BalanceOf<T> = if total <= available {
+					amount
+				} else {
+					multiply_by_rational_with_rounding(
+						amount.unique_saturated_into(),
+						available.unique_saturated_into(),
+						total.unique_saturated_into(),
+						Rounding::Down,
+					)
+					.unwrap_or_default()
+					.unique_saturated_into()
+				};
+				if refund.is_zero() {
+					continue;
+				}
+				let transferred = <T as Config>::Currency::transfer(
+					&account,
+					&depositor,
+					refund,
+					ExistenceRequirement::AllowDeath,
+				);
+				if transferred.is_ok() {
+					Self::deposit_event(Event::ClusterDepositRefunded {
+						cluster,
+						account: depositor,
+						amount: refund,
+					});
+				}
+			}
+			let rest = <T as Config>::Currency::free_balance(&account);
+			if !rest.is_zero()
+				&& <T as Config>::Currency::transfer(
+					&account,
+					&owner,
+					rest,
+					ExistenceRequirement::AllowDeath,
+				)
+				.is_ok()
+			{
+				Self::deposit_event(Event::ClusterDepositRefunded {
+					cluster,
+					account: owner,
+					amount: rest,
+				});
+			}
+			Self::do_destroy_cluster(cluster);
+		}
+
+		/// Clears the storage of a cluster once its accounts are refunded.
+		fn do_destroy_cluster(cluster: ContractClusterId) {
+			let contracts = ClusterContracts::<T>::take(cluster);
+			T::OnClusterDestroyed::on_cluster_destroyed(cluster, &contracts);
+			for contract in contracts {
+				Contracts::<T>::remove(contract);
+				ContractCodeHistory::<T>::remove(contract);
+				PendingCodeChanges::<T>::remove(contract);
+				ScheduledUpgrades::<T>::remove(contract);
+			}
+			// Put the workers to cluster 0 to avoid them to be added to some cluster again.
+			for worker in ClusterWorkers::<T>::take(cluster) {
+				if ClusterByWorkers::<T>::get(worker) == Some(cluster) {
+					ClusterByWorkers::<T>::insert(worker, ContractClusterId::from_low_u64_be(0));
+				}
+			}
+			Clusters::<T>::remove(cluster);
+			let _ = ClusterReadyWorkers::<T>::clear_prefix(cluster, u32::MAX, None);
+			let _ = ClusterDeployers::<T>::clear_prefix(cluster, u32::MAX, None);
+			ClusterCodeAllowList::<T>::remove(cluster);
+			ClusterMinReplicas::<T>::remove(cluster);
+			DrainingClusters::<T>::remove(cluster);
+			SettlingClusters::<T>::remove(cluster);
+			Self::push_message(ClusterOperation::<T::AccountId>::DestroyCluster(cluster));
+			Self::deposit_event(Event::ClusterDestroyed { cluster });
+		}
+
+		pub fn is_cluster_draining(cluster: &ContractClusterId) -> bool {
+			DrainingClusters::<T>::contains_key(cluster)
+		}
+
 		pub fn on_cluster_message_received(
 			message: DecodedMessage<ClusterRegistryEvent>,
 		) -> DispatchResult {
@@ -620,6 +1525,7 @@ pub mod pallet {
 				WorkerClusterReport::ClusterDeployed { id, pubkey } => {
 					// TODO.shelven: scalability concern for large number of workers
 					ClusterWorkers::<T>::append(id, worker_pubkey);
+					ClusterReadyWorkers::<T>::insert(id, worker_pubkey, ());
 					Self::deposit_event(Event::ClusterDeployed {
 						cluster: id,
 						pubkey,
@@ -632,6 +1538,42 @@ pub mod pallet {
 						worker: worker_pubkey,
 					});
 				}
+				WorkerClusterReport::ClusterStateSynced { id } => {
+					ensure!(
+						ClusterByWorkers::<T>::get(worker_pubkey) == Some(id),
+						Error::<T>::WorkerNotFound
+					);
+					ClusterReadyWorkers::<T>::insert(id, worker_pubkey, ());
+					Self::deposit_event(Event::ClusterStateSynced {
+						cluster: id,
+						worker: worker_pubkey,
+					});
+				}
+				WorkerClusterReport::ClusterSettled { id, balances } => {
+					ensure!(
+						ClusterByWorkers::<T>::get(worker_pubkey) == Some(id),
+						Error::<T>::WorkerNotFound
+					);
+					// The workers of the cluster share the same state, the first report wins.
+					ensure!(
+						SettlingClusters::<T>::contains_key(id),
+						Error::<T>::ClusterNotSettling
+					);
+					Self::settle_cluster(id, balances);
+				}
+				WorkerClusterReport::ContractCodeReported {
+					contract_id,
+					code_hash,
+				} => {
+					let contract =
+						Contracts::<T>::get(contract_id).ok_or(Error::<T>::ContractNotFound)?;
+					ensure!(
+						ClusterByWorkers::<T>::get(worker_pubkey) == Some(contract.cluster),
+						Error::<T>::WorkerNotFound
+					);
+					// The workers of the cluster share the same state, the first report wins.
+					Self::confirm_code_change(contract_id, code_hash);
+				}
 			}
 			Ok(())
 		}
@@ -648,10 +1590,14 @@ pub mod pallet {
 	}
 
 	#[pallet::hooks]
-	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
-		fn on_initialize(_now: BlockNumberFor<T>) -> Weight {
+	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T>
+	where
+		T: crate::mq::Config,
+	{
+		fn on_initialize(now: BlockNumberFor<T>) -> Weight {
+			let weight = Self::apply_scheduled_upgrades(now);
 			let Some(next_code) = NextPinkSystemCode::<T>::take() else {
-				return Weight::zero();
+				return weight;
 			};
 			let hash: H256 = crate::hashing::blake2_256(&next_code).into();
 			PinkSystemCodeHash::<T>::put(hash);
@@ -659,7 +1605,7 @@ pub mod pallet {
 				*ver += 1;
 				*code = next_code;
 			});
-			Weight::zero()
+			weight.saturating_add(T::DbWeight::get().reads_writes(1, 3))
 		}
 	}
 
@@ -54,6 +54,10 @@ pub mod pallet {
 	#[pallet::storage]
 	pub type MinStake<T: Config> = StorageValue<_, BalanceOf<T>, ValueQuery>;
 
+	/// The contracts whose cluster has been destroyed, the stakes on them can only be withdrawn.
+	#[pallet::storage]
+	pub type DestroyedContracts<T: Config> = StorageMap<_, Twox64Concat, ContractId, ()>;
+
 	#[pallet::event]
 	#[pallet::generate_deposit(pub(super) fn deposit_event)]
 	pub enum Event<T: Config> {
@@ -73,6 +77,8 @@ pub mod pallet {
 	#[pallet::error]
 	pub enum Error<T> {
 		InvalidAmountOfStake,
+		ClusterIsDraining,
+		ContractDestroyed,
 	}
 
 	#[pallet::call]
@@ -108,7 +114,18 @@ pub mod pallet {
 
 			let mut total = ContractTotalStakes::<T>::get(contract);
 			let orig = ContractUserStakes::<T>::get(&user, contract);
+			let cluster = crate::phat::Pallet::<T>::get_contract_info(&contract).map(|x| x.cluster);
 			if amount > orig {
+				ensure!(
+					!DestroyedContracts::<T>::contains_key(contract),
+					Error::<T>::ContractDestroyed
+				);
+				if let Some(cluster) = &cluster {
+					ensure!(
+						!crate::phat::Pallet::<T>::is_cluster_draining(cluster),
+						Error::<T>::ClusterIsDraining
+					);
+				}
 				let delta = amount - orig;
 				total += delta;
 				<T as Config>::Currency::transfer(&user, &Self::pallet_id(), delta, KeepAlive)?;
@@ -120,8 +137,6 @@ pub mod pallet {
 			ContractUserStakes::<T>::insert(&user, contract, amount);
 			ContractTotalStakes::<T>::insert(contract, total);
 
-			let cluster = crate::phat::Pallet::<T>::get_contract_info(&contract).map(|x| x.cluster);
-
 			Self::deposit_event(Event::ContractDepositChanged {
 				cluster,
 				contract,
@@ -152,6 +167,18 @@ pub mod pallet {
 		}
 	}
 
+	impl<T: Config> crate::phat::OnClusterDestroyed for Pallet<T> {
+		/// Stops accepting stakes on the contracts of the destroyed cluster.
+		///
+		/// The stakes are not refunded here, since the stakes are not indexed by contract. The
+		/// users withdraw them by `adjust_stake` with zero amount.
+		fn on_cluster_destroyed(_cluster: ContractClusterId, contracts: &[ContractId]) {
+			for contract in contracts {
+				DestroyedContracts::<T>::insert(contract, ());
+			}
+		}
+	}
+
 	impl<T: Config + crate::mq::Config> MessageOriginInfo for Pallet<T> {
 		type Config = T;
 	}
@@ -1,6 +1,11 @@
 use super::*;
+use crate::phat;
 use frame_support::{assert_err, assert_ok};
 use mock::{RuntimeOrigin as Origin, Test, DOLLARS};
+use phala_types::{
+	contract::{messaging::WorkerClusterReport, ClusterInfo, ClusterPermission, CodeIndex},
+	messaging::{DecodedMessage, MessageOrigin, Topic},
+};
 
 use sp_core::crypto::AccountId32;
 use sp_core::H256;
@@ -9,6 +14,7 @@ mod mock;
 
 const ALICE: AccountId32 = AccountId32::new([1u8; 32]);
 const BOB: AccountId32 = AccountId32::new([2u8; 32]);
+const CHARLIE: AccountId32 = AccountId32::new([3u8; 32]);
 const CONTRACT: H256 = H256([42u8; 32]);
 
 macro_rules! stake {
@@ -98,3 +104,685 @@ fn can_restake_without_any_changes() {
 		insta::assert_debug_snapshot!(events);
 	});
 }
+
+const CLUSTER: H256 = H256([7u8; 32]);
+
+fn setup_cluster() {
+	phat::Clusters::<Test>::insert(
+		CLUSTER,
+		ClusterInfo {
+			owner: ALICE,
+			permission: ClusterPermission::Public,
+			workers: vec![],
+			system_contract: Default::default(),
+			gas_price: 0,
+			deposit_per_item: 0,
+			deposit_per_byte: 0,
+		},
+	);
+	phat::ClusterContracts::<Test>::insert(CLUSTER, vec![CONTRACT]);
+	phat::Contracts::<Test>::insert(
+		CONTRACT,
+		phat::BasicContractInfo {
+			deployer: ALICE,
+			cluster: CLUSTER,
+		},
+	);
+}
+
+#[test]
+fn destroying_cluster_refunds_stakes_and_deposits() {
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		assert_ok!(stake!(ALICE, DOLLARS));
+		assert_ok!(phat::Pallet::<Test>::transfer_to_cluster(
+			Origin::signed(BOB),
+			10 * DOLLARS,
+			CLUSTER,
+			BOB,
+		));
+		assert_eq!(balance_of_user(&BOB), 90 * DOLLARS);
+		assert_err!(
+			phat::Pallet::<Test>::cluster_destroy(Origin::root(), CLUSTER),
+			phat::Error::<Test>::ClusterNotDraining
+		);
+
+		assert_ok!(phat::Pallet::<Test>::drain_cluster(
+			Origin::signed(ALICE),
+			CLUSTER
+		));
+		assert_err!(stake!(BOB, DOLLARS), Error::<Test>::ClusterIsDraining);
+		assert_err!(
+			phat::Pallet::<Test>::transfer_to_cluster(Origin::signed(BOB), DOLLARS, CLUSTER, BOB),
+			phat::Error::<Test>::ClusterIsDraining
+		);
+
+		assert_ok!(phat::Pallet::<Test>::cluster_destroy(
+			Origin::root(),
+			CLUSTER
+		));
+		assert_err!(
+			phat::Pallet::<Test>::cluster_destroy(Origin::root(), CLUSTER),
+			phat::Error::<Test>::ClusterIsSettling
+		);
+		// The cluster is kept until a worker reports the in-cluster balances
+		assert!(phat::Clusters::<Test>::get(CLUSTER).is_some());
+
+		// Only the workers of the cluster can report, and only the registered accounts are
+		// refunded. Bob has spent 6 DOLLARS in the cluster.
+		let worker = sp_core::sr25519::Public::from_raw([0u8; 32]);
+		let settled = |worker| {
+			phat::Pallet::<Test>::on_worker_cluster_message_received(DecodedMessage {
+				sender: MessageOrigin::Worker(worker),
+				destination: Topic::new(*b"phala/cluster/report"),
+				payload: WorkerClusterReport::ClusterSettled {
+					id: CLUSTER,
+					balances: vec![(BOB, 4 * DOLLARS), (ALICE, 6 * DOLLARS)],
+				},
+			})
+		};
+		assert_err!(settled(worker), phat::Error::<Test>::WorkerNotFound);
+		phat::ClusterByWorkers::<Test>::insert(worker, CLUSTER);
+		assert_ok!(settled(worker));
+		assert_eq!(balance_of_user(&BOB), 94 * DOLLARS);
+		// The rest goes to the owner
+		assert_eq!(balance_of_user(&ALICE), 105 * DOLLARS);
+		assert!(phat::Clusters::<Test>::get(CLUSTER).is_none());
+		assert!(phat::Contracts::<Test>::get(CONTRACT).is_none());
+		assert!(!phat::Pallet::<Test>::is_cluster_draining(&CLUSTER));
+		assert_err!(settled(worker), phat::Error::<Test>::ClusterNotSettling);
+
+		// The stakes are withdrawn by the users
+		assert_err!(stake!(BOB, DOLLARS), Error::<Test>::ContractDestroyed);
+		assert_ok!(stake!(ALICE, 0));
+		assert_eq!(stake_of_user(&ALICE), 0);
+		assert_eq!(stake_of_contract(), 0);
+		assert_eq!(balance_of_user(&ALICE), 106 * DOLLARS);
+	});
+}
+
+#[test]
+fn unrecorded_depositors_can_register_refund() {
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		// Deposited before the deposits were recorded
+		let cluster_account = phat::cluster_account(&CLUSTER);
+		assert_ok!(mock::Balances::force_set_balance(
+			Origin::root(),
+			cluster_account,
+			10 * DOLLARS
+		));
+		assert_ok!(phat::Pallet::<Test>::register_cluster_refund(
+			Origin::signed(BOB),
+			CLUSTER
+		));
+		assert_ok!(phat::Pallet::<Test>::drain_cluster(
+			Origin::signed(ALICE),
+			CLUSTER
+		));
+		assert_ok!(phat::Pallet::<Test>::cluster_destroy(
+			Origin::root(),
+			CLUSTER
+		));
+		assert_err!(
+			phat::Pallet::<Test>::register_cluster_refund(Origin::signed(ALICE), CLUSTER),
+			phat::Error::<Test>::ClusterIsSettling
+		);
+		let worker = sp_core::sr25519::Public::from_raw([0u8; 32]);
+		phat::ClusterByWorkers::<Test>::insert(worker, CLUSTER);
+		// The cluster account can not cover the reported balances, refund in proportion
+		assert_ok!(phat::Pallet::<Test>::on_worker_cluster_message_received(
+			DecodedMessage {
+				sender: MessageOrigin::Worker(worker),
+				destination: Topic::new(*b"phala/cluster/report"),
+				payload: WorkerClusterReport::ClusterSettled {
+					id: CLUSTER,
+					balances: vec![(BOB, 20 * DOLLARS)],
+				},
+			}
+		));
+		assert_eq!(balance_of_user(&BOB), 110 * DOLLARS);
+		assert!(phat::Clusters::<Test>::get(CLUSTER).is_none());
+	});
+}
+
+#[test]
+fn removing_worker_requires_ready_replicas() {
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		let worker = sp_core::sr25519::Public::from_raw([0u8; 32]);
+		let replica = sp_core::sr25519::Public::from_raw([1u8; 32]);
+		phat::ClusterByWorkers::<Test>::insert(worker, CLUSTER);
+		phat::ClusterReadyWorkers::<Test>::insert(CLUSTER, worker, ());
+
+		assert_err!(
+			phat::Pallet::<Test>::remove_worker_from_cluster(
+				Origin::signed(ALICE),
+				worker,
+				CLUSTER
+			),
+			phat::Error::<Test>::NotEnoughReplicas
+		);
+		phat::ClusterReadyWorkers::<Test>::insert(CLUSTER, replica, ());
+		assert_ok!(phat::Pallet::<Test>::set_cluster_min_replicas(
+			Origin::signed(ALICE),
+			CLUSTER,
+			2
+		));
+		assert_err!(
+			phat::Pallet::<Test>::remove_worker_from_cluster(
+				Origin::signed(ALICE),
+				worker,
+				CLUSTER
+			),
+			phat::Error::<Test>::NotEnoughReplicas
+		);
+		assert_ok!(phat::Pallet::<Test>::set_cluster_min_replicas(
+			Origin::signed(ALICE),
+			CLUSTER,
+			1
+		));
+		assert_ok!(phat::Pallet::<Test>::remove_worker_from_cluster(
+			Origin::signed(ALICE),
+			worker,
+			CLUSTER
+		));
+		assert!(phat::ClusterReadyWorkers::<Test>::get(CLUSTER, worker).is_none());
+	});
+}
+
+#[test]
+fn draining_cluster_keeps_a_worker_to_settle() {
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		let worker = sp_core::sr25519::Public::from_raw([0u8; 32]);
+		let replica = sp_core::sr25519::Public::from_raw([1u8; 32]);
+		for w in [worker, replica] {
+			phat::ClusterByWorkers::<Test>::insert(w, CLUSTER);
+			phat::ClusterReadyWorkers::<Test>::insert(CLUSTER, w, ());
+		}
+		assert_ok!(phat::Pallet::<Test>::set_cluster_min_replicas(
+			Origin::signed(ALICE),
+			CLUSTER,
+			2
+		));
+		assert_ok!(phat::Pallet::<Test>::drain_cluster(
+			Origin::signed(ALICE),
+			CLUSTER
+		));
+		// The min replicas is not required while draining, but the last worker is kept
+		assert_ok!(phat::Pallet::<Test>::remove_worker_from_cluster(
+			Origin::signed(ALICE),
+			worker,
+			CLUSTER
+		));
+		assert_err!(
+			phat::Pallet::<Test>::remove_worker_from_cluster(
+				Origin::signed(ALICE),
+				replica,
+				CLUSTER
+			),
+			phat::Error::<Test>::NotEnoughReplicas
+		);
+	});
+}
+
+#[test]
+fn cluster_without_workers_can_be_force_destroyed() {
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		assert_ok!(phat::Pallet::<Test>::transfer_to_cluster(
+			Origin::signed(BOB),
+			10 * DOLLARS,
+			CLUSTER,
+			BOB,
+		));
+		assert_ok!(phat::Pallet::<Test>::drain_cluster(
+			Origin::signed(ALICE),
+			CLUSTER
+		));
+		assert_err!(
+			phat::Pallet::<Test>::force_destroy_cluster(Origin::root(), CLUSTER),
+			phat::Error::<Test>::ClusterNotSettling
+		);
+		// No worker is left to report the balances
+		assert_ok!(phat::Pallet::<Test>::cluster_destroy(
+			Origin::root(),
+			CLUSTER
+		));
+		assert_err!(
+			phat::Pallet::<Test>::cluster_destroy(Origin::root(), CLUSTER),
+			phat::Error::<Test>::ClusterIsSettling
+		);
+		assert!(
+			phat::Pallet::<Test>::force_destroy_cluster(Origin::signed(ALICE), CLUSTER).is_err()
+		);
+		assert_ok!(phat::Pallet::<Test>::force_destroy_cluster(
+			Origin::root(),
+			CLUSTER
+		));
+		// Only the owner is refunded
+		assert_eq!(balance_of_user(&BOB), 90 * DOLLARS);
+		assert_eq!(balance_of_user(&ALICE), 110 * DOLLARS);
+		assert!(phat::Clusters::<Test>::get(CLUSTER).is_none());
+		assert!(!phat::SettlingClusters::<Test>::contains_key(CLUSTER));
+	});
+}
+
+#[test]
+fn contract_upgrade_can_be_scheduled_and_rolled_back() {
+	use frame_support::traits::Hooks;
+
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		let code_v1 = H256([1u8; 32]);
+		let code_v2 = H256([2u8; 32]);
+		phat::ContractCodeHistory::<Test>::append(
+			CONTRACT,
+			phat::CodeHistoryEntry {
+				code_hash: code_v1,
+				since: 1,
+				rollback: false,
+			},
+		);
+
+		assert_err!(
+			phat::Pallet::<Test>::schedule_contract_upgrade(
+				Origin::signed(BOB),
+				CONTRACT,
+				code_v2,
+				10,
+				vec![],
+				0
+			),
+			phat::Error::<Test>::ContractPermissionDenied
+		);
+		assert_err!(
+			phat::Pallet::<Test>::schedule_contract_upgrade(
+				Origin::signed(ALICE),
+				CONTRACT,
+				code_v2,
+				1,
+				vec![],
+				0
+			),
+			phat::Error::<Test>::InvalidUpgradeBlock
+		);
+		assert_ok!(phat::Pallet::<Test>::schedule_contract_upgrade(
+			Origin::signed(ALICE),
+			CONTRACT,
+			code_v2,
+			10,
+			vec![0xde, 0xad, 0xbe, 0xef],
+			1_000_000
+		));
+		assert_err!(
+			phat::Pallet::<Test>::rollback_contract_upgrade(
+				Origin::signed(ALICE),
+				CONTRACT,
+				vec![],
+				0
+			),
+			phat::Error::<Test>::NothingToRollback
+		);
+
+		mock::System::set_block_number(10);
+		phat::Pallet::<Test>::on_initialize(10);
+		assert!(phat::ScheduledUpgrades::<Test>::get(CONTRACT).is_none());
+		// Not recorded until the workers report the code hash
+		assert_eq!(phat::ContractCodeHistory::<Test>::get(CONTRACT).len(), 1);
+		assert_err!(
+			phat::Pallet::<Test>::rollback_contract_upgrade(
+				Origin::signed(ALICE),
+				CONTRACT,
+				vec![],
+				0
+			),
+			phat::Error::<Test>::CodeChangePending
+		);
+		let worker = sp_core::sr25519::Public::from_raw([0u8; 32]);
+		phat::ClusterByWorkers::<Test>::insert(worker, CLUSTER);
+		assert_ok!(report_code(worker, Some(code_v2)));
+		let history = phat::ContractCodeHistory::<Test>::get(CONTRACT);
+		assert_eq!(history.len(), 2);
+		assert_eq!(history[1].code_hash, code_v2);
+		assert_eq!(history[1].since, 10);
+
+		mock::System::set_block_number(50);
+		assert_ok!(phat::Pallet::<Test>::rollback_contract_upgrade(
+			Origin::signed(ALICE),
+			CONTRACT,
+			vec![],
+			0
+		));
+		assert_ok!(report_code(worker, Some(code_v1)));
+		let history = phat::ContractCodeHistory::<Test>::get(CONTRACT);
+		assert_eq!(history.len(), 3);
+		assert_eq!(history[2].code_hash, code_v1);
+		assert!(history[2].rollback);
+		assert_err!(
+			phat::Pallet::<Test>::rollback_contract_upgrade(
+				Origin::signed(ALICE),
+				CONTRACT,
+				vec![],
+				0
+			),
+			phat::Error::<Test>::NothingToRollback
+		);
+
+		assert_ok!(phat::Pallet::<Test>::schedule_contract_upgrade(
+			Origin::signed(ALICE),
+			CONTRACT,
+			code_v2,
+			60,
+			vec![],
+			0
+		));
+		mock::System::set_block_number(60);
+		phat::Pallet::<Test>::on_initialize(60);
+		assert_ok!(report_code(worker, Some(code_v2)));
+		mock::System::set_block_number(161);
+		assert_err!(
+			phat::Pallet::<Test>::rollback_contract_upgrade(
+				Origin::signed(ALICE),
+				CONTRACT,
+				vec![],
+				0
+			),
+			phat::Error::<Test>::RollbackWindowExpired
+		);
+	});
+}
+
+fn report_code(
+	worker: sp_core::sr25519::Public,
+	code_hash: Option<H256>,
+) -> frame_support::dispatch::DispatchResult {
+	phat::Pallet::<Test>::on_worker_cluster_message_received(DecodedMessage {
+		sender: MessageOrigin::Worker(worker),
+		destination: Topic::new(*b"phala/cluster/report"),
+		payload: WorkerClusterReport::ContractCodeReported {
+			contract_id: CONTRACT,
+			code_hash,
+		},
+	})
+}
+
+#[test]
+fn contract_code_changes_are_confirmed_by_workers() {
+	use frame_support::traits::Hooks;
+
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		let code_v1 = H256([1u8; 32]);
+		let code_v2 = H256([2u8; 32]);
+		let worker = sp_core::sr25519::Public::from_raw([0u8; 32]);
+		let upgrade = |at| {
+			phat::Pallet::<Test>::schedule_contract_upgrade(
+				Origin::signed(ALICE),
+				CONTRACT,
+				code_v2,
+				at,
+				vec![],
+				0,
+			)
+		};
+
+		assert_ok!(upgrade(10));
+		mock::System::set_block_number(10);
+		phat::Pallet::<Test>::on_initialize(10);
+		assert_err!(
+			report_code(worker, Some(code_v2)),
+			phat::Error::<Test>::WorkerNotFound
+		);
+		phat::ClusterByWorkers::<Test>::insert(worker, CLUSTER);
+		// The migration failed, the code is not changed
+		assert_ok!(report_code(worker, Some(code_v1)));
+		assert!(phat::PendingCodeChanges::<Test>::get(CONTRACT).is_none());
+		assert!(phat::ContractCodeHistory::<Test>::get(CONTRACT).is_empty());
+
+		// An upgrade is postponed until the previous change is confirmed
+		assert_ok!(upgrade(20));
+		mock::System::set_block_number(20);
+		phat::Pallet::<Test>::on_initialize(20);
+		assert_ok!(upgrade(21));
+		mock::System::set_block_number(21);
+		phat::Pallet::<Test>::on_initialize(21);
+		assert_eq!(phat::UpgradesAt::<Test>::get(22), vec![CONTRACT]);
+		assert_eq!(
+			phat::ScheduledUpgrades::<Test>::get(CONTRACT).map(|x| x.at),
+			Some(22)
+		);
+		assert_ok!(report_code(worker, Some(code_v2)));
+		mock::System::set_block_number(22);
+		phat::Pallet::<Test>::on_initialize(22);
+		assert!(phat::ScheduledUpgrades::<Test>::get(CONTRACT).is_none());
+		assert!(phat::PendingCodeChanges::<Test>::get(CONTRACT).is_some());
+
+		// The history is bounded
+		for _ in 0..phat::MAX_CODE_HISTORY_LEN + 2 {
+			phat::PendingCodeChanges::<Test>::insert(
+				CONTRACT,
+				phat::CodeHistoryEntry {
+					code_hash: code_v2,
+					since: 20,
+					rollback: false,
+				},
+			);
+			assert_ok!(report_code(worker, Some(code_v2)));
+		}
+		assert_eq!(
+			phat::ContractCodeHistory::<Test>::get(CONTRACT).len(),
+			phat::MAX_CODE_HISTORY_LEN
+		);
+
+		// The upgrades at a block are bounded
+		phat::UpgradesAt::<Test>::insert(100u64, vec![H256::zero(); phat::MAX_UPGRADES_PER_BLOCK]);
+		assert_err!(upgrade(100), phat::Error::<Test>::TooManyUpgradesAtBlock);
+	});
+}
+
+fn instantiate(
+	who: AccountId32,
+	code_hash: H256,
+	salt: u8,
+) -> frame_support::dispatch::DispatchResult {
+	phat::Pallet::<Test>::instantiate_contract(
+		Origin::signed(who),
+		CodeIndex::WasmCode(code_hash),
+		vec![],
+		vec![salt],
+		CLUSTER,
+		0,
+		0,
+		None,
+		0,
+	)
+}
+
+fn set_permission(
+	origin: Origin,
+	permission: ClusterPermission<AccountId32>,
+) -> frame_support::dispatch::DispatchResult {
+	phat::Pallet::<Test>::set_cluster_permission(origin, CLUSTER, permission)
+}
+
+#[test]
+fn only_owner_and_deny_list_ignore_the_deployer_role() {
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		let code = H256([1u8; 32]);
+		assert_ok!(set_permission(
+			Origin::signed(ALICE),
+			ClusterPermission::OnlyOwner(ALICE)
+		));
+		assert_ok!(phat::Pallet::<Test>::add_cluster_deployer(
+			Origin::signed(ALICE),
+			CLUSTER,
+			BOB
+		));
+		assert_err!(
+			instantiate(BOB, code, 0),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+		assert_ok!(instantiate(ALICE, code, 0));
+
+		assert_ok!(set_permission(
+			Origin::signed(ALICE),
+			ClusterPermission::DenyList(vec![BOB])
+		));
+		assert_err!(
+			instantiate(BOB, code, 1),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+		assert_ok!(instantiate(CHARLIE, code, 1));
+	});
+}
+
+#[test]
+fn allow_list_admits_owner_listed_accounts_and_deployers() {
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		let code = H256([1u8; 32]);
+		assert_ok!(set_permission(
+			Origin::signed(ALICE),
+			ClusterPermission::AllowList(vec![BOB])
+		));
+		assert_ok!(instantiate(ALICE, code, 0));
+		assert_ok!(instantiate(BOB, code, 0));
+		assert_err!(
+			instantiate(CHARLIE, code, 0),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+
+		assert_ok!(phat::Pallet::<Test>::add_cluster_deployer(
+			Origin::signed(ALICE),
+			CLUSTER,
+			CHARLIE
+		));
+		assert_ok!(instantiate(CHARLIE, code, 0));
+		assert_ok!(phat::Pallet::<Test>::remove_cluster_deployer(
+			Origin::signed(ALICE),
+			CLUSTER,
+			CHARLIE
+		));
+		assert_err!(
+			instantiate(CHARLIE, code, 1),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+		assert_err!(
+			phat::Pallet::<Test>::remove_cluster_deployer(Origin::signed(ALICE), CLUSTER, CHARLIE),
+			phat::Error::<Test>::DeployerNotFound
+		);
+
+		// Only the owner can manage the cluster
+		assert_err!(
+			set_permission(Origin::signed(BOB), ClusterPermission::Public),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+		assert_err!(
+			set_permission(
+				Origin::signed(ALICE),
+				ClusterPermission::AllowList(vec![BOB; phat::MAX_PERMISSION_LIST_LEN + 1])
+			),
+			phat::Error::<Test>::PermissionListTooLong
+		);
+	});
+}
+
+#[test]
+fn governance_mode_locks_out_the_owner() {
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		let code = H256([1u8; 32]);
+		assert_ok!(set_permission(
+			Origin::signed(ALICE),
+			ClusterPermission::Governance
+		));
+
+		// The owner can neither manage nor deploy to the cluster any more
+		assert_err!(
+			set_permission(Origin::signed(ALICE), ClusterPermission::Public),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+		assert_err!(
+			phat::Pallet::<Test>::add_cluster_deployer(Origin::signed(ALICE), CLUSTER, BOB),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+		assert_err!(
+			instantiate(ALICE, code, 0),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+
+		// Only the deployers granted by the governance can deploy
+		assert_ok!(phat::Pallet::<Test>::add_cluster_deployer(
+			Origin::root(),
+			CLUSTER,
+			BOB
+		));
+		assert_ok!(instantiate(BOB, code, 0));
+		assert_err!(
+			instantiate(CHARLIE, code, 0),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+
+		assert_ok!(set_permission(Origin::root(), ClusterPermission::Public));
+		assert_ok!(set_permission(
+			Origin::signed(ALICE),
+			ClusterPermission::OnlyOwner(ALICE)
+		));
+	});
+}
+
+#[test]
+fn code_allow_list_restricts_instantiation() {
+	mock::new_test_ext().execute_with(|| {
+		prapare();
+		setup_cluster();
+		let allowed = H256([1u8; 32]);
+		let other = H256([2u8; 32]);
+		assert_err!(
+			phat::Pallet::<Test>::set_cluster_code_allowlist(
+				Origin::signed(BOB),
+				CLUSTER,
+				Some(vec![allowed])
+			),
+			phat::Error::<Test>::ClusterPermissionDenied
+		);
+		assert_err!(
+			phat::Pallet::<Test>::set_cluster_code_allowlist(
+				Origin::signed(ALICE),
+				CLUSTER,
+				Some(vec![allowed; phat::MAX_PERMISSION_LIST_LEN + 1])
+			),
+			phat::Error::<Test>::PermissionListTooLong
+		);
+		assert_ok!(phat::Pallet::<Test>::set_cluster_code_allowlist(
+			Origin::signed(ALICE),
+			CLUSTER,
+			Some(vec![allowed])
+		));
+		assert_err!(
+			instantiate(BOB, other, 0),
+			phat::Error::<Test>::CodeNotAllowed
+		);
+		assert_ok!(instantiate(BOB, allowed, 0));
+
+		assert_ok!(phat::Pallet::<Test>::set_cluster_code_allowlist(
+			Origin::signed(ALICE),
+			CLUSTER,
+			None
+		));
+		assert_ok!(instantiate(BOB, other, 0));
+	});
+}
@@ -1,7 +1,11 @@
 use crate::{mq, phat, phat_tokenomic, registry};
 
-use crate::mock::{MockValidator, NoneAttestationEnabled};
-use frame_support::{pallet_prelude::ConstU32, parameter_types, traits::GenesisBuild};
+use crate::mock::{MockAttestationEnabled, MockValidator, NoneAttestationEnabled};
+use frame_support::{
+	pallet_prelude::{ConstU32, ConstU64},
+	parameter_types,
+	traits::GenesisBuild,
+};
 use frame_system as system;
 use sp_core::H256;
 use sp_runtime::{
@@ -114,6 +118,7 @@ impl registry::Config for Test {
 	type LegacyAttestationValidator = MockValidator;
 	type UnixTime = Timestamp;
 	type NoneAttestationEnabled = NoneAttestationEnabled;
+	type MockAttestationEnabled = MockAttestationEnabled;
 	type VerifyPRuntime = VerifyPRuntime;
 	type VerifyRelaychainGenesisBlockHash = VerifyRelaychainGenesisBlockHash;
 	type GovernanceOrigin = frame_system::EnsureRoot<Self::AccountId>;
@@ -125,6 +130,8 @@ impl phat::Config for Test {
 	type InkCodeSizeLimit = ConstU32<{ 1024 * 1024 }>;
 	type SidevmCodeSizeLimit = ConstU32<{ 1024 * 1024 }>;
 	type Currency = Balances;
+	type OnClusterDestroyed = PhatTokenomic;
+	type CodeRollbackWindow = ConstU64<100>;
 }
 
 impl phat_tokenomic::Config for Test {
@@ -0,0 +1,291 @@
+//! # Randomness Pallet
+//!
+//! Records the randomness beacon published by the gatekeepers. Each beacon is the output of a
+//! schnorrkel VRF keyed by the gatekeeper master key, over the block number and the previous
+//! beacon. The VRF proof is verified against the master pubkey before the beacon is recorded, and
+//! kept on chain along with the pubkey, so that anyone can verify the beacon afterwards.
+//!
+//! Since the VRF output is unique for the key and the input, the gatekeepers cannot choose among
+//! several random numbers for a block. They can still withhold a beacon, which shows up as a gap
+//! in the beacon chain.
+//!
+//! The beacons are exposed to the other pallets via [`Randomness`], and to the Phat Contracts via
+//! the `randomness_beacon` pink chain extension.
+
+pub use self::pallet::*;
+
+#[frame_support::pallet]
+pub mod pallet {
+	use crate::registry;
+	use frame_support::{
+		dispatch::DispatchResult,
+		pallet_prelude::*,
+		traits::{Randomness, StorageVersion},
+	};
+	use phala_types::{
+		messaging::{DecodedMessage, MessageOrigin, RandomNumber, RandomnessBeaconEvent},
+		MasterPublicKey,
+	};
+	use sp_runtime::traits::{Hash, Zero};
+
+	#[pallet::config]
+	pub trait Config: frame_system::Config + registry::Config {
+		type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
+
+		/// The number of the latest beacons kept on chain
+		#[pallet::constant]
+		type MaxBeaconHistory: Get<u32>;
+
+		/// The number of blocks between two beacons generated by the gatekeepers
+		#[pallet::constant]
+		type BeaconInterval: Get<u32>;
+	}
+
+	const STORAGE_VERSION: StorageVersion = StorageVersion::new(0);
+
+	#[pallet::pallet]
+	#[pallet::storage_version(STORAGE_VERSION)]
+	#[pallet::without_storage_info]
+	pub struct Pallet<T>(_);
+
+	#[derive(Encode, Decode, TypeInfo, Clone, Debug, PartialEq, Eq)]
+	pub struct RandomnessBeacon {
+		pub random_number: RandomNumber,
+		/// The previous beacon, which links the beacons into a chain
+		pub last_random_number: RandomNumber,
+		/// The master pubkey that evaluated the VRF
+		pub master_pubkey: MasterPublicKey,
+		/// The encoded schnorrkel `VRFOutput`, which the random number is derived from
+		pub vrf_output: [u8; 32],
+		/// The encoded schnorrkel `VRFProof` of `vrf_output`
+		pub vrf_proof: [u8; 64],
+	}
+
+	/// The beacons indexed by the block number they are generated at
+	#[pallet::storage]
+	pub type Beacons<T: Config> = StorageMap<_, Twox64Concat, u32, RandomnessBeacon>;
+
+	/// The block numbers of the beacons kept on chain, as a ring of `MaxBeaconHistory` slots
+	#[pallet::storage]
+	pub type BeaconRing<T: Config> = StorageMap<_, Twox64Concat, u32, u32>;
+
+	/// The number of beacons ever recorded, the latest beacon is in the slot before it
+	#[pallet::storage]
+	pub type BeaconCount<T: Config> = StorageValue<_, u32, ValueQuery>;
+
+	#[pallet::event]
+	#[pallet::generate_deposit(pub(super) fn deposit_event)]
+	pub enum Event<T: Config> {
+		BeaconRecorded {
+			block_number: u32,
+			random_number: RandomNumber,
+		},
+	}
+
+	#[pallet::error]
+	pub enum Error<T> {
+		BadSender,
+		MasterKeyUninitialized,
+		InvalidVrfProof,
+		/// The beacon is not newer than the latest one on chain
+		StaleBeacon,
+		/// The beacon is not derived from the previous beacon
+		BrokenBeaconChain,
+	}
+
+	impl<T: Config> Pallet<T> {
+		pub fn on_gk_message_received(
+			message: DecodedMessage<RandomnessBeaconEvent>,
+		) -> DispatchResult {
+			if !matches!(message.sender, MessageOrigin::Gatekeeper) {
+				return Err(Error::<T>::BadSender.into());
+			}
+
+			let event = message.payload;
+			let master_pubkey = registry::GatekeeperMasterPubkey::<T>::get()
+				.ok_or(Error::<T>::MasterKeyUninitialized)?;
+			let random_number = event
+				.verify(&master_pubkey)
+				.ok_or(Error::<T>::InvalidVrfProof)?;
+
+			if let Some((latest_block, latest)) = Self::latest_beacon() {
+				ensure!(event.block_number > latest_block, Error::<T>::StaleBeacon);
+				// A beacon may be lost, e.g. when it is signed by a rotated-out master key. There
+				// is nothing to link to in that case.
+				if event.block_number == latest_block.saturating_add(T::BeaconInterval::get()) {
+					ensure!(
+						event.last_random_number == latest.random_number,
+						Error::<T>::BrokenBeaconChain
+					);
+				}
+			}
+			let count = BeaconCount::<T>::get();
+			let slot = count % Self::max_history();
+			if let Some(expired) = BeaconRing::<T>::get(slot) {
+				Beacons::<T>::remove(expired);
+			}
+			BeaconRing::<T>::insert(slot, event.block_number);
+			BeaconCount::<T>::put(count.wrapping_add(1));
+			Beacons::<T>::insert(
+				event.block_number,
+				RandomnessBeacon {
+					random_number,
+					last_random_number: event.last_random_number,
+					master_pubkey,
+					vrf_output: event.vrf_output,
+					vrf_proof: event.vrf_proof,
+				},
+			);
+			Self::deposit_event(Event::<T>::BeaconRecorded {
+				block_number: event.block_number,
+				random_number,
+			});
+			Ok(())
+		}
+
+		/// The beacon generated at the given block, if it is still kept on chain
+		pub fn beacon_at(block_number: u32) -> Option<RandomNumber> {
+			Beacons::<T>::get(block_number).map(|beacon| beacon.random_number)
+		}
+
+		/// The latest beacon and the block number it is generated at
+		pub fn latest_beacon() -> Option<(u32, RandomnessBeacon)> {
+			let count = BeaconCount::<T>::get();
+			if count == 0 {
+				return None;
+			}
+			let slot = (count - 1) % Self::max_history();
+			let block_number = BeaconRing::<T>::get(slot)?;
+			Beacons::<T>::get(block_number).map(|beacon| (block_number, beacon))
+		}
+
+		fn max_history() -> u32 {
+			T::MaxBeaconHistory::get().max(1)
+		}
+	}
+
+	impl<T: Config> Randomness<T::Hash, T::BlockNumber> for Pallet<T> {
+		/// Derives a random value for `subject` from the latest beacon.
+		///
+		/// Returns the block number the beacon is generated at, which is zero if there is no
+		/// beacon on chain yet.
+		fn random(subject: &[u8]) -> (T::Hash, T::BlockNumber) {
+			match Self::latest_beacon() {
+				Some((block_number, beacon)) => (
+					T::Hashing::hash_of(&(beacon.random_number, subject)),
+					block_number.into(),
+				),
+				None => (Default::default(), Zero::zero()),
+			}
+		}
+	}
+
+	#[cfg(test)]
+	mod test {
+		use super::*;
+		use crate::mock::{new_test_ext, set_block_1, Test};
+		// Pallets
+		use crate::mock::PhalaRandomness;
+		use frame_support::{assert_noop, assert_ok};
+		use phala_types::messaging::Topic;
+		use sp_core::{sr25519, Pair};
+
+		fn beacon(
+			key: &sr25519::Pair,
+			block_number: u32,
+			last_random_number: RandomNumber,
+		) -> (DecodedMessage<RandomnessBeaconEvent>, RandomNumber) {
+			let (event, random_number) =
+				RandomnessBeaconEvent::sign(key, block_number, last_random_number);
+			let message = DecodedMessage {
+				sender: MessageOrigin::Gatekeeper,
+				destination: Topic::new(*b"^phala/randomness/beacon"),
+				payload: event,
+			};
+			(message, random_number)
+		}
+
+		#[test]
+		fn test_record_beacons() {
+			new_test_ext().execute_with(|| {
+				set_block_1();
+				let master_key = sr25519::Pair::from_seed(&[1; 32]);
+				let forged_key = sr25519::Pair::from_seed(&[2; 32]);
+				assert_noop!(
+					PhalaRandomness::on_gk_message_received(beacon(&master_key, 5, [0; 32]).0),
+					Error::<Test>::MasterKeyUninitialized
+				);
+				registry::GatekeeperMasterPubkey::<Test>::put(master_key.public());
+				assert_noop!(
+					PhalaRandomness::on_gk_message_received(beacon(&forged_key, 5, [0; 32]).0),
+					Error::<Test>::InvalidVrfProof
+				);
+				// The output can't be replaced without the master key
+				let (mut tampered, _) = beacon(&master_key, 5, [0; 32]);
+				tampered.payload.vrf_output = beacon(&master_key, 5, [1; 32]).0.payload.vrf_output;
+				assert_noop!(
+					PhalaRandomness::on_gk_message_received(tampered),
+					Error::<Test>::InvalidVrfProof
+				);
+
+				let mut random_numbers = vec![[0; 32]];
+				for i in 1..=5u32 {
+					let (message, random_number) =
+						beacon(&master_key, i * 5, *random_numbers.last().unwrap());
+					// The VRF output is unique for the input
+					assert_eq!(
+						beacon(&master_key, i * 5, *random_numbers.last().unwrap()).1,
+						random_number
+					);
+					assert_ok!(PhalaRandomness::on_gk_message_received(message));
+					random_numbers.push(random_number);
+				}
+				assert_noop!(
+					PhalaRandomness::on_gk_message_received(
+						beacon(&master_key, 25, random_numbers[4]).0
+					),
+					Error::<Test>::StaleBeacon
+				);
+
+				// Only the latest beacons are kept
+				assert_eq!(BeaconCount::<Test>::get(), 5);
+				assert_eq!(BeaconRing::<Test>::iter().count(), 4);
+				assert_eq!(Beacons::<Test>::iter().count(), 4);
+				assert_eq!(PhalaRandomness::beacon_at(5), None);
+				assert_eq!(PhalaRandomness::beacon_at(10), Some(random_numbers[2]));
+				let (block_number, latest) = PhalaRandomness::latest_beacon().unwrap();
+				assert_eq!(block_number, 25);
+				assert_eq!(latest.master_pubkey, master_key.public());
+				assert_eq!(latest.random_number, random_numbers[5]);
+				assert_eq!(latest.last_random_number, random_numbers[4]);
+
+				let (random, at) = PhalaRandomness::random(b"lottery");
+				assert_eq!(at, 25);
+				assert_ne!(random, PhalaRandomness::random(b"another lottery").0);
+			});
+		}
+
+		#[test]
+		fn test_beacon_chain() {
+			new_test_ext().execute_with(|| {
+				set_block_1();
+				let master_key = sr25519::Pair::from_seed(&[1; 32]);
+				registry::GatekeeperMasterPubkey::<Test>::put(master_key.public());
+				let (message, first) = beacon(&master_key, 5, [0; 32]);
+				assert_ok!(PhalaRandomness::on_gk_message_received(message));
+				// The next beacon must be derived from the previous one
+				assert_noop!(
+					PhalaRandomness::on_gk_message_received(beacon(&master_key, 10, [9; 32]).0),
+					Error::<Test>::BrokenBeaconChain
+				);
+				let (message, _second) = beacon(&master_key, 10, first);
+				assert_ok!(PhalaRandomness::on_gk_message_received(message));
+				// The beacon at block 15 is lost, there is nothing to link to
+				assert_ok!(PhalaRandomness::on_gk_message_received(
+					beacon(&master_key, 20, [3; 32]).0
+				));
+				assert_eq!(PhalaRandomness::latest_beacon().unwrap().0, 20);
+			});
+		}
+	}
+}
@@ -13,7 +13,7 @@ pub mod pallet {
 	use frame_system::pallet_prelude::*;
 	use scale_info::TypeInfo;
 	use sp_core::{sr25519, H256};
-	use sp_runtime::SaturatedConversion;
+	use sp_runtime::{traits::Zero, SaturatedConversion};
 	use sp_std::prelude::*;
 	use sp_std::{convert::TryFrom, vec};
 
@@ -51,6 +51,13 @@ pub mod pallet {
 		MasterPubkey {
 			master_pubkey: MasterPublicKey,
 		},
+		///	MessageOrigin::Worker -> Pallet
+		///
+		/// Sent by each gatekeeper once it has received and sealed the rotated master key.
+		MasterKeyRotationAck {
+			rotation_id: u64,
+			master_pubkey: MasterPublicKey,
+		},
 	}
 
 	bind_topic!(GatekeeperRegistryEvent, b"^phala/registry/gk_event");
@@ -62,6 +69,37 @@ pub mod pallet {
 		},
 	}
 
+	#[derive(Encode, Decode, TypeInfo, Clone, Debug, PartialEq, Eq)]
+	pub enum MasterKeyRotationStatus {
+		Pending,
+		Succeeded,
+		Aborted,
+	}
+
+	/// The audit record of a master key rotation.
+	#[derive(Encode, Decode, TypeInfo, Clone, Debug, PartialEq, Eq)]
+	pub struct MasterKeyRotationAttempt<BlockNumber> {
+		pub started_at: BlockNumber,
+		/// The rotation is aborted if the quorum is not reached after this block.
+		pub deadline: BlockNumber,
+		/// The number of gatekeeper acknowledgements required to switch the master key.
+		pub quorum: u32,
+		/// The rotated master pubkey reported by the gatekeepers.
+		pub master_pubkey: Option<MasterPublicKey>,
+		/// The gatekeepers which have acknowledged the rotated master key.
+		pub acks: Vec<WorkerPublicKey>,
+		pub status: MasterKeyRotationStatus,
+	}
+
+	/// The liveness record of the endpoints reported by a worker.
+	#[derive(Encode, Decode, TypeInfo, Clone, Debug, PartialEq, Eq)]
+	pub struct EndpointHeartbeat<BlockNumber> {
+		/// The signing time of the latest accepted endpoint payload, in milliseconds.
+		pub signing_time: u64,
+		/// The block at which the latest endpoint payload is accepted.
+		pub updated_at: BlockNumber,
+	}
+
 	#[derive(Encode, Decode, TypeInfo, Clone, Debug, Default)]
 	pub struct KnownConsensusVersion {
 		version: u32,
@@ -83,6 +121,10 @@ pub mod pallet {
 		#[pallet::constant]
 		type NoneAttestationEnabled: Get<bool>;
 
+		/// Enable Mock Attestation from the software platform, SHOULD BE SET TO FALSE ON PRODUCTION !!!
+		#[pallet::constant]
+		type MockAttestationEnabled: Get<bool>;
+
 		/// Verify attestation
 		///
 		/// SHOULD NOT SET TO FALSE ON PRODUCTION!!!
@@ -104,6 +146,10 @@ pub mod pallet {
 
 	const STORAGE_VERSION: StorageVersion = StorageVersion::new(7);
 
+	/// Since this pRuntime consensus version, the master key rotation requires a quorum of the
+	/// gatekeepers to acknowledge the rotated master key.
+	pub const MASTER_KEY_QUORUM_CONSENSUS_VERSION: u32 = 1;
+
 	#[pallet::pallet]
 	#[pallet::storage_version(STORAGE_VERSION)]
 	#[pallet::without_storage_info]
@@ -134,6 +180,26 @@ pub mod pallet {
 	#[pallet::storage]
 	pub type MasterKeyRotationLock<T: Config> = StorageValue<_, Option<u64>, ValueQuery>;
 
+	/// The audit log of the master key rotations, indexed by the rotation id.
+	#[pallet::storage]
+	pub type MasterKeyRotations<T: Config> =
+		StorageMap<_, Twox64Concat, u64, MasterKeyRotationAttempt<T::BlockNumber>>;
+
+	/// The number of gatekeeper acknowledgements required by a master key rotation.
+	///
+	/// Zero means a simple majority of the registered gatekeepers.
+	#[pallet::storage]
+	pub type MasterKeyRotationQuorum<T: Config> = StorageValue<_, u32, ValueQuery>;
+
+	#[pallet::type_value]
+	pub fn DefaultMasterKeyRotationTimeout<T: Config>() -> T::BlockNumber {
+		600u32.into()
+	}
+	/// The number of blocks after which an unfinished master key rotation is aborted.
+	#[pallet::storage]
+	pub type MasterKeyRotationTimeout<T: Config> =
+		StorageValue<_, T::BlockNumber, ValueQuery, DefaultMasterKeyRotationTimeout<T>>;
+
 	/// Mapping from worker pubkey to WorkerInfo
 	#[pallet::storage]
 	pub type Workers<T: Config> =
@@ -183,6 +249,14 @@ pub mod pallet {
 	pub type Endpoints<T: Config> =
 		StorageMap<_, Twox64Concat, WorkerPublicKey, VersionedWorkerEndpoints>;
 
+	/// Mapping from worker pubkey to the liveness record of its endpoints
+	///
+	/// Workers refresh their endpoints periodically, so a stale heartbeat indicates that the
+	/// worker is likely offline.
+	#[pallet::storage]
+	pub type EndpointHeartbeats<T: Config> =
+		StorageMap<_, Twox64Concat, WorkerPublicKey, EndpointHeartbeat<T::BlockNumber>>;
+
 	/// Allow list of pRuntime binary digest
 	///
 	/// Only pRuntime within the list can register.
@@ -204,6 +278,10 @@ pub mod pallet {
 	pub type MaxKnownPRuntimeConsensusVersion<T: Config> =
 		StorageValue<_, KnownConsensusVersion, ValueQuery>;
 
+	/// The DER encoded Intel SGX Root CA cert used to verify DCAP quotes
+	#[pallet::storage]
+	pub type DcapRootCa<T: Config> = StorageValue<_, Vec<u8>>;
+
 	#[pallet::event]
 	#[pallet::generate_deposit(pub(super) fn deposit_event)]
 	pub enum Event<T: Config> {
@@ -232,6 +310,22 @@ pub mod pallet {
 			rotation_lock: Option<u64>,
 			gatekeeper_rotation_id: u64,
 		},
+		MasterKeyRotationStarted {
+			rotation_id: u64,
+			quorum: u32,
+			deadline: T::BlockNumber,
+		},
+		MasterKeyRotationAcknowledged {
+			rotation_id: u64,
+			gatekeeper: WorkerPublicKey,
+		},
+		MasterKeyRotationAborted {
+			rotation_id: u64,
+		},
+		MasterKeyRotationConfigChanged {
+			quorum: u32,
+			timeout: T::BlockNumber,
+		},
 		InitialScoreSet {
 			pubkey: WorkerPublicKey,
 			init_score: u32,
@@ -239,6 +333,7 @@ pub mod pallet {
 		MinimumPRuntimeVersionChangedTo(u32, u32, u32),
 		PRuntimeConsensusVersionChangedTo(u32),
 		GatekeeperLaunched,
+		DcapRootCaChanged,
 	}
 
 	#[pallet::error]
@@ -283,12 +378,30 @@ pub mod pallet {
 		CannotRemoveLastGatekeeper,
 		MasterKeyInRotation,
 		InvalidRotatedMasterPubkey,
+		InvalidRotationQuorum,
 		// PRouter related
 		InvalidEndpointSigningTime,
+		/// The endpoint payload is signed earlier than the one on chain
+		StaleEndpointPayload,
 		/// Migration root not authorized
 		NotMigrationRoot,
 		ParachainIdMismatch,
 		InvalidConsensusVersion,
+		// DCAP related
+		InvalidDcapQuote,
+		UnsupportedDcapQuoteVersion,
+		InvalidPckCertChain,
+		InvalidQuoteSignature,
+		InvalidDcapCollateral,
+		OutdatedDcapCollateral,
+		QeIdentityMismatch,
+		DcapRootCaNotSet,
+		InvalidDcapRootCa,
+		InvalidDcapCrl,
+		DcapCertRevoked,
+		// Mock attestation related
+		MockAttestationDisabled,
+		OutdatedMockReport,
 	}
 
 	#[pallet::call]
@@ -443,6 +556,9 @@ pub mod pallet {
 		}
 
 		/// Rotate the master key
+		///
+		/// The new master key takes effect on-chain once it is acknowledged by a quorum of the
+		/// gatekeepers, otherwise the rotation is aborted after `MasterKeyRotationTimeout` blocks.
 		#[pallet::call_index(5)]
 		#[pallet::weight(Weight::from_parts(10_000u64, 0) + T::DbWeight::get().writes(1u64))]
 		pub fn rotate_master_key(origin: OriginFor<T>) -> DispatchResult {
@@ -452,6 +568,14 @@ pub mod pallet {
 			ensure!(rotating.is_none(), Error::<T>::MasterKeyInRotation);
 
 			let gatekeepers = Gatekeeper::<T>::get();
+			let quorum = match MasterKeyRotationQuorum::<T>::get() {
+				0 => gatekeepers.len() as u32 / 2 + 1,
+				quorum => quorum,
+			};
+			ensure!(
+				quorum as usize <= gatekeepers.len(),
+				Error::<T>::InvalidRotationQuorum
+			);
 			let gk_identities = gatekeepers
 				.iter()
 				.map(|gk| {
@@ -469,10 +593,28 @@ pub mod pallet {
 			});
 
 			MasterKeyRotationLock::<T>::put(Some(rotation_id));
+			let now = frame_system::Pallet::<T>::block_number();
+			let deadline = now + MasterKeyRotationTimeout::<T>::get();
+			MasterKeyRotations::<T>::insert(
+				rotation_id,
+				MasterKeyRotationAttempt {
+					started_at: now,
+					deadline,
+					quorum,
+					master_pubkey: None,
+					acks: Vec::new(),
+					status: MasterKeyRotationStatus::Pending,
+				},
+			);
 			Self::push_message(GatekeeperLaunch::rotate_master_key(
 				rotation_id,
 				gk_identities,
 			));
+			Self::deposit_event(Event::<T>::MasterKeyRotationStarted {
+				rotation_id,
+				quorum,
+				deadline,
+			});
 			Ok(())
 		}
 
@@ -599,6 +741,8 @@ pub mod pallet {
 				T::VerifyPRuntime::get(),
 				PRuntimeAllowList::<T>::get(),
 				T::NoneAttestationEnabled::get(),
+				T::MockAttestationEnabled::get(),
+				DcapRootCa::<T>::get().as_deref(),
 			)
 			.map_err(Into::<Error<T>>::into)?;
 
@@ -731,6 +875,21 @@ pub mod pallet {
 				Error::<T>::InvalidPubKey
 			);
 
+			// Reject replayed payloads signed before the one on chain
+			if let Some(heartbeat) = EndpointHeartbeats::<T>::get(endpoint_payload.pubkey) {
+				ensure!(
+					endpoint_payload.signing_time >= heartbeat.signing_time,
+					Error::<T>::StaleEndpointPayload
+				);
+			}
+
+			EndpointHeartbeats::<T>::insert(
+				endpoint_payload.pubkey,
+				EndpointHeartbeat {
+					signing_time: endpoint_payload.signing_time,
+					updated_at: frame_system::Pallet::<T>::block_number(),
+				},
+			);
 			Endpoints::<T>::insert(
 				endpoint_payload.pubkey,
 				endpoint_payload.versioned_endpoints,
@@ -860,10 +1019,65 @@ pub mod pallet {
 			version: u32,
 		) -> DispatchResult {
 			T::GovernanceOrigin::ensure_origin(origin)?;
+			// The gatekeepers decide how to rotate the master key by the consensus version, it
+			// must not change in the middle of a rotation.
+			ensure!(
+				MasterKeyRotationLock::<T>::get().is_none(),
+				Error::<T>::MasterKeyInRotation
+			);
 			PRuntimeConsensusVersion::<T>::put(version);
 			Self::deposit_event(Event::<T>::PRuntimeConsensusVersionChangedTo(version));
 			Ok(())
 		}
+
+		/// Sets [`DcapRootCa`], the trust anchor of DCAP attestation
+		///
+		/// Can only be called by `GovernanceOrigin`.
+		#[pallet::call_index(15)]
+		#[pallet::weight({0})]
+		pub fn set_dcap_root_ca(origin: OriginFor<T>, root_ca: Vec<u8>) -> DispatchResult {
+			T::GovernanceOrigin::ensure_origin(origin)?;
+			ensure!(
+				webpki::TrustAnchor::try_from_cert_der(&root_ca).is_ok(),
+				Error::<T>::InvalidDcapRootCa
+			);
+			DcapRootCa::<T>::put(root_ca);
+			Self::deposit_event(Event::<T>::DcapRootCaChanged);
+			Ok(())
+		}
+
+		/// Sets the quorum and the timeout of the master key rotations
+		///
+		/// A `quorum` of zero means a simple majority of the gatekeepers.
+		///
+		/// Can only be called by `GovernanceOrigin`.
+		#[pallet::call_index(16)]
+		#[pallet::weight({0})]
+		pub fn set_master_key_rotation_config(
+			origin: OriginFor<T>,
+			quorum: u32,
+			timeout: T::BlockNumber,
+		) -> DispatchResult {
+			T::GovernanceOrigin::ensure_origin(origin)?;
+			ensure!(
+				!timeout.is_zero() && quorum as usize <= Gatekeeper::<T>::decode_len().unwrap_or(0),
+				Error::<T>::InvalidRotationQuorum
+			);
+			MasterKeyRotationQuorum::<T>::put(quorum);
+			MasterKeyRotationTimeout::<T>::put(timeout);
+			Self::deposit_event(Event::<T>::MasterKeyRotationConfigChanged { quorum, timeout });
+			Ok(())
+		}
+	}
+
+	#[pallet::hooks]
+	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T>
+	where
+		T: crate::mq::Config,
+	{
+		fn on_initialize(n: T::BlockNumber) -> Weight {
+			Self::abort_expired_rotation(n)
+		}
 	}
 
 	// TODO.kevin: Move it to mq
@@ -968,10 +1182,96 @@ pub mod pallet {
 						}
 					}
 				}
+				RegistryEvent::MasterKeyRotationAck {
+					rotation_id,
+					master_pubkey,
+				} => {
+					ensure!(
+						Self::master_key_quorum_enabled(),
+						Error::<T>::InvalidRotatedMasterPubkey
+					);
+					ensure!(
+						Gatekeeper::<T>::get().contains(worker_pubkey),
+						Error::<T>::InvalidGatekeeper
+					);
+					Self::ensure_rotating(rotation_id)?;
+					let mut attempt = MasterKeyRotations::<T>::get(rotation_id)
+						.ok_or(Error::<T>::InvalidRotatedMasterPubkey)?;
+					match attempt.master_pubkey {
+						Some(expected) => {
+							ensure!(expected == master_pubkey, Error::<T>::MasterKeyMismatch)
+						}
+						None => attempt.master_pubkey = Some(master_pubkey),
+					}
+					if attempt.acks.contains(worker_pubkey) {
+						return Ok(());
+					}
+					attempt.acks.push(*worker_pubkey);
+					Self::deposit_event(Event::<T>::MasterKeyRotationAcknowledged {
+						rotation_id,
+						gatekeeper: *worker_pubkey,
+					});
+					if attempt.acks.len() as u32 >= attempt.quorum {
+						attempt.status = MasterKeyRotationStatus::Succeeded;
+						GatekeeperMasterPubkey::<T>::put(master_pubkey);
+						MasterKeyRotationLock::<T>::put(Option::<u64>::None);
+						Self::deposit_event(Event::<T>::MasterKeyRotated {
+							rotation_id,
+							master_pubkey,
+						});
+						Self::push_message(GatekeeperLaunch::master_pubkey_rotated(master_pubkey));
+					}
+					MasterKeyRotations::<T>::insert(rotation_id, attempt);
+				}
 			}
 			Ok(())
 		}
 
+		fn ensure_rotating(rotation_id: u64) -> DispatchResult {
+			let rotating = MasterKeyRotationLock::<T>::get();
+			if rotating != Some(rotation_id) {
+				Self::deposit_event(Event::<T>::MasterKeyRotationFailed {
+					rotation_lock: rotating,
+					gatekeeper_rotation_id: rotation_id,
+				});
+				return Err(Error::<T>::InvalidRotatedMasterPubkey.into());
+			}
+			Ok(())
+		}
+
+		fn master_key_quorum_enabled() -> bool {
+			PRuntimeConsensusVersion::<T>::get() >= MASTER_KEY_QUORUM_CONSENSUS_VERSION
+		}
+
+		/// Aborts the master key rotation in progress if it has passed its deadline, returns the
+		/// consumed weight.
+		fn abort_expired_rotation(now: T::BlockNumber) -> Weight {
+			let db = T::DbWeight::get();
+			let Some(rotation_id) = MasterKeyRotationLock::<T>::get() else {
+				return db.reads(1);
+			};
+			// The legacy rotation has no deadline, it completes once the gatekeepers report the
+			// rotated master pubkey.
+			if !Self::master_key_quorum_enabled() {
+				return db.reads(2);
+			}
+			let Some(mut attempt) = MasterKeyRotations::<T>::get(rotation_id) else {
+				return db.reads(3);
+			};
+			if now <= attempt.deadline {
+				return db.reads(3);
+			}
+			// The rotation id is not reused, so that the late acks of the aborted rotation
+			// can never be mistaken for a later one.
+			attempt.status = MasterKeyRotationStatus::Aborted;
+			MasterKeyRotations::<T>::insert(rotation_id, attempt);
+			MasterKeyRotationLock::<T>::put(Option::<u64>::None);
+			Self::push_message(GatekeeperLaunch::master_key_rotation_aborted(rotation_id));
+			Self::deposit_event(Event::<T>::MasterKeyRotationAborted { rotation_id });
+			// The message push reads and writes the sequence of the pallet's outbound queue.
+			db.reads_writes(4, 4)
+		}
+
 		pub fn on_gk_message_received(
 			message: DecodedMessage<GatekeeperRegistryEvent>,
 		) -> DispatchResult {
@@ -984,15 +1284,26 @@ pub mod pallet {
 					rotation_id,
 					master_pubkey,
 				} => {
-					let rotating = MasterKeyRotationLock::<T>::get();
-					if rotating.is_none() || rotating.unwrap() != rotation_id {
-						Self::deposit_event(Event::<T>::MasterKeyRotationFailed {
-							rotation_lock: rotating,
-							gatekeeper_rotation_id: rotation_id,
+					Self::ensure_rotating(rotation_id)?;
+					if Self::master_key_quorum_enabled() {
+						// The master key is only switched once a quorum of the gatekeepers has
+						// acknowledged it, see `RegistryEvent::MasterKeyRotationAck`.
+						MasterKeyRotations::<T>::mutate(rotation_id, |attempt| {
+							if let Some(attempt) = attempt {
+								attempt.master_pubkey.get_or_insert(master_pubkey);
+							}
 						});
-						return Err(Error::<T>::InvalidRotatedMasterPubkey.into());
+						return Ok(());
 					}
 
+					// The gatekeepers before the quorum rotation have already switched to the new
+					// master key.
+					MasterKeyRotations::<T>::mutate(rotation_id, |attempt| {
+						if let Some(attempt) = attempt {
+							attempt.master_pubkey = Some(master_pubkey);
+							attempt.status = MasterKeyRotationStatus::Succeeded;
+						}
+					});
 					GatekeeperMasterPubkey::<T>::put(master_pubkey);
 					MasterKeyRotationLock::<T>::put(Option::<u64>::None);
 					Self::deposit_event(Event::<T>::MasterKeyRotated {
@@ -1201,6 +1512,18 @@ pub mod pallet {
 				AttestationError::UnknownQuoteBodyFormat => Self::UnknownQuoteBodyFormat,
 				AttestationError::InvalidUserDataHash => Self::InvalidRuntimeInfoHash,
 				AttestationError::NoneAttestationDisabled => Self::NoneAttestationDisabled,
+				AttestationError::InvalidDcapQuote => Self::InvalidDcapQuote,
+				AttestationError::UnsupportedDcapQuoteVersion => Self::UnsupportedDcapQuoteVersion,
+				AttestationError::InvalidPckCertChain => Self::InvalidPckCertChain,
+				AttestationError::InvalidQuoteSignature => Self::InvalidQuoteSignature,
+				AttestationError::InvalidDcapCollateral => Self::InvalidDcapCollateral,
+				AttestationError::OutdatedDcapCollateral => Self::OutdatedDcapCollateral,
+				AttestationError::QeIdentityMismatch => Self::QeIdentityMismatch,
+				AttestationError::DcapRootCaNotSet => Self::DcapRootCaNotSet,
+				AttestationError::InvalidDcapCrl => Self::InvalidDcapCrl,
+				AttestationError::DcapCertRevoked => Self::DcapCertRevoked,
+				AttestationError::MockAttestationDisabled => Self::MockAttestationDisabled,
+				AttestationError::OutdatedMockReport => Self::OutdatedMockReport,
 			}
 		}
 	}
@@ -1431,5 +1754,242 @@ pub mod pallet {
 				assert_eq!(RelaychainGenesisBlockHashAllowList::<Test>::get().len(), 0);
 			});
 		}
+
+		#[test]
+		fn test_set_dcap_root_ca() {
+			new_test_ext().execute_with(|| {
+				set_block_1();
+
+				assert_noop!(
+					PhalaRegistry::set_dcap_root_ca(Origin::root(), b"not a cert".to_vec()),
+					Error::<Test>::InvalidDcapRootCa
+				);
+				let sample: serde_json::Value =
+					serde_json::from_slice(include_bytes!("../sample/dcap_attestation.json"))
+						.unwrap();
+				let cert = hex::decode(sample["root_ca"].as_str().unwrap()).unwrap();
+				assert_ok!(PhalaRegistry::set_dcap_root_ca(
+					Origin::root(),
+					cert.clone()
+				));
+				assert_eq!(DcapRootCa::<Test>::get(), Some(cert));
+			});
+		}
+
+		#[test]
+		fn test_update_worker_endpoint_heartbeat() {
+			use phala_types::{WorkerCapabilities, WorkerEndpointsV2};
+			use sp_core::Pair;
+
+			let worker_key = sr25519::Pair::from_seed(&[1; 32]);
+			let signed_payload = |signing_time: u64, load_permill: u32| {
+				let payload = WorkerEndpointPayload {
+					pubkey: worker_key.public(),
+					versioned_endpoints: VersionedWorkerEndpoints::V2(WorkerEndpointsV2 {
+						endpoints: vec!["http://worker.local:8000".into()],
+						capabilities: WorkerCapabilities {
+							load_permill,
+							..Default::default()
+						},
+					}),
+					signing_time,
+				};
+				let data = wrap_content_to_sign(&payload.encode(), SignedContentType::EndpointInfo);
+				let signature = worker_key.sign(&data).0.to_vec();
+				(payload, signature)
+			};
+
+			new_test_ext().execute_with(|| {
+				set_block_1();
+				assert_ok!(PhalaRegistry::force_register_worker(
+					Origin::root(),
+					worker_key.public(),
+					ecdh_pubkey(1),
+					Some(1),
+				));
+				elapse_seconds(100);
+
+				let (payload, signature) = signed_payload(50_000, 10);
+				assert_ok!(PhalaRegistry::update_worker_endpoint(
+					Origin::signed(1),
+					payload,
+					signature
+				));
+				let (payload, signature) = signed_payload(90_000, 20);
+				assert_ok!(PhalaRegistry::update_worker_endpoint(
+					Origin::signed(1),
+					payload.clone(),
+					signature
+				));
+				assert_eq!(
+					EndpointHeartbeats::<Test>::get(worker_key.public()),
+					Some(EndpointHeartbeat {
+						signing_time: 90_000,
+						updated_at: 1,
+					})
+				);
+				assert_eq!(
+					Endpoints::<Test>::get(worker_key.public()),
+					Some(payload.versioned_endpoints)
+				);
+
+				// An older payload can not override the latest one
+				let (payload, signature) = signed_payload(50_000, 10);
+				assert_noop!(
+					PhalaRegistry::update_worker_endpoint(Origin::signed(1), payload, signature),
+					Error::<Test>::StaleEndpointPayload
+				);
+			});
+		}
+
+		#[test]
+		fn test_master_key_rotation_quorum_and_timeout() {
+			use frame_system::Pallet as System;
+			use messaging::Topic;
+
+			fn ack(gk: u8, rotation_id: u64, master_pubkey: MasterPublicKey) -> DispatchResult {
+				PhalaRegistry::on_message_received(DecodedMessage {
+					sender: MessageOrigin::Worker(worker_pubkey(gk)),
+					destination: Topic::new(*b"^phala/registry/event"),
+					payload: RegistryEvent::MasterKeyRotationAck {
+						rotation_id,
+						master_pubkey,
+					},
+				})
+			}
+
+			new_test_ext().execute_with(|| {
+				set_block_1();
+				setup_relaychain_genesis_allowlist();
+				for i in 1..=3 {
+					assert_ok!(PhalaRegistry::register_worker(
+						Origin::signed(1),
+						WorkerRegistrationInfo::<u64> {
+							version: 1,
+							machine_id: Default::default(),
+							pubkey: worker_pubkey(i),
+							ecdh_pubkey: ecdh_pubkey(i),
+							genesis_block_hash: H256::repeat_byte(1),
+							features: vec![4, 1],
+							operator: Some(1),
+						},
+						Attestation::SgxIas {
+							ra_report: Vec::new(),
+							signature: Vec::new(),
+							raw_signing_cert: Vec::new(),
+						},
+					));
+				}
+				Gatekeeper::<Test>::put(vec![worker_pubkey(1), worker_pubkey(2), worker_pubkey(3)]);
+				let old_key = sr25519::Public::from_raw([8; 32]);
+				let new_key = sr25519::Public::from_raw([9; 32]);
+				GatekeeperMasterPubkey::<Test>::put(old_key);
+				assert_ok!(PhalaRegistry::set_pruntime_consensus_version(
+					Origin::root(),
+					MASTER_KEY_QUORUM_CONSENSUS_VERSION
+				));
+
+				assert_noop!(
+					PhalaRegistry::set_master_key_rotation_config(Origin::root(), 4, 10),
+					Error::<Test>::InvalidRotationQuorum
+				);
+				assert_ok!(PhalaRegistry::set_master_key_rotation_config(
+					Origin::root(),
+					0,
+					10
+				));
+
+				// A majority of the gatekeepers is required to switch the master key
+				assert_ok!(PhalaRegistry::rotate_master_key(Origin::root()));
+				assert_eq!(MasterKeyRotationLock::<Test>::get(), Some(1));
+				assert_noop!(
+					PhalaRegistry::set_pruntime_consensus_version(Origin::root(), 0),
+					Error::<Test>::MasterKeyInRotation
+				);
+				assert_ok!(ack(1, 1, new_key));
+				assert_ok!(ack(1, 1, new_key));
+				assert_noop!(
+					ack(2, 1, sr25519::Public::from_raw([7; 32])),
+					Error::<Test>::MasterKeyMismatch
+				);
+				assert_eq!(GatekeeperMasterPubkey::<Test>::get(), Some(old_key));
+				assert_ok!(ack(2, 1, new_key));
+				assert_eq!(GatekeeperMasterPubkey::<Test>::get(), Some(new_key));
+				assert_eq!(MasterKeyRotationLock::<Test>::get(), None);
+				let attempt = MasterKeyRotations::<Test>::get(1).unwrap();
+				assert_eq!(attempt.status, MasterKeyRotationStatus::Succeeded);
+				assert_eq!(attempt.acks, vec![worker_pubkey(1), worker_pubkey(2)]);
+				// Late acks are rejected
+				assert_eq!(
+					ack(3, 1, new_key),
+					Err(Error::<Test>::InvalidRotatedMasterPubkey.into())
+				);
+
+				// An unfinished rotation is aborted after the timeout
+				assert_ok!(PhalaRegistry::rotate_master_key(Origin::root()));
+				assert_ok!(ack(1, 2, old_key));
+				System::<Test>::set_block_number(11);
+				PhalaRegistry::on_initialize(11);
+				assert_eq!(MasterKeyRotationLock::<Test>::get(), Some(2));
+				System::<Test>::set_block_number(12);
+				PhalaRegistry::on_initialize(12);
+				assert_eq!(MasterKeyRotationLock::<Test>::get(), None);
+				assert_eq!(
+					MasterKeyRotations::<Test>::get(2).unwrap().status,
+					MasterKeyRotationStatus::Aborted
+				);
+				assert_eq!(GatekeeperMasterPubkey::<Test>::get(), Some(new_key));
+				assert_eq!(
+					ack(2, 2, old_key),
+					Err(Error::<Test>::InvalidRotatedMasterPubkey.into())
+				);
+			});
+		}
+
+		#[test]
+		fn test_legacy_master_key_rotation() {
+			use frame_system::Pallet as System;
+
+			new_test_ext().execute_with(|| {
+				set_block_1();
+				Gatekeeper::<Test>::put(vec![worker_pubkey(1)]);
+				let old_key = sr25519::Public::from_raw([8; 32]);
+				let new_key = sr25519::Public::from_raw([9; 32]);
+				GatekeeperMasterPubkey::<Test>::put(old_key);
+
+				assert_ok!(PhalaRegistry::rotate_master_key(Origin::root()));
+				// No deadline and no acks before the quorum rotation is enabled
+				System::<Test>::set_block_number(1000);
+				PhalaRegistry::on_initialize(1000);
+				assert_eq!(MasterKeyRotationLock::<Test>::get(), Some(1));
+				assert_eq!(
+					PhalaRegistry::on_message_received(DecodedMessage {
+						sender: MessageOrigin::Worker(worker_pubkey(1)),
+						destination: messaging::Topic::new(*b"^phala/registry/event"),
+						payload: RegistryEvent::MasterKeyRotationAck {
+							rotation_id: 1,
+							master_pubkey: new_key,
+						},
+					}),
+					Err(Error::<Test>::InvalidRotatedMasterPubkey.into())
+				);
+
+				// The master key is switched once the gatekeepers report it
+				assert_ok!(PhalaRegistry::on_gk_message_received(DecodedMessage {
+					sender: MessageOrigin::Gatekeeper,
+					destination: messaging::Topic::new(*b"^phala/registry/gk_event"),
+					payload: GatekeeperRegistryEvent::RotatedMasterPubkey {
+						rotation_id: 1,
+						master_pubkey: new_key,
+					},
+				}));
+				assert_eq!(GatekeeperMasterPubkey::<Test>::get(), Some(new_key));
+				assert_eq!(MasterKeyRotationLock::<Test>::get(), None);
+				assert_eq!(
+					MasterKeyRotations::<Test>::get(1).unwrap().status,
+					MasterKeyRotationStatus::Succeeded
+				);
+			});
+		}
 	}
 }
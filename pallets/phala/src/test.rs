@@ -2056,6 +2056,340 @@ fn test_check_and_maybe_force_withdraw() {
 	});
 }
 
+#[test]
+fn test_valuation() {
+	use crate::compute::valuation;
+	new_test_ext().execute_with(|| {
+		mock_asset_id();
+		for staker in 1..=3 {
+			assert_ok!(PhalaWrappedBalances::wrap(
+				RuntimeOrigin::signed(staker),
+				500 * DOLLARS
+			));
+		}
+		set_block_1();
+		setup_workers(2);
+		setup_stake_pool_with_workers(1, &[1, 2]); // pid = 0
+		for staker in 1..=3 {
+			assert_ok!(PhalaStakePoolv2::contribute(
+				RuntimeOrigin::signed(staker),
+				0,
+				300 * DOLLARS,
+				None
+			));
+		}
+		assert_ok!(PhalaStakePoolv2::start_computing(
+			RuntimeOrigin::signed(1),
+			0,
+			worker_pubkey(1),
+			400 * DOLLARS
+		));
+		assert_ok!(PhalaStakePoolv2::start_computing(
+			RuntimeOrigin::signed(1),
+			0,
+			worker_pubkey(2),
+			300 * DOLLARS
+		));
+		// 200 PHA withdrawn from the free stake, 100 PHA queued
+		assert_ok!(PhalaStakePoolv2::withdraw(
+			RuntimeOrigin::signed(2),
+			0,
+			300 * DOLLARS,
+			None
+		));
+
+		let summary = valuation::pool_summary::<Test>(0).unwrap();
+		assert_eq!(summary.pool_type, PoolType::StakePool);
+		assert_eq!(summary.total_value, 700 * DOLLARS);
+		assert_eq!(summary.share_price, Some(DOLLARS));
+		assert_eq!(summary.free_stake, 0);
+		assert_eq!(summary.releasing_stake, 0);
+		assert_eq!(summary.withdrawing_shares, 100 * DOLLARS);
+		assert_eq!(summary.withdraw_queue_len, 1);
+		assert_eq!(summary.workers, 2);
+		assert!(valuation::pool_summary::<Test>(1).is_none());
+
+		let stakes = valuation::account_stakes::<Test>(&1);
+		assert_eq!(stakes.len(), 1);
+		assert_eq!(
+			(stakes[0].pid, stakes[0].shares, stakes[0].value),
+			(0, 300 * DOLLARS, 300 * DOLLARS)
+		);
+
+		// Not covered by any releasing stake, waits for the force withdrawal
+		let cool_down = PhalaComputation::cool_down_period();
+		let grace_period: u64 = <Test as stake_pool_v2::Config>::GracePeriod::get();
+		let withdrawals = valuation::pending_withdrawals::<Test>(&2);
+		assert_eq!(withdrawals.len(), 1);
+		let start_time = withdrawals[0].start_time;
+		assert_eq!(
+			(withdrawals[0].pid, withdrawals[0].value),
+			(0, 100 * DOLLARS)
+		);
+		assert_eq!(
+			withdrawals[0].estimated_release_at,
+			Some(start_time + grace_period + cool_down)
+		);
+		assert!(valuation::pending_withdrawals::<Test>(&1).is_empty());
+
+		// Covered by the stake of the cooling down worker
+		elapse_seconds(10);
+		assert_ok!(PhalaStakePoolv2::stop_computing(
+			RuntimeOrigin::signed(1),
+			0,
+			worker_pubkey(1),
+		));
+		let summary = valuation::pool_summary::<Test>(0).unwrap();
+		assert_eq!(summary.releasing_stake, 400 * DOLLARS);
+		let withdrawals = valuation::pending_withdrawals::<Test>(&2);
+		assert_eq!(
+			withdrawals[0].estimated_release_at,
+			Some(start_time + 10 + cool_down)
+		);
+
+		let rewards = valuation::owner_rewards::<Test>(0).unwrap();
+		assert_eq!((rewards.claimable, rewards.pending), (0, 0));
+	});
+}
+
+#[test]
+fn test_cancel_and_replace_withdrawal() {
+	use crate::compute::valuation;
+	new_test_ext().execute_with(|| {
+		setup_pool_with_queued_withdrawal(); // pid = 0, staker 2 has 100 shares queued
+		let queued_shares = |user: u64| {
+			let pool = ensure_stake_pool::<Test>(0).unwrap();
+			let withdraw = pool
+				.basepool
+				.withdraw_queue
+				.iter()
+				.find(|withdraw| withdraw.user == user)
+				.cloned()
+				.unwrap();
+			let shares = PhalaBasePool::get_nft_attr(pool.basepool.cid, withdraw.nft_id)
+				.unwrap()
+				.shares;
+			(withdraw, shares)
+		};
+		let free_shares = |user: u64| valuation::account_stakes::<Test>(&user)[0].shares;
+
+		assert_noop!(
+			PhalaBasePool::replace_withdrawal(RuntimeOrigin::signed(3), 0, 50 * DOLLARS, 0, None),
+			base_pool::Error::<Test>::NoWithdrawalInQueue
+		);
+		assert_noop!(
+			PhalaBasePool::replace_withdrawal(RuntimeOrigin::signed(2), 0, 101 * DOLLARS, 0, None),
+			base_pool::Error::<Test>::InvalidWithdrawalAmount
+		);
+		assert_noop!(
+			PhalaBasePool::replace_withdrawal(
+				RuntimeOrigin::signed(2),
+				0,
+				50 * DOLLARS,
+				60 * DOLLARS,
+				None
+			),
+			base_pool::Error::<Test>::InvalidMinFill
+		);
+		// Decreasing the shares keeps the place in the queue
+		let (withdraw, _) = queued_shares(2);
+		elapse_seconds(10);
+		assert_ok!(PhalaBasePool::replace_withdrawal(
+			RuntimeOrigin::signed(2),
+			0,
+			50 * DOLLARS,
+			0,
+			None
+		));
+		let (replaced, shares) = queued_shares(2);
+		assert_eq!(replaced, withdraw);
+		assert_eq!(shares, 50 * DOLLARS);
+		assert_eq!(free_shares(2), 50 * DOLLARS);
+
+		// Increasing the shares moves the withdrawal to the end of the queue
+		assert_ok!(PhalaStakePoolv2::withdraw(
+			RuntimeOrigin::signed(1),
+			0,
+			100 * DOLLARS,
+			None
+		));
+		assert_ok!(PhalaBasePool::replace_withdrawal(
+			RuntimeOrigin::signed(2),
+			0,
+			80 * DOLLARS,
+			0,
+			None
+		));
+		let pool = ensure_stake_pool::<Test>(0).unwrap();
+		let users: Vec<_> = pool
+			.basepool
+			.withdraw_queue
+			.iter()
+			.map(|w| w.user)
+			.collect();
+		assert_eq!(users, vec![1, 2]);
+		let (replaced, shares) = queued_shares(2);
+		assert_eq!(replaced.start_time, withdraw.start_time + 10);
+		assert_eq!(shares, 80 * DOLLARS);
+		assert_eq!(free_shares(2), 20 * DOLLARS);
+
+		// Cancelling returns the shares to the staker
+		assert_noop!(
+			PhalaBasePool::cancel_withdrawal(RuntimeOrigin::signed(3), 0, None),
+			base_pool::Error::<Test>::NoWithdrawalInQueue
+		);
+		assert_ok!(PhalaBasePool::cancel_withdrawal(
+			RuntimeOrigin::signed(1),
+			0,
+			None
+		));
+		let pool = ensure_stake_pool::<Test>(0).unwrap();
+		let users: Vec<_> = pool
+			.basepool
+			.withdraw_queue
+			.iter()
+			.map(|w| w.user)
+			.collect();
+		assert_eq!(users, vec![2]);
+		assert_eq!(pool.basepool.total_shares, 700 * DOLLARS);
+		assert_eq!(free_shares(1), 300 * DOLLARS);
+		let nfts: Vec<_> =
+			pallet_uniques::Pallet::<Test>::owned_in_collection(&pool.basepool.cid, &1).collect();
+		assert_eq!(nfts.len(), 1);
+	});
+}
+
+#[test]
+fn test_withdrawal_min_fill_and_on_idle() {
+	use frame_support::{
+		traits::{fungibles::Mutate, tokens::Preservation, Hooks},
+		weights::Weight,
+	};
+	new_test_ext().execute_with(|| {
+		setup_pool_with_queued_withdrawal(); // pid = 0, staker 2 has 100 shares queued
+		assert_ok!(PhalaBasePool::replace_withdrawal(
+			RuntimeOrigin::signed(2),
+			0,
+			100 * DOLLARS,
+			60 * DOLLARS,
+			None
+		));
+		let pool = ensure_stake_pool::<Test>(0).unwrap();
+		let withdraw_nft_id = pool.basepool.withdraw_queue[0].nft_id;
+		assert_eq!(
+			base_pool::WithdrawMinFills::<Test>::get((pool.basepool.cid, withdraw_nft_id)),
+			Some(60 * DOLLARS)
+		);
+		// The free stake is not enough for the minimum fill
+		assert_ok!(PhalaStakePoolv2::contribute(
+			RuntimeOrigin::signed(99),
+			0,
+			50 * DOLLARS,
+			None
+		));
+		assert_eq!(get_balance(2), 400 * DOLLARS);
+		let pool = ensure_stake_pool::<Test>(0).unwrap();
+		assert_eq!(pool.basepool.get_free_stakes::<Test>(), 50 * DOLLARS);
+
+		// Free stake shows up without touching the pool
+		assert_ok!(<pallet_assets::Pallet<Test> as Mutate<u64>>::transfer(
+			<Test as wrapped_balances::Config>::WPhaAssetId::get(),
+			&99,
+			&pool.basepool.pool_account_id,
+			50 * DOLLARS,
+			Preservation::Expendable,
+		));
+		PhalaBasePool::on_idle(1, Weight::MAX);
+		assert_eq!(get_balance(2), 500 * DOLLARS);
+		let pool = ensure_stake_pool::<Test>(0).unwrap();
+		assert!(pool.basepool.withdraw_queue.is_empty());
+		assert!(
+			base_pool::WithdrawMinFills::<Test>::get((pool.basepool.cid, withdraw_nft_id))
+				.is_none()
+		);
+		assert_eq!(base_pool::WithdrawQueueIterateStartPos::<Test>::get(), 0);
+	});
+}
+
+#[test]
+fn test_withdrawal_min_fill_does_not_block_queue() {
+	new_test_ext().execute_with(|| {
+		setup_pool_with_queued_withdrawal(); // pid = 0, staker 2 has 100 shares queued
+		assert_ok!(PhalaBasePool::replace_withdrawal(
+			RuntimeOrigin::signed(2),
+			0,
+			100 * DOLLARS,
+			60 * DOLLARS,
+			None
+		));
+		assert_ok!(PhalaStakePoolv2::withdraw(
+			RuntimeOrigin::signed(3),
+			0,
+			50 * DOLLARS,
+			None
+		));
+		assert_eq!(get_balance(3), 200 * DOLLARS);
+		// The free stake is below the minimum fill of the first withdrawal, but enough for the
+		// second one
+		assert_ok!(PhalaStakePoolv2::contribute(
+			RuntimeOrigin::signed(99),
+			0,
+			50 * DOLLARS,
+			None
+		));
+		assert_eq!(get_balance(2), 400 * DOLLARS);
+		assert_eq!(get_balance(3), 250 * DOLLARS);
+		let pool = ensure_stake_pool::<Test>(0).unwrap();
+		assert_eq!(pool.basepool.withdraw_queue.len(), 1);
+		assert_eq!(pool.basepool.withdraw_queue[0].user, 2);
+	});
+}
+
+/// Sets up stake pool 0 with 700 PHA computing and 100 shares of staker 2 in the withdraw queue
+fn setup_pool_with_queued_withdrawal() {
+	mock_asset_id();
+	for staker in [1, 2, 3] {
+		assert_ok!(PhalaWrappedBalances::wrap(
+			RuntimeOrigin::signed(staker),
+			500 * DOLLARS
+		));
+	}
+	assert_ok!(PhalaWrappedBalances::wrap(
+		RuntimeOrigin::signed(99),
+		5000 * DOLLARS
+	));
+	set_block_1();
+	setup_workers(2);
+	setup_stake_pool_with_workers(1, &[1, 2]); // pid = 0
+	for staker in [1, 2, 3] {
+		assert_ok!(PhalaStakePoolv2::contribute(
+			RuntimeOrigin::signed(staker),
+			0,
+			300 * DOLLARS,
+			None
+		));
+	}
+	assert_ok!(PhalaStakePoolv2::start_computing(
+		RuntimeOrigin::signed(1),
+		0,
+		worker_pubkey(1),
+		400 * DOLLARS
+	));
+	assert_ok!(PhalaStakePoolv2::start_computing(
+		RuntimeOrigin::signed(1),
+		0,
+		worker_pubkey(2),
+		300 * DOLLARS
+	));
+	// 200 PHA withdrawn from the free stake, 100 PHA queued
+	assert_ok!(PhalaStakePoolv2::withdraw(
+		RuntimeOrigin::signed(2),
+		0,
+		300 * DOLLARS,
+		None
+	));
+}
+
 fn mock_asset_id() {
 	<pallet_assets::pallet::Pallet<Test> as Create<u64>>::create(
 		<Test as wrapped_balances::Config>::WPhaAssetId::get(),
@@ -17,6 +17,18 @@ pub enum Error {
 	UnknownQuoteBodyFormat,
 	InvalidUserDataHash,
 	NoneAttestationDisabled,
+	InvalidDcapQuote,
+	UnsupportedDcapQuoteVersion,
+	InvalidPckCertChain,
+	InvalidQuoteSignature,
+	InvalidDcapCollateral,
+	OutdatedDcapCollateral,
+	QeIdentityMismatch,
+	DcapRootCaNotSet,
+	InvalidDcapCrl,
+	DcapCertRevoked,
+	MockAttestationDisabled,
+	OutdatedMockReport,
 }
 
 #[derive(Encode, Decode, TypeInfo, Debug, Clone, PartialEq, Eq)]
@@ -126,6 +138,8 @@ pub fn validate(
 	verify_pruntime_hash: bool,
 	pruntime_allowlist: Vec<Vec<u8>>,
 	opt_out_enabled: bool,
+	mock_enabled: bool,
+	dcap_root_ca: Option<&[u8]>,
 ) -> Result<ConfidentialReport, Error> {
 	match attestation {
 		Some(AttestationReport::SgxIas {
@@ -141,6 +155,35 @@ pub fn validate(
 			verify_pruntime_hash,
 			pruntime_allowlist,
 		),
+		Some(AttestationReport::SgxDcap { quote, collateral }) => {
+			crate::utils::attestation_dcap::validate_dcap_quote(
+				user_data_hash,
+				&quote,
+				&collateral,
+				now,
+				verify_pruntime_hash,
+				pruntime_allowlist,
+				dcap_root_ca,
+			)
+		}
+		Some(AttestationReport::Mock {
+			runtime_hash,
+			report_data,
+			timestamp,
+		}) => {
+			if !mock_enabled {
+				return Err(Error::MockAttestationDisabled);
+			}
+			validate_mock_report(
+				user_data_hash,
+				runtime_hash,
+				&report_data,
+				timestamp,
+				now,
+				verify_pruntime_hash,
+				pruntime_allowlist,
+			)
+		}
 		None => {
 			if opt_out_enabled {
 				Ok(ConfidentialReport {
@@ -207,6 +250,35 @@ pub fn validate_ias_report(
 	})
 }
 
+pub fn validate_mock_report(
+	user_data_hash: &[u8],
+	runtime_hash: Vec<u8>,
+	report_data: &[u8],
+	timestamp: u64,
+	now: u64,
+	verify_pruntime_hash: bool,
+	pruntime_allowlist: Vec<Vec<u8>>,
+) -> Result<ConfidentialReport, Error> {
+	if verify_pruntime_hash && !pruntime_allowlist.contains(&runtime_hash) {
+		return Err(Error::PRuntimeRejected);
+	}
+
+	// Same validity period as IAS reports
+	if (now as i64 - timestamp as i64) >= 7200 {
+		return Err(Error::OutdatedMockReport);
+	}
+
+	if report_data.get(..32) != Some(user_data_hash) {
+		return Err(Error::InvalidUserDataHash);
+	}
+
+	Ok(ConfidentialReport {
+		provider: Some(AttestationProvider::Mock),
+		runtime_hash,
+		confidence_level: 128u8,
+	})
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -280,4 +352,59 @@ mod test {
 			vec![hex::decode(PRUNTIME_HASH).unwrap()]
 		));
 	}
+
+	#[test]
+	fn test_mock_validator() {
+		let now = 1_000_000u64;
+		let report = AttestationReport::Mock {
+			runtime_hash: vec![1, 2, 3],
+			report_data: [7u8; 32].to_vec(),
+			timestamp: now,
+		};
+		assert_eq!(
+			validate(
+				Some(report),
+				&[7u8; 32],
+				now,
+				false,
+				vec![],
+				false,
+				false,
+				None
+			),
+			Err(Error::MockAttestationDisabled)
+		);
+
+		let validate_mock = |user_data_hash: &[u8], timestamp, allowlist: Option<Vec<u8>>| {
+			validate_mock_report(
+				user_data_hash,
+				vec![1, 2, 3],
+				&[7u8; 32],
+				timestamp,
+				now,
+				allowlist.is_some(),
+				allowlist.into_iter().collect(),
+			)
+		};
+		assert_eq!(
+			validate_mock(&[0u8; 32], now, None),
+			Err(Error::InvalidUserDataHash)
+		);
+		assert_eq!(
+			validate_mock(&[7u8; 32], now - 7200, None),
+			Err(Error::OutdatedMockReport)
+		);
+		assert_eq!(
+			validate_mock(&[7u8; 32], now, Some(vec![4, 5, 6])),
+			Err(Error::PRuntimeRejected)
+		);
+		assert_eq!(
+			validate_mock(&[7u8; 32], now, Some(vec![1, 2, 3])),
+			Ok(ConfidentialReport {
+				provider: Some(AttestationProvider::Mock),
+				runtime_hash: vec![1, 2, 3],
+				confidence_level: 128,
+			})
+		);
+	}
 }
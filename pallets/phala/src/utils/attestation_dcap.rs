@@ -0,0 +1,819 @@
+//! Verification of SGX DCAP (ECDSA) quotes.
+//!
+//! A quote is verified against the collateral fetched from the PCCS by the worker and the Intel SGX
+//! Root CA configured on chain. All the inputs, including the current time, are passed in as plain
+//! values, so recorded quotes and collaterals can be replayed as test fixtures.
+
+use crate::constants::*;
+use crate::utils::attestation::{ConfidentialReport, Error};
+
+use phala_dcap::der::{der_time, parse_signed, CertFields, Der, DER_TAG_INTEGER, DER_TAG_SEQUENCE};
+use phala_dcap::{pem_to_der_chain, EnclaveReport, PckExtensions, Quote, QuoteError};
+use phala_types::{AttestationProvider, Collateral, SgxV30QuoteCollateral};
+use sp_std::{convert::TryFrom, vec, vec::Vec};
+
+impl From<QuoteError> for Error {
+	fn from(err: QuoteError) -> Self {
+		match err {
+			QuoteError::Invalid => Error::InvalidDcapQuote,
+			QuoteError::UnsupportedVersion => Error::UnsupportedDcapQuoteVersion,
+		}
+	}
+}
+
+const V2_TCB_COMPONENT_KEYS: [&str; 16] = [
+	"sgxtcbcomp01svn",
+	"sgxtcbcomp02svn",
+	"sgxtcbcomp03svn",
+	"sgxtcbcomp04svn",
+	"sgxtcbcomp05svn",
+	"sgxtcbcomp06svn",
+	"sgxtcbcomp07svn",
+	"sgxtcbcomp08svn",
+	"sgxtcbcomp09svn",
+	"sgxtcbcomp10svn",
+	"sgxtcbcomp11svn",
+	"sgxtcbcomp12svn",
+	"sgxtcbcomp13svn",
+	"sgxtcbcomp14svn",
+	"sgxtcbcomp15svn",
+	"sgxtcbcomp16svn",
+];
+
+/// Verify `tbs` is signed by `issuer_cert`, whose subject must be `issuer`.
+fn verify_issued_by(issuer_cert: &[u8], issuer: &[u8], tbs: &[u8], signature: &[u8]) -> bool {
+	let Some(cert) = CertFields::parse(issuer_cert) else {
+		return false;
+	};
+	cert.subject == issuer
+		&& webpki::EndEntityCert::try_from(issuer_cert)
+			.and_then(|signer| signer.verify_signature(&webpki::ECDSA_P256_SHA256, tbs, signature))
+			.is_ok()
+}
+
+/// Verify the CA cert is issued by `issuer_cert` and is valid at `now`.
+///
+/// webpki only verifies the chains of end-entity certs, while the PCK CRL is issued by an
+/// intermediate CA. The Intel SGX PKI has only two levels, so the intermediate CAs are issued
+/// by the root CA directly.
+fn verify_ca_issued_by(cert: &CertFields, issuer_cert: &[u8], now: u64) -> bool {
+	let now = now as i64;
+	cert.not_before <= now
+		&& now <= cert.not_after
+		&& verify_issued_by(issuer_cert, cert.issuer, cert.tbs, cert.signature)
+}
+
+/// A DER encoded certificate revocation list.
+struct Crl<'a> {
+	/// The signed `tbsCertList`.
+	tbs: &'a [u8],
+	signature: &'a [u8],
+	/// The DER encoded issuer name.
+	issuer: &'a [u8],
+	this_update: i64,
+	next_update: i64,
+	revoked_serials: Vec<&'a [u8]>,
+}
+
+impl<'a> Crl<'a> {
+	fn parse(crl: &'a [u8]) -> Option<Self> {
+		let (tbs, signature) = parse_signed(crl)?;
+		let mut fields = Der::new(tbs.value);
+		if fields.peek_tag() == Some(DER_TAG_INTEGER) {
+			// version
+			fields.read()?;
+		}
+		// signature
+		fields.expect(DER_TAG_SEQUENCE)?;
+		let issuer = fields.expect(DER_TAG_SEQUENCE)?.raw;
+		let this_update = der_time(&fields.read()?)?;
+		// nextUpdate is optional in X.509, but always present in the CRLs of Intel
+		let next_update = der_time(&fields.read()?)?;
+		let mut revoked_serials = Vec::new();
+		if fields.peek_tag() == Some(DER_TAG_SEQUENCE) {
+			let mut entries = fields.sequence()?;
+			while entries.peek_tag().is_some() {
+				revoked_serials.push(entries.sequence()?.expect(DER_TAG_INTEGER)?.value);
+			}
+		}
+		Some(Self {
+			tbs: tbs.raw,
+			signature,
+			issuer,
+			this_update,
+			next_update,
+			revoked_serials,
+		})
+	}
+
+	/// Verify the CRL is issued by `issuer_cert` and is valid at `now`.
+	fn verify(&self, issuer_cert: &[u8], now: u64) -> Result<(), Error> {
+		if !verify_issued_by(issuer_cert, self.issuer, self.tbs, self.signature) {
+			return Err(Error::InvalidDcapCrl);
+		}
+		let now = now as i64;
+		if now < self.this_update || now > self.next_update {
+			return Err(Error::OutdatedDcapCollateral);
+		}
+		Ok(())
+	}
+
+	/// Check the cert is not revoked by this CRL. Certs issued by other CAs are ignored.
+	fn check(&self, cert: &[u8]) -> Result<(), Error> {
+		let cert = CertFields::parse(cert).ok_or(Error::InvalidPckCertChain)?;
+		if cert.issuer == self.issuer && self.revoked_serials.contains(&cert.serial) {
+			return Err(Error::DcapCertRevoked);
+		}
+		Ok(())
+	}
+}
+
+/// Encode a raw `r || s` ECDSA P-256 signature in ASN.1 DER, as expected by webpki.
+fn raw_signature_to_der(signature: &[u8]) -> Option<Vec<u8>> {
+	fn integer(mut bytes: &[u8]) -> Vec<u8> {
+		while bytes.len() > 1 && bytes[0] == 0 {
+			bytes = &bytes[1..];
+		}
+		let mut der = vec![DER_TAG_INTEGER];
+		if bytes[0] & 0x80 != 0 {
+			der.push(bytes.len() as u8 + 1);
+			der.push(0);
+		} else {
+			der.push(bytes.len() as u8);
+		}
+		der.extend_from_slice(bytes);
+		der
+	}
+	if signature.len() != 64 {
+		return None;
+	}
+	let r = integer(&signature[..32]);
+	let s = integer(&signature[32..]);
+	let mut der = vec![0x30, (r.len() + s.len()) as u8];
+	der.extend_from_slice(&r);
+	der.extend_from_slice(&s);
+	Some(der)
+}
+
+fn verify_raw_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+	let mut key = vec![0x04];
+	key.extend_from_slice(public_key);
+	ring::signature::UnparsedPublicKey::new(&ring::signature::ECDSA_P256_SHA256_FIXED, key)
+		.verify(message, signature)
+		.is_ok()
+}
+
+/// Verify the cert chain up to the root CA and return the leaf cert.
+fn verify_cert_chain<'a>(
+	certs: &'a [Vec<u8>],
+	root_ca: &[u8],
+	now: u64,
+) -> Option<webpki::EndEntityCert<'a>> {
+	let anchors = [webpki::TrustAnchor::try_from_cert_der(root_ca).ok()?];
+	let (leaf, intermediates) = certs.split_first()?;
+	let leaf = webpki::EndEntityCert::try_from(leaf.as_slice()).ok()?;
+	let intermediates: Vec<&[u8]> = intermediates.iter().map(|cert| cert.as_slice()).collect();
+	let time_now = webpki::Time::from_seconds_since_unix_epoch(now);
+	leaf.verify_is_valid_tls_server_cert(
+		DCAP_SUPPORTED_SIG_ALGS,
+		&webpki::TlsServerTrustAnchors(&anchors),
+		&intermediates,
+		time_now,
+	)
+	.ok()?;
+	Some(leaf)
+}
+
+/// Verify a signed collateral item and parse its JSON body.
+fn verify_signed_json(
+	issuer_chain: &str,
+	body: &str,
+	signature: &[u8],
+	root_ca: &[u8],
+	now: u64,
+) -> Result<serde_json::Value, Error> {
+	let certs = pem_to_der_chain(issuer_chain.as_bytes()).ok_or(Error::InvalidDcapCollateral)?;
+	let signer = verify_cert_chain(&certs, root_ca, now).ok_or(Error::InvalidDcapCollateral)?;
+	let signature = raw_signature_to_der(signature).ok_or(Error::InvalidDcapCollateral)?;
+	signer
+		.verify_signature(&webpki::ECDSA_P256_SHA256, body.as_bytes(), &signature)
+		.or(Err(Error::InvalidDcapCollateral))?;
+	let body: serde_json::Value =
+		serde_json::from_str(body).or(Err(Error::InvalidDcapCollateral))?;
+	let next_update = body["nextUpdate"]
+		.as_str()
+		.ok_or(Error::InvalidDcapCollateral)?;
+	let next_update = chrono::DateTime::parse_from_rfc3339(next_update)
+		.or(Err(Error::InvalidDcapCollateral))?
+		.timestamp();
+	if now as i64 > next_update {
+		return Err(Error::OutdatedDcapCollateral);
+	}
+	Ok(body)
+}
+
+fn decode_hex<const N: usize>(value: &serde_json::Value) -> Option<[u8; N]> {
+	let mut buf = [0u8; N];
+	hex::decode_to_slice(value.as_str()?, &mut buf).ok()?;
+	Some(buf)
+}
+
+fn tcb_level_components(tcb: &serde_json::Value) -> Option<([u8; 16], u16)> {
+	let mut components = [0u8; 16];
+	if let Some(list) = tcb["sgxtcbcomponents"].as_array() {
+		// TCB info v3
+		if list.len() != components.len() {
+			return None;
+		}
+		for (component, item) in components.iter_mut().zip(list) {
+			*component = u8::try_from(item["svn"].as_u64()?).ok()?;
+		}
+	} else {
+		// TCB info v2
+		for (component, key) in components.iter_mut().zip(V2_TCB_COMPONENT_KEYS) {
+			*component = u8::try_from(tcb[key].as_u64()?).ok()?;
+		}
+	}
+	let pce_svn = u16::try_from(tcb["pcesvn"].as_u64()?).ok()?;
+	Some((components, pce_svn))
+}
+
+/// Find the TCB status and the advisory IDs of the platform in the TCB info.
+pub fn platform_tcb_status<'a>(
+	tcb_info: &'a serde_json::Value,
+	pck: &PckExtensions,
+) -> Result<(&'a str, Vec<&'a str>), Error> {
+	let levels = tcb_info["tcbLevels"]
+		.as_array()
+		.ok_or(Error::InvalidDcapCollateral)?;
+	for level in levels {
+		let (components, pce_svn) =
+			tcb_level_components(&level["tcb"]).ok_or(Error::InvalidDcapCollateral)?;
+		let below_level = pck.pce_svn < pce_svn
+			|| pck
+				.tcb_components
+				.iter()
+				.zip(components.iter())
+				.any(|(platform, level)| platform < level);
+		if below_level {
+			continue;
+		}
+		let status = level["tcbStatus"]
+			.as_str()
+			.ok_or(Error::InvalidDcapCollateral)?;
+		let advisory_ids = level["advisoryIDs"]
+			.as_array()
+			.map(|ids| ids.iter().filter_map(|id| id.as_str()).collect())
+			.unwrap_or_default();
+		return Ok((status, advisory_ids));
+	}
+	Err(Error::InvalidQuoteStatus)
+}
+
+/// Check the QE report against the QE identity, and find the TCB status of the QE.
+///
+/// `miscselect` and `attributes` are compared under their masks. `miscselect` is the hex of the
+/// 32-bit value, while `attributes` is the hex of the raw bytes in the report.
+pub fn qe_tcb_status<'a>(
+	qe_identity: &'a serde_json::Value,
+	qe_report: &EnclaveReport,
+) -> Result<&'a str, Error> {
+	let mr_signer: [u8; 32] =
+		decode_hex(&qe_identity["mrsigner"]).ok_or(Error::InvalidDcapCollateral)?;
+	let prod_id = qe_identity["isvprodid"]
+		.as_u64()
+		.ok_or(Error::InvalidDcapCollateral)?;
+	let misc_select = decode_hex(&qe_identity["miscselect"])
+		.map(u32::from_be_bytes)
+		.ok_or(Error::InvalidDcapCollateral)?;
+	let misc_select_mask = decode_hex(&qe_identity["miscselectMask"])
+		.map(u32::from_be_bytes)
+		.ok_or(Error::InvalidDcapCollateral)?;
+	let attributes: [u8; 16] =
+		decode_hex(&qe_identity["attributes"]).ok_or(Error::InvalidDcapCollateral)?;
+	let attributes_mask: [u8; 16] =
+		decode_hex(&qe_identity["attributesMask"]).ok_or(Error::InvalidDcapCollateral)?;
+	let attributes_match = qe_report
+		.attributes
+		.iter()
+		.zip(attributes_mask)
+		.map(|(attribute, mask)| attribute & mask)
+		.eq(attributes);
+	if mr_signer != qe_report.mr_signer
+		|| prod_id != u16::from_le_bytes(qe_report.isv_prod_id) as u64
+		|| qe_report.misc_select & misc_select_mask != misc_select
+		|| !attributes_match
+	{
+		return Err(Error::QeIdentityMismatch);
+	}
+
+	let isv_svn = u16::from_le_bytes(qe_report.isv_svn) as u64;
+	let levels = qe_identity["tcbLevels"]
+		.as_array()
+		.ok_or(Error::InvalidDcapCollateral)?;
+	for level in levels {
+		let level_svn = level["tcb"]["isvsvn"]
+			.as_u64()
+			.ok_or(Error::InvalidDcapCollateral)?;
+		if isv_svn >= level_svn {
+			return level["tcbStatus"]
+				.as_str()
+				.ok_or(Error::InvalidDcapCollateral);
+		}
+	}
+	Err(Error::InvalidQuoteStatus)
+}
+
+/// Check none of the certs involved in the quote verification is revoked.
+///
+/// The PCK cert is checked against the CRL of its issuing CA, and all the intermediate certs,
+/// including the signers of the collateral, against the CRL of the root CA.
+fn check_revocation(
+	collateral: &SgxV30QuoteCollateral,
+	pck_chain: &[Vec<u8>],
+	root_ca: &[u8],
+	now: u64,
+) -> Result<(), Error> {
+	let root_ca_crl = Crl::parse(&collateral.root_ca_crl).ok_or(Error::InvalidDcapCrl)?;
+	root_ca_crl.verify(root_ca, now)?;
+
+	let pck_crl_chain = pem_to_der_chain(collateral.pck_crl_issuer_chain.as_bytes())
+		.ok_or(Error::InvalidDcapCrl)?;
+	let pck_crl_issuer = CertFields::parse(&pck_crl_chain[0]).ok_or(Error::InvalidDcapCrl)?;
+	if !verify_ca_issued_by(&pck_crl_issuer, root_ca, now) {
+		return Err(Error::InvalidDcapCrl);
+	}
+	let pck_crl = Crl::parse(&collateral.pck_crl).ok_or(Error::InvalidDcapCrl)?;
+	pck_crl.verify(&pck_crl_chain[0], now)?;
+	let pck_cert = CertFields::parse(&pck_chain[0]).ok_or(Error::InvalidPckCertChain)?;
+	if pck_cert.issuer != pck_crl.issuer {
+		return Err(Error::InvalidDcapCrl);
+	}
+	pck_crl.check(&pck_chain[0])?;
+
+	let mut certs = pck_chain.to_vec();
+	certs.extend(pck_crl_chain);
+	for chain in [
+		&collateral.tcb_info_issuer_chain,
+		&collateral.qe_identity_issuer_chain,
+	] {
+		certs.extend(pem_to_der_chain(chain.as_bytes()).ok_or(Error::InvalidDcapCollateral)?);
+	}
+	for cert in certs.iter().filter(|cert| cert.as_slice() != root_ca) {
+		root_ca_crl.check(cert)?;
+	}
+	Ok(())
+}
+
+/// Map the TCB status to the confidence level, in the same way as the IAS quote status.
+pub fn confidence_level(tcb_status: &str, advisory_ids: &[&str]) -> Result<u8, Error> {
+	let confidence_level = if DCAP_TCB_STATUS_LEVEL_1.contains(&tcb_status) {
+		1
+	} else if DCAP_TCB_STATUS_LEVEL_2.contains(&tcb_status) {
+		2
+	} else if DCAP_TCB_STATUS_LEVEL_3.contains(&tcb_status) {
+		3
+	} else if DCAP_TCB_STATUS_LEVEL_5.contains(&tcb_status) {
+		5
+	} else {
+		return Err(Error::InvalidQuoteStatus);
+	};
+	if confidence_level == 3
+		&& advisory_ids
+			.iter()
+			.any(|id| !IAS_QUOTE_ADVISORY_ID_WHITELIST.contains(id))
+	{
+		return Ok(4);
+	}
+	Ok(confidence_level)
+}
+
+pub fn validate_dcap_quote(
+	user_data_hash: &[u8],
+	raw_quote: &[u8],
+	collateral: &Collateral,
+	now: u64,
+	verify_pruntime_hash: bool,
+	pruntime_allowlist: Vec<Vec<u8>>,
+	root_ca: Option<&[u8]>,
+) -> Result<ConfidentialReport, Error> {
+	let root_ca = root_ca.ok_or(Error::DcapRootCaNotSet)?;
+	let Collateral::SgxV30(collateral) = collateral;
+	let quote = Quote::parse(raw_quote)?;
+
+	// The QE report is signed by the PCK cert issued by Intel
+	let pck_chain = pem_to_der_chain(quote.pck_cert_chain).ok_or(Error::InvalidPckCertChain)?;
+	let pck_cert = verify_cert_chain(&pck_chain, root_ca, now).ok_or(Error::InvalidPckCertChain)?;
+	check_revocation(collateral, &pck_chain, root_ca, now)?;
+	let qe_report_signature =
+		raw_signature_to_der(quote.qe_report_signature).ok_or(Error::InvalidQuoteSignature)?;
+	pck_cert
+		.verify_signature(
+			&webpki::ECDSA_P256_SHA256,
+			quote.raw_qe_report,
+			&qe_report_signature,
+		)
+		.or(Err(Error::InvalidQuoteSignature))?;
+
+	// The QE binds the attestation key to its report
+	let mut key_binding = quote.attestation_key.to_vec();
+	key_binding.extend_from_slice(quote.qe_auth_data);
+	let key_hash = ring::digest::digest(&ring::digest::SHA256, &key_binding);
+	if quote.qe_report.report_data[..32] != *key_hash.as_ref() {
+		return Err(Error::InvalidQuoteSignature);
+	}
+
+	// The enclave report is signed by the attestation key
+	if !verify_raw_signature(quote.attestation_key, quote.signed_data, quote.signature) {
+		return Err(Error::InvalidQuoteSignature);
+	}
+
+	// Check the QE is the one published by Intel
+	let qe_identity = verify_signed_json(
+		&collateral.qe_identity_issuer_chain,
+		&collateral.qe_identity,
+		&collateral.qe_identity_signature,
+		root_ca,
+		now,
+	)?;
+	let qe_tcb_status = qe_tcb_status(&qe_identity, &quote.qe_report)?;
+
+	// Evaluate the TCB status of the platform
+	let pck = PckExtensions::parse(&pck_chain[0]).ok_or(Error::InvalidPckCertChain)?;
+	let tcb_info = verify_signed_json(
+		&collateral.tcb_info_issuer_chain,
+		&collateral.tcb_info,
+		&collateral.tcb_info_signature,
+		root_ca,
+		now,
+	)?;
+	let fmspc: [u8; 6] = decode_hex(&tcb_info["fmspc"]).ok_or(Error::InvalidDcapCollateral)?;
+	if fmspc != pck.fmspc {
+		return Err(Error::InvalidDcapCollateral);
+	}
+	let (tcb_status, advisory_ids) = platform_tcb_status(&tcb_info, &pck)?;
+	let mut confidence_level = confidence_level(tcb_status, &advisory_ids)?;
+	match qe_tcb_status {
+		"UpToDate" => {}
+		"OutOfDate" => {
+			confidence_level = confidence_level.max(self::confidence_level(qe_tcb_status, &[])?)
+		}
+		_ => return Err(Error::InvalidQuoteStatus),
+	}
+
+	// Validate PRuntime
+	let pruntime_hash = quote.report.runtime_hash();
+	if verify_pruntime_hash && !pruntime_allowlist.contains(&pruntime_hash) {
+		return Err(Error::PRuntimeRejected);
+	}
+
+	let commit = &quote.report.report_data[..32];
+	if commit != user_data_hash {
+		return Err(Error::InvalidUserDataHash);
+	}
+
+	Ok(ConfidentialReport {
+		provider: Some(AttestationProvider::Dcap),
+		runtime_hash: pruntime_hash,
+		confidence_level,
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_signature_to_der() {
+		let mut signature = [0u8; 64];
+		signature[0] = 0x80;
+		signature[63] = 1;
+		assert_eq!(
+			raw_signature_to_der(&signature).unwrap()[..8],
+			[0x30, 38, 0x02, 33, 0x00, 0x80, 0x00, 0x00]
+		);
+		assert_eq!(
+			raw_signature_to_der(&signature).unwrap()[37..],
+			[0x02, 1, 1]
+		);
+		assert!(raw_signature_to_der(&signature[..63]).is_none());
+	}
+
+	/// A quote with its collateral, issued by a test root CA. See `gen_dcap_attestation.py`.
+	fn sample() -> serde_json::Value {
+		serde_json::from_slice(include_bytes!("../../sample/dcap_attestation.json")).unwrap()
+	}
+
+	fn sample_bytes(value: &serde_json::Value) -> Vec<u8> {
+		hex::decode(value.as_str().unwrap()).unwrap()
+	}
+
+	fn sample_collateral(sample: &serde_json::Value) -> SgxV30QuoteCollateral {
+		let collateral = &sample["collateral"];
+		let text = |key: &str| collateral[key].as_str().unwrap().to_string();
+		SgxV30QuoteCollateral {
+			tcb_info_issuer_chain: text("tcb_info_issuer_chain"),
+			tcb_info: text("tcb_info"),
+			tcb_info_signature: sample_bytes(&collateral["tcb_info_signature"]),
+			qe_identity_issuer_chain: text("qe_identity_issuer_chain"),
+			qe_identity: text("qe_identity"),
+			qe_identity_signature: sample_bytes(&collateral["qe_identity_signature"]),
+			root_ca_crl: sample_bytes(&collateral["root_ca_crl"]),
+			pck_crl_issuer_chain: text("pck_crl_issuer_chain"),
+			pck_crl: sample_bytes(&collateral["pck_crl"]),
+		}
+	}
+
+	fn validate_sample(
+		sample: &serde_json::Value,
+		quote: &[u8],
+		collateral: SgxV30QuoteCollateral,
+		now: u64,
+	) -> Result<ConfidentialReport, Error> {
+		validate_dcap_quote(
+			&sample_bytes(&sample["user_data_hash"]),
+			quote,
+			&Collateral::SgxV30(collateral),
+			now,
+			true,
+			vec![sample_bytes(&sample["runtime_hash"])],
+			Some(&sample_bytes(&sample["root_ca"])),
+		)
+	}
+
+	#[test]
+	fn test_parse_pck_extensions() {
+		let raw = sample_bytes(&sample()["quote"]);
+		let quote = Quote::parse(&raw).unwrap();
+		let pck_chain = pem_to_der_chain(quote.pck_cert_chain).unwrap();
+		let pck = PckExtensions::parse(&pck_chain[0]).unwrap();
+		assert_eq!(pck.fmspc, [0, 0x60, 0x6a, 0, 0, 0]);
+		assert_eq!(pck.pce_svn, 13);
+		assert_eq!(pck.tcb_components, [5; 16]);
+		// The intermediate CA has no SGX extensions
+		assert!(PckExtensions::parse(&pck_chain[1]).is_none());
+		assert!(PckExtensions::parse(b"not a cert").is_none());
+	}
+
+	fn tcb_level(svn: u64, pce_svn: u64, status: &str, advisory_ids: &[&str]) -> serde_json::Value {
+		serde_json::json!({
+			"tcb": {
+				"sgxtcbcomponents": (0..16).map(|_| serde_json::json!({ "svn": svn })).collect::<Vec<_>>(),
+				"pcesvn": pce_svn,
+			},
+			"tcbStatus": status,
+			"advisoryIDs": advisory_ids,
+		})
+	}
+
+	#[test]
+	fn test_tcb_status() {
+		let tcb_info = serde_json::json!({
+			"tcbLevels": [
+				tcb_level(20, 13, "UpToDate", &[]),
+				tcb_level(1, 13, "SWHardeningNeeded", &["INTEL-SA-00334"]),
+				tcb_level(1, 10, "OutOfDate", &["INTEL-SA-00615"]),
+			]
+		});
+		let pck = |pce_svn| PckExtensions {
+			fmspc: [0, 0x60, 0x6a, 0, 0, 0],
+			tcb_components: [5; 16],
+			pce_svn,
+		};
+		assert_eq!(
+			platform_tcb_status(&tcb_info, &pck(13)).unwrap(),
+			("SWHardeningNeeded", vec!["INTEL-SA-00334"])
+		);
+		assert_eq!(
+			platform_tcb_status(&tcb_info, &pck(11)).unwrap().0,
+			"OutOfDate"
+		);
+		assert!(matches!(
+			platform_tcb_status(&tcb_info, &pck(9)),
+			Err(Error::InvalidQuoteStatus)
+		));
+	}
+
+	#[test]
+	fn test_qe_tcb_status() {
+		let sample = sample();
+		let qe_identity: serde_json::Value =
+			serde_json::from_str(sample["collateral"]["qe_identity"].as_str().unwrap()).unwrap();
+		let raw = sample_bytes(&sample["quote"]);
+		let mut qe_report = Quote::parse(&raw).unwrap().qe_report;
+		assert_eq!(qe_tcb_status(&qe_identity, &qe_report), Ok("UpToDate"));
+
+		qe_report.isv_svn = 7u16.to_le_bytes();
+		assert_eq!(qe_tcb_status(&qe_identity, &qe_report), Ok("OutOfDate"));
+		qe_report.isv_svn = 5u16.to_le_bytes();
+		assert_eq!(
+			qe_tcb_status(&qe_identity, &qe_report),
+			Err(Error::InvalidQuoteStatus)
+		);
+		qe_report.isv_svn = 8u16.to_le_bytes();
+
+		// Masked out bits are ignored
+		qe_report.attributes[0] |= 0x04;
+		qe_report.attributes[15] = 0xff;
+		assert_eq!(qe_tcb_status(&qe_identity, &qe_report), Ok("UpToDate"));
+		// DEBUG enclave
+		qe_report.attributes[0] |= 0x02;
+		assert_eq!(
+			qe_tcb_status(&qe_identity, &qe_report),
+			Err(Error::QeIdentityMismatch)
+		);
+		qe_report.attributes[0] &= !0x02;
+		qe_report.misc_select = 1;
+		assert_eq!(
+			qe_tcb_status(&qe_identity, &qe_report),
+			Err(Error::QeIdentityMismatch)
+		);
+		qe_report.misc_select = 0;
+		qe_report.mr_signer[0] ^= 1;
+		assert_eq!(
+			qe_tcb_status(&qe_identity, &qe_report),
+			Err(Error::QeIdentityMismatch)
+		);
+	}
+
+	#[test]
+	fn test_confidence_level() {
+		assert_eq!(confidence_level("UpToDate", &[]), Ok(1));
+		assert_eq!(
+			confidence_level("SWHardeningNeeded", &["INTEL-SA-00615"]),
+			Ok(2)
+		);
+		assert_eq!(
+			confidence_level("ConfigurationNeeded", &["INTEL-SA-00334"]),
+			Ok(3)
+		);
+		assert_eq!(
+			confidence_level("ConfigurationNeeded", &["INTEL-SA-00615"]),
+			Ok(4)
+		);
+		assert_eq!(confidence_level("OutOfDate", &[]), Ok(5));
+		assert_eq!(
+			confidence_level("Revoked", &[]),
+			Err(Error::InvalidQuoteStatus)
+		);
+	}
+
+	#[test]
+	fn test_root_ca_required() {
+		let raw = sample_bytes(&sample()["quote"]);
+		let collateral = Collateral::SgxV30(SgxV30QuoteCollateral {
+			tcb_info_issuer_chain: Default::default(),
+			tcb_info: Default::default(),
+			tcb_info_signature: Default::default(),
+			qe_identity_issuer_chain: Default::default(),
+			qe_identity: Default::default(),
+			qe_identity_signature: Default::default(),
+			root_ca_crl: Default::default(),
+			pck_crl_issuer_chain: Default::default(),
+			pck_crl: Default::default(),
+		});
+		assert_eq!(
+			validate_dcap_quote(&[0u8; 32], &raw, &collateral, 0, false, vec![], None),
+			Err(Error::DcapRootCaNotSet)
+		);
+		assert_eq!(
+			validate_dcap_quote(
+				&[0u8; 32],
+				&raw,
+				&collateral,
+				0,
+				false,
+				vec![],
+				Some(b"bad ca")
+			),
+			Err(Error::InvalidPckCertChain)
+		);
+		assert_eq!(
+			validate_dcap_quote(
+				&[0u8; 32],
+				&raw[..raw.len() - 1],
+				&collateral,
+				0,
+				false,
+				vec![],
+				Some(b"bad ca")
+			),
+			Err(Error::InvalidDcapQuote)
+		);
+	}
+
+	#[test]
+	fn test_validate_dcap_quote() {
+		let sample = sample();
+		let raw = sample_bytes(&sample["quote"]);
+		let now = sample["now"].as_u64().unwrap();
+		assert_eq!(
+			validate_sample(&sample, &raw, sample_collateral(&sample), now),
+			Ok(ConfidentialReport {
+				provider: Some(AttestationProvider::Dcap),
+				runtime_hash: sample_bytes(&sample["runtime_hash"]),
+				confidence_level: 2,
+			})
+		);
+		assert_eq!(
+			validate_dcap_quote(
+				&[0u8; 32],
+				&raw,
+				&Collateral::SgxV30(sample_collateral(&sample)),
+				now,
+				false,
+				vec![],
+				Some(&sample_bytes(&sample["root_ca"])),
+			),
+			Err(Error::InvalidUserDataHash)
+		);
+		assert_eq!(
+			validate_dcap_quote(
+				&sample_bytes(&sample["user_data_hash"]),
+				&raw,
+				&Collateral::SgxV30(sample_collateral(&sample)),
+				now,
+				true,
+				vec![],
+				Some(&sample_bytes(&sample["root_ca"])),
+			),
+			Err(Error::PRuntimeRejected)
+		);
+	}
+
+	#[test]
+	fn test_reject_tampered_quote() {
+		let sample = sample();
+		let now = sample["now"].as_u64().unwrap();
+		let mut raw = sample_bytes(&sample["quote"]);
+		// MR_ENCLAVE, after the 48 bytes header
+		raw[48 + 64] ^= 1;
+		assert_eq!(
+			validate_sample(&sample, &raw, sample_collateral(&sample), now),
+			Err(Error::InvalidQuoteSignature)
+		);
+	}
+
+	#[test]
+	fn test_reject_bad_collateral() {
+		let sample = sample();
+		let raw = sample_bytes(&sample["quote"]);
+		let now = sample["now"].as_u64().unwrap();
+
+		let mut collateral = sample_collateral(&sample);
+		collateral.tcb_info = collateral.tcb_info.replace("UpToDate", "Revoked");
+		assert_eq!(
+			validate_sample(&sample, &raw, collateral, now),
+			Err(Error::InvalidDcapCollateral)
+		);
+
+		// The TCB info and the QE identity expire before the certs and the CRLs
+		let expired = 2507760000; // 2049-06-20
+		assert_eq!(
+			validate_sample(&sample, &raw, sample_collateral(&sample), expired),
+			Err(Error::OutdatedDcapCollateral)
+		);
+		let not_yet_valid = 1640995200; // 2022-01-01
+		assert_eq!(
+			validate_sample(&sample, &raw, sample_collateral(&sample), not_yet_valid),
+			Err(Error::InvalidPckCertChain)
+		);
+	}
+
+	#[test]
+	fn test_check_crls() {
+		let sample = sample();
+		let raw = sample_bytes(&sample["quote"]);
+		let now = sample["now"].as_u64().unwrap();
+
+		let mut collateral = sample_collateral(&sample);
+		collateral.pck_crl = sample_bytes(&sample["collateral"]["revoking_pck_crl"]);
+		assert_eq!(
+			validate_sample(&sample, &raw, collateral, now),
+			Err(Error::DcapCertRevoked)
+		);
+
+		// CRLs signed by the wrong CA
+		let mut collateral = sample_collateral(&sample);
+		core::mem::swap(&mut collateral.root_ca_crl, &mut collateral.pck_crl);
+		assert_eq!(
+			validate_sample(&sample, &raw, collateral, now),
+			Err(Error::InvalidDcapCrl)
+		);
+
+		let mut collateral = sample_collateral(&sample);
+		let last = collateral.pck_crl.len() - 1;
+		collateral.pck_crl[last] ^= 1;
+		assert_eq!(
+			validate_sample(&sample, &raw, collateral, now),
+			Err(Error::InvalidDcapCrl)
+		);
+
+		let mut collateral = sample_collateral(&sample);
+		collateral.root_ca_crl = b"not a crl".to_vec();
+		assert_eq!(
+			validate_sample(&sample, &raw, collateral, now),
+			Err(Error::InvalidDcapCrl)
+		);
+	}
+}
@@ -1,4 +1,5 @@
 pub mod attestation;
+pub mod attestation_dcap;
 pub(crate) mod attestation_legacy;
 pub(crate) mod balance_convert;
 pub mod constants;
@@ -0,0 +1,175 @@
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use phactory_api::cluster_state::{
+    diff, ClusterStateSummary, ClusterStateVerification, LoadClusterStateRequest,
+};
+use phactory_api::pruntime_client::new_pruntime_client;
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+
+/// Cluster state dumps can only be read by the pRuntime of the worker they are sent to, so most
+/// of the commands ask that pRuntime to do the work.
+#[derive(Debug, Subcommand)]
+pub enum ClusterStateCommand {
+    /// List the contracts and the storage roots in a dump.
+    Inspect {
+        /// The pRuntime of the worker the dump is sent to.
+        #[arg(long, default_value = "http://localhost:8000")]
+        url: String,
+        /// The file name of the dump in the storage path of the pRuntime.
+        filename: String,
+    },
+    /// Verify a dump against the on-chain cluster info and the hashes of its storage.
+    Verify {
+        #[arg(long, default_value = "http://localhost:8000")]
+        url: String,
+        filename: String,
+    },
+    /// Compare two dumps of the same cluster.
+    Diff {
+        #[arg(long, default_value = "http://localhost:8000")]
+        url: String,
+        /// The pRuntime to inspect the second dump with, defaults to `--url`.
+        #[arg(long)]
+        other_url: Option<String>,
+        filename: String,
+        other_filename: String,
+    },
+    /// Load a dump, optionally with only the given contracts.
+    Load {
+        #[arg(long, default_value = "http://localhost:8000")]
+        url: String,
+        /// Ids of the contracts to restore, all contracts are restored if not given.
+        #[arg(long, value_delimiter = ',')]
+        contracts: Option<Vec<String>>,
+        filename: String,
+    },
+    /// Move the cluster state from a worker of the cluster to a new worker.
+    Transfer {
+        /// The pRuntime of a worker which is running the cluster.
+        #[arg(long, default_value = "http://localhost:8000")]
+        from: String,
+        /// The pRuntime of the new worker.
+        #[arg(long, default_value = "http://localhost:8001")]
+        to: String,
+        /// The storage path of the new pRuntime, where the dump is downloaded to.
+        #[arg(long, default_value = "data/storage_files")]
+        to_storage_path: PathBuf,
+        #[arg(long, value_delimiter = ',')]
+        contracts: Option<Vec<String>>,
+        /// Load the dump even if the verification fails.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+pub async fn handle_command(command: ClusterStateCommand) -> Result<()> {
+    match command {
+        ClusterStateCommand::Inspect { url, filename } => {
+            let summary = inspect(&url, &filename).await?;
+            print_json(&summary);
+        }
+        ClusterStateCommand::Verify { url, filename } => {
+            let verification = verify(&url, &filename).await?;
+            print_json(&verification);
+            if !verification.is_valid() {
+                bail!("Verification failed");
+            }
+        }
+        ClusterStateCommand::Diff {
+            url,
+            other_url,
+            filename,
+            other_filename,
+        } => {
+            let a = inspect(&url, &filename).await?;
+            let b = inspect(other_url.as_ref().unwrap_or(&url), &other_filename).await?;
+            print_json(&diff(&a, &b));
+        }
+        ClusterStateCommand::Load {
+            url,
+            contracts,
+            filename,
+        } => {
+            load(&url, filename, contracts).await?;
+            println!("Loaded, the state is applied when the worker reaches its block");
+        }
+        ClusterStateCommand::Transfer {
+            from,
+            to,
+            to_storage_path,
+            contracts,
+            force,
+        } => {
+            let request = new_pruntime_client(to.clone())
+                .generate_cluster_state_request(())
+                .await
+                .context("Failed to generate the cluster state request")?;
+            let saved = new_pruntime_client(from.clone())
+                .save_cluster_state(request)
+                .await
+                .context("Failed to save the cluster state")?;
+            println!("Saved {} at block {}", saved.filename, saved.block_number);
+            let dump = http_get(&format!("{from}/download/{}", saved.filename)).await?;
+            let path = to_storage_path.join(&saved.filename);
+            std::fs::write(&path, dump)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+
+            let verification = verify(&to, &saved.filename).await?;
+            print_json(&verification);
+            if !verification.is_valid() && !force {
+                bail!("Verification failed, use --force to load it anyway");
+            }
+            load(&to, saved.filename, contracts).await?;
+            println!("Loaded, the state is applied when the worker reaches its block");
+        }
+    }
+    Ok(())
+}
+
+async fn inspect(url: &str, filename: &str) -> Result<ClusterStateSummary> {
+    get_json(&format!("{url}/cluster_state/inspect/{filename}")).await
+}
+
+async fn verify(url: &str, filename: &str) -> Result<ClusterStateVerification> {
+    get_json(&format!("{url}/cluster_state/verify/{filename}")).await
+}
+
+async fn load(url: &str, filename: String, contracts: Option<Vec<String>>) -> Result<()> {
+    let response = reqwest::Client::new()
+        .post(format!("{url}/cluster_state/load"))
+        .json(&LoadClusterStateRequest {
+            filename,
+            contracts,
+        })
+        .send()
+        .await?;
+    check_status(response).await?;
+    Ok(())
+}
+
+async fn http_get(url: &str) -> Result<Vec<u8>> {
+    let response = reqwest::get(url)
+        .await
+        .with_context(|| format!("Failed to request {url}"))?;
+    Ok(check_status(response).await?.bytes().await?.to_vec())
+}
+
+async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T> {
+    serde_json::from_slice(&http_get(url).await?).context("Invalid response")
+}
+
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if !status.is_success() {
+        bail!("{status}: {}", response.text().await.unwrap_or_default());
+    }
+    Ok(response)
+}
+
+fn print_json(value: &impl serde::Serialize) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("Failed to serialize")
+    );
+}
@@ -1,3 +1,4 @@
+mod cluster_state;
 mod event_chain;
 mod query;
 
@@ -67,6 +68,10 @@ enum Cli {
     DecodeEventChain {
         log_file: String,
     },
+    ClusterState {
+        #[command(subcommand)]
+        command: cluster_state::ClusterStateCommand,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -194,6 +199,12 @@ async fn main() {
                     println!("======= event block end   ========");
                 });
         }
+        Cli::ClusterState { command } => {
+            if let Err(err) = cluster_state::handle_command(command).await {
+                eprintln!("Error: {err:?}");
+                std::process::exit(1);
+            }
+        }
     }
 }
 
@@ -242,6 +253,11 @@ async fn handle_pink_command(command: PinkCommand) {
         BadOrigin,
         RuntimeError(String),
         SidevmNotFound,
+        NoResponse,
+        ServiceUnavailable,
+        Timeout,
+        Forbidden,
+        QuotaExceeded,
     }
 
     match command {
@@ -12,7 +12,7 @@ use std::{
         atomic::{AtomicI32, Ordering},
         Arc, Mutex, Weak,
     },
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Context, Result};
@@ -23,7 +23,7 @@ use tracing::{debug, error, info, instrument, warn, Instrument};
 
 use serde::Serialize;
 
-use phala_types::WorkerPublicKey;
+use phala_types::{WorkerCapabilities, WorkerPublicKey};
 
 pub type Config = RunArgs;
 pub type TaskId = u64;
@@ -220,12 +220,35 @@ impl App {
 
         let probe_start = Instant::now();
         let timeout = self.config.poll_timeout;
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let max_age_ms = self.config.max_endpoint_age.as_millis() as u64;
         for worker in workers {
-            let endpoints = chain_rpc.get_endpoints(&worker).await?;
-            debug!("worker {worker:?} endpoints: {endpoints:?}");
-            let Some(uri) = endpoints
-                .into_iter()
+            let Some(info) = chain_rpc.get_endpoint_info(&worker).await? else {
+                workers_without_endpoints.push(worker);
+                continue;
+            };
+            debug!("worker {worker:?} endpoints: {info:?}");
+            if let Some(capabilities) = info.capabilities() {
+                if !capabilities.supports_rpc(WorkerCapabilities::RPC_CONTRACT_QUERY) {
+                    debug!("worker {worker:?} does not serve contract queries, skipped");
+                    continue;
+                }
+            }
+            // Workers without heartbeat on chain are reported by legacy pherry, keep them.
+            if let Some(signing_time) = chain_rpc.get_endpoint_signing_time(&worker).await? {
+                if now_ms.saturating_sub(signing_time) > max_age_ms {
+                    debug!("worker {worker:?} endpoints are stale, skipped");
+                    continue;
+                }
+            }
+            let Some(uri) = info
+                .endpoints()
+                .iter()
                 .find(|url| url.starts_with("http://") || url.starts_with("https://"))
+                .cloned()
             else {
                 workers_without_endpoints.push(worker);
                 continue;
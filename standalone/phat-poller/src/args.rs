@@ -42,6 +42,10 @@ pub struct RunArgs {
     #[arg(long, value_parser = parse_duration, default_value = "5s")]
     pub probe_timeout: Duration,
 
+    /// Workers whose endpoints are not refreshed on chain within this duration are skipped
+    #[arg(long, value_parser = parse_duration, default_value = "2h")]
+    pub max_endpoint_age: Duration,
+
     /// Contract poll interval
     #[arg(long, value_parser = parse_duration, default_value = "10s")]
     pub poll_interval: Duration,
@@ -41,6 +41,12 @@ pub enum QueryError {
     BadOrigin,
     RuntimeError(String),
     SidevmNotFound,
+    NoResponse,
+    ServiceUnavailable,
+    Timeout,
+    Forbidden,
+    QuotaExceeded,
+    PaymentRequired,
 }
 impl std::fmt::Display for QueryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -48,6 +54,12 @@ impl std::fmt::Display for QueryError {
             QueryError::BadOrigin => write!(f, "Bad origin"),
             QueryError::RuntimeError(msg) => write!(f, "Runtime error: {}", msg),
             QueryError::SidevmNotFound => write!(f, "Sidevm not found"),
+            QueryError::NoResponse => write!(f, "No response"),
+            QueryError::ServiceUnavailable => write!(f, "Service unavailable"),
+            QueryError::Timeout => write!(f, "Timeout"),
+            QueryError::Forbidden => write!(f, "Forbidden"),
+            QueryError::QuotaExceeded => write!(f, "Quota exceeded"),
+            QueryError::PaymentRequired => write!(f, "Insufficient balance"),
         }
     }
 }
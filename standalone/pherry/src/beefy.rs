@@ -0,0 +1,445 @@
+//! Syncs the parachain headers to pRuntime with the BEEFY proofs of the relaychain, instead of
+//! syncing every relaychain header with GRANDPA. See the BEEFY mode in `docs/relayer.md`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use codec::{Decode, Encode};
+use log::{debug, info};
+use phactory_api::blocks::{
+    BeefyAuthorityId, BeefyParaHeadProof, InitBeefyReq, MmrLeafProof, ParaHeadProof,
+    SyncParachainHeaderWithBeefyReq,
+};
+use phactory_api::prpc::PhactoryInfo;
+use phactory_api::pruntime_client;
+use phaxt::dynamic::storage_key;
+use serde::Deserialize;
+use sp_core::{keccak_256, H256};
+use subxt::rpc::rpc_params;
+
+use crate::types::{BlockNumber, Hash, Header, ParachainApi, RelaychainApi};
+use crate::{chain_client, get_block_at, get_header_at, get_header_hash, subxt, Args};
+
+const BEEFY_ENGINE_ID: sp_runtime::ConsensusEngineId = *b"BEEF";
+
+/// The response of `mmr_generateProof`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeavesProof {
+    leaves: sp_core::Bytes,
+    proof: sp_core::Bytes,
+}
+
+/// The BEEFY state of pRuntime, see `/bin_api/beefy_state`.
+struct WorkerState {
+    validator_set_id: u64,
+    latest_block: BlockNumber,
+}
+
+async fn worker_state(args: &Args) -> Result<Option<WorkerState>> {
+    let state =
+        pruntime_client::bin_api_request(&args.pruntime_endpoint, "beefy_state", &()).await?;
+    let Some(validator_set_id) = state["validator_set_id"].as_u64() else {
+        return Ok(None);
+    };
+    let latest_block = state["latest_block"]
+        .as_u64()
+        .ok_or_else(|| anyhow!("Invalid beefy_state response: {state}"))?;
+    Ok(Some(WorkerState {
+        validator_set_id,
+        latest_block: latest_block as _,
+    }))
+}
+
+async fn fetch<T: Decode>(api: &RelaychainApi, key: &[u8], hash: Hash) -> Result<Option<T>> {
+    let Some(value) = api.rpc().storage(key, Some(hash)).await? else {
+        return Ok(None);
+    };
+    Ok(Some(T::decode(&mut &value.0[..])?))
+}
+
+async fn validator_set_id_at(api: &RelaychainApi, block: BlockNumber) -> Result<u64> {
+    let hash = get_header_hash(api, Some(block)).await?;
+    let set_id = fetch(api, &storage_key("Beefy", "ValidatorSetId"), hash).await?;
+    Ok(set_id.unwrap_or_default())
+}
+
+async fn authorities_at(api: &RelaychainApi, hash: Hash) -> Result<Vec<BeefyAuthorityId>> {
+    let authorities = fetch(api, &storage_key("Beefy", "Authorities"), hash).await?;
+    Ok(authorities.unwrap_or_default())
+}
+
+/// Returns the first block in `(after, to]` whose BEEFY validator set id is at least `set_id`.
+///
+/// The first block of a set is a mandatory block of BEEFY, which is always signed by the set.
+async fn bisec_set_start(
+    api: &RelaychainApi,
+    after: BlockNumber,
+    to: BlockNumber,
+    set_id: u64,
+) -> Result<BlockNumber> {
+    let (mut l, mut r) = (after + 1, to);
+    while l < r {
+        let mid = l + (r - l) / 2;
+        if validator_set_id_at(api, mid).await? >= set_id {
+            r = mid;
+        } else {
+            l = mid + 1;
+        }
+    }
+    debug!("BEEFY set {set_id} starts at block {l}");
+    Ok(l)
+}
+
+async fn finality_proof_at(api: &RelaychainApi, block: BlockNumber) -> Result<Vec<u8>> {
+    let (block, _) = get_block_at(api, Some(block)).await?;
+    block
+        .justifications
+        .and_then(|justifications| justifications.into_justification(BEEFY_ENGINE_ID))
+        .ok_or_else(|| {
+            anyhow!(
+                "No BEEFY justification at relaychain block {}",
+                block.block.header.number
+            )
+        })
+}
+
+/// The MMR leaf of `block` with its proof against the MMR root at `best_known`.
+async fn mmr_leaf_proof(
+    api: &RelaychainApi,
+    block: BlockNumber,
+    best_known: BlockNumber,
+) -> Result<(Vec<u8>, MmrLeafProof)> {
+    let proof: LeavesProof = api
+        .rpc()
+        .request(
+            "mmr_generateProof",
+            rpc_params![vec![block], Some(best_known)],
+        )
+        .await
+        .context("Failed to generate the MMR proof")?;
+    let leaves = Vec::<Vec<u8>>::decode(&mut &proof.leaves[..])?;
+    let [leaf] = <[Vec<u8>; 1]>::try_from(leaves)
+        .map_err(|_| anyhow!("Expected one MMR leaf of block {block}"))?;
+    let proof = MmrLeafProof::decode(&mut &proof.proof[..])?;
+    Ok((leaf, proof))
+}
+
+fn keccak_merge(left: &H256, right: &H256) -> H256 {
+    keccak_256(&[left.as_bytes(), right.as_bytes()].concat()).into()
+}
+
+/// The same proof as `binary_merkle_tree::merkle_proof` with keccak256.
+fn merkle_proof(leaves: &[Vec<u8>], leaf_index: usize) -> ParaHeadProof {
+    let mut level: Vec<H256> = leaves.iter().map(|leaf| keccak_256(leaf).into()).collect();
+    let mut position = leaf_index;
+    let mut proof = vec![];
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+        if sibling < level.len() {
+            proof.push(level[sibling]);
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => keccak_merge(left, right),
+                [single] => *single,
+                _ => unreachable!("chunks of 2"),
+            })
+            .collect();
+        position /= 2;
+    }
+    ParaHeadProof {
+        proof,
+        number_of_leaves: leaves.len() as _,
+        leaf_index: leaf_index as _,
+    }
+}
+
+/// Reads the head of the parachain committed in the MMR leaf of `block`, and its proof in the
+/// para heads root of the leaf.
+///
+/// The leaf is appended at the beginning of `block`, so the heads are read at its parent.
+async fn para_head_proof(
+    api: &RelaychainApi,
+    block: BlockNumber,
+    para_id: u32,
+) -> Result<(Header, ParaHeadProof)> {
+    let hash = get_header_hash(api, Some(block - 1)).await?;
+    let para_ids: Vec<u32> = fetch(api, &storage_key("Paras", "Parachains"), hash)
+        .await?
+        .unwrap_or_default();
+    let mut leaves = vec![];
+    let mut our_head = None;
+    for id in para_ids {
+        let head: Option<Vec<u8>> = fetch(api, &api.paras_heads_key(id)?, hash).await?;
+        let Some(head) = head else {
+            continue;
+        };
+        if id == para_id {
+            our_head = Some((leaves.len(), Header::decode(&mut &head[..])?));
+        }
+        leaves.push((id, head).encode());
+    }
+    let (index, header) =
+        our_head.ok_or_else(|| anyhow!("Parachain head not found at relaychain block {block}"))?;
+    Ok((header, merkle_proof(&leaves, index)))
+}
+
+async fn fetch_para_headers(
+    para_api: &ParachainApi,
+    from: BlockNumber,
+    to: BlockNumber,
+) -> Result<Vec<Header>> {
+    let mut headers = vec![];
+    for number in from..=to {
+        headers.push(get_header_at(para_api, Some(number)).await?.0);
+    }
+    Ok(headers)
+}
+
+async fn req_sync(args: &Args, headers: Vec<Header>, proof: BeefyParaHeadProof) -> Result<()> {
+    let request = SyncParachainHeaderWithBeefyReq { headers, proof };
+    let response = pruntime_client::bin_api_request(
+        &args.pruntime_endpoint,
+        "sync_para_header_with_beefy",
+        &request,
+    )
+    .await?;
+    info!("..sync_para_header_with_beefy: {response}");
+    Ok(())
+}
+
+/// Starts BEEFY validation with the BEEFY authority set at the last relaychain block synced to
+/// pRuntime. Returns false if BEEFY is not active at that block yet.
+async fn init_beefy(api: &RelaychainApi, info: &PhactoryInfo, args: &Args) -> Result<bool> {
+    let block = info.headernum.saturating_sub(1);
+    let hash = get_header_hash(api, Some(block)).await?;
+    let authorities = authorities_at(api, hash).await?;
+    if authorities.is_empty() {
+        info!("BEEFY is not active at relaychain block {block}, keep syncing with GRANDPA");
+        return Ok(false);
+    }
+    let authorities_key = storage_key("Beefy", "Authorities");
+    let set_id_key = storage_key("Beefy", "ValidatorSetId");
+    let validator_set_id = fetch(api, &set_id_key, hash).await?.unwrap_or_default();
+    let proof =
+        chain_client::read_proofs(api, Some(hash), [&authorities_key[..], &set_id_key]).await?;
+    info!("Initializing BEEFY at relaychain block {block} with validator set {validator_set_id}");
+    let request = InitBeefyReq {
+        authorities,
+        validator_set_id,
+        proof,
+    };
+    pruntime_client::bin_api_request(&args.pruntime_endpoint, "init_beefy", &request).await?;
+    Ok(true)
+}
+
+/// Imports the BEEFY authority set following the worker's current one.
+async fn import_set_change(
+    api: &RelaychainApi,
+    state: &WorkerState,
+    finalized: BlockNumber,
+    args: &Args,
+) -> Result<()> {
+    let set_id = state.validator_set_id;
+    let next_set_start = bisec_set_start(api, state.latest_block, finalized, set_id + 1).await?;
+    // Learn the commitment of the next set from a leaf of the current set.
+    let proof = if state.latest_block > 0 {
+        let (mmr_leaf, mmr_proof) =
+            mmr_leaf_proof(api, state.latest_block, state.latest_block).await?;
+        BeefyParaHeadProof {
+            finality_proof: None,
+            next_authorities: None,
+            mmr_leaf,
+            mmr_proof,
+            para_head_proof: merkle_proof(&[], 0),
+        }
+    } else {
+        let set_start = bisec_set_start(api, 0, next_set_start, set_id).await?;
+        let (mmr_leaf, mmr_proof) = mmr_leaf_proof(api, set_start, set_start).await?;
+        BeefyParaHeadProof {
+            finality_proof: Some(finality_proof_at(api, set_start).await?),
+            next_authorities: None,
+            mmr_leaf,
+            mmr_proof,
+            para_head_proof: merkle_proof(&[], 0),
+        }
+    };
+    req_sync(args, vec![], proof).await?;
+
+    let hash = get_header_hash(api, Some(next_set_start)).await?;
+    let (mmr_leaf, mmr_proof) = mmr_leaf_proof(api, next_set_start, next_set_start).await?;
+    let proof = BeefyParaHeadProof {
+        finality_proof: Some(finality_proof_at(api, next_set_start).await?),
+        next_authorities: Some(authorities_at(api, hash).await?),
+        mmr_leaf,
+        mmr_proof,
+        para_head_proof: merkle_proof(&[], 0),
+    };
+    info!(
+        "Importing BEEFY set {} at block {next_set_start}",
+        set_id + 1
+    );
+    req_sync(args, vec![], proof).await
+}
+
+async fn beefy_finalized_head(api: &RelaychainApi) -> Result<BlockNumber> {
+    let finalized_hash: Hash = api
+        .rpc()
+        .request("beefy_getFinalizedHead", rpc_params![])
+        .await
+        .context("Failed to get the BEEFY finalized head")?;
+    Ok(api
+        .rpc()
+        .header(Some(finalized_hash))
+        .await?
+        .ok_or_else(|| anyhow!("BEEFY finalized head not found"))?
+        .number)
+}
+
+/// Syncs the parachain headers up to the one committed in the latest BEEFY finalized block.
+///
+/// Returns `None` if BEEFY can not be initialized yet, in which case the relaychain headers should
+/// be synced with GRANDPA. Otherwise returns the number of synced parachain headers.
+pub(crate) async fn sync_para_headers(
+    api: &RelaychainApi,
+    para_api: &ParachainApi,
+    info: &PhactoryInfo,
+    args: &Args,
+) -> Result<Option<BlockNumber>> {
+    let mut state = match worker_state(args).await? {
+        Some(state) => state,
+        None => {
+            if !init_beefy(api, info, args).await? {
+                return Ok(None);
+            }
+            worker_state(args)
+                .await?
+                .ok_or_else(|| anyhow!("BEEFY not initialized after init_beefy"))?
+        }
+    };
+
+    let finalized = beefy_finalized_head(api).await?;
+    if finalized <= state.latest_block {
+        return Ok(Some(0));
+    }
+
+    while validator_set_id_at(api, finalized).await? > state.validator_set_id {
+        import_set_change(api, &state, finalized, args).await?;
+        state = worker_state(args)
+            .await?
+            .ok_or_else(|| anyhow!("BEEFY state lost"))?;
+    }
+    if finalized <= state.latest_block {
+        return Ok(Some(0));
+    }
+
+    let para_id = para_api.get_paraid(None).await?;
+    let (para_head, para_head_proof) = para_head_proof(api, finalized, para_id).await?;
+    let headers = if para_head.number >= info.para_headernum {
+        fetch_para_headers(para_api, info.para_headernum, para_head.number).await?
+    } else {
+        vec![]
+    };
+    let synced = headers.len() as BlockNumber;
+    let (mmr_leaf, mmr_proof) = mmr_leaf_proof(api, finalized, finalized).await?;
+    let proof = BeefyParaHeadProof {
+        finality_proof: Some(finality_proof_at(api, finalized).await?),
+        next_authorities: None,
+        mmr_leaf,
+        mmr_proof,
+        para_head_proof,
+    };
+    info!(
+        "Syncing parachain headers to {} with the BEEFY proof of relaychain block {finalized}",
+        para_head.number
+    );
+    req_sync(args, headers, proof).await?;
+    Ok(Some(synced))
+}
+
+/// The BEEFY proofs replayed by the tests of the pRuntime BEEFY client, see `Fixture` in
+/// `phactory::light_validation::beefy`.
+#[derive(Encode)]
+struct Fixture {
+    authorities: Vec<BeefyAuthorityId>,
+    validator_set_id: u64,
+    para_id: u32,
+    steps: Vec<FixtureStep>,
+}
+
+#[derive(Encode)]
+struct FixtureStep {
+    para_head: Option<Vec<u8>>,
+    proof: BeefyParaHeadProof,
+}
+
+/// Records the proofs to follow the last BEEFY authority set change of the relaychain and to prove
+/// the parachain head at the BEEFY finalized block, in the same way as `sync_para_headers`.
+pub(crate) async fn record_fixture(
+    api: &RelaychainApi,
+    para_api: &ParachainApi,
+    path: &str,
+) -> Result<()> {
+    let finalized = beefy_finalized_head(api).await?;
+    let set_id = validator_set_id_at(api, finalized).await?;
+    if set_id == 0 {
+        bail!("No BEEFY authority set change on the relaychain yet");
+    }
+    let set_start = bisec_set_start(api, 0, finalized, set_id - 1).await?;
+    let next_set_start = bisec_set_start(api, set_start, finalized, set_id).await?;
+    if finalized <= next_set_start {
+        bail!("BEEFY set {set_id} has just started, retry in a few blocks");
+    }
+    let authorities = authorities_at(api, get_header_hash(api, Some(set_start)).await?).await?;
+
+    let (mmr_leaf, mmr_proof) = mmr_leaf_proof(api, set_start, set_start).await?;
+    let set_start_step = FixtureStep {
+        para_head: None,
+        proof: BeefyParaHeadProof {
+            finality_proof: Some(finality_proof_at(api, set_start).await?),
+            next_authorities: None,
+            mmr_leaf,
+            mmr_proof,
+            para_head_proof: merkle_proof(&[], 0),
+        },
+    };
+    let hash = get_header_hash(api, Some(next_set_start)).await?;
+    let (mmr_leaf, mmr_proof) = mmr_leaf_proof(api, next_set_start, next_set_start).await?;
+    let set_change_step = FixtureStep {
+        para_head: None,
+        proof: BeefyParaHeadProof {
+            finality_proof: Some(finality_proof_at(api, next_set_start).await?),
+            next_authorities: Some(authorities_at(api, hash).await?),
+            mmr_leaf,
+            mmr_proof,
+            para_head_proof: merkle_proof(&[], 0),
+        },
+    };
+    let para_id = para_api.get_paraid(None).await?;
+    let (para_head, para_head_proof) = para_head_proof(api, finalized, para_id).await?;
+    let (mmr_leaf, mmr_proof) = mmr_leaf_proof(api, finalized, finalized).await?;
+    let para_head_step = FixtureStep {
+        para_head: Some(para_head.encode()),
+        proof: BeefyParaHeadProof {
+            finality_proof: Some(finality_proof_at(api, finalized).await?),
+            next_authorities: None,
+            mmr_leaf,
+            mmr_proof,
+            para_head_proof,
+        },
+    };
+
+    let fixture = Fixture {
+        authorities,
+        validator_set_id: set_id - 1,
+        para_id,
+        steps: vec![set_start_step, set_change_step, para_head_step],
+    };
+    std::fs::write(path, fixture.encode())
+        .with_context(|| format!("Failed to write the BEEFY fixture to {path}"))?;
+    info!(
+        "Recorded BEEFY set {} from block {set_start} and the head of parachain {para_id} at block {finalized} to {path}",
+        set_id - 1
+    );
+    Ok(())
+}
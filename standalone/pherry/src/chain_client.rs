@@ -5,11 +5,14 @@ use crate::{
 use anyhow::{Context, Result};
 use codec::Decode;
 use codec::Encode;
-use phactory_api::blocks::StorageProof;
+use phactory_api::blocks::{ReplayBlock, StorageProof};
 use phala_node_rpc_ext::MakeInto as _;
 use phala_trie_storage::ser::StorageChanges;
-use phala_types::messaging::MessageOrigin;
-use phaxt::{rpc::ExtraRpcExt as _, subxt, BlockNumber, RpcClient};
+use phala_types::messaging::{
+    BindTopic, Message, MessageOrigin, SystemEvent, WorkerEvent, WorkerEventWithKey,
+};
+use phala_types::WorkerPublicKey;
+use phaxt::{dynamic::storage_key, rpc::ExtraRpcExt as _, subxt, BlockNumber, RpcClient};
 use serde_json::to_value;
 use subxt::rpc::rpc_params;
 
@@ -77,7 +80,7 @@ pub async fn fetch_genesis_storage(api: &ParachainApi) -> Result<Vec<(Vec<u8>, V
     fetch_genesis_storage_at(api, hash).await
 }
 
-async fn fetch_genesis_storage_at(
+pub async fn fetch_genesis_storage_at(
     api: &ParachainApi,
     hash: Option<sp_core::H256>,
 ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
@@ -147,6 +150,101 @@ pub async fn search_suitable_genesis_for_worker(
     Ok((block, genesis))
 }
 
+/// Search backwards from `block` for the block the worker started its current computing session
+/// at, and read the messages of it with the proof.
+///
+/// Returns `None` if the worker is not computing at `block`.
+pub async fn search_worker_started_block(
+    api: &ParachainApi,
+    worker: &WorkerPublicKey,
+    block: BlockNumber,
+) -> Result<Option<ReplayBlock>> {
+    const BATCH: BlockNumber = 32;
+
+    let hash = block_hash(api, block).await?;
+    if !api.worker_computing_at(hash, worker).await? {
+        return Ok(None);
+    }
+    let registered_at = api.worker_added_at(worker.as_ref()).await?.unwrap_or(0);
+    let mut to = block;
+    loop {
+        let from = to.saturating_sub(BATCH - 1).max(registered_at);
+        log::info!("Searching the Started event of the worker in blocks {from}..={to}");
+        let blocks =
+            futures::future::try_join_all((from..=to).map(|b| fetch_mq_messages(api, b))).await?;
+        for (block_number, hash, key, messages) in blocks.into_iter().rev() {
+            if !messages.iter().any(|msg| is_worker_started(msg, worker)) {
+                continue;
+            }
+            let now_key = storage_key("Timestamp", "Now");
+            let now_ms = api
+                .rpc()
+                .storage(&now_key, Some(hash))
+                .await?
+                .map(|data| u64::decode(&mut &data.0[..]))
+                .transpose()?
+                .unwrap_or_default();
+            let proof = api
+                .rpc()
+                .read_proof([&key[..], &now_key[..]], Some(hash))
+                .await
+                .map(raw_proof)?;
+            return Ok(Some(ReplayBlock {
+                block_number,
+                now_ms,
+                messages,
+                proof,
+            }));
+        }
+        if from <= registered_at {
+            anyhow::bail!("No Started event found for the computing worker");
+        }
+        to = from - 1;
+    }
+}
+
+async fn block_hash(api: &ParachainApi, block: BlockNumber) -> Result<Hash> {
+    api.rpc()
+        .block_hash(Some(block.into()))
+        .await
+        .context("Failed to resolve block number")?
+        .ok_or_else(|| anyhow::anyhow!("Block number {block} not found"))
+}
+
+/// Fetch the outbound messages of the pallets in the given block, with the storage key of them.
+async fn fetch_mq_messages(
+    api: &ParachainApi,
+    block: BlockNumber,
+) -> Result<(BlockNumber, Hash, Vec<u8>, Vec<Message>)> {
+    let hash = block_hash(api, block).await?;
+    let mut last_key = vec![];
+    for name in ["OutboundMessagesV2", "OutboundMessages"] {
+        let key = storage_key("PhalaMq", name);
+        let messages: Vec<Message> = match api.rpc().storage(&key, Some(hash)).await? {
+            Some(data) => Decode::decode(&mut &data.0[..])?,
+            None => vec![],
+        };
+        if !messages.is_empty() {
+            return Ok((block, hash, key, messages));
+        }
+        last_key = key;
+    }
+    Ok((block, hash, last_key, vec![]))
+}
+
+fn is_worker_started(message: &Message, worker: &WorkerPublicKey) -> bool {
+    if !message.sender.is_pallet() || message.destination.path() != &SystemEvent::topic() {
+        return false;
+    }
+    matches!(
+        SystemEvent::decode(&mut &message.payload[..]),
+        Ok(SystemEvent::WorkerEvent(WorkerEventWithKey {
+            pubkey,
+            event: WorkerEvent::Started { .. },
+        })) if &pubkey == worker
+    )
+}
+
 async fn get_worker_unregistered_block(
     api: &ParachainApi,
     worker: &[u8],
@@ -3,7 +3,7 @@ use log::{debug, error, info, warn};
 use sp_core::crypto::AccountId32;
 use std::cmp;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 
 use codec::{Decode, Encode};
@@ -18,6 +18,7 @@ use phaxt::{
 use sp_consensus_grandpa::{AuthorityList, SetId, VersionedAuthorityList, GRANDPA_AUTHORITIES_KEY};
 use subxt::config::{substrate::Era, Header as _};
 
+mod beefy;
 mod endpoint;
 mod error;
 mod msg_sync;
@@ -44,7 +45,7 @@ use clap::Parser;
 use headers_cache::Client as CacheClient;
 use msg_sync::{Error as MsgSyncError, Receiver, Sender};
 use notify_client::NotifyClient;
-use phala_types::AttestationProvider;
+use phala_types::{AttestationProvider, WorkerPublicKey};
 
 pub use phaxt::connect as subxt_connect;
 
@@ -79,6 +80,13 @@ pub struct Args {
     #[arg(long, help = "Skip binding the worker endpoint.")]
     no_bind: bool,
 
+    #[arg(
+        default_value = "3600",
+        long,
+        help = "Re-sign and update the worker endpoint on chain every given seconds as a liveness heartbeat. 0 to disable."
+    )]
+    endpoint_refresh_interval_secs: u64,
+
     #[arg(
         long,
         help = "Inject dev key (0x1) to pRuntime. Cannot be used with remote attestation enabled."
@@ -231,6 +239,8 @@ pub struct Args {
     use_ias: bool,
 
     /// Try to load chain state from the latest block that the worker haven't registered at.
+    ///
+    /// A registered worker loads the latest chain state instead, after the headers are synced.
     #[arg(long)]
     fast_sync: bool,
 
@@ -241,12 +251,25 @@ pub struct Args {
     /// Load handover proof after blocks synced.
     #[arg(long)]
     load_handover_proof: bool,
+
+    /// Sync the parachain headers with the BEEFY proofs of the relaychain instead of syncing the
+    /// relaychain headers. The worker can't go back to GRANDPA once switched to BEEFY.
+    #[arg(long, requires = "parachain")]
+    beefy: bool,
+
+    /// Record the BEEFY proofs of the last authority set change and of the parachain head at the
+    /// BEEFY finalized block to the given file, as a test fixture of the pRuntime BEEFY client,
+    /// then exit.
+    #[arg(long, requires = "parachain")]
+    record_beefy_fixture: Option<String>,
 }
 
 #[derive(clap::ValueEnum, Clone, Copy, Debug)]
 enum RaOption {
     None,
     Ias,
+    Dcap,
+    Mock,
 }
 
 impl From<RaOption> for Option<AttestationProvider> {
@@ -254,20 +277,35 @@ impl From<RaOption> for Option<AttestationProvider> {
         match other {
             RaOption::None => None,
             RaOption::Ias => Some(AttestationProvider::Ias),
+            RaOption::Dcap => Some(AttestationProvider::Dcap),
+            RaOption::Mock => Some(AttestationProvider::Mock),
         }
     }
 }
 
 struct RunningFlags {
     worker_registered: bool,
-    endpoint_registered: bool,
+    endpoint_registered_at: Option<Instant>,
     restart_failure_count: u32,
 }
 
+impl RunningFlags {
+    fn should_update_endpoint(&self, args: &Args) -> bool {
+        match self.endpoint_registered_at {
+            None => true,
+            Some(_) if args.endpoint_refresh_interval_secs == 0 => false,
+            Some(at) => at.elapsed() >= Duration::from_secs(args.endpoint_refresh_interval_secs),
+        }
+    }
+}
+
 pub struct BlockSyncState {
     pub blocks: Vec<Block>,
     /// Tracks the latest known authority set id at a certain block.
     pub authory_set_state: Option<(BlockNumber, SetId)>,
+    /// The block to fast sync to once the headers are synced beyond it. No block is dispatched
+    /// until then.
+    pub fast_sync_at: Option<BlockNumber>,
 }
 
 pub async fn get_header_hash(client: &phaxt::RpcClient, h: Option<u32>) -> Result<Hash> {
@@ -542,7 +580,8 @@ async fn batch_sync_block(
     .saturating_sub(1);
     macro_rules! sync_blocks_to {
         ($to: expr) => {
-            if next_blocknum <= $to {
+            // The blocks before the fast sync block are skipped.
+            if next_blocknum <= $to && sync_state.fast_sync_at.is_none() {
                 batch_sync_storage_changes(pr, paraclient, cache, next_blocknum, $to, batch_window)
                     .await?;
                 synced_blocks += $to - next_blocknum + 1;
@@ -1016,11 +1055,18 @@ async fn try_register_worker(
     }
 }
 
-async fn try_load_chain_state(pr: &PrClient, para_api: &ParachainApi, args: &Args) -> Result<()> {
+/// Load the chain state for an unregistered worker.
+///
+/// Returns the block to fast sync to if the worker is registered, see `fast_sync`.
+async fn try_load_chain_state(
+    pr: &PrClient,
+    para_api: &ParachainApi,
+    args: &Args,
+) -> Result<Option<BlockNumber>> {
     let info = pr.get_info(()).await?;
     info!("info: {info:#?}");
     if !info.can_load_chain_state {
-        return Ok(());
+        return Ok(None);
     }
     let Some(pubkey) = &info.public_key else {
         return Err(anyhow!("No public key found for worker"));
@@ -1028,6 +1074,20 @@ async fn try_load_chain_state(pr: &PrClient, para_api: &ParachainApi, args: &Arg
     let Ok(pubkey) = hex::decode(pubkey) else {
         return Err(anyhow!("pRuntime returned an invalid pubkey"));
     };
+    if para_api.worker_added_at(&pubkey).await?.is_some() {
+        let block = match args.prefer_genesis_at_block {
+            Some(block) => block,
+            None => {
+                para_api
+                    .extra_rpc()
+                    .system_sync_state()
+                    .await?
+                    .current_block as _
+            }
+        };
+        info!("Fast sync to block {block} after the headers are synced");
+        return Ok(Some(block));
+    }
     let (block_number, state) = chain_client::search_suitable_genesis_for_worker(
         para_api,
         &pubkey,
@@ -1037,6 +1097,42 @@ async fn try_load_chain_state(pr: &PrClient, para_api: &ParachainApi, args: &Arg
     .context("Failed to search suitable genesis state for worker")?;
     pr.load_chain_state(prpc::ChainState::new(block_number, state))
         .await?;
+    Ok(None)
+}
+
+/// Load the chain state at `block_number` along with the messages needed to restore the state of
+/// the registered worker. The headers must have been synced beyond `block_number`.
+async fn fast_sync(
+    pr: &PrClient,
+    para_api: &ParachainApi,
+    args: &Args,
+    block_number: BlockNumber,
+) -> Result<()> {
+    let info = pr.get_info(()).await?;
+    let Some(pubkey) = &info.public_key else {
+        return Err(anyhow!("No public key found for worker"));
+    };
+    let pubkey = hex::decode(pubkey)
+        .ok()
+        .and_then(|pubkey| WorkerPublicKey::decode(&mut &pubkey[..]).ok())
+        .ok_or_else(|| anyhow!("pRuntime returned an invalid pubkey"))?;
+    let hash = get_header_hash(para_api, Some(block_number)).await?;
+    let storage = chain_client::fetch_genesis_storage_at(para_api, Some(hash))
+        .await
+        .context("Failed to fetch the chain state")?;
+    let replay = chain_client::search_worker_started_block(para_api, &pubkey, block_number)
+        .await
+        .context("Failed to search the block the worker started computing")?;
+    info!(
+        "Fast syncing to block {block_number}, replaying {:?}",
+        replay.as_ref().map(|b| b.block_number)
+    );
+    let request = blocks::FastSyncReq {
+        block_number,
+        storage,
+        replay: replay.into_iter().collect(),
+    };
+    pruntime_client::bin_api_request(&args.pruntime_endpoint, "fast_sync", &request).await?;
     Ok(())
 }
 
@@ -1088,6 +1184,10 @@ async fn bridge(
         info!("Substrate sync blocks done");
     }
 
+    if let Some(path) = &args.record_beefy_fixture {
+        return beefy::record_fixture(&api, &para_api, path).await;
+    }
+
     let cache_client = if !args.headers_cache_uri.is_empty() {
         Some(CacheClient::new(&args.headers_cache_uri))
     } else {
@@ -1103,6 +1203,7 @@ async fn bridge(
     let mut pruntime_initialized = false;
     let mut pruntime_new_init = false;
     let mut initial_sync_finished = false;
+    let mut fast_sync_at = None;
 
     // Try to initialize pRuntime and register on-chain
     let info = pr.get_info(()).await?;
@@ -1165,7 +1266,7 @@ async fn bridge(
         }
 
         if args.fast_sync {
-            try_load_chain_state(&pr, &para_api, args).await?;
+            fast_sync_at = try_load_chain_state(&pr, &para_api, args).await?;
         }
     }
 
@@ -1180,7 +1281,9 @@ async fn bridge(
             // Here the reason we dont directly report errors when `try_update_worker_endpoint` fails is that we want the endpoint can be registered anytime (e.g. days after the pherry initialization)
             match endpoint::try_update_worker_endpoint(&pr, &para_api, &mut signer, args).await {
                 Ok(registered) => {
-                    flags.endpoint_registered = registered;
+                    if registered {
+                        flags.endpoint_registered_at = Some(Instant::now());
+                    }
                 }
                 Err(e) => {
                     error!("FailedToCallBindWorkerEndpoint: {:?}", e);
@@ -1195,6 +1298,7 @@ async fn bridge(
     let mut sync_state = BlockSyncState {
         blocks: Vec::new(),
         authory_set_state: None,
+        fast_sync_at,
     };
 
     for round in 0u64.. {
@@ -1223,7 +1327,14 @@ async fn bridge(
         } else {
             info.headernum
         };
-        if info.blocknum < next_headernum {
+        if let Some(block_number) = sync_state.fast_sync_at {
+            if next_headernum > block_number {
+                fast_sync(&pr, &para_api, args, block_number).await?;
+                sync_state.fast_sync_at = None;
+                continue;
+            }
+        }
+        if info.blocknum < next_headernum && sync_state.fast_sync_at.is_none() {
             info!("blocks fall behind");
             batch_sync_storage_changes(
                 &pr,
@@ -1235,8 +1346,15 @@ async fn bridge(
             )
             .await?;
         }
+        let beefy_synced = if args.beefy && sync_state.fast_sync_at.is_none() {
+            beefy::sync_para_headers(&api, &para_api, &info, args).await?
+        } else {
+            None
+        };
         if args.parachain
             && !args.disable_sync_waiting_paraheaders
+            && sync_state.fast_sync_at.is_none()
+            && beefy_synced.is_none()
             // `round == 0` is for old pruntimes which don't return `waiting_for_paraheaders`
             && (info.waiting_for_paraheaders || round == 0)
         {
@@ -1251,93 +1369,107 @@ async fn bridge(
             .await?;
         }
 
-        // Sync the relaychain and parachain data from the cache service as much as possible
-        if let (true, Some(cache)) = (args.parachain, &cache_client) {
-            info!("Fetching headers at {} from cache...", info.headernum);
-            let cached_headers = cache.get_headers(info.headernum).await.unwrap_or_default();
-            if cached_headers.is_empty() {
-                info!("Header cache missing at {}", info.headernum);
-            } else {
-                info!(
-                    "Syncing {} cached headers start from {}",
-                    cached_headers.len(),
-                    cached_headers[0].header.number
-                );
-                sync_state.authory_set_state = None;
-                sync_state.blocks.clear();
-                sync_with_cached_headers(
-                    &pr,
-                    &para_api,
-                    cache_client.as_ref(),
-                    info.blocknum,
-                    info.para_headernum,
-                    cached_headers,
-                    args.sync_blocks,
-                )
-                .await?;
-                continue;
-            }
-        }
+        let (synced_blocks, more_blocks) = match beefy_synced {
+            Some(synced) => (synced, false),
+            None => {
+                // Sync the relaychain and parachain data from the cache service as much as possible
+                if let (true, Some(cache), None) =
+                    (args.parachain, &cache_client, sync_state.fast_sync_at)
+                {
+                    info!("Fetching headers at {} from cache...", info.headernum);
+                    let cached_headers =
+                        cache.get_headers(info.headernum).await.unwrap_or_default();
+                    if cached_headers.is_empty() {
+                        info!("Header cache missing at {}", info.headernum);
+                    } else {
+                        info!(
+                            "Syncing {} cached headers start from {}",
+                            cached_headers.len(),
+                            cached_headers[0].header.number
+                        );
+                        sync_state.authory_set_state = None;
+                        sync_state.blocks.clear();
+                        sync_with_cached_headers(
+                            &pr,
+                            &para_api,
+                            cache_client.as_ref(),
+                            info.blocknum,
+                            info.para_headernum,
+                            cached_headers,
+                            args.sync_blocks,
+                        )
+                        .await?;
+                        continue;
+                    }
+                }
 
-        let latest_block = get_block_at(&api, None).await?.0.block;
-        // remove the blocks not needed in the buffer. info.blocknum is the next required block
-        while let Some(b) = sync_state.blocks.first() {
-            if b.block.header.number >= info.headernum {
-                break;
-            }
-            sync_state.blocks.remove(0);
-        }
+                let latest_block = get_block_at(&api, None).await?.0.block;
+                // remove the blocks not needed in the buffer. info.blocknum is the next required block
+                while let Some(b) = sync_state.blocks.first() {
+                    if b.block.header.number >= info.headernum {
+                        break;
+                    }
+                    sync_state.blocks.remove(0);
+                }
 
-        if args.parachain {
-            info!(
-                "try to sync blocks. next required: (relay_header={}, para_header={}, body={}), relay finalized tip: {}, buffered: {}",
-                info.headernum, info.para_headernum, info.blocknum, latest_block.header.number, sync_state.blocks.len());
-        } else {
-            info!(
-                "try to sync blocks. next required: (body={}, header={}), finalized tip: {}, buffered: {}",
-                info.blocknum, info.headernum, latest_block.header.number, sync_state.blocks.len());
-        }
+                if args.parachain {
+                    info!(
+                        "try to sync blocks. next required: (relay_header={}, para_header={}, body={}), relay finalized tip: {}, buffered: {}",
+                        info.headernum, info.para_headernum, info.blocknum, latest_block.header.number, sync_state.blocks.len());
+                } else {
+                    info!(
+                        "try to sync blocks. next required: (body={}, header={}), finalized tip: {}, buffered: {}",
+                        info.blocknum, info.headernum, latest_block.header.number, sync_state.blocks.len());
+                }
 
-        // fill the sync buffer to catch up the chain tip
-        let next_block = match sync_state.blocks.last() {
-            Some(b) => b.block.header.number + 1,
-            None => info.headernum,
-        };
+                // fill the sync buffer to catch up the chain tip
+                let next_block = match sync_state.blocks.last() {
+                    Some(b) => b.block.header.number + 1,
+                    None => info.headernum,
+                };
 
-        let (batch_end, more_blocks) = {
-            let latest = latest_block.header.number;
-            let fetch_limit = next_block + args.fetch_blocks - 1;
-            if fetch_limit < latest {
-                (fetch_limit, true)
-            } else {
-                (latest, false)
-            }
-        };
+                let (batch_end, more_blocks) = {
+                    let latest = latest_block.header.number;
+                    let fetch_limit = next_block + args.fetch_blocks - 1;
+                    if fetch_limit < latest {
+                        (fetch_limit, true)
+                    } else {
+                        (latest, false)
+                    }
+                };
 
-        for b in next_block..=batch_end {
-            let block = get_block_without_storage_changes(&api, Some(b)).await?;
+                for b in next_block..=batch_end {
+                    let block = get_block_without_storage_changes(&api, Some(b)).await?;
 
-            if block.justifications.is_some() {
-                debug!("block with justification at: {}", block.block.header.number);
-            }
-            sync_state.blocks.push(block);
-        }
+                    if block.justifications.is_some() {
+                        debug!("block with justification at: {}", block.block.header.number);
+                    }
+                    sync_state.blocks.push(block);
+                }
 
-        // send the blocks to pRuntime in batch
-        let synced_blocks = batch_sync_block(
-            &api,
-            &para_api,
-            cache_client.as_ref(),
-            &pr,
-            &mut sync_state,
-            args.sync_blocks,
-            &info,
-            args.parachain,
-        )
-        .await?;
+                // send the blocks to pRuntime in batch
+                let synced_blocks = batch_sync_block(
+                    &api,
+                    &para_api,
+                    cache_client.as_ref(),
+                    &pr,
+                    &mut sync_state,
+                    args.sync_blocks,
+                    &info,
+                    args.parachain,
+                )
+                .await?;
+                (synced_blocks, more_blocks)
+            }
+        };
 
         // check if pRuntime has already reached the chain tip.
         if synced_blocks == 0 && !more_blocks {
+            if sync_state.fast_sync_at.is_some() {
+                info!("Waiting for the headers to reach the fast sync block");
+                sleep(Duration::from_millis(args.dev_wait_block_ms)).await;
+                continue;
+            }
             if args.load_handover_proof {
                 try_load_handover_proof(&pr, &para_api)
                     .await
@@ -1349,12 +1481,14 @@ async fn bridge(
                         .await?;
             }
 
-            if !args.no_bind && !flags.endpoint_registered && info.public_key.is_some() {
+            if !args.no_bind && flags.should_update_endpoint(args) && info.public_key.is_some() {
                 // Here the reason we dont directly report errors when `try_update_worker_endpoint` fails is that we want the endpoint can be registered anytime (e.g. days after the pherry initialization)
                 match endpoint::try_update_worker_endpoint(&pr, &para_api, &mut signer, args).await
                 {
                     Ok(registered) => {
-                        flags.endpoint_registered = registered;
+                        if registered {
+                            flags.endpoint_registered_at = Some(Instant::now());
+                        }
                     }
                     Err(e) => {
                         error!("FailedToCallBindWorkerEndpoint: {:?}", e);
@@ -1502,7 +1636,7 @@ pub async fn pherry_main() {
 
     let mut flags = RunningFlags {
         worker_registered: false,
-        endpoint_registered: false,
+        endpoint_registered_at: None,
         restart_failure_count: 0,
     };
 
@@ -2,6 +2,7 @@ use crate::configurator;
 use crate::wm::wm;
 use clap::{Parser, Subcommand, ValueEnum};
 use log::debug;
+use phala_types::AttestationProvider;
 use serde::{Deserialize, Serialize};
 
 #[derive(Parser, Debug, Clone)]
@@ -33,6 +34,29 @@ pub struct WorkerManagerCliArgs {
     /// URL of webhook endpoint
     #[arg(short = 'w', long, env)]
     pub webhook_url: Option<String>,
+
+    /// Attestation provider used to initialize pRuntimes
+    #[arg(long, env, value_enum, default_value_t = RaOption::Ias)]
+    pub attestation_provider: RaOption,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RaOption {
+    None,
+    Ias,
+    Dcap,
+    Mock,
+}
+
+impl From<RaOption> for Option<AttestationProvider> {
+    fn from(other: RaOption) -> Self {
+        match other {
+            RaOption::None => None,
+            RaOption::Ias => Some(AttestationProvider::Ias),
+            RaOption::Dcap => Some(AttestationProvider::Dcap),
+            RaOption::Mock => Some(AttestationProvider::Mock),
+        }
+    }
 }
 
 pub async fn start_wm() {
@@ -626,6 +626,7 @@ macro_rules! use_parachain_hc {
 impl DataSourceManager {
     pub async fn do_get_init_runtime_default_request(
         self: Arc<Self>,
+        attestation_provider: Option<AttestationProvider>,
     ) -> Result<Arc<DataSourceCacheItem>> {
         let relay_hc = use_relaychain_hc!(self);
 
@@ -673,15 +674,22 @@ impl DataSourceManager {
             genesis_state,
             None,
             true,
-            Some(AttestationProvider::Ias),
+            attestation_provider,
         );
         Ok(Arc::new(DataSourceCacheItem::InitRuntimeRequest(ret)))
     }
-    pub async fn get_init_runtime_default_request(self: Arc<Self>) -> Result<InitRuntimeRequest> {
-        let key = "init".to_string();
+    pub async fn get_init_runtime_default_request(
+        self: Arc<Self>,
+        attestation_provider: Option<AttestationProvider>,
+    ) -> Result<InitRuntimeRequest> {
+        let key = format!("init:{attestation_provider:?}");
         let cache = self.cache.clone();
         match cache
-            .try_get_with(key, self.clone().do_get_init_runtime_default_request())
+            .try_get_with(
+                key,
+                self.clone()
+                    .do_get_init_runtime_default_request(attestation_provider),
+            )
             .await
         {
             Ok(ret) => match *ret {
@@ -9,6 +9,7 @@ use crate::wm::{
 use crate::worker::{WorkerContext, WrappedWorkerContext};
 use anyhow::Result;
 use log::{debug, info, warn};
+use phala_types::AttestationProvider;
 use reqwest::Client;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -29,6 +30,7 @@ pub struct WorkerLifecycleManager {
     pub fast_sync_semaphore: Arc<Semaphore>,
     pub webhook_url: Option<String>,
     pub reqwest: Client,
+    pub attestation_provider: Option<AttestationProvider>,
 }
 pub type WrappedWorkerLifecycleManager = Arc<WorkerLifecycleManager>;
 
@@ -43,6 +45,7 @@ impl WorkerLifecycleManager {
         fast_sync_enabled: bool,
         webhook_url: Option<String>,
         txm: Arc<TxManager>,
+        attestation_provider: Option<AttestationProvider>,
     ) -> WrappedWorkerLifecycleManager {
         let workers =
             get_all_workers(inv_db.clone()).expect("Failed to load workers from local database");
@@ -106,6 +109,7 @@ impl WorkerLifecycleManager {
             fast_sync_semaphore,
             webhook_url,
             reqwest: Client::new(),
+            attestation_provider,
         };
         Arc::new(lm)
     }
@@ -10,6 +10,7 @@ use crate::worker::{WorkerLifecycleState, WrappedWorkerContext};
 use anyhow::{anyhow, Result};
 use futures::future::{try_join, try_join3, try_join_all};
 use log::{debug, info};
+use phala_types::AttestationProvider;
 use std::collections::HashMap;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
@@ -131,7 +132,7 @@ pub async fn wm(args: WorkerManagerCliArgs) {
             loop {
                 let (reload_tx, mut reload_rx) = mpsc::channel::<()>(1);
                 let main_handle =
-                    set_lifecycle_manager(ctx.clone(), reload_tx.clone(), fast_sync_enabled, args.webhook_url.clone());
+                    set_lifecycle_manager(ctx.clone(), reload_tx.clone(), fast_sync_enabled, args.webhook_url.clone(), args.attestation_provider.into());
 
                 tokio::select! {
                     ret = main_handle => {
@@ -155,6 +156,7 @@ pub async fn set_lifecycle_manager(
     reload_tx: WrappedReloadTx,
     fast_sync_enabled: bool,
     webhook_url: Option<String>,
+    attestation_provider: Option<AttestationProvider>,
 ) -> Result<()> {
     let (tx, rx) = mpsc::unbounded_channel::<WorkerManagerCommand>();
 
@@ -166,6 +168,7 @@ pub async fn set_lifecycle_manager(
         fast_sync_enabled,
         webhook_url,
         ctx.txm.clone(),
+        attestation_provider,
     )
     .await;
 
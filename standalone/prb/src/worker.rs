@@ -362,7 +362,10 @@ impl WorkerContext {
         if !i.initialized {
             set_worker_message!(c, "Initializing pRuntime...");
             let res = pr
-                .init_runtime(dsm.get_init_runtime_default_request().await?)
+                .init_runtime(
+                    dsm.get_init_runtime_default_request(lm.attestation_provider)
+                        .await?,
+                )
                 .await?;
             set_worker_message!(c, "Initialized pRuntime.");
             debug!(
@@ -13,8 +13,8 @@ pub async fn get_endpoint_info_by_pubkey(
         .storage_key("PhalaRegistry", "Endpoints", &pubkey)
         .ok()?;
     let storage_data = api.rpc().storage(&address, None).await.ok().flatten()?;
-    let VersionedWorkerEndpoints::V1(endpoints_info) = Decode::decode(&mut &storage_data.0[..]).ok()?;
-    return endpoints_info.into_iter().nth(0);
+    let endpoints_info: VersionedWorkerEndpoints = Decode::decode(&mut &storage_data.0[..]).ok()?;
+    return endpoints_info.endpoints().first().cloned();
 }
 
 pub fn block_get_endpoint_info_by_pubkey(
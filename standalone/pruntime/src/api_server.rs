@@ -14,6 +14,10 @@ use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use tracing::{error, info, instrument};
 
+use phactory::handover::{HandoverError, SignedHandoverCommand, StateManifest};
+use phactory_api::cluster_state::{
+    ClusterStateSummary, ClusterStateVerification, LoadClusterStateRequest,
+};
 use phactory_api::{actions, prpc};
 use phala_rocket_middleware::{RequestTracer, ResponseSigner, TimeMeter, TraceId};
 
@@ -144,6 +148,90 @@ fn getinfo(_id: TraceId) -> String {
     runtime::ecall_getinfo()
 }
 
+#[get("/metrics")]
+fn metrics() -> String {
+    runtime::ecall_prometheus_metrics()
+}
+
+fn handover_error(err: HandoverError) -> Custom<String> {
+    let status = match &err {
+        HandoverError::Unavailable(_) => Status::NotImplemented,
+        HandoverError::NoSession => Status::Conflict,
+        HandoverError::Other(_) => Status::InternalServerError,
+    };
+    error!("Handover request failed: {err:?}");
+    Custom(status, err.to_string())
+}
+
+#[get("/manifest")]
+fn handover_manifest() -> Result<Json<StateManifest>, Custom<String>> {
+    runtime::ecall_handover_manifest()
+        .map(Json)
+        .map_err(handover_error)
+}
+
+#[get("/chunk/<session_id>/<index>")]
+fn handover_chunk(session_id: &str, index: u32) -> Result<Vec<u8>, Custom<String>> {
+    let mut id = [0u8; 32];
+    hex::decode_to_slice(session_id, &mut id)
+        .map_err(|_| Custom(Status::BadRequest, "Invalid session id".into()))?;
+    runtime::ecall_handover_chunk(&id, index).map_err(handover_error)
+}
+
+#[post("/command", format = "json", data = "<command>")]
+fn handover_command(command: Json<SignedHandoverCommand>) -> Result<(), Custom<String>> {
+    runtime::ecall_handover_command(command.into_inner()).map_err(handover_error)
+}
+
+#[get("/status")]
+fn handover_status() -> JsonValue {
+    runtime::ecall_handover_status()
+}
+
+fn cluster_state_error(err: anyhow::Error) -> Custom<String> {
+    error!("Cluster state request failed: {err:?}");
+    Custom(Status::BadRequest, format!("{err:#}"))
+}
+
+#[get("/inspect/<filename>")]
+fn inspect_cluster_state(filename: &str) -> Result<Json<ClusterStateSummary>, Custom<String>> {
+    runtime::ecall_inspect_cluster_state(filename)
+        .map(Json)
+        .map_err(cluster_state_error)
+}
+
+#[get("/verify/<filename>")]
+fn verify_cluster_state(filename: &str) -> Result<Json<ClusterStateVerification>, Custom<String>> {
+    runtime::ecall_verify_cluster_state(filename)
+        .map(Json)
+        .map_err(cluster_state_error)
+}
+
+#[post("/load", format = "json", data = "<request>")]
+fn load_cluster_state(request: Json<LoadClusterStateRequest>) -> Result<(), Custom<String>> {
+    runtime::ecall_load_cluster_state(request.into_inner()).map_err(cluster_state_error)
+}
+
+#[instrument(target="prpc", name="contract_dry_run", fields(%id), skip_all)]
+#[post("/dry_run", format = "json", data = "<request>")]
+async fn contract_dry_run(
+    id: TraceId,
+    request: Json<prpc::ContractQueryRequest>,
+) -> Result<Json<prpc::ContractQueryResponse>, Custom<String>> {
+    use prpc::server::Error;
+    runtime::ecall_contract_dry_run(id.id(), request.into_inner())
+        .await
+        .map(Json)
+        .map_err(|err| {
+            error!("Contract dry run failed: {err:?}");
+            let status = match &err {
+                Error::DecodeError(_) => Status::BadRequest,
+                _ => Status::InternalServerError,
+            };
+            Custom(status, format!("{err:?}"))
+        })
+}
+
 #[get("/help")]
 fn help() -> String {
     phactory_api::prpc::PROTO_DEF.to_string()
@@ -401,9 +489,21 @@ pub(super) fn rocket(args: &super::Args, storage_path: &str) -> rocket::Rocket<i
                     sync_combined_headers,
                     actions::BIN_ACTION_SYNC_COMBINED_HEADERS
                 ),
+                ("/init_beefy", init_beefy, actions::BIN_ACTION_INIT_BEEFY),
+                (
+                    "/sync_para_header_with_beefy",
+                    sync_para_header_with_beefy,
+                    actions::BIN_ACTION_SYNC_PARA_HEADER_WITH_BEEFY
+                ),
+                ("/fast_sync", fast_sync, actions::BIN_ACTION_FAST_SYNC),
+                (
+                    "/beefy_state",
+                    beefy_state,
+                    actions::BIN_ACTION_GET_BEEFY_STATE
+                ),
             ],
         )
-        .mount("/", routes![getinfo, help]);
+        .mount("/", routes![getinfo, help, metrics]);
 
     if args.enable_kick_api {
         info!("ENABLE `kick` API");
@@ -411,6 +511,27 @@ pub(super) fn rocket(args: &super::Args, storage_path: &str) -> rocket::Rocket<i
         server = server.mount("/", routes![kick]);
     }
 
+    server = server.mount(
+        "/handover",
+        routes![
+            handover_manifest,
+            handover_chunk,
+            handover_command,
+            handover_status
+        ],
+    );
+
+    server = server.mount(
+        "/cluster_state",
+        routes![
+            inspect_cluster_state,
+            verify_cluster_state,
+            load_cluster_state
+        ],
+    );
+
+    server = server.mount("/contract", routes![contract_dry_run]);
+
     server = server.mount("/prpc", routes![prpc_proxy, prpc_proxy_get]);
     print_rpc_methods("/prpc", prpc::phactory_api_server::supported_methods());
 
@@ -443,6 +564,8 @@ pub(super) fn rocket_acl(args: &super::Args, storage_path: &str) -> Option<rocke
 
     server_acl = server_acl.mount("/prpc", routes![prpc_proxy_acl, prpc_proxy_get_acl]);
 
+    server_acl = server_acl.mount("/contract", routes![contract_dry_run]);
+
     if args.allow_cors {
         info!("Allow CORS");
 
@@ -452,7 +575,8 @@ pub(super) fn rocket_acl(args: &super::Args, storage_path: &str) -> Option<rocke
             .manage(cors_options().to_cors().expect("To not fail"));
     }
 
-    let signer = ResponseSigner::new(1024 * 1024 * 10, runtime::ecall_sign_http_response);
+    let signer = ResponseSigner::new(1024 * 1024 * 10, runtime::ecall_sign_http_response)
+        .with_chunked_signing(1024 * 64, runtime::ecall_sign_http_response_chunk);
     server_acl = server_acl
         .attach(signer)
         .attach(RequestTracer)
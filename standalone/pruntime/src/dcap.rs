@@ -0,0 +1,159 @@
+use anyhow::{anyhow, Context as _, Result};
+use serde_json::value::RawValue;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::info;
+
+use phala_dcap::{pck_ca_type, pem_to_der_chain, PckExtensions, Quote};
+use phala_types::{Collateral, SgxV30QuoteCollateral};
+use reqwest_env_proxy::EnvProxyBuilder as _;
+
+use crate::ias::create_quote_vec;
+
+const DEFAULT_PCCS_URL: &str = "https://localhost:8081/sgx/certification/v3";
+
+fn pccs_url() -> String {
+    std::env::var("PCCS_URL").unwrap_or_else(|_| DEFAULT_PCCS_URL.into())
+}
+
+fn pccs_get(url: &str, timeout: Duration) -> Result<reqwest::blocking::Response> {
+    let url: reqwest::Url = url.parse()?;
+    info!(from=%url, "Getting DCAP collateral");
+    let res = reqwest::blocking::Client::builder()
+        .trust_dns(true)
+        .timeout(Some(timeout))
+        .env_proxy(url.domain().unwrap_or_default())
+        .build()
+        .context("Failed to create http client, maybe invalid PCCS URI")?
+        .get(url)
+        .send()
+        .context("Failed to send http request")?;
+    let status_code = res.status().as_u16();
+    if status_code != 200 {
+        return Err(anyhow!("Bad http status: {status_code}"));
+    }
+    Ok(res)
+}
+
+fn issuer_chain(res: &reqwest::blocking::Response, header: &str) -> Result<String> {
+    let issuer_chain = res
+        .headers()
+        .get(header)
+        .with_context(|| format!("No header {header}"))?
+        .to_str()
+        .with_context(|| format!("Failed to decode {header}"))?;
+    Ok(urlencoding::decode(issuer_chain)
+        .context("Failed to urldecode issuer chain")?
+        .into_owned())
+}
+
+/// Fetch a signed collateral item from the PCCS.
+///
+/// Returns the issuer chain, the raw JSON of the signed body and the signature.
+fn get_signed_collateral(
+    url: &str,
+    issuer_chain_header: &str,
+    body_field: &str,
+    timeout: Duration,
+) -> Result<(String, String, Vec<u8>)> {
+    let res = pccs_get(url, timeout)?;
+    let issuer_chain = issuer_chain(&res, issuer_chain_header)?;
+    let body = res
+        .text()
+        .context("Failed to read response body from PCCS")?;
+
+    // The signature covers the exact bytes of the body field, so we can not re-serialize it.
+    let fields: BTreeMap<&str, &RawValue> =
+        serde_json::from_str(&body).context("Failed to parse collateral")?;
+    let signed = fields
+        .get(body_field)
+        .with_context(|| format!("No {body_field} in collateral"))?
+        .get()
+        .to_string();
+    let signature: String = serde_json::from_str(
+        fields
+            .get("signature")
+            .context("No signature in collateral")?
+            .get(),
+    )
+    .context("Invalid signature in collateral")?;
+    let signature = hex::decode(signature).context("Failed to decode collateral signature")?;
+    Ok((issuer_chain, signed, signature))
+}
+
+/// Fetch a CRL from the PCCS, which might be served in PEM, hex or DER.
+///
+/// Returns the DER encoded CRL and the issuer chain if `issuer_chain_header` is given.
+fn get_crl(
+    url: &str,
+    issuer_chain_header: Option<&str>,
+    timeout: Duration,
+) -> Result<(Vec<u8>, Option<String>)> {
+    let res = pccs_get(url, timeout)?;
+    let issuer_chain = issuer_chain_header
+        .map(|header| issuer_chain(&res, header))
+        .transpose()?;
+    let body = res
+        .bytes()
+        .context("Failed to read response body from PCCS")?;
+    const PEM_BEGIN: &str = "-----BEGIN X509 CRL-----";
+    const PEM_END: &str = "-----END X509 CRL-----";
+    let text = std::str::from_utf8(&body).map(str::trim);
+    let crl = match text {
+        Ok(text) if text.starts_with(PEM_BEGIN) => {
+            let encoded: String = text
+                .trim_start_matches(PEM_BEGIN)
+                .trim_end_matches(PEM_END)
+                .split_whitespace()
+                .collect();
+            base64::decode(encoded).context("Failed to decode PEM CRL")?
+        }
+        Ok(text) => hex::decode(text).context("Failed to decode hex CRL")?,
+        // DER
+        Err(_) => body.to_vec(),
+    };
+    Ok((crl, issuer_chain))
+}
+
+fn get_collateral(quote: &[u8], timeout: Duration) -> Result<Collateral> {
+    let quote = Quote::parse(quote).map_err(|err| anyhow!("Invalid quote: {err:?}"))?;
+    let pck_chain = pem_to_der_chain(quote.pck_cert_chain).context("Invalid PCK cert chain")?;
+    let pck = PckExtensions::parse(&pck_chain[0]).context("Invalid PCK cert")?;
+    let pck_ca = pck_ca_type(&pck_chain[0]).context("Unknown PCK cert issuer")?;
+    let base_url = pccs_url();
+    let (tcb_info_issuer_chain, tcb_info, tcb_info_signature) = get_signed_collateral(
+        &format!("{base_url}/tcb?fmspc={}", hex::encode(pck.fmspc)),
+        "SGX-TCB-Info-Issuer-Chain",
+        "tcbInfo",
+        timeout,
+    )?;
+    let (qe_identity_issuer_chain, qe_identity, qe_identity_signature) = get_signed_collateral(
+        &format!("{base_url}/qe/identity"),
+        "SGX-Enclave-Identity-Issuer-Chain",
+        "enclaveIdentity",
+        timeout,
+    )?;
+    let (root_ca_crl, _) = get_crl(&format!("{base_url}/rootcacrl"), None, timeout)?;
+    let (pck_crl, pck_crl_issuer_chain) = get_crl(
+        &format!("{base_url}/pckcrl?ca={pck_ca}"),
+        Some("SGX-PCK-CRL-Issuer-Chain"),
+        timeout,
+    )?;
+    Ok(Collateral::SgxV30(SgxV30QuoteCollateral {
+        tcb_info_issuer_chain,
+        tcb_info,
+        tcb_info_signature,
+        qe_identity_issuer_chain,
+        qe_identity,
+        qe_identity_signature,
+        root_ca_crl,
+        pck_crl_issuer_chain: pck_crl_issuer_chain.unwrap_or_default(),
+        pck_crl,
+    }))
+}
+
+pub fn create_attestation_report(data: &[u8], timeout: Duration) -> Result<(Vec<u8>, Collateral)> {
+    let quote = create_quote_vec(data)?;
+    let collateral = get_collateral(&quote, timeout)?;
+    Ok((quote, collateral))
+}
@@ -1,16 +1,140 @@
-use crate::pal_gramine::GraminePlatform;
-use anyhow::{Context, Result};
-use phactory::RpcService;
+use crate::{platform, runtime, Platform};
+use anyhow::{bail, Context, Result};
+use phactory::handover::{self, HandoverCommand, HandoverProgress, HandoverStage, StateManifest};
+use phactory::{Phactory, RpcService};
 use phactory_api::{
     ecall_args::InitArgs, prpc::phactory_api_server::PhactoryApi,
     pruntime_client::new_pruntime_client,
 };
-use tracing::info;
+use reqwest::StatusCode;
+use rocket::tokio::time::sleep;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
 
-pub(crate) async fn handover_from(url: &str, args: InitArgs) -> Result<()> {
-    let mut this = RpcService::new(GraminePlatform);
-    this.lock_phactory(true, false).expect("Failed to lock Phactory").init(args);
+const SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
+/// A handover with the worker key and the state received, waiting for the new pRuntime to catch up.
+pub(crate) struct PendingHandover {
+    from: String,
+    args: InitArgs,
+    manifest: StateManifest,
+}
+
+enum Step {
+    Continue,
+    Done(Option<PendingHandover>),
+}
+
+/// Receives the worker key and the state from the old pRuntime.
+///
+/// Progress is saved after each step, so a failed handover resumes from where it stopped when
+/// started again. Returns `None` if only the worker key is handed over.
+pub(crate) async fn handover_from(
+    url: &str,
+    args: InitArgs,
+    max_retries: u32,
+) -> Result<Option<PendingHandover>> {
+    let mut this = RpcService::new(platform());
+    this.lock_phactory(true, false)
+        .expect("Failed to lock Phactory")
+        .init(args.clone());
+
+    let mut progress = match HandoverProgress::load(&args.storage_path)? {
+        Some(progress) if progress.from == url && !progress.is_finished() => {
+            info!(stage = ?progress.stage, "Resuming handover");
+            progress
+        }
+        _ => HandoverProgress::new(url),
+    };
+    let mut retries = 0;
+    loop {
+        let result = step(&mut this, &mut progress, &args).await;
+        if let Err(err) = &result {
+            progress.retries += 1;
+            progress.last_error = Some(format!("{err:#}"));
+        }
+        progress
+            .save(&args.storage_path)
+            .context("Failed to save handover progress")?;
+        match result {
+            Ok(Step::Continue) => retries = 0,
+            Ok(Step::Done(pending)) => return Ok(pending),
+            Err(err) => {
+                if retries >= max_retries {
+                    return Err(err.context("Handover failed, start again to resume"));
+                }
+                let backoff = Duration::from_secs((1 << retries).min(60));
+                warn!("Handover step failed, retrying in {backoff:?}: {err:#}");
+                sleep(backoff).await;
+                retries += 1;
+            }
+        }
+    }
+}
+
+async fn step(
+    this: &mut RpcService<Platform>,
+    progress: &mut HandoverProgress,
+    args: &InitArgs,
+) -> Result<Step> {
+    let url = progress.from.clone();
+    match &progress.stage {
+        HandoverStage::KeyExchange => {
+            exchange_key(this, &url).await?;
+            if !args.enable_checkpoint {
+                info!("Checkpoint disabled, skip the state transfer");
+                progress.stage = HandoverStage::Completed;
+                return Ok(Step::Done(None));
+            }
+            match fetch_manifest(&url).await? {
+                Some(manifest) => {
+                    info!(
+                        block = manifest.block_number,
+                        size = manifest.total_size,
+                        "Transferring state"
+                    );
+                    progress.start_transfer(&args.storage_path, manifest)?;
+                }
+                None => {
+                    info!("The old pRuntime does not serve its state, skip the state transfer");
+                    progress.stage = HandoverStage::Completed;
+                    return Ok(Step::Done(None));
+                }
+            }
+        }
+        HandoverStage::TransferringState { .. } => {
+            let Some(manifest) = fetch_manifest(&url).await? else {
+                warn!("The old pRuntime lost the handover session, restarting the handover");
+                progress.stage = HandoverStage::KeyExchange;
+                return Ok(Step::Continue);
+            };
+            let session_id = hex::encode(manifest.session_id);
+            progress.start_transfer(&args.storage_path, manifest)?;
+            for index in progress.missing_chunks() {
+                let chunk = http_get(&format!("{url}/handover/chunk/{session_id}/{index}")).await?;
+                progress.receive_chunk(&args.storage_path, index, &chunk)?;
+                progress.save(&args.storage_path)?;
+                if let Some((received, total)) = progress.transfer_progress() {
+                    info!(received, total, "Receiving state");
+                }
+            }
+            progress.finish_transfer(&args.storage_path)?;
+        }
+        HandoverStage::Syncing { manifest } => {
+            return Ok(Step::Done(Some(PendingHandover {
+                from: url,
+                args: args.clone(),
+                manifest: manifest.clone(),
+            })));
+        }
+        HandoverStage::Completed | HandoverStage::RolledBack { .. } => {
+            bail!("Handover already finished")
+        }
+    }
+    Ok(Step::Continue)
+}
+
+async fn exchange_key(this: &mut RpcService<Platform>, url: &str) -> Result<()> {
     let from_pruntime = new_pruntime_client(url.into());
     info!("Requesting for challenge");
     let challenge = from_pruntime
@@ -33,3 +157,133 @@ pub(crate) async fn handover_from(url: &str, args: InitArgs) -> Result<()> {
         .context("Failed to receive handover result")?;
     Ok(())
 }
+
+/// Returns `None` if the old pRuntime has no state to serve for this handover.
+async fn fetch_manifest(url: &str) -> Result<Option<StateManifest>> {
+    match http_get(&format!("{url}/handover/manifest")).await {
+        Ok(body) => Ok(Some(
+            serde_json::from_slice(&body).context("Invalid state manifest")?,
+        )),
+        Err(err) => match err.downcast_ref::<HttpError>() {
+            // Older pRuntimes don't serve the state, or it is in safe mode.
+            Some(HttpError(StatusCode::NOT_FOUND | StatusCode::NOT_IMPLEMENTED, _)) => Ok(None),
+            // The old pRuntime has restarted since the key handover.
+            Some(HttpError(StatusCode::CONFLICT, _)) => Ok(None),
+            _ => Err(err),
+        },
+    }
+}
+
+async fn http_get(url: &str) -> Result<Vec<u8>> {
+    let response = reqwest::get(url)
+        .await
+        .with_context(|| format!("Failed to request {url}"))?;
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(HttpError(status, message).into());
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+#[derive(Debug)]
+struct HttpError(StatusCode, String);
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl PendingHandover {
+    async fn send_command(&self, command: HandoverCommand) -> Result<()> {
+        let signed = Phactory::<Platform>::sign_handover_command(
+            &platform(),
+            &self.args.sealing_path,
+            command,
+            self.manifest.session_id,
+        )?;
+        let response = reqwest::Client::new()
+            .post(format!("{}/handover/command", self.from))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&signed)?)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!(
+                "{}: {}",
+                response.status(),
+                response.text().await.unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    fn set_stage(&self, stage: HandoverStage) {
+        let mut progress = HandoverProgress::load(&self.args.storage_path)
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| HandoverProgress::new(&self.from));
+        progress.stage = stage;
+        if let Err(err) = progress.save(&self.args.storage_path) {
+            error!("Failed to save handover progress: {err:?}");
+        }
+    }
+
+    /// Gives the worker back to the old pRuntime and discards the received key and state.
+    pub(crate) async fn rollback(&self, reason: String) {
+        error!(%reason, "Rolling back the handover");
+        if let Err(err) = self.send_command(HandoverCommand::Abort).await {
+            error!("Failed to notify the old pRuntime, it keeps running anyway: {err:?}");
+        }
+        if let Err(err) = handover::discard_received_state(&self.args) {
+            error!("Failed to discard the received state: {err:?}");
+        }
+        self.set_stage(HandoverStage::RolledBack { reason });
+    }
+}
+
+/// Completes the handover once this pRuntime catches up with the old one, or rolls it back on
+/// timeout.
+pub(crate) async fn watch_sync(pending: PendingHandover, timeout: Duration) {
+    let from_pruntime = new_pruntime_client(pending.from.clone());
+    let started_at = Instant::now();
+    loop {
+        sleep(SYNC_CHECK_INTERVAL).await;
+        let local = runtime::ecall_current_block();
+        let remote = from_pruntime
+            .get_info(())
+            .await
+            .map(|info| info.blocknum.saturating_sub(1));
+        match (local, remote) {
+            (Ok(local), Ok(remote)) => {
+                info!(local, remote, "Catching up with the old pRuntime");
+                if local >= remote {
+                    match pending.send_command(HandoverCommand::Complete).await {
+                        Ok(()) => {
+                            pending.set_stage(HandoverStage::Completed);
+                            info!("Handover completed, the old pRuntime is retired");
+                            return;
+                        }
+                        Err(err) => warn!("Failed to complete the handover: {err:?}"),
+                    }
+                }
+            }
+            (local, remote) => {
+                warn!(
+                    ?local,
+                    ?remote,
+                    "Failed to check the handover sync progress"
+                )
+            }
+        }
+        if started_at.elapsed() > timeout {
+            pending
+                .rollback(format!("Not caught up within {timeout:?}"))
+                .await;
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 mod api_server;
+mod dcap;
 mod ias;
 mod pal_gramine;
 mod runtime;
@@ -16,6 +17,22 @@ use phala_clap_parsers::parse_duration;
 mod handover;
 use phala_sanitized_logger as logger;
 
+#[cfg(not(feature = "mock-platform"))]
+type Platform = pal_gramine::GraminePlatform;
+/// Runs without SGX, for integration tests.
+#[cfg(feature = "mock-platform")]
+type Platform = phactory::mock_platform::MockPlatform;
+
+#[cfg(not(feature = "mock-platform"))]
+fn platform() -> Platform {
+    pal_gramine::GraminePlatform
+}
+
+#[cfg(feature = "mock-platform")]
+fn platform() -> Platform {
+    phactory::mock_platform::MockPlatform::from_env()
+}
+
 #[derive(Parser, Debug, Clone)]
 #[clap(about = "The Phala TEE worker app.", version, author)]
 struct Args {
@@ -65,10 +82,29 @@ struct Args {
     #[arg(default_value_t = 5)]
     max_checkpoint_files: u32,
 
+    /// Write checkpoints as a base plus deltas in a background thread, instead of a full dump
+    /// each time. `--max-checkpoint-files` then limits the number of bases kept.
+    #[arg(long)]
+    incremental_checkpoint: bool,
+
+    /// Max number of deltas on top of a base checkpoint before a new base is written
+    #[arg(long)]
+    #[arg(default_value_t = 8)]
+    max_checkpoint_deltas: u32,
+
     /// Handover key from another running pruntime instance
     #[arg(long)]
     request_handover_from: Option<String>,
 
+    /// Max retries of each handover step before giving up. A failed handover resumes when started
+    /// again.
+    #[arg(long, default_value_t = 10)]
+    handover_max_retries: u32,
+
+    /// Roll back the handover if this instance does not catch up with the old one in time.
+    #[arg(long, value_parser = parse_duration, default_value = "1h")]
+    handover_sync_timeout: Duration,
+
     /// Safe mode level
     ///
     /// - 0, All features enabled.
@@ -91,6 +127,10 @@ struct Args {
     /// The max retry times of getting the attestation report.
     #[arg(long, default_value = "1")]
     ra_max_retries: u32,
+
+    /// The region hint advertised along with the worker endpoints, e.g. `eu-west`.
+    #[arg(long)]
+    region: Option<String>,
 }
 
 #[rocket::main]
@@ -152,26 +192,43 @@ async fn serve(sgx: bool) -> Result<(), rocket::Error> {
             checkpoint_interval: args.checkpoint_interval,
             remove_corrupted_checkpoint: args.remove_corrupted_checkpoint,
             max_checkpoint_files: args.max_checkpoint_files,
+            incremental_checkpoint: args.incremental_checkpoint,
+            max_checkpoint_deltas: args.max_checkpoint_deltas,
             cores,
             public_port: args.public_port,
             safe_mode_level: args.safe_mode_level,
             no_rcu: args.no_rcu,
             ra_timeout: args.ra_timeout,
             ra_max_retries: args.ra_max_retries,
+            region: args.region,
         }
     };
     info!("init_args: {:#?}", init_args);
-    if let Some(from) = args.request_handover_from {
+    let mut pending_handover = None;
+    if let Some(from) = &args.request_handover_from {
         info!(%from, "Starting handover");
-        handover::handover_from(&from, init_args)
-            .await
-            .expect("Handover failed");
-        info!("Handover done");
-        return Ok(());
+        pending_handover =
+            handover::handover_from(from, init_args.clone(), args.handover_max_retries)
+                .await
+                .expect("Handover failed");
+        if pending_handover.is_none() {
+            info!("Handover done");
+            return Ok(());
+        }
+        info!("Worker key and state received, catching up with the old pRuntime");
     }
     if let Err(err) = runtime::ecall_init(init_args) {
+        if let Some(pending) = &pending_handover {
+            pending.rollback(format!("Initialize failed: {err:?}")).await;
+        }
         panic!("Initialize Failed: {err:?}");
     }
+    if let Some(pending) = pending_handover {
+        rocket::tokio::spawn(
+            handover::watch_sync(pending, args.handover_sync_timeout)
+                .instrument(info_span!("handover")),
+        );
+    }
 
     for i in 0..cores {
         thread::Builder::new()
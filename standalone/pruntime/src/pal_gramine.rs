@@ -9,11 +9,12 @@ use std::io::ErrorKind;
 use std::str::FromStr as _;
 use std::time::Duration;
 
-use crate::ias;
+use crate::{dcap, ias};
 
 use phala_types::AttestationProvider;
 
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "mock-platform", allow(dead_code))]
 pub(crate) struct GraminePlatform;
 
 impl Sealing for GraminePlatform {
@@ -64,6 +65,13 @@ impl RA for GraminePlatform {
 
                 Ok(Encode::encode(&attestation_report))
             }
+            Some(AttestationProvider::Dcap) => {
+                let (quote, collateral) = dcap::create_attestation_report(data, timeout)?;
+                let attestation_report =
+                    Some(phala_types::AttestationReport::SgxDcap { quote, collateral });
+
+                Ok(Encode::encode(&attestation_report))
+            }
             None => Ok(Encode::encode(&None::<AttestationProvider>)),
             _ => Err(anyhow!("Unknown attestation provider `{:?}`", provider)),
         }
@@ -71,7 +79,9 @@ impl RA for GraminePlatform {
 
     fn quote_test(&self, provider: Option<AttestationProvider>) -> Result<(), Self::Error> {
         match provider {
-            Some(AttestationProvider::Ias) => ias::create_quote_vec(&[0u8; 64]).map(|_| ()),
+            Some(AttestationProvider::Ias | AttestationProvider::Dcap) => {
+                ias::create_quote_vec(&[0u8; 64]).map(|_| ())
+            }
             None => Ok(()),
             _ => Err(anyhow!("Unknown attestation provider `{:?}`", provider)),
         }
@@ -1,12 +1,17 @@
-use crate::pal_gramine::GraminePlatform;
+use crate::{platform, Platform};
 
 use anyhow::Result;
 use core::sync::atomic::{AtomicU32, Ordering};
-use phactory::{benchmark, Phactory, RpcService};
+use phactory::handover::{HandoverError, HandoverProgress, SignedHandoverCommand, StateManifest};
+use phactory::{benchmark, BlockNumber, Phactory, RpcService};
+use phactory_api::cluster_state::{
+    ClusterStateSummary, ClusterStateVerification, LoadClusterStateRequest,
+};
+use phactory_api::prpc::{self, server::Error as RpcError};
 use tracing::info;
 
 lazy_static::lazy_static! {
-    static ref APPLICATION: RpcService<GraminePlatform> = RpcService::new(GraminePlatform);
+    static ref APPLICATION: RpcService<Platform> = RpcService::new(platform());
 }
 
 pub fn ecall_handle(req_id: u64, action: u8, input: &[u8]) -> Result<Vec<u8>> {
@@ -27,14 +32,88 @@ pub fn ecall_sign_http_response(data: &[u8]) -> Option<String> {
     APPLICATION.lock_phactory(true, true).ok()?.sign_http_response(data)
 }
 
+pub fn ecall_sign_http_response_chunk(message: &[u8]) -> Option<String> {
+    APPLICATION
+        .lock_phactory(true, true)
+        .ok()?
+        .sign_http_response_chunk(message)
+}
+
+pub fn ecall_prometheus_metrics() -> String {
+    phactory::metrics::render_prometheus()
+}
+
+pub fn ecall_current_block() -> Result<BlockNumber> {
+    let info = APPLICATION.lock_phactory(true, true)?.get_info();
+    Ok(info.blocknum.saturating_sub(1))
+}
+
+pub fn ecall_handover_manifest() -> Result<StateManifest, HandoverError> {
+    APPLICATION
+        .lock_phactory(false, true)
+        .map_err(anyhow::Error::from)?
+        .handover_manifest()
+}
+
+pub fn ecall_handover_chunk(session_id: &[u8; 32], index: u32) -> Result<Vec<u8>, HandoverError> {
+    APPLICATION
+        .lock_phactory(false, true)
+        .map_err(anyhow::Error::from)?
+        .handover_chunk(session_id, index)
+}
+
+pub fn ecall_handover_command(command: SignedHandoverCommand) -> Result<(), HandoverError> {
+    APPLICATION
+        .lock_phactory(false, true)
+        .map_err(anyhow::Error::from)?
+        .handover_command(command)
+}
+
+pub fn ecall_handover_status() -> serde_json::Value {
+    let Ok(guard) = APPLICATION.lock_phactory(true, true) else {
+        return serde_json::json!({"error": "Failed to lock Phactory"});
+    };
+    let receiving = HandoverProgress::load(&guard.args.storage_path).unwrap_or_default();
+    serde_json::json!({
+        "serving": guard.handover_serving_status(),
+        "receiving": receiving,
+        "retired": guard.is_retired(),
+    })
+}
+
+pub fn ecall_inspect_cluster_state(filename: &str) -> Result<ClusterStateSummary> {
+    let reader = APPLICATION
+        .lock_phactory(true, true)?
+        .cluster_state_reader()?;
+    reader.inspect(filename)
+}
+
+pub fn ecall_verify_cluster_state(filename: &str) -> Result<ClusterStateVerification> {
+    let reader = APPLICATION
+        .lock_phactory(true, true)?
+        .cluster_state_reader()?;
+    let checked = reader.check(filename)?;
+    APPLICATION
+        .lock_phactory(true, true)?
+        .verify_cluster_state(checked)
+}
+
+pub fn ecall_load_cluster_state(request: LoadClusterStateRequest) -> Result<()> {
+    APPLICATION
+        .lock_phactory(false, false)?
+        .load_cluster_state_selected(&request.filename, request.contracts.as_deref())
+}
+
 pub fn ecall_init(args: phactory_api::ecall_args::InitArgs) -> Result<()> {
     static INITIALIZED: AtomicU32 = AtomicU32::new(0);
     if INITIALIZED.fetch_add(1, Ordering::SeqCst) != 0 {
         anyhow::bail!("Enclave already initialized.");
     }
 
+    phactory::metrics::load(&platform(), &args.sealing_path);
+
     if args.enable_checkpoint {
-        match Phactory::restore_from_checkpoint(&GraminePlatform, &args) {
+        match Phactory::restore_from_checkpoint(&platform(), &args) {
             Ok(Some(factory)) => {
                 info!("Loaded checkpoint");
                 **APPLICATION.lock_phactory(true, true).expect("Failed to lock Phactory") = factory;
@@ -64,6 +143,13 @@ pub fn ecall_bench_run(index: u32) {
     }
 }
 
+pub async fn ecall_contract_dry_run(
+    req_id: u64,
+    request: prpc::ContractQueryRequest,
+) -> Result<prpc::ContractQueryResponse, RpcError> {
+    APPLICATION.contract_dry_run(req_id, request).await
+}
+
 pub async fn ecall_prpc_request(req_id: u64, path: String, data: &[u8], json: bool) -> (u16, Vec<u8>) {
     info!(%path, json, "Handling pRPC request");
     let (code, data) = APPLICATION.dispatch_request(req_id, path, data, json).await;
@@ -8,8 +8,8 @@ use phala_pallets::{
 use phala_types::{
     contract::messaging::{ClusterEvent, ClusterOperation, ContractOperation, WorkerClusterReport},
     messaging::{
-        GatekeeperChange, GatekeeperEvent, GatekeeperLaunch, KeyDistribution, SystemEvent,
-        WorkingInfoUpdateEvent, WorkingReportEvent,
+        GatekeeperChange, GatekeeperEvent, GatekeeperLaunch, KeyDistribution,
+        RandomnessBeaconEvent, SystemEvent, WorkingInfoUpdateEvent, WorkingReportEvent,
     },
 };
 
@@ -44,6 +44,7 @@ pub(crate) fn try_decode_message(topic: &[u8], payload: &[u8]) -> String {
     try_decode!(GatekeeperChange);
     try_decode!(KeyDistribution<BlockNumber>);
     try_decode!(GatekeeperEvent);
+    try_decode!(RandomnessBeaconEvent);
     try_decode!(ClusterRegistryEvent);
     try_decode!(ContractRegistryEvent);
     try_decode!(RegistryEvent);
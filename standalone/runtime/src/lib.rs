@@ -105,9 +105,12 @@ use sp_runtime::generic::Era;
 /// Generated voter bag information.
 mod voter_bags;
 
+use phala_pallets::compute::valuation::{
+    self, OwnerRewards, PendingWithdrawal, PoolSummary, StakeValue,
+};
 pub use phala_pallets::{
     pallet_base_pool, pallet_computation, pallet_mq, pallet_phat, pallet_phat_tokenomic,
-    pallet_registry, pallet_stake_pool, pallet_stake_pool_v2, pallet_vault,
+    pallet_randomness, pallet_registry, pallet_stake_pool, pallet_stake_pool_v2, pallet_vault,
     pallet_wrapped_balances, puppets,
 };
 use phat_offchain_rollup::{anchor as pallet_anchor, oracle as pallet_oracle};
@@ -1317,6 +1320,7 @@ parameter_types! {
     pub const MinInitP: u32 = 50;
     pub const MaxPoolWorkers: u32 = 200;
     pub const NoneAttestationEnabled: bool = true;
+    pub const MockAttestationEnabled: bool = true;
     pub const VerifyPRuntime: bool = false;
     pub const VerifyRelaychainGenesisBlockHash: bool = false;
     pub ParachainId: u32 = ParachainInfo::parachain_id().0;
@@ -1329,11 +1333,17 @@ impl pallet_registry::Config for Runtime {
     type UnixTime = Timestamp;
     type LegacyAttestationValidator = pallet_registry::IasValidator;
     type NoneAttestationEnabled = NoneAttestationEnabled;
+    type MockAttestationEnabled = MockAttestationEnabled;
     type VerifyPRuntime = VerifyPRuntime;
     type VerifyRelaychainGenesisBlockHash = VerifyRelaychainGenesisBlockHash;
     type GovernanceOrigin = EnsureRootOrHalfCouncil;
     type ParachainId = ParachainId;
 }
+impl pallet_randomness::Config for Runtime {
+    type RuntimeEvent = RuntimeEvent;
+    type MaxBeaconHistory = ConstU32<{ DAYS / 5 }>;
+    type BeaconInterval = ConstU32<5>;
+}
 impl pallet_mq::Config for Runtime {
     type QueueNotifyConfig = msg_routing::MessageRouteConfig;
     type CallMatcher = MqCallMatcher;
@@ -1439,6 +1449,8 @@ impl pallet_phat::Config for Runtime {
     type InkCodeSizeLimit = ConstU32<{ 1024 * 1024 * 2 }>;
     type SidevmCodeSizeLimit = ConstU32<{ 1024 * 1024 * 8 }>;
     type Currency = Balances;
+    type OnClusterDestroyed = PhalaPhatTokenomic;
+    type CodeRollbackWindow = ConstU32<{ 2 * DAYS }>;
 }
 
 pub struct WrappedBalancesPalletAccount;
@@ -1587,6 +1599,7 @@ construct_runtime!(
         PhalaBasePool: pallet_base_pool,
         PhalaPhatContracts: pallet_phat,
         PhalaPhatTokenomic: pallet_phat_tokenomic,
+        PhalaRandomness: pallet_randomness,
 
         // Rollup and Oracles
         PhatRollupAnchor: pallet_anchor = 100,
@@ -1874,6 +1887,30 @@ impl_runtime_apis! {
         }
     }
 
+    impl pallet_compute_runtime_api::PhalaComputeApi<Block, AccountId, Balance> for Runtime {
+        fn pool_summary(pid: u64) -> Option<PoolSummary<AccountId, Balance>> {
+            valuation::pool_summary::<Runtime>(pid)
+        }
+
+        fn account_stakes(account: AccountId) -> Vec<StakeValue<Balance>> {
+            valuation::account_stakes::<Runtime>(&account)
+        }
+
+        fn pending_withdrawals(account: AccountId) -> Vec<PendingWithdrawal<Balance>> {
+            valuation::pending_withdrawals::<Runtime>(&account)
+        }
+
+        fn owner_rewards(pid: u64) -> Option<OwnerRewards<Balance>> {
+            valuation::owner_rewards::<Runtime>(pid)
+        }
+
+        fn worker_performance(
+            worker: phala_types::WorkerPublicKey,
+        ) -> Option<pallet_computation::PerformanceHistory> {
+            pallet_computation::WorkerPerformance::<Runtime>::get(worker)
+        }
+    }
+
     impl sp_session::SessionKeys<Block> for Runtime {
         fn generate_session_keys(seed: Option<Vec<u8>>) -> Vec<u8> {
             SessionKeys::generate(seed)
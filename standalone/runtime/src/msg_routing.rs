@@ -37,6 +37,7 @@ impl pallet_mq::QueueNotifyConfig for MessageRouteConfig {
             PhalaPhatContracts::on_worker_cluster_message_received,
             PhalaPhatContracts::on_cluster_message_received,
             PhalaPhatContracts::on_contract_message_received,
+            PhalaRandomness::on_gk_message_received,
             // BridgeTransfer::on_message_received,
         };
         Ok(())